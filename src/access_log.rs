@@ -0,0 +1,379 @@
+//! 请求 ID 与访问日志：每个请求分配一个 ID（沿用合法的 `X-Request-Id` 请求头），
+//! 写入响应头并作为 tracing span 的字段，同一请求的应用日志都带有这个 ID；
+//! 访问日志按 `[access_log]` 以 Common / Combined Log Format、带请求 ID 和耗时的 Combined
+//! 或 JSON 写入单独的文件。
+
+use std::{io::Write, sync::Arc, time::Instant};
+
+use axum::{
+    extract::{OriginalUri, Request, State},
+    http::{HeaderMap, HeaderName, HeaderValue, header},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use tracing::Instrument;
+use tracing_appender::non_blocking::{NonBlocking, NonBlockingBuilder, WorkerGuard};
+
+use crate::{
+    extractors::read_token_from_req,
+    logging,
+    model::{AccessLogFormat, AppState, ConfigFromFile},
+    proxy::Client,
+    utils::ObservedBody,
+};
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+const ACCESS_LOG_FILE_NAME: &str = "access.log";
+/// 客户端提供的请求 ID 的最大长度，超过时重新生成
+const MAX_REQUEST_ID_LEN: usize = 128;
+/// 写入访问日志前隐藏取值的查询参数
+const SECRET_QUERY_KEYS: [&str; 2] = ["token", "password"];
+
+/// 当前请求的 ID，作为请求扩展提供给处理函数和其他中间件
+#[derive(Clone)]
+pub struct RequestId(pub String);
+
+struct Writer {
+    format: AccessLogFormat,
+    writer: NonBlocking,
+    _guard: WorkerGuard,
+}
+
+/// 访问日志，未启用时不写入，但仍然分配请求 ID
+#[derive(Clone, Default)]
+pub struct AccessLog(Option<Arc<Writer>>);
+
+#[derive(Serialize)]
+struct AccessRecord {
+    timestamp: DateTime<Local>,
+    request_id: String,
+    client_ip: Option<String>,
+    user: Option<String>,
+    method: String,
+    uri: String,
+    version: String,
+    status: u16,
+    bytes: u64,
+    /// 响应体是否完整发送，为 false 表示连接中途断开
+    complete: bool,
+    duration_ms: u64,
+    referer: Option<String>,
+    user_agent: Option<String>,
+}
+
+impl AccessLog {
+    /// 按 `[access_log]` 打开访问日志，默认启用，写入 `{log_dir}/access.log`
+    pub fn open(config: &ConfigFromFile) -> std::io::Result<Self> {
+        let access_log = config.access_log.as_ref();
+        if !access_log.and_then(|a| a.enable).unwrap_or(true) {
+            return Ok(AccessLog(None));
+        }
+        let dir = access_log
+            .and_then(|a| a.dir.as_deref())
+            .unwrap_or(config.log_dir());
+        let appender = logging::rolling_appender(
+            dir,
+            ACCESS_LOG_FILE_NAME,
+            access_log.and_then(|a| a.rotation),
+            access_log.and_then(|a| a.max_files),
+        )
+        .map_err(std::io::Error::other)?;
+        let (writer, guard) = NonBlockingBuilder::default()
+            .lossy(false)
+            .thread_name("access-log")
+            .finish(appender);
+        Ok(AccessLog(Some(Arc::new(Writer {
+            format: access_log
+                .and_then(|a| a.format)
+                .unwrap_or(AccessLogFormat::Combined),
+            writer,
+            _guard: guard,
+        }))))
+    }
+
+    fn write(&self, record: &AccessRecord) {
+        let Some(inner) = &self.0 else {
+            return;
+        };
+        let Some(mut line) = format_line(inner.format, record) else {
+            return;
+        };
+        line.push('\n');
+        if let Err(e) = inner.writer.clone().write_all(line.as_bytes()) {
+            tracing::error!("写入访问日志失败: {}", e);
+        }
+    }
+}
+
+fn format_line(format: AccessLogFormat, record: &AccessRecord) -> Option<String> {
+    Some(match format {
+        AccessLogFormat::Common => common_line(record),
+        AccessLogFormat::Combined => combined_line(record),
+        AccessLogFormat::Extended => format!(
+            "{} {} {}ms",
+            combined_line(record),
+            record.request_id,
+            record.duration_ms
+        ),
+        AccessLogFormat::Json => serde_json::to_string(record).ok()?,
+    })
+}
+
+/// `host ident user [time] "request" status bytes`
+fn common_line(record: &AccessRecord) -> String {
+    format!(
+        "{} - {} [{}] \"{} {} {}\" {} {}",
+        record.client_ip.as_deref().unwrap_or("-"),
+        record
+            .user
+            .as_deref()
+            .map(escape)
+            .unwrap_or("-".to_string()),
+        record.timestamp.format("%d/%b/%Y:%H:%M:%S %z"),
+        escape(&record.method),
+        escape(&record.uri),
+        record.version,
+        record.status,
+        if record.bytes == 0 {
+            "-".to_string()
+        } else {
+            record.bytes.to_string()
+        }
+    )
+}
+
+/// Common 加上 `"referer" "user-agent"`
+fn combined_line(record: &AccessRecord) -> String {
+    format!(
+        "{} \"{}\" \"{}\"",
+        common_line(record),
+        escape(record.referer.as_deref().unwrap_or("-")),
+        escape(record.user_agent.as_deref().unwrap_or("-"))
+    )
+}
+
+/// 转义引号、反斜杠和控制字符，避免伪造日志行
+fn escape(s: &str) -> String {
+    s.chars()
+        .flat_map(|c| match c {
+            '"' | '\\' => vec!['\\', c],
+            c if c.is_control() => c.escape_default().collect(),
+            c => vec![c],
+        })
+        .collect()
+}
+
+/// 沿用可以安全写入日志的请求 ID，否则生成新的
+fn request_id(headers: &HeaderMap) -> String {
+    headers
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LEN
+                && id.bytes().all(|b| b.is_ascii_graphic())
+        })
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string())
+}
+
+/// 隐藏查询参数中的会话 token 和分享密码
+fn redact_uri(uri: &axum::http::Uri) -> String {
+    let Some(query) = uri.query() else {
+        return uri.path().to_string();
+    };
+    let query: Vec<String> = query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((key, _)) if SECRET_QUERY_KEYS.contains(&key) => format!("{}=***", key),
+            _ => pair.to_string(),
+        })
+        .collect();
+    format!("{}?{}", uri.path(), query.join("&"))
+}
+
+fn header_string(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .map(|h| String::from_utf8_lossy(h.as_bytes()).into_owned())
+}
+
+/// 分配请求 ID，在它的 span 中处理请求，并在响应体发送完后写入访问日志。挂在 [`crate::proxy::middleware`] 内侧
+pub async fn middleware(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let started = Instant::now();
+    let id = request_id(request.headers());
+    let header_value = HeaderValue::from_str(&id).expect("请求 ID 只包含可见 ASCII 字符");
+    request
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER, header_value.clone());
+    request.extensions_mut().insert(RequestId(id.clone()));
+
+    let span = tracing::info_span!(
+        "request",
+        id = %id,
+        method = %request.method(),
+        path = %request.uri().path()
+    );
+
+    let record = if state.access_log.0.is_some() {
+        let (parts, body) = request.into_parts();
+        let user = match read_token_from_req(&parts).await {
+            Some(token) => state.get_username_by_session(token).await,
+            None => None,
+        };
+        let record = AccessRecord {
+            timestamp: Local::now(),
+            request_id: id,
+            client_ip: parts.extensions.get::<Client>().map(|c| c.ip.to_string()),
+            user,
+            method: parts.method.to_string(),
+            // 记录去掉 `base_path` 之前的路径
+            uri: redact_uri(
+                parts
+                    .extensions
+                    .get::<OriginalUri>()
+                    .map(|u| &u.0)
+                    .unwrap_or(&parts.uri),
+            ),
+            version: format!("{:?}", parts.version),
+            status: 0,
+            bytes: 0,
+            complete: false,
+            duration_ms: 0,
+            referer: header_string(&parts.headers, header::REFERER),
+            user_agent: header_string(&parts.headers, header::USER_AGENT),
+        };
+        request = Request::from_parts(parts, body);
+        Some(record)
+    } else {
+        None
+    };
+
+    let mut response = next.run(request).instrument(span).await;
+    response
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER, header_value);
+
+    let Some(mut record) = record else {
+        return response;
+    };
+    record.status = response.status().as_u16();
+    let access_log = state.access_log.clone();
+    let (parts, body) = response.into_parts();
+    let body = ObservedBody::wrap(body, move |sent, complete| {
+        record.bytes = sent;
+        record.complete = complete;
+        record.duration_ms = started.elapsed().as_millis() as u64;
+        access_log.write(&record);
+    });
+    Response::from_parts(parts, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn record() -> AccessRecord {
+        AccessRecord {
+            timestamp: Local
+                .from_local_datetime(&"2026-01-05T16:00:00".parse().unwrap())
+                .unwrap(),
+            request_id: "5bdee6058c7f40839101120fcd31a27d".to_string(),
+            client_ip: Some("192.168.1.20".to_string()),
+            user: Some("alice".to_string()),
+            method: "GET".to_string(),
+            uri: "/api/download?root=C&path=/a.pdf".to_string(),
+            version: "HTTP/1.1".to_string(),
+            status: 200,
+            bytes: 52341,
+            complete: true,
+            duration_ms: 12,
+            referer: None,
+            user_agent: Some("Mozilla/5.0".to_string()),
+        }
+    }
+
+    #[test]
+    fn formats() {
+        let record = record();
+        let time = record.timestamp.format("%d/%b/%Y:%H:%M:%S %z").to_string();
+        let common = format!(
+            "192.168.1.20 - alice [{time}] \"GET /api/download?root=C&path=/a.pdf HTTP/1.1\" 200 52341"
+        );
+        let combined = format!("{common} \"-\" \"Mozilla/5.0\"");
+        assert_eq!(
+            format_line(AccessLogFormat::Common, &record).unwrap(),
+            common
+        );
+        assert_eq!(
+            format_line(AccessLogFormat::Combined, &record).unwrap(),
+            combined
+        );
+        assert_eq!(
+            format_line(AccessLogFormat::Extended, &record).unwrap(),
+            format!("{combined} 5bdee6058c7f40839101120fcd31a27d 12ms")
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&format_line(AccessLogFormat::Json, &record).unwrap()).unwrap();
+        assert_eq!(json["request_id"], "5bdee6058c7f40839101120fcd31a27d");
+        assert_eq!(json["bytes"], 52341);
+        assert!(json["referer"].is_null());
+    }
+
+    #[test]
+    fn missing_fields_and_escaping() {
+        let record = AccessRecord {
+            client_ip: None,
+            user: Some("a\"b".to_string()),
+            uri: "/x\ny".to_string(),
+            bytes: 0,
+            user_agent: Some("evil\" \"agent".to_string()),
+            ..record()
+        };
+        let line = format_line(AccessLogFormat::Combined, &record).unwrap();
+        assert!(line.starts_with("- - a\\\"b ["), "{line}");
+        assert!(line.contains("\"GET /x\\ny HTTP/1.1\" 200 - \"-\" \"evil\\\" \\\"agent\""));
+        assert!(!line.contains('\n'));
+    }
+
+    #[test]
+    fn secrets_are_redacted() {
+        let redact = |uri: &str| redact_uri(&uri.parse().unwrap());
+        assert_eq!(redact("/api/files?root=C"), "/api/files?root=C");
+        assert_eq!(
+            redact("/api/download?root=C&token=abc&path=/a"),
+            "/api/download?root=C&token=***&path=/a"
+        );
+        assert_eq!(
+            redact("/s/x?password=p&tokens=1"),
+            "/s/x?password=***&tokens=1"
+        );
+        assert_eq!(redact("/healthz"), "/healthz");
+    }
+
+    #[test]
+    fn request_ids() {
+        let mut headers = HeaderMap::new();
+        let generated = request_id(&headers);
+        assert_eq!(generated.len(), 32);
+
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("abc-123"));
+        assert_eq!(request_id(&headers), "abc-123");
+
+        // 含空格或过长时重新生成
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("a b"));
+        assert_ne!(request_id(&headers), "a b");
+        let long = "a".repeat(MAX_REQUEST_ID_LEN + 1);
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_str(&long).unwrap());
+        assert_eq!(request_id(&headers).len(), 32);
+    }
+}
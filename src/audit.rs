@@ -0,0 +1,740 @@
+//! 审计日志：HTTP 接口上的登录、退出、列目录、下载、上传以及分享等操作，和 S3、SFTP、FTP 上的
+//! 文件操作各写一条 JSON Lines 记录，与普通日志分开保存和滚动，管理员可以通过
+//! `/api/admin/audit` 按用户、路径和时间查询。
+//!
+//! HTTP 的记录由 [`middleware`] 在响应体发送完（或连接中断）时写入，用户、路径和字节数由
+//! [`AuditContext`] 在处理请求的过程中补充。上传和下载的字节数与进行中的传输也在这里计入
+//! [`crate::telemetry`] 的指标。其他协议使用 [`Operation`]，同样计入传输指标；FTP 的记录中
+//! 没有客户端地址，libunftp 不向存储后端提供连接信息。
+
+use std::{
+    collections::VecDeque,
+    io::Write,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use axum::{
+    extract::{MatchedPath, Query, Request, State},
+    http::{Method, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncBufReadExt;
+use tracing_appender::non_blocking::{NonBlocking, NonBlockingBuilder, WorkerGuard};
+
+use crate::{
+    access_log::RequestId,
+    extractors::read_token_from_req,
+    logging,
+    model::{AppState, ConfigFromFile},
+    proxy::Client,
+    telemetry::{self, Transfer},
+    utils::ObservedBody,
+};
+
+pub const AUDIT_FILE_NAME: &str = "audit.jsonl";
+const DEFAULT_QUERY_LIMIT: usize = 1000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Login,
+    Logout,
+    List,
+    Download,
+    Upload,
+    ShareList,
+    ShareCreate,
+    ShareRevoke,
+    ShareDownload,
+    FileRequestList,
+    FileRequestCreate,
+    FileRequestRevoke,
+    FileRequestUpload,
+    AdminLogLevel,
+    AdminAuditQuery,
+    /// 以下只出现在 S3、SFTP、FTP 的记录中
+    Delete,
+    Mkdir,
+    Rename,
+}
+
+impl AuditAction {
+    /// 按请求方法和路由确定操作，不在表中的请求不记录
+    fn from_route(method: &Method, route: &str) -> Option<Self> {
+        use AuditAction::*;
+        Some(match (method.as_str(), route) {
+            ("POST", "/api/login") => Login,
+            ("POST", "/api/logout") => Logout,
+            ("GET", "/api/files") => List,
+            ("GET", "/api/download") => Download,
+            ("POST", "/api/upload") => Upload,
+            ("GET", "/api/shares") => ShareList,
+            ("POST", "/api/shares") => ShareCreate,
+            ("DELETE", "/api/shares/{token}") => ShareRevoke,
+            ("GET", "/s/{token}") => ShareDownload,
+            ("GET", "/api/file-requests") => FileRequestList,
+            ("POST", "/api/file-requests") => FileRequestCreate,
+            ("DELETE", "/api/file-requests/{token}") => FileRequestRevoke,
+            ("POST", "/r/{token}") => FileRequestUpload,
+            (_, "/api/admin/log-level") => AdminLogLevel,
+            ("GET", "/api/admin/audit") => AdminAuditQuery,
+            _ => return None,
+        })
+    }
+
+    /// 字节数为响应体大小的操作
+    fn sends_file(self) -> bool {
+        matches!(self, AuditAction::Download | AuditAction::ShareDownload)
+    }
+
+    /// 传输指标的方向，不传输文件的操作为 `None`
+    fn transfer_direction(self) -> Option<&'static str> {
+        match self {
+            AuditAction::Download | AuditAction::ShareDownload => Some("download"),
+            AuditAction::Upload | AuditAction::FileRequestUpload => Some("upload"),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditResult {
+    Ok,
+    /// 未登录、密码错误或没有权限
+    Denied,
+    Failed,
+    /// 响应体发送完之前连接就断开了
+    Aborted,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub timestamp: DateTime<Utc>,
+    /// 与访问日志和应用日志中的请求 ID 相同
+    pub request_id: Option<String>,
+    pub user: Option<String>,
+    /// 会话 token 的 SHA-256 前 16 位，不记录 token 本身
+    pub session: Option<String>,
+    pub client_ip: Option<IpAddr>,
+    pub protocol: String,
+    pub action: AuditAction,
+    pub root: Option<String>,
+    pub path: Option<String>,
+    /// 重命名后的路径，与 `path` 在同一个根目录下
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_path: Option<String>,
+    pub bytes: Option<u64>,
+    pub result: AuditResult,
+    pub status: Option<u16>,
+    pub duration_ms: u64,
+}
+
+#[derive(Default)]
+struct AuditInfo {
+    user: Option<String>,
+    root: Option<String>,
+    path: Option<String>,
+    bytes: Option<u64>,
+    result: Option<AuditResult>,
+}
+
+/// 请求扩展，处理函数和 [`crate::extractors::AuthUser`] 通过它补充审计记录
+#[derive(Clone, Default)]
+pub struct AuditContext(Arc<Mutex<AuditInfo>>);
+
+impl AuditContext {
+    fn info(&self) -> std::sync::MutexGuard<'_, AuditInfo> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn set_user(&self, user: &str) {
+        self.info().user = Some(user.to_string());
+    }
+
+    pub fn set_root(&self, root: &str) {
+        self.info().root = Some(root.to_string());
+    }
+
+    pub fn set_path(&self, path: &str) {
+        self.info().path = Some(path.to_string());
+    }
+
+    pub fn set_target(&self, root: &str, path: &str) {
+        self.set_root(root);
+        self.set_path(path);
+    }
+
+    pub fn set_bytes(&self, bytes: u64) {
+        self.info().bytes = Some(bytes);
+    }
+
+    /// 状态码无法反映结果时使用，例如登录失败也返回 200
+    pub fn set_result(&self, result: AuditResult) {
+        self.info().result = Some(result);
+    }
+}
+
+struct Writer {
+    dir: PathBuf,
+    writer: NonBlocking,
+    _guard: WorkerGuard,
+}
+
+/// 审计日志，未启用时记录和查询都不做任何事
+#[derive(Clone, Default)]
+pub struct Audit(Option<Arc<Writer>>);
+
+#[derive(Default, Deserialize)]
+pub struct AuditQuery {
+    pub user: Option<String>,
+    pub action: Option<AuditAction>,
+    pub root: Option<String>,
+    /// 路径前缀
+    pub path: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    /// 最多返回最近的多少条，默认 1000
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    fn matches(&self, record: &AuditRecord) -> bool {
+        self.user
+            .as_ref()
+            .is_none_or(|u| record.user.as_ref() == Some(u))
+            && self.action.is_none_or(|a| record.action == a)
+            && self
+                .root
+                .as_ref()
+                .is_none_or(|r| record.root.as_ref() == Some(r))
+            && self.path.as_ref().is_none_or(|p| {
+                record.path.as_ref().is_some_and(|rp| {
+                    Path::new(rp.trim_start_matches('/')).starts_with(p.trim_start_matches('/'))
+                })
+            })
+            && self.from.is_none_or(|t| record.timestamp >= t)
+            && self.to.is_none_or(|t| record.timestamp <= t)
+    }
+}
+
+impl Audit {
+    /// 按 `[audit]` 打开审计日志，默认启用，写入 `{log_dir}/audit`
+    pub fn open(config: &ConfigFromFile) -> std::io::Result<Self> {
+        let audit = config.audit.as_ref();
+        if !audit.and_then(|a| a.enable).unwrap_or(true) {
+            return Ok(Audit(None));
+        }
+        let dir = audit
+            .and_then(|a| a.dir.as_ref())
+            .map(PathBuf::from)
+            .unwrap_or(PathBuf::from(config.log_dir()).join("audit"));
+        let appender = logging::rolling_appender(
+            &dir,
+            AUDIT_FILE_NAME,
+            audit.and_then(|a| a.rotation),
+            audit.and_then(|a| a.max_files),
+        )
+        .map_err(std::io::Error::other)?;
+        // 审计记录不能因为队列满而丢弃
+        let (writer, guard) = NonBlockingBuilder::default()
+            .lossy(false)
+            .thread_name("audit-log")
+            .finish(appender);
+        Ok(Audit(Some(Arc::new(Writer {
+            dir,
+            writer,
+            _guard: guard,
+        }))))
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    pub fn record(&self, record: &AuditRecord) {
+        let Some(inner) = &self.0 else {
+            return;
+        };
+        let Ok(mut line) = serde_json::to_string(record) else {
+            return;
+        };
+        line.push('\n');
+        // 每条记录一次写入，多个请求同时写也不会交错
+        if let Err(e) = inner.writer.clone().write_all(line.as_bytes()) {
+            tracing::error!("写入审计日志失败: {}", e);
+        }
+    }
+
+    /// 返回最近的 `limit` 条匹配记录，按时间顺序排列。文件名顺序即时间顺序，从最新的文件开始
+    /// 逐行读取，每个文件只保留还需要的条数，凑够 `limit` 条后不再读取更早的文件
+    pub async fn query(&self, query: &AuditQuery) -> std::io::Result<Vec<AuditRecord>> {
+        let Some(inner) = &self.0 else {
+            return Ok(Vec::new());
+        };
+        let mut files = Vec::new();
+        let mut entries = tokio::fs::read_dir(&inner.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with(AUDIT_FILE_NAME)
+            {
+                files.push(entry.path());
+            }
+        }
+        files.sort();
+
+        let limit = query.limit.unwrap_or(DEFAULT_QUERY_LIMIT);
+        let mut records = VecDeque::new();
+        for file in files.iter().rev() {
+            let wanted = limit - records.len();
+            if wanted == 0 {
+                break;
+            }
+            let mut matched = VecDeque::with_capacity(wanted.min(DEFAULT_QUERY_LIMIT));
+            let mut lines = tokio::io::BufReader::new(tokio::fs::File::open(file).await?).lines();
+            while let Some(line) = lines.next_line().await? {
+                let Ok(record) = serde_json::from_str::<AuditRecord>(&line) else {
+                    continue;
+                };
+                if !query.matches(&record) {
+                    continue;
+                }
+                if matched.len() == wanted {
+                    matched.pop_front();
+                }
+                matched.push_back(record);
+            }
+            // 这个文件中的记录早于已经取到的记录
+            while let Some(record) = matched.pop_back() {
+                records.push_front(record);
+            }
+        }
+        Ok(records.into())
+    }
+}
+
+/// S3、SFTP、FTP 上的一次文件操作。调用 [`Operation::finish`] 后在丢弃时写入记录，
+/// 没有调用就被丢弃（例如传输中连接断开）的记为 `Aborted`
+pub struct Operation {
+    audit: Audit,
+    record: AuditRecord,
+    started: Instant,
+    transfer: Option<Transfer>,
+}
+
+impl Operation {
+    /// `protocol` 为 `s3`、`sftp` 或 `ftp`，`root` 为 `[[paths]]` 的名称，空字符串记为无
+    pub fn new(
+        audit: &Audit,
+        protocol: &str,
+        action: AuditAction,
+        user: &str,
+        client_ip: Option<IpAddr>,
+        root: &str,
+        path: &str,
+    ) -> Self {
+        Operation {
+            audit: audit.clone(),
+            record: AuditRecord {
+                timestamp: Utc::now(),
+                request_id: None,
+                user: Some(user.to_string()),
+                session: None,
+                client_ip,
+                protocol: protocol.to_string(),
+                action,
+                root: (!root.is_empty()).then(|| root.to_string()),
+                path: (!path.is_empty()).then(|| path.to_string()),
+                new_path: None,
+                bytes: None,
+                result: AuditResult::Aborted,
+                status: None,
+                duration_ms: 0,
+            },
+            started: Instant::now(),
+            transfer: action.transfer_direction().map(Transfer::start),
+        }
+    }
+
+    /// 字节数已经在别处计入传输指标时使用，例如 S3 分片上传的各个分片
+    pub fn without_transfer(mut self) -> Self {
+        self.transfer = None;
+        self
+    }
+
+    pub fn set_new_path(&mut self, path: &str) {
+        self.record.new_path = Some(path.to_string());
+    }
+
+    pub fn add_bytes(&mut self, bytes: u64) {
+        *self.record.bytes.get_or_insert(0) += bytes;
+    }
+
+    pub fn finish(mut self, result: AuditResult) {
+        self.record.result = result;
+    }
+}
+
+impl Drop for Operation {
+    fn drop(&mut self) {
+        if let Some(transfer) = self.transfer.take() {
+            transfer.finish(self.record.root.as_deref(), self.record.bytes.unwrap_or(0));
+        }
+        self.record.duration_ms = self.started.elapsed().as_millis() as u64;
+        self.audit.record(&self.record);
+    }
+}
+
+/// 会话 token 的摘要，可以关联同一会话的记录而不泄露 token
+fn session_id(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))[..16].to_string()
+}
+
+#[derive(Deserialize)]
+struct Target {
+    root: Option<String>,
+    path: Option<String>,
+}
+
+/// 等待响应体发送完后写入的记录
+struct Pending {
+    audit: Audit,
+    context: AuditContext,
+    record: AuditRecord,
+    started: Instant,
+    transfer: Option<Transfer>,
+}
+
+impl Pending {
+    fn finish(self, sent: u64, complete: bool) {
+        let mut record = self.record;
+        let info = self.context.info();
+        record.user = info.user.clone().or(record.user);
+        record.root = info.root.clone().or(record.root);
+        record.path = info.path.clone().or(record.path);
+        // 失败时的响应体是错误信息，不计入下载的字节数
+        let sent_file = record.action.sends_file() && record.result == AuditResult::Ok;
+        record.bytes = info.bytes.or(sent_file.then_some(sent));
+        record.result = match info.result {
+            Some(result) => result,
+            None if record.result == AuditResult::Ok && !complete => AuditResult::Aborted,
+            None => record.result,
+        };
+        record.duration_ms = self.started.elapsed().as_millis() as u64;
+        drop(info);
+        if let Some(transfer) = self.transfer {
+            transfer.finish(record.root.as_deref(), record.bytes.unwrap_or(0));
+        }
+        self.audit.record(&record);
+    }
+}
+
+/// 为需要审计的路由写入审计记录并统计传输指标，用 `route_layer` 挂在路由上
+pub async fn middleware(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let action = request
+        .extensions()
+        .get::<MatchedPath>()
+        .and_then(|route| AuditAction::from_route(request.method(), route.as_str()));
+    let Some(action) = action.filter(|_| state.audit.enabled() || telemetry::enabled()) else {
+        return next.run(request).await;
+    };
+
+    let started = Instant::now();
+    // 上传在处理请求期间进行，所以在调用处理函数之前开始计数
+    let transfer = action.transfer_direction().map(Transfer::start);
+    let (mut parts, body) = request.into_parts();
+    let request_id = parts.extensions.get::<RequestId>().cloned();
+    let client_ip = parts.extensions.get::<Client>().map(|c| c.ip);
+    let token = read_token_from_req(&parts).await.map(str::to_string);
+    // 退出登录会删除会话，所以在处理请求之前查出用户
+    let user = match &token {
+        Some(token) => state.get_username_by_session(token).await,
+        None => None,
+    };
+    let target = Query::<Target>::try_from_uri(&parts.uri).ok();
+    let context = AuditContext::default();
+    parts.extensions.insert(context.clone());
+
+    let response = next.run(Request::from_parts(parts, body)).await;
+    let status = response.status();
+    let result = if status.is_success() || status.is_redirection() {
+        AuditResult::Ok
+    } else if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+        AuditResult::Denied
+    } else {
+        AuditResult::Failed
+    };
+    let record = AuditRecord {
+        timestamp: Utc::now(),
+        request_id: request_id.map(|r| r.0),
+        user,
+        session: token.as_deref().map(session_id),
+        client_ip,
+        protocol: "http".to_string(),
+        action,
+        root: target.as_ref().and_then(|t| t.root.clone()),
+        path: target.and_then(|t| t.0.path),
+        new_path: None,
+        bytes: None,
+        result,
+        status: Some(status.as_u16()),
+        duration_ms: 0,
+    };
+    let (parts, body) = response.into_parts();
+    let pending = Pending {
+        audit: state.audit.clone(),
+        context,
+        record,
+        started,
+        transfer,
+    };
+    // 响应体发送完或连接断开时才写入记录
+    let body = ObservedBody::wrap(body, move |sent, complete| pending.finish(sent, complete));
+    Response::from_parts(parts, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open(dir: &Path) -> Audit {
+        let config = format!(
+            "users = []\npaths = []\n[audit]\ndir = \"{}\"\n",
+            dir.display()
+        );
+        Audit::open(&toml::from_str(&config).unwrap()).unwrap()
+    }
+
+    fn record(timestamp: &str, user: &str, action: AuditAction, path: &str) -> AuditRecord {
+        let mut record =
+            Operation::new(&Audit::default(), "sftp", action, user, None, "docs", path)
+                .record
+                .clone();
+        record.timestamp = timestamp.parse().unwrap();
+        record.result = AuditResult::Ok;
+        record
+    }
+
+    fn write_file(dir: &Path, name: &str, records: &[AuditRecord]) {
+        let lines: Vec<_> = records
+            .iter()
+            .map(|r| serde_json::to_string(r).unwrap() + "\n")
+            .collect();
+        std::fs::write(dir.join(name), lines.concat()).unwrap();
+    }
+
+    fn paths(records: &[AuditRecord]) -> Vec<&str> {
+        records.iter().filter_map(|r| r.path.as_deref()).collect()
+    }
+
+    #[test]
+    fn record_shape() {
+        let mut operation = Operation::new(
+            &Audit::default(),
+            "sftp",
+            AuditAction::Rename,
+            "alice",
+            Some("10.0.0.1".parse().unwrap()),
+            "docs",
+            "a.txt",
+        );
+        let value = serde_json::to_value(&operation.record).unwrap();
+        assert_eq!(value["protocol"], "sftp");
+        assert_eq!(value["action"], "rename");
+        assert_eq!(value["user"], "alice");
+        assert_eq!(value["client_ip"], "10.0.0.1");
+        assert_eq!(value["result"], "aborted");
+        assert!(value.get("new_path").is_none());
+
+        operation.set_new_path("b.txt");
+        let value = serde_json::to_value(&operation.record).unwrap();
+        assert_eq!(value["new_path"], "b.txt");
+
+        // 虚拟根目录上的操作没有根目录和路径
+        let operation = Operation::new(
+            &Audit::default(),
+            "ftp",
+            AuditAction::List,
+            "alice",
+            None,
+            "",
+            "",
+        );
+        let value = serde_json::to_value(&operation.record).unwrap();
+        assert!(value["root"].is_null() && value["path"].is_null());
+    }
+
+    #[tokio::test]
+    async fn operations_are_written_on_drop() {
+        let dir = tempfile::tempdir().unwrap();
+        let audit = open(dir.path());
+        let mut upload = Operation::new(
+            &audit,
+            "ftp",
+            AuditAction::Upload,
+            "alice",
+            None,
+            "docs",
+            "a.txt",
+        );
+        upload.add_bytes(3);
+        upload.add_bytes(4);
+        upload.finish(AuditResult::Ok);
+        // 传输中断开连接，没有调用 finish
+        Operation::new(
+            &audit,
+            "ftp",
+            AuditAction::Download,
+            "alice",
+            None,
+            "docs",
+            "b.txt",
+        );
+        // 丢弃最后一个引用时才会把队列中的记录写完
+        drop(audit);
+
+        let records = open(dir.path())
+            .query(&AuditQuery::default())
+            .await
+            .unwrap();
+        assert_eq!(paths(&records), ["a.txt", "b.txt"]);
+        assert_eq!(records[0].bytes, Some(7));
+        assert!(records[0].result == AuditResult::Ok);
+        assert_eq!(records[1].bytes, None);
+        assert!(records[1].result == AuditResult::Aborted);
+    }
+
+    #[tokio::test]
+    async fn query_filters() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(
+            dir.path(),
+            "audit.jsonl.2020-01-01",
+            &[
+                record(
+                    "2020-01-01T10:00:00Z",
+                    "alice",
+                    AuditAction::Upload,
+                    "a/x.txt",
+                ),
+                record(
+                    "2020-01-01T11:00:00Z",
+                    "bob",
+                    AuditAction::Download,
+                    "a/y.txt",
+                ),
+            ],
+        );
+        write_file(
+            dir.path(),
+            "audit.jsonl.2020-01-02",
+            &[
+                record(
+                    "2020-01-02T10:00:00Z",
+                    "alice",
+                    AuditAction::Delete,
+                    "ab/z.txt",
+                ),
+                record(
+                    "2020-01-02T11:00:00Z",
+                    "alice",
+                    AuditAction::Upload,
+                    "b.txt",
+                ),
+            ],
+        );
+        let audit = open(dir.path());
+
+        let all = audit.query(&AuditQuery::default()).await.unwrap();
+        assert_eq!(paths(&all), ["a/x.txt", "a/y.txt", "ab/z.txt", "b.txt"]);
+
+        let q = AuditQuery {
+            user: Some("alice".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            paths(&audit.query(&q).await.unwrap()),
+            ["a/x.txt", "ab/z.txt", "b.txt"]
+        );
+
+        let q = AuditQuery {
+            action: Some(AuditAction::Upload),
+            ..Default::default()
+        };
+        assert_eq!(paths(&audit.query(&q).await.unwrap()), ["a/x.txt", "b.txt"]);
+
+        // 路径前缀按路径组成部分匹配，`a` 不匹配 `ab/z.txt`
+        let q = AuditQuery {
+            path: Some("/a".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            paths(&audit.query(&q).await.unwrap()),
+            ["a/x.txt", "a/y.txt"]
+        );
+
+        let q = AuditQuery {
+            root: Some("other".to_string()),
+            ..Default::default()
+        };
+        assert!(audit.query(&q).await.unwrap().is_empty());
+
+        let q = AuditQuery {
+            from: Some("2020-01-01T11:00:00Z".parse().unwrap()),
+            to: Some("2020-01-02T10:00:00Z".parse().unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(
+            paths(&audit.query(&q).await.unwrap()),
+            ["a/y.txt", "ab/z.txt"]
+        );
+    }
+
+    #[tokio::test]
+    async fn query_limit_keeps_the_latest_records() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(
+            dir.path(),
+            "audit.jsonl.2020-01-01",
+            &[
+                record("2020-01-01T10:00:00Z", "alice", AuditAction::List, "1"),
+                record("2020-01-01T11:00:00Z", "alice", AuditAction::List, "2"),
+                record("2020-01-01T12:00:00Z", "alice", AuditAction::List, "3"),
+            ],
+        );
+        write_file(
+            dir.path(),
+            "audit.jsonl.2020-01-02",
+            &[record(
+                "2020-01-02T10:00:00Z",
+                "alice",
+                AuditAction::List,
+                "4",
+            )],
+        );
+        // 无法解析的行被跳过
+        std::fs::write(dir.path().join("audit.jsonl.2020-01-03"), "not json\n").unwrap();
+        let audit = open(dir.path());
+
+        let q = |limit| AuditQuery {
+            limit: Some(limit),
+            ..Default::default()
+        };
+        assert_eq!(paths(&audit.query(&q(1)).await.unwrap()), ["4"]);
+        assert_eq!(paths(&audit.query(&q(3)).await.unwrap()), ["2", "3", "4"]);
+        assert_eq!(
+            paths(&audit.query(&q(10)).await.unwrap()),
+            ["1", "2", "3", "4"]
+        );
+        assert!(audit.query(&q(0)).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,233 @@
+//! 命令行参数：子命令，以及覆盖 `[misc]` 配置的选项，选项也可以通过环境变量设置
+
+use std::{
+    io::{BufRead, IsTerminal, Write},
+    net::IpAddr,
+};
+
+use clap::{Args, Parser, Subcommand};
+
+use crate::model::{
+    ConfigFromFile, MiscFromFile, READ_MASK, VIEW_MASK, WRITE_MASK,
+    auth::hash_password,
+    layers::Layers,
+    validate::{ConfigIssue, Severity},
+};
+
+const DEFAULT_CONFIG: &str = include_str!("../config.default.toml");
+
+#[derive(Parser)]
+#[command(version, about = "简单的文件管理服务器")]
+pub struct Cli {
+    /// 配置文件
+    #[arg(
+        short,
+        long,
+        env = "SFM_CONFIG",
+        default_value = "config.toml",
+        global = true
+    )]
+    pub config: String,
+
+    #[command(flatten)]
+    pub overrides: Overrides,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// 优先于配置文件中 `[misc]` 的选项
+#[derive(Args)]
+pub struct Overrides {
+    /// 监听地址，覆盖 `misc.host`
+    #[arg(long, env = "SFM_BIND", global = true)]
+    pub bind: Option<IpAddr>,
+    /// HTTP 端口，覆盖 `misc.port`
+    #[arg(short, long, env = "SFM_PORT", global = true)]
+    pub port: Option<u16>,
+    /// 从磁盘读取前端文件的目录，覆盖 `misc.static_dir`
+    #[arg(long, env = "SFM_STATIC_DIR", global = true)]
+    pub static_dir: Option<String>,
+    /// 日志目录，覆盖 `misc.log_dir`
+    #[arg(long, env = "SFM_LOG_DIR", global = true)]
+    pub log_dir: Option<String>,
+    /// 日志级别或过滤规则，覆盖 `misc.log_level`
+    #[arg(long, env = "SFM_LOG_LEVEL", global = true)]
+    pub log_level: Option<String>,
+    /// 证书目录，覆盖 `misc.cert_path`
+    #[arg(long, env = "SFM_CERT_DIR", global = true)]
+    pub cert_dir: Option<String>,
+}
+
+impl Overrides {
+    /// 只包含命令行中设置了的项的 `[misc]`
+    pub fn to_misc(&self) -> MiscFromFile {
+        MiscFromFile {
+            host: self.bind.map(|b| b.to_string()),
+            port: self.port,
+            static_dir: self.static_dir.clone(),
+            log_dir: self.log_dir.clone(),
+            log_level: self.log_level.clone(),
+            cert_path: self.cert_dir.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// 启动服务（默认）
+    Serve,
+    /// 检查配置文件并列出所有问题，有错误时退出码为 1
+    CheckConfig {
+        /// 要检查的配置文件，默认与 --config 相同
+        path: Option<String>,
+        /// 同时列出合并后的每个值来自哪个文件或环境变量
+        #[arg(long)]
+        show_origin: bool,
+    },
+    /// 生成 `password_hash`，不提供密码时从标准输入读取
+    HashPassword { password: Option<String> },
+    /// 输出默认配置文件
+    PrintDefaultConfig,
+    /// 列出配置文件中的用户及其权限
+    ListUsers,
+}
+
+/// 执行 `serve` 以外的子命令，返回退出码
+pub async fn run(cli: &Cli, command: &Command) -> i32 {
+    match command {
+        Command::Serve => 0,
+        Command::CheckConfig { path, show_origin } => {
+            let path = path.as_deref().unwrap_or(&cli.config);
+            if *show_origin && show_origins(path, &cli.overrides) != 0 {
+                return 1;
+            }
+            check_config(path, &cli.overrides).await
+        }
+        Command::HashPassword { password } => hash_password_command(password.clone()),
+        Command::PrintDefaultConfig => {
+            print!("{}", DEFAULT_CONFIG);
+            0
+        }
+        Command::ListUsers => list_users(&cli.config, &cli.overrides).await,
+    }
+}
+
+fn errors(issues: &[ConfigIssue]) -> usize {
+    issues
+        .iter()
+        .filter(|i| i.severity == Severity::Error)
+        .count()
+}
+
+/// 输出合并后的每个值及其来源
+fn show_origins(path: &str, overrides: &Overrides) -> i32 {
+    let layers = match Layers::load(path, &overrides.to_misc()) {
+        Ok(layers) => layers,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    for (key, value, source) in layers.origins() {
+        println!("{} = {}  # {}", key, value, source);
+    }
+    println!();
+    0
+}
+
+/// 输出配置文件中的所有问题，有错误时返回 1
+async fn check_config(path: &str, overrides: &Overrides) -> i32 {
+    let (_, issues) = match ConfigFromFile::check(path, &overrides.to_misc()).await {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    for issue in &issues {
+        println!("{}", issue.report(path));
+    }
+    let errors = errors(&issues);
+    println!(
+        "{}: {} 个错误，{} 个警告",
+        path,
+        errors,
+        issues.len() - errors
+    );
+    if errors > 0 { 1 } else { 0 }
+}
+
+fn hash_password_command(password: Option<String>) -> i32 {
+    let password = match password {
+        Some(p) => p,
+        None => {
+            let stdin = std::io::stdin();
+            if stdin.is_terminal() {
+                eprint!("密码: ");
+                let _ = std::io::stderr().flush();
+            }
+            let mut line = String::new();
+            if let Err(e) = stdin.lock().read_line(&mut line) {
+                eprintln!("读取密码失败: {}", e);
+                return 1;
+            }
+            line.trim_end_matches(['\r', '\n']).to_string()
+        }
+    };
+    if password.is_empty() {
+        eprintln!("密码不能为空");
+        return 1;
+    }
+    println!("{}", hash_password(&password));
+    0
+}
+
+/// `rwv` 形式的权限
+fn permission_string(permission: u8) -> String {
+    [(READ_MASK, 'r'), (WRITE_MASK, 'w'), (VIEW_MASK, 'v')]
+        .iter()
+        .map(|&(mask, c)| if permission & mask != 0 { c } else { '-' })
+        .collect()
+}
+
+async fn list_users(path: &str, overrides: &Overrides) -> i32 {
+    let (config, issues) = match ConfigFromFile::check(path, &overrides.to_misc()).await {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    for user in &config.users {
+        let login = match (&user.password, &user.password_hash) {
+            (_, Some(_)) => "密码哈希",
+            (Some(_), None) => "明文密码",
+            (None, None) => "无密码",
+        };
+        let permissions: Vec<String> = user
+            .permissions
+            .iter()
+            .map(|p| format!("{}:{}", p.path_name, permission_string(p.permission)))
+            .collect();
+        println!(
+            "{}{}\t{}\t{}\tS3 密钥 {} 个\tSFTP 公钥 {} 个",
+            user.username,
+            if user.admin { "（管理员）" } else { "" },
+            login,
+            permissions.join(" "),
+            user.access_keys.len(),
+            user.authorized_keys.len()
+        );
+    }
+    let errors = errors(&issues);
+    if errors > 0 {
+        eprintln!(
+            "{}: 配置有 {} 个错误，请运行 check-config 查看",
+            path, errors
+        );
+        return 1;
+    }
+    0
+}
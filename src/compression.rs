@@ -0,0 +1,154 @@
+//! 响应压缩：按 `Accept-Encoding` 协商 zstd、brotli 或 gzip。
+//! 本身已经压缩过的内容（图片、音视频、压缩包等）和范围请求的响应不再压缩。
+
+use axum::http::{Extensions, HeaderMap, StatusCode, Version, header};
+use tower_http::compression::{
+    CompressionLayer,
+    predicate::{And, NotForContentType, Predicate, SizeAbove},
+};
+
+use crate::model::MiscFromFile;
+
+/// 小于这个大小的响应压缩后几乎不会变小
+const MIN_COMPRESS_SIZE: u16 = 1024;
+
+/// 压缩后基本不会变小的类型，按前缀匹配
+const COMPRESSED_CONTENT_TYPES: [&str; 14] = [
+    "video/",
+    "audio/",
+    "font/woff",
+    "application/zip",
+    "application/gzip",
+    "application/x-gzip",
+    "application/zstd",
+    "application/x-xz",
+    "application/x-bzip2",
+    "application/x-7z-compressed",
+    "application/vnd.rar",
+    "application/x-rar-compressed",
+    "application/pdf",
+    "application/octet-stream",
+];
+
+type ShouldCompress = fn(StatusCode, Version, &HeaderMap, &Extensions) -> bool;
+
+pub type Layer = CompressionLayer<
+    And<
+        And<And<And<SizeAbove, NotForContentType>, NotForContentType>, NotForContentType>,
+        ShouldCompress,
+    >,
+>;
+
+/// `misc.compression` 为 false 时返回 `None`
+pub fn layer(misc: Option<&MiscFromFile>) -> Option<Layer> {
+    if !misc.and_then(|m| m.compression).unwrap_or(true) {
+        return None;
+    }
+    let predicate = SizeAbove::new(MIN_COMPRESS_SIZE)
+        .and(NotForContentType::GRPC)
+        .and(NotForContentType::IMAGES)
+        .and(NotForContentType::SSE)
+        .and(should_compress as ShouldCompress);
+    Some(CompressionLayer::new().compress_when(predicate))
+}
+
+/// 跳过部分内容响应和已经压缩过的类型，带 `Content-Range` 的响应由 `CompressionLayer` 自己跳过
+fn should_compress(
+    status: StatusCode,
+    _version: Version,
+    headers: &HeaderMap,
+    _extensions: &Extensions,
+) -> bool {
+    if status == StatusCode::PARTIAL_CONTENT {
+        return false;
+    }
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("");
+    !COMPRESSED_CONTENT_TYPES
+        .iter()
+        .any(|prefix| content_type.starts_with(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Router,
+        body::Body,
+        http::{HeaderValue, Request},
+        response::{IntoResponse, Response},
+        routing::get,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+
+    async fn encoding(
+        misc: Option<&MiscFromFile>,
+        content_type: &'static str,
+        status: StatusCode,
+        size: usize,
+    ) -> Option<HeaderValue> {
+        let handler = move || async move {
+            (
+                status,
+                [(header::CONTENT_TYPE, content_type)],
+                "a".repeat(size),
+            )
+                .into_response()
+        };
+        let mut router = Router::new().route("/", get(handler));
+        if let Some(layer) = layer(misc) {
+            router = router.layer(layer);
+        }
+        let request = Request::get("/")
+            .header(header::ACCEPT_ENCODING, "gzip, br, zstd")
+            .body(Body::empty())
+            .unwrap();
+        let response: Response = router.oneshot(request).await.unwrap();
+        response.headers().get(header::CONTENT_ENCODING).cloned()
+    }
+
+    #[tokio::test]
+    async fn text_is_compressed() {
+        let encoding = encoding(None, "text/html", StatusCode::OK, 4096).await;
+        assert_eq!(encoding.unwrap(), "zstd");
+    }
+
+    #[tokio::test]
+    async fn small_or_already_compressed_responses_are_not() {
+        assert!(
+            encoding(None, "text/html", StatusCode::OK, 100)
+                .await
+                .is_none()
+        );
+        for content_type in [
+            "image/png",
+            "video/mp4",
+            "application/zip",
+            "application/octet-stream",
+        ] {
+            assert!(
+                encoding(None, content_type, StatusCode::OK, 4096)
+                    .await
+                    .is_none(),
+                "{}",
+                content_type
+            );
+        }
+        let partial = encoding(None, "text/plain", StatusCode::PARTIAL_CONTENT, 4096).await;
+        assert!(partial.is_none());
+    }
+
+    #[tokio::test]
+    async fn compression_can_be_disabled() {
+        let misc = MiscFromFile {
+            compression: Some(false),
+            ..Default::default()
+        };
+        assert!(layer(Some(&misc)).is_none());
+        let encoding = encoding(Some(&misc), "text/html", StatusCode::OK, 4096).await;
+        assert!(encoding.is_none());
+    }
+}
@@ -0,0 +1,327 @@
+//! 配置热加载：定期检查配置文件和 `conf.d/` 是否变化，Unix 上收到 SIGHUP 时也会重新加载。
+//! 用户和 `[[paths]]`（包括权限）立即生效，已登录的会话保持不变；其余配置需要重启。
+
+use std::{collections::BTreeMap, path::Path, time::Duration};
+
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+use crate::{
+    model::{AppState, Config, ConfigFromFile, MiscFromFile, layers::config_files},
+    utils::{FileStamp, stamp, watch_hangup},
+};
+
+const DEFAULT_RELOAD_INTERVAL: u64 = 10;
+
+/// 主配置文件和 `conf.d/` 下每个文件的时间戳，增删文件也算变化
+fn stamps(path: &Path) -> Vec<FileStamp> {
+    config_files(path).iter().map(|f| stamp(f)).collect()
+}
+
+fn json<T: Serialize>(value: &T) -> Option<serde_json::Value> {
+    serde_json::to_value(value).ok()
+}
+
+/// 按名称比较两组配置项，返回新增、删除和修改的名称
+fn diff<'a, T: Serialize + 'a>(
+    old: impl Iterator<Item = (&'a str, &'a T)>,
+    new: impl Iterator<Item = (&'a str, &'a T)>,
+) -> (Vec<String>, Vec<String>, Vec<String>) {
+    let old: BTreeMap<_, _> = old.collect();
+    let new: BTreeMap<_, _> = new.collect();
+    let added = new
+        .keys()
+        .filter(|k| !old.contains_key(*k))
+        .map(|k| k.to_string())
+        .collect();
+    let removed = old
+        .keys()
+        .filter(|k| !new.contains_key(*k))
+        .map(|k| k.to_string())
+        .collect();
+    let changed = new
+        .iter()
+        .filter(|(k, v)| old.get(*k).is_some_and(|o| json(o) != json(v)))
+        .map(|(k, _)| k.to_string())
+        .collect();
+    (added, removed, changed)
+}
+
+fn log_diff(kind: &str, (added, removed, changed): (Vec<String>, Vec<String>, Vec<String>)) {
+    if !added.is_empty() {
+        info!("新增{}: {}", kind, added.join(", "));
+    }
+    if !removed.is_empty() {
+        info!("删除{}: {}", kind, removed.join(", "));
+    }
+    if !changed.is_empty() {
+        info!("修改{}: {}", kind, changed.join(", "));
+    }
+}
+
+/// 与启动时相比发生了变化、但热加载不会生效的配置段
+fn restart_required(old: &ConfigFromFile, new: &ConfigFromFile) -> Vec<&'static str> {
+    [
+        ("misc", json(&old.misc) != json(&new.misc)),
+        ("debug", json(&old.debug) != json(&new.debug)),
+        ("s3", json(&old.s3) != json(&new.s3)),
+        ("sftp", json(&old.sftp) != json(&new.sftp)),
+        ("ftp", json(&old.ftp) != json(&new.ftp)),
+        ("share", json(&old.share) != json(&new.share)),
+        ("acme", json(&old.acme) != json(&new.acme)),
+        ("audit", json(&old.audit) != json(&new.audit)),
+        ("access_log", json(&old.access_log) != json(&new.access_log)),
+        ("metrics", json(&old.metrics) != json(&new.metrics)),
+        ("cors", json(&old.cors) != json(&new.cors)),
+        (
+            "security_headers",
+            json(&old.security_headers) != json(&new.security_headers),
+        ),
+    ]
+    .into_iter()
+    .filter(|(_, changed)| *changed)
+    .map(|(name, _)| name)
+    .collect()
+}
+
+async fn reload(
+    path: &Path,
+    overrides: &MiscFromFile,
+    state: &AppState,
+    startup: &ConfigFromFile,
+    current: &mut ConfigFromFile,
+) {
+    let new = match ConfigFromFile::from_toml(&path.to_string_lossy(), overrides).await {
+        Ok(c) => c,
+        Err(e) => {
+            error!(
+                "配置文件 {} 加载失败，继续使用原配置: {}",
+                path.display(),
+                e
+            );
+            return;
+        }
+    };
+    let config = match Config::from_config_file(&new).await {
+        Ok(c) => c,
+        Err(e) => {
+            error!("配置文件 {} 无效，继续使用原配置: {}", path.display(), e);
+            return;
+        }
+    };
+
+    info!("配置文件 {} 已重新加载", path.display());
+    log_diff(
+        "用户",
+        diff(
+            current.users.iter().map(|u| (u.username.as_str(), u)),
+            new.users.iter().map(|u| (u.username.as_str(), u)),
+        ),
+    );
+    log_diff(
+        "路径",
+        diff(
+            current.paths.iter().map(|p| (p.name.as_str(), p)),
+            new.paths.iter().map(|p| (p.name.as_str(), p)),
+        ),
+    );
+    let sections = restart_required(startup, &new);
+    if !sections.is_empty() {
+        warn!("以下配置需要重启才能生效: {}", sections.join(", "));
+    }
+
+    state.replace_config(config).await;
+    *current = new;
+}
+
+/// 在后台监视配置文件，变化或收到 SIGHUP 时重新加载用户和路径，
+/// 命令行参数对 `[misc]` 的覆盖同样作用于重新加载的配置
+pub fn spawn_config_reloader(
+    path: &Path,
+    overrides: MiscFromFile,
+    state: AppState,
+    config: ConfigFromFile,
+) {
+    let interval = config
+        .misc
+        .as_ref()
+        .and_then(|m| m.config_reload_interval)
+        .unwrap_or(DEFAULT_RELOAD_INTERVAL);
+    let path = path.to_path_buf();
+    let (tx, mut rx) = mpsc::channel(1);
+    watch_hangup(tx);
+
+    // 先取时间戳再启动任务，任务第一次运行前的修改不会被漏掉
+    let mut last = stamps(&path);
+    tokio::spawn(async move {
+        let mut current = config.clone();
+        let startup = config;
+        // 间隔为 0 时只响应 SIGHUP
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval.max(1)));
+        ticker.tick().await;
+        loop {
+            tokio::select! {
+                _ = ticker.tick(), if interval > 0 => {
+                    let now = stamps(&path);
+                    if now == last {
+                        continue;
+                    }
+                    // 解析失败时也记下新的时间戳，等文件再次修改后重试
+                    last = now;
+                    reload(&path, &overrides, &state, &startup, &mut current).await;
+                }
+                Some(()) = rx.recv() => {
+                    info!("收到 SIGHUP，重新加载配置文件");
+                    last = stamps(&path);
+                    reload(&path, &overrides, &state, &startup, &mut current).await;
+                }
+                else => break,
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: &str = r#"
+        [[users]]
+        username = "alice"
+        password = "x"
+        permissions = [{ path_name = "data", permission = 0b111 }]
+        "#;
+    const BOB: &str = r#"
+        [[users]]
+        username = "bob"
+        password = "x"
+        permissions = [{ path_name = "data", permission = 0b001 }]
+        "#;
+
+    /// 写入 `config.toml` 并返回按它创建的状态和配置
+    async fn setup(dir: &Path, users: &str) -> (AppState, ConfigFromFile) {
+        let content = write(dir, users);
+        let state = AppState::for_test(&content, dir).await;
+        let config = toml::from_str(&content).unwrap();
+        (state, config)
+    }
+
+    fn write(dir: &Path, users: &str) -> String {
+        std::fs::create_dir_all(dir.join("data")).unwrap();
+        let content = format!(
+            "[misc]\nconfig_reload_interval = 1\nsession_store = {:?}\n\n\
+             [[paths]]\nname = \"data\"\npath = {:?}\npermission = 0b111\n{}",
+            dir.join("sessions.json").display().to_string(),
+            dir.join("data").display().to_string(),
+            users
+        );
+        std::fs::write(dir.join("config.toml"), &content).unwrap();
+        content
+    }
+
+    #[test]
+    fn diff_reports_added_removed_and_changed() {
+        let old = [("a", 1), ("b", 2), ("c", 3)];
+        let new = [("b", 2), ("c", 4), ("d", 5)];
+        let (added, removed, changed) = diff(
+            old.iter().map(|(k, v)| (*k, v)),
+            new.iter().map(|(k, v)| (*k, v)),
+        );
+        assert_eq!(added, ["d"]);
+        assert_eq!(removed, ["a"]);
+        assert_eq!(changed, ["c"]);
+    }
+
+    #[test]
+    fn restart_required_ignores_users_and_paths() {
+        let old: ConfigFromFile = toml::from_str("users = []\npaths = []").unwrap();
+        let new: ConfigFromFile = toml::from_str(&format!("paths = []\n{}", ALICE)).unwrap();
+        assert!(restart_required(&old, &new).is_empty());
+
+        let new: ConfigFromFile =
+            toml::from_str("users = []\npaths = []\n[misc]\nport = 9000\n[audit]\ndir = \"audit\"")
+                .unwrap();
+        assert_eq!(restart_required(&old, &new), ["misc", "audit"]);
+    }
+
+    #[tokio::test]
+    async fn reload_applies_users_and_keeps_their_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, mut current) = setup(dir.path(), &format!("{}{}", ALICE, BOB)).await;
+        let startup = current.clone();
+        state.add_session("a".into(), "alice".into()).await;
+        state.add_session("b".into(), "bob".into()).await;
+
+        let carol = ALICE.replace("alice", "carol");
+        write(dir.path(), &format!("{}{}", ALICE, carol));
+        let path = dir.path().join("config.toml");
+        reload(
+            &path,
+            &MiscFromFile::default(),
+            &state,
+            &startup,
+            &mut current,
+        )
+        .await;
+
+        assert!(state.get_user_config("carol").is_some());
+        assert!(state.get_user_config("bob").is_none());
+        assert_eq!(current.users.len(), 2);
+        assert_eq!(
+            state.get_username_by_session("a").await.as_deref(),
+            Some("alice")
+        );
+        assert!(state.get_username_by_session("b").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn invalid_config_keeps_the_old_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, mut current) = setup(dir.path(), ALICE).await;
+        let startup = current.clone();
+        let path = dir.path().join("config.toml");
+
+        // 引用了不存在的 [[paths]]
+        let broken = BOB.replace("\"data\"", "\"missing\"");
+        write(dir.path(), &format!("{}{}", ALICE, broken));
+        reload(
+            &path,
+            &MiscFromFile::default(),
+            &state,
+            &startup,
+            &mut current,
+        )
+        .await;
+        assert!(state.get_user_config("bob").is_none());
+
+        std::fs::write(&path, "[[users]\n").unwrap();
+        reload(
+            &path,
+            &MiscFromFile::default(),
+            &state,
+            &startup,
+            &mut current,
+        )
+        .await;
+        assert!(state.get_user_config("alice").is_some());
+        assert_eq!(current.users.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn reloader_picks_up_changed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, config) = setup(dir.path(), ALICE).await;
+        let path = dir.path().join("config.toml");
+        spawn_config_reloader(&path, MiscFromFile::default(), state.clone(), config);
+
+        write(dir.path(), &format!("{}{}", ALICE, BOB));
+        for _ in 0..50 {
+            if state.get_user_config("bob").is_some() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("配置没有重新加载");
+    }
+}
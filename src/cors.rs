@@ -0,0 +1,155 @@
+//! 跨域访问（CORS）：`[cors]` 中列出的来源可以在浏览器中调用接口，
+//! 预检请求（`OPTIONS`）在这里直接应答，不经过路由和认证。
+
+use std::time::Duration;
+
+use axum::http::{HeaderName, HeaderValue, Method, header};
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer, ExposeHeaders};
+
+use crate::{access_log::REQUEST_ID_HEADER, model::CorsFromFile};
+
+/// 预检结果默认缓存 10 分钟
+const DEFAULT_MAX_AGE: u64 = 600;
+const DEFAULT_METHODS: [Method; 4] = [Method::GET, Method::POST, Method::PUT, Method::DELETE];
+const ANY: &str = "*";
+
+/// 没有 `[cors]` 或 `allowed_origins` 为空时返回 `None`。
+/// 各项已在加载配置时校验过，无法解析的值直接忽略
+pub fn layer(cors: Option<&CorsFromFile>) -> Option<CorsLayer> {
+    let cors = cors.filter(|c| !c.allowed_origins.is_empty())?;
+    tracing::info!("允许跨域访问的来源: {}", cors.allowed_origins.join(", "));
+
+    let origins = if is_any(&cors.allowed_origins) {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(
+            cors.allowed_origins
+                .iter()
+                .filter_map(|o| HeaderValue::from_str(o.trim_end_matches('/')).ok()),
+        )
+    };
+    let methods = match &cors.allowed_methods {
+        Some(methods) if is_any(methods) => AllowMethods::any(),
+        Some(methods) => AllowMethods::list(
+            methods
+                .iter()
+                .filter_map(|m| Method::from_bytes(m.to_ascii_uppercase().as_bytes()).ok()),
+        ),
+        None => AllowMethods::list(DEFAULT_METHODS),
+    };
+    let headers = match &cors.allowed_headers {
+        Some(headers) if is_any(headers) => AllowHeaders::any(),
+        Some(headers) => AllowHeaders::list(header_names(headers)),
+        None => AllowHeaders::list([header::AUTHORIZATION, header::CONTENT_TYPE]),
+    };
+    let exposed = match &cors.exposed_headers {
+        Some(headers) => ExposeHeaders::list(header_names(headers)),
+        None => ExposeHeaders::list([header::CONTENT_DISPOSITION, REQUEST_ID_HEADER]),
+    };
+
+    Some(
+        CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods(methods)
+            .allow_headers(headers)
+            .expose_headers(exposed)
+            .allow_credentials(cors.allow_credentials.unwrap_or(false))
+            .max_age(Duration::from_secs(cors.max_age.unwrap_or(DEFAULT_MAX_AGE))),
+    )
+}
+
+/// 列表中有 `*`
+pub fn is_any(values: &[String]) -> bool {
+    values.iter().any(|v| v.trim() == ANY)
+}
+
+fn header_names(names: &[String]) -> Vec<HeaderName> {
+    names
+        .iter()
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{Router, body::Body, http::Request, response::Response, routing::get};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn cors(content: &str) -> CorsFromFile {
+        toml::from_str(content).unwrap()
+    }
+
+    async fn preflight(cors: &CorsFromFile, origin: &str) -> Response {
+        let router = Router::new()
+            .route("/api/files", get(|| async { "ok" }))
+            .layer(layer(Some(cors)).unwrap());
+        let request = Request::options("/api/files")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "PUT")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "authorization")
+            .body(Body::empty())
+            .unwrap();
+        router.oneshot(request).await.unwrap()
+    }
+
+    #[test]
+    fn missing_or_empty_origins_disable_cors() {
+        assert!(layer(None).is_none());
+        assert!(layer(Some(&cors("allowed_origins = []"))).is_none());
+    }
+
+    #[tokio::test]
+    async fn preflight_with_credentials_echoes_the_origin() {
+        let cors = cors(
+            r#"
+            allowed_origins = ["https://portal.example.com/"]
+            allow_credentials = true
+            max_age = 60
+            "#,
+        );
+        let response = preflight(&cors, "https://portal.example.com").await;
+        let headers = response.headers();
+        assert!(response.status().is_success());
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://portal.example.com"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "60");
+        let methods = headers[header::ACCESS_CONTROL_ALLOW_METHODS]
+            .to_str()
+            .unwrap();
+        assert!(methods.contains("PUT"), "{}", methods);
+        let allowed = headers[header::ACCESS_CONTROL_ALLOW_HEADERS]
+            .to_str()
+            .unwrap();
+        assert!(allowed.contains("authorization"), "{}", allowed);
+    }
+
+    #[tokio::test]
+    async fn unlisted_origin_gets_no_cors_headers() {
+        let cors = cors(
+            r#"
+            allowed_origins = ["https://portal.example.com"]
+            allow_credentials = true
+            "#,
+        );
+        let response = preflight(&cors, "https://evil.example.com").await;
+        assert!(
+            !response
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        );
+    }
+
+    #[tokio::test]
+    async fn any_origin_without_credentials() {
+        let response = preflight(&cors(r#"allowed_origins = ["*"]"#), "https://a.example").await;
+        let headers = response.headers();
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_CREDENTIALS));
+        assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "600");
+    }
+}
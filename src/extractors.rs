@@ -1,74 +1,177 @@
-use axum::{
-    Json,
-    extract::FromRequestParts,
-    http::{self, StatusCode, header},
-    response::{IntoResponse, Response},
-};
-use serde_json::json;
-
-use crate::model::{UserConfig, app_state::AppState};
-
-/// 自动从请求中提取并验证用户信息的Extractor
-///
-/// 使用方法：在handler参数中直接使用 `AuthUser`
-/// ```ignore
-/// pub async fn my_handler(
-///     AuthUser(user): AuthUser,
-/// ) -> impl IntoResponse {
-///     // user 是 UserConfig，包含用户信息
-/// }
-/// ```
-pub struct AuthUser(pub UserConfig);
-
-/// 认证失败的错误响应
-pub struct AuthError;
-
-impl IntoResponse for AuthError {
-    fn into_response(self) -> Response {
-        // 返回401状态码而不是重定向，前端可以处理此错误
-        let body = Json(json!({
-            "error": "Unauthorized",
-            "message": "Authentication failed. Please log in again."
-        }));
-        (StatusCode::UNAUTHORIZED, body).into_response()
-    }
-}
-
-impl<S> FromRequestParts<S> for AuthUser
-where
-    S: Send + Sync + AsRef<AppState>,
-{
-    type Rejection = AuthError;
-
-    async fn from_request_parts(
-        parts: &mut http::request::Parts,
-        state: &S,
-    ) -> Result<Self, Self::Rejection> {
-        let app_state = state.as_ref();
-        // 从header中获取token
-        let token = read_token_from_req(parts).await.ok_or(AuthError)?;
-
-        // 从session中获取username
-        app_state
-            .get_user_by_token(token)
-            .await
-            .map(|e| AuthUser(e.clone()))
-            .ok_or(AuthError)
-    }
-}
-
-async fn read_token_from_req(parts: &http::request::Parts) -> Option<&str> {
-    parts
-        .headers
-        .get(header::AUTHORIZATION)
-        .and_then(|h| h.to_str().ok())
-        .and_then(|s| s.strip_prefix("Bearer "))
-        .or_else(|| parts.headers.get("x-token").and_then(|h| h.to_str().ok()))
-        .or_else(|| {
-            parts.uri.query().and_then(|q| {
-                q.split('&')
-                    .find(|p| p.starts_with("token="))
-                    .and_then(|p| p.split('=').nth(1))
-            })
-        })
-}
+use axum::{
+    Json,
+    extract::FromRequestParts,
+    http::{self, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde_json::json;
+
+use crate::audit::AuditContext;
+use crate::model::{UserConfig, app_state::AppState};
+use crate::tls::ClientCertNames;
+
+/// 自动从请求中提取并验证用户信息的Extractor
+///
+/// 使用方法：在handler参数中直接使用 `AuthUser`
+/// ```ignore
+/// pub async fn my_handler(
+///     AuthUser(user): AuthUser,
+/// ) -> impl IntoResponse {
+///     // user 是 UserConfig，包含用户信息
+/// }
+/// ```
+pub struct AuthUser(pub UserConfig);
+
+/// 要求当前用户是管理员（`[[users]]` 中 `admin = true`），否则返回 403
+pub struct AdminUser(pub UserConfig);
+
+/// 认证失败的错误响应
+pub struct AuthError;
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        // 返回401状态码而不是重定向，前端可以处理此错误
+        let body = Json(json!({
+            "error": "Unauthorized",
+            "message": "Authentication failed. Please log in again."
+        }));
+        (StatusCode::UNAUTHORIZED, body).into_response()
+    }
+}
+
+impl<S> FromRequestParts<S> for AuthUser
+where
+    S: Send + Sync + AsRef<AppState>,
+{
+    type Rejection = AuthError;
+
+    async fn from_request_parts(
+        parts: &mut http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let app_state = state.as_ref();
+        // HTTPS 连接上经过验证的客户端证书可以代替登录
+        if let Some(names) = parts.extensions.get::<ClientCertNames>()
+            && let Some(user) = app_state.get_user_by_cert_names(&names.0)
+        {
+            // 审计中间件只能从会话查出用户，证书登录的用户在这里补上
+            if let Some(audit) = parts.extensions.get::<AuditContext>() {
+                audit.set_user(&user.username);
+            }
+            return Ok(AuthUser(user));
+        }
+
+        // 从header中获取token
+        let token = read_token_from_req(parts).await.ok_or(AuthError)?;
+
+        // 从session中获取username
+        app_state
+            .get_user_by_token(token)
+            .await
+            .map(AuthUser)
+            .ok_or(AuthError)
+    }
+}
+
+impl<S> FromRequestParts<S> for AdminUser
+where
+    S: Send + Sync + AsRef<AppState>,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let AuthUser(user) = AuthUser::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        if !user.admin {
+            return Err((StatusCode::FORBIDDEN, "需要管理员权限").into_response());
+        }
+        Ok(AdminUser(user))
+    }
+}
+
+pub(crate) async fn read_token_from_req(parts: &http::request::Parts) -> Option<&str> {
+    parts
+        .headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|s| s.strip_prefix("Bearer "))
+        .or_else(|| parts.headers.get("x-token").and_then(|h| h.to_str().ok()))
+        .or_else(|| {
+            parts.uri.query().and_then(|q| {
+                q.split('&')
+                    .find(|p| p.starts_with("token="))
+                    .and_then(|p| p.split('=').nth(1))
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{
+        Router,
+        body::{Body, to_bytes},
+        extract::Request,
+        routing::get,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+
+    async fn whoami(names: Option<&[&str]>) -> (StatusCode, String) {
+        let dir = tempfile::tempdir().unwrap();
+        let config = r#"
+            paths = []
+
+            [[users]]
+            username = "alice"
+            password = "x"
+            client_cert_names = ["alice-laptop"]
+            permissions = []
+
+            [[users]]
+            username = "bob"
+            password = "x"
+            permissions = []
+            "#;
+        let state = AppState::for_test(config, dir.path()).await;
+        let router = Router::new()
+            .route(
+                "/",
+                get(|AuthUser(user): AuthUser| async move { user.username }),
+            )
+            .with_state(state);
+        let mut request = Request::get("/").body(Body::empty()).unwrap();
+        if let Some(names) = names {
+            let names = names.iter().map(|n| n.to_string()).collect();
+            request
+                .extensions_mut()
+                .insert(ClientCertNames(Arc::new(names)));
+        }
+        let response = router.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn client_certificates_map_to_users() {
+        assert_eq!(
+            whoami(Some(&["alice-laptop"])).await,
+            (StatusCode::OK, "alice".to_string())
+        );
+        // 配置了 client_cert_names 的用户不再按用户名匹配
+        assert_eq!(whoami(Some(&["alice"])).await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            whoami(Some(&["bob", "bob@example.com"])).await,
+            (StatusCode::OK, "bob".to_string())
+        );
+        assert_eq!(whoami(Some(&[])).await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(whoami(None).await.0, StatusCode::UNAUTHORIZED);
+    }
+}
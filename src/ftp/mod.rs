@@ -0,0 +1,156 @@
+//! FTP/FTPS 服务：只支持被动模式，登录使用 `[[users]]` 的用户名和密码，
+//! 每个用户看到的是由其有权限的 `[[paths]]` 组成的虚拟文件系统（见 [`crate::vfs`]）。
+
+mod storage;
+
+use std::{fmt, sync::Arc};
+
+use async_trait::async_trait;
+use axum_server::tls_rustls::RustlsConfig;
+use libunftp::{
+    Server, ServerBuilder,
+    options::{self, FtpsRequired, PassiveHost},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use unftp_core::auth::{
+    AuthenticationError, Authenticator, Credentials, Principal, UserDetail, UserDetailError,
+    UserDetailProvider,
+};
+
+use crate::ftp::storage::FtpStorage;
+use crate::model::{AppState, ConfigFromFile, FtpFromFile};
+use crate::{telemetry, tls};
+
+pub const DEFAULT_PORT: u16 = 2121;
+const DEFAULT_PASSIVE_PORTS: [u16; 2] = [50000, 50100];
+
+/// 登录后的会话用户
+/// 只保存用户名，每个命令都按当前配置检查权限，热加载后立即生效
+pub struct FtpUser {
+    username: String,
+}
+
+impl fmt::Display for FtpUser {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.username)
+    }
+}
+
+impl fmt::Debug for FtpUser {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FtpUser")
+            .field("username", &self.username)
+            .finish()
+    }
+}
+
+impl UserDetail for FtpUser {}
+
+/// 按 `[[users]]` 校验用户名和密码，同时提供登录后的用户信息
+struct FtpAuthenticator {
+    app: AppState,
+}
+
+impl fmt::Debug for FtpAuthenticator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FtpAuthenticator").finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl Authenticator for FtpAuthenticator {
+    async fn authenticate(
+        &self,
+        username: &str,
+        creds: &Credentials,
+    ) -> Result<Principal, AuthenticationError> {
+        let Some(user) = self.app.get_user_config(username) else {
+            warn!("FTP 用户 {} 不存在，来自 {}", username, creds.source_ip);
+            telemetry::login_failed("ftp");
+            return Err(AuthenticationError::BadUser);
+        };
+        let valid = match creds.password.as_deref() {
+            Some(p) => user.verify_password(p).await,
+            None => false,
+        };
+        if !valid {
+            warn!("FTP 用户 {} 密码错误，来自 {}", username, creds.source_ip);
+            telemetry::login_failed("ftp");
+            return Err(AuthenticationError::BadPassword);
+        }
+        info!(
+            "FTP 用户 {} 登录，来自 {}（{:?}）",
+            username, creds.source_ip, creds.command_channel_security
+        );
+        Ok(Principal {
+            username: username.to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl UserDetailProvider for FtpAuthenticator {
+    type User = FtpUser;
+
+    async fn provide_user_detail(&self, principal: &Principal) -> Result<FtpUser, UserDetailError> {
+        self.app
+            .get_user_config(&principal.username)
+            .map(|user| FtpUser {
+                username: user.username,
+            })
+            .ok_or_else(|| UserDetailError::UserNotFound {
+                username: principal.username.clone(),
+            })
+    }
+}
+
+/// 证书存在时 `rustls_config` 为 HTTPS 使用的配置（见 [`ConfigFromFile::tls_files`]），
+/// 用它启用显式 TLS，证书热重载和 ACME 续期同样生效。
+/// `shutdown` 被取消后断开控制连接，最多等待 `misc.shutdown_timeout` 后退出
+pub fn create_server(
+    app: AppState,
+    config: &ConfigFromFile,
+    ftp_config: &FtpFromFile,
+    rustls_config: Option<&RustlsConfig>,
+    shutdown: CancellationToken,
+) -> std::io::Result<Server<FtpStorage, FtpUser>> {
+    let auth = Arc::new(FtpAuthenticator { app: app.clone() });
+    let [min_port, max_port] = ftp_config.passive_ports.unwrap_or(DEFAULT_PASSIVE_PORTS);
+
+    let mut builder = ServerBuilder::<FtpStorage, FtpUser>::with_user_detail_provider(
+        Box::new(move || FtpStorage::new(app.clone())),
+        auth.clone(),
+    )
+    .authenticator(auth)
+    .greeting("Simple File Manager FTP")
+    .passive_ports(min_port..=max_port)
+    .shutdown_indicator({
+        let grace_period = config.shutdown_timeout();
+        async move {
+            shutdown.cancelled_owned().await;
+            options::Shutdown::new().grace_period(grace_period)
+        }
+    });
+
+    if let Some(passive_host) = &ftp_config.passive_host {
+        builder = builder.passive_host(PassiveHost::from(passive_host.as_str()));
+    }
+
+    let (cert_file, _) = config.tls_files();
+    if let Some(rustls_config) = rustls_config {
+        info!("FTP 启用显式 TLS，证书: {}", cert_file.display());
+        // 类型参数在 libunftp 中没有用到
+        builder = builder.ftps_manual::<&str>(tls::ftps_config(rustls_config)?);
+        if ftp_config.require_tls.unwrap_or(false) {
+            builder = builder.ftps_required(FtpsRequired::All, FtpsRequired::All);
+        }
+    } else if ftp_config.require_tls.unwrap_or(false) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("require_tls 需要证书文件 {}", cert_file.display()),
+        ));
+    }
+
+    builder.build().map_err(std::io::Error::other)
+}
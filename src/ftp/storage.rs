@@ -0,0 +1,577 @@
+//! FTP 存储后端：在 [`crate::vfs`] 的虚拟文件系统上按 rwv 权限执行每个命令，
+//! 上传与 HTTP 上传共用 [`write_upload`]。libunftp 不向存储后端提供客户端地址，
+//! 审计记录中没有 `client_ip`
+
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+    time::SystemTime,
+};
+
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncSeekExt, ReadBuf};
+use unftp_core::storage::{Error, ErrorKind, Fileinfo, Metadata, Result, StorageBackend};
+
+use crate::audit::{AuditAction, AuditResult, Operation};
+use crate::ftp::FtpUser;
+use crate::model::{AppState, READ_MASK, VIEW_MASK, WRITE_MASK};
+use crate::utils::{check_permission, is_temp_file, write_upload};
+use crate::vfs::{self, Target};
+
+#[derive(Debug)]
+pub enum FtpMetadata {
+    Fs(std::fs::Metadata),
+    /// 虚拟根目录，或无法读取元数据的根目录
+    VirtualDir,
+}
+
+impl Metadata for FtpMetadata {
+    fn len(&self) -> u64 {
+        match self {
+            FtpMetadata::Fs(m) => m.len(),
+            FtpMetadata::VirtualDir => 0,
+        }
+    }
+
+    fn is_dir(&self) -> bool {
+        match self {
+            FtpMetadata::Fs(m) => m.is_dir(),
+            FtpMetadata::VirtualDir => true,
+        }
+    }
+
+    fn is_file(&self) -> bool {
+        match self {
+            FtpMetadata::Fs(m) => m.is_file(),
+            FtpMetadata::VirtualDir => false,
+        }
+    }
+
+    fn is_symlink(&self) -> bool {
+        match self {
+            FtpMetadata::Fs(m) => m.file_type().is_symlink(),
+            FtpMetadata::VirtualDir => false,
+        }
+    }
+
+    fn modified(&self) -> Result<SystemTime> {
+        match self {
+            FtpMetadata::Fs(m) => m.modified().map_err(Error::from),
+            FtpMetadata::VirtualDir => Ok(SystemTime::UNIX_EPOCH),
+        }
+    }
+
+    fn gid(&self) -> u32 {
+        0
+    }
+
+    fn uid(&self) -> u32 {
+        0
+    }
+}
+
+fn audit_result<T>(result: &Result<T>) -> AuditResult {
+    match result {
+        Ok(_) => AuditResult::Ok,
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => AuditResult::Denied,
+        Err(_) => AuditResult::Failed,
+    }
+}
+
+/// 下载中的文件，统计读出的字节数，读到末尾时记为完成，没读完就被丢弃的记为中断
+struct AuditedReader {
+    file: tokio::fs::File,
+    operation: Option<Operation>,
+}
+
+impl AsyncRead for AuditedReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        let before = buf.filled().len();
+        let eof_possible = buf.remaining() > 0;
+        let poll = Pin::new(&mut this.file).poll_read(cx, buf);
+        if let Poll::Ready(result) = &poll {
+            let read = (buf.filled().len() - before) as u64;
+            match result {
+                Ok(()) if read > 0 => {
+                    if let Some(operation) = &mut this.operation {
+                        operation.add_bytes(read);
+                    }
+                }
+                Ok(()) if eof_possible => {
+                    if let Some(operation) = this.operation.take() {
+                        operation.finish(AuditResult::Ok);
+                    }
+                }
+                Ok(()) => {}
+                Err(_) => {
+                    if let Some(operation) = this.operation.take() {
+                        operation.finish(AuditResult::Failed);
+                    }
+                }
+            }
+        }
+        poll
+    }
+}
+
+pub struct FtpStorage {
+    app: AppState,
+}
+
+impl fmt::Debug for FtpStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FtpStorage").finish_non_exhaustive()
+    }
+}
+
+impl FtpStorage {
+    pub fn new(app: AppState) -> Self {
+        FtpStorage { app }
+    }
+
+    fn resolve(&self, path: &Path) -> Result<Target> {
+        vfs::resolve(&self.app, &path.to_string_lossy())
+            .ok_or(Error::from(ErrorKind::PermanentFileNotAvailable))
+    }
+
+    fn operation(&self, user: &FtpUser, action: AuditAction, path: &Path) -> Operation {
+        let (root, path) = vfs::audit_target(&path.to_string_lossy());
+        Operation::new(
+            &self.app.audit,
+            "ftp",
+            action,
+            &user.username,
+            None,
+            &root,
+            &path,
+        )
+    }
+
+    /// 立即完成的操作，写入一条审计记录后原样返回结果
+    fn audited<T>(
+        &self,
+        user: &FtpUser,
+        action: AuditAction,
+        path: &Path,
+        result: Result<T>,
+    ) -> Result<T> {
+        self.operation(user, action, path)
+            .finish(audit_result(&result));
+        result
+    }
+
+    /// 用户已从配置中删除时拒绝一切操作
+    fn allowed(&self, user: &FtpUser, path: &Path, required: u8) -> bool {
+        self.app
+            .config()
+            .users
+            .get(&user.username)
+            .is_some_and(|u| {
+                check_permission(&u.permissions_tree, &path.to_string_lossy(), required)
+            })
+    }
+
+    /// 解析为文件系统路径并检查权限，根目录本身与虚拟根目录不能修改
+    async fn resolve_fs(&self, user: &FtpUser, path: &Path, required: u8) -> Result<PathBuf> {
+        match self.resolve(path)? {
+            Target::Root => Err(ErrorKind::PermissionDenied.into()),
+            Target::Fs { root, path } => {
+                if required & WRITE_MASK != 0 && path == root {
+                    return Err(ErrorKind::PermissionDenied.into());
+                }
+                if !self.allowed(user, &path, required) {
+                    return Err(ErrorKind::PermissionDenied.into());
+                }
+                vfs::ensure_contained(&root, &path).await?;
+                Ok(path)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl StorageBackend<FtpUser> for FtpStorage {
+    type Metadata = FtpMetadata;
+
+    async fn metadata<P: AsRef<Path> + Send + fmt::Debug>(
+        &self,
+        user: &FtpUser,
+        path: P,
+    ) -> Result<FtpMetadata> {
+        let path = match self.resolve(path.as_ref())? {
+            Target::Root => return Ok(FtpMetadata::VirtualDir),
+            Target::Fs { root, path } => {
+                if !self.allowed(user, &path, VIEW_MASK) && !self.allowed(user, &path, READ_MASK) {
+                    return Err(ErrorKind::PermissionDenied.into());
+                }
+                vfs::ensure_contained(&root, &path).await?;
+                path
+            }
+        };
+        Ok(FtpMetadata::Fs(tokio::fs::metadata(&path).await?))
+    }
+
+    async fn list<P: AsRef<Path> + Send + fmt::Debug>(
+        &self,
+        user: &FtpUser,
+        path: P,
+    ) -> Result<Vec<Fileinfo<PathBuf, FtpMetadata>>> {
+        let path = path.as_ref();
+        if let Target::Root = self.resolve(path)? {
+            let Some(user) = self.app.get_user_config(&user.username) else {
+                return Ok(Vec::new());
+            };
+            return Ok(vfs::visible_roots(&self.app, &user)
+                .into_iter()
+                .map(|p| Fileinfo {
+                    path: PathBuf::from(&p.name),
+                    metadata: std::fs::metadata(&p.path)
+                        .map(FtpMetadata::Fs)
+                        .unwrap_or(FtpMetadata::VirtualDir),
+                })
+                .collect());
+        }
+
+        let result = async {
+            let dir = self.resolve_fs(user, path, VIEW_MASK).await?;
+            let mut entries = Vec::new();
+            let mut read_dir = tokio::fs::read_dir(&dir).await?;
+            while let Some(entry) = read_dir.next_entry().await? {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if is_temp_file(&name) || !self.allowed(user, &entry.path(), VIEW_MASK) {
+                    continue;
+                }
+                let Ok(metadata) = entry.metadata().await else {
+                    continue;
+                };
+                entries.push(Fileinfo {
+                    path: PathBuf::from(name),
+                    metadata: FtpMetadata::Fs(metadata),
+                });
+            }
+            Ok(entries)
+        }
+        .await;
+        self.audited(user, AuditAction::List, path, result)
+    }
+
+    async fn get<P: AsRef<Path> + Send + fmt::Debug>(
+        &self,
+        user: &FtpUser,
+        path: P,
+        start_pos: u64,
+    ) -> Result<Box<dyn tokio::io::AsyncRead + Send + Sync + Unpin>> {
+        let path = path.as_ref();
+        let result = async {
+            let path = self.resolve_fs(user, path, READ_MASK).await?;
+            let mut file = tokio::fs::File::open(&path).await?;
+            if start_pos > 0 {
+                file.seek(std::io::SeekFrom::Start(start_pos)).await?;
+            }
+            Ok(file)
+        }
+        .await;
+        let operation = self.operation(user, AuditAction::Download, path);
+        let outcome = audit_result(&result);
+        match result {
+            Ok(file) => Ok(Box::new(AuditedReader {
+                file,
+                operation: Some(operation),
+            })),
+            Err(e) => {
+                operation.finish(outcome);
+                Err(e)
+            }
+        }
+    }
+
+    /// 不支持断点续传（未声明 FEATURE_RESTART），每次上传都完整替换目标文件
+    async fn put<
+        P: AsRef<Path> + Send + fmt::Debug,
+        R: tokio::io::AsyncRead + Send + Sync + Unpin + 'static,
+    >(
+        &self,
+        user: &FtpUser,
+        mut input: R,
+        path: P,
+        _start_pos: u64,
+    ) -> Result<u64> {
+        let path = path.as_ref();
+        let mut operation = self.operation(user, AuditAction::Upload, path);
+        let result = async {
+            let target = match self.resolve(path)? {
+                Target::Fs { root, path } if path != root => {
+                    vfs::ensure_contained(&root, &path).await?;
+                    path
+                }
+                _ => return Err(ErrorKind::PermissionDenied.into()),
+            };
+            let Some(current) = self.app.get_user_config(&user.username) else {
+                return Err(ErrorKind::PermissionDenied.into());
+            };
+            match write_upload(&current.permissions_tree, &target, &mut input).await {
+                Ok(written) => {
+                    tracing::info!("FTP 用户 {} 上传文件: {:?}", user, target);
+                    Ok(written)
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                    Err(ErrorKind::PermissionDenied.into())
+                }
+                Err(e) => Err(e.into()),
+            }
+        }
+        .await;
+        if let Ok(written) = result {
+            operation.add_bytes(written);
+        }
+        operation.finish(audit_result(&result));
+        result
+    }
+
+    async fn del<P: AsRef<Path> + Send + fmt::Debug>(&self, user: &FtpUser, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let result = async {
+            let path = self.resolve_fs(user, path, WRITE_MASK).await?;
+            Ok(tokio::fs::remove_file(&path).await?)
+        }
+        .await;
+        self.audited(user, AuditAction::Delete, path, result)
+    }
+
+    async fn mkd<P: AsRef<Path> + Send + fmt::Debug>(&self, user: &FtpUser, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let result = async {
+            let path = self.resolve_fs(user, path, WRITE_MASK).await?;
+            Ok(tokio::fs::create_dir(&path).await?)
+        }
+        .await;
+        self.audited(user, AuditAction::Mkdir, path, result)
+    }
+
+    /// 与 SFTP 一致，不覆盖已存在的目标
+    async fn rename<P: AsRef<Path> + Send + fmt::Debug>(
+        &self,
+        user: &FtpUser,
+        from: P,
+        to: P,
+    ) -> Result<()> {
+        let (from, to) = (from.as_ref(), to.as_ref());
+        let result = async {
+            let from = self.resolve_fs(user, from, WRITE_MASK).await?;
+            let to = self.resolve_fs(user, to, WRITE_MASK).await?;
+            if tokio::fs::try_exists(&to).await? {
+                return Err(ErrorKind::PermanentFileNotAvailable.into());
+            }
+            Ok(tokio::fs::rename(&from, &to).await?)
+        }
+        .await;
+        let mut operation = self.operation(user, AuditAction::Rename, from);
+        operation.set_new_path(&vfs::audit_target(&to.to_string_lossy()).1);
+        operation.finish(audit_result(&result));
+        result
+    }
+
+    async fn rmd<P: AsRef<Path> + Send + fmt::Debug>(&self, user: &FtpUser, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let result = async {
+            let path = self.resolve_fs(user, path, WRITE_MASK).await?;
+            Ok(tokio::fs::remove_dir(&path).await?)
+        }
+        .await;
+        self.audited(user, AuditAction::Delete, path, result)
+    }
+
+    async fn cwd<P: AsRef<Path> + Send + fmt::Debug>(&self, user: &FtpUser, path: P) -> Result<()> {
+        if let Target::Root = self.resolve(path.as_ref())? {
+            return Ok(());
+        }
+        let path = self.resolve_fs(user, path.as_ref(), VIEW_MASK).await?;
+        if tokio::fs::metadata(&path).await?.is_dir() {
+            Ok(())
+        } else {
+            Err(ErrorKind::PermanentDirectoryNotAvailable.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuditQuery;
+    use crate::model::{Config, ConfigFromFile};
+    use tokio::io::AsyncReadExt;
+
+    fn config(dir: &Path, data_grant: u8) -> String {
+        let dir = dir.display();
+        format!(
+            r#"
+            [[paths]]
+            name = "data"
+            path = "{dir}/data"
+            permission = 0b111
+
+            [[paths]]
+            name = "ro"
+            path = "{dir}/ro"
+            permission = 0b111
+
+            [[users]]
+            username = "alice"
+            password = "x"
+            permissions = [
+                {{ path_name = "data", permission = {data_grant} }},
+                {{ path_name = "ro", permission = 0b101 }},
+            ]
+            "#
+        )
+    }
+
+    async fn storage(dir: &Path) -> (FtpStorage, FtpUser) {
+        for name in ["data", "ro"] {
+            std::fs::create_dir_all(dir.join(name)).unwrap();
+        }
+        let app = AppState::for_test(&config(dir, 0b111), dir).await;
+        let user = FtpUser {
+            username: "alice".to_string(),
+        };
+        (FtpStorage::new(app), user)
+    }
+
+    async fn get(storage: &FtpStorage, user: &FtpUser, path: &str) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut reader = storage.get(user, path, 0).await?;
+        reader.read_to_end(&mut data).await?;
+        Ok(data)
+    }
+
+    #[tokio::test]
+    async fn put_then_get() {
+        let dir = tempfile::tempdir().unwrap();
+        let (storage, user) = storage(dir.path()).await;
+        let written = storage
+            .put(&user, &b"hello"[..], "/data/a.txt", 0)
+            .await
+            .unwrap();
+        assert_eq!(written, 5);
+        assert_eq!(get(&storage, &user, "/data/a.txt").await.unwrap(), b"hello");
+
+        let names: Vec<_> = storage
+            .list(&user, "/")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|f| f.path)
+            .collect();
+        assert_eq!(names, [PathBuf::from("data"), PathBuf::from("ro")]);
+    }
+
+    #[tokio::test]
+    async fn read_only_grant_cannot_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let (storage, user) = storage(dir.path()).await;
+        let err = storage
+            .put(&user, &b"x"[..], "/ro/a.txt", 0)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert!(!dir.path().join("ro/a.txt").exists());
+        let err = storage.mkd(&user, "/ro/sub").await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn reloaded_permissions_apply_to_logged_in_users() {
+        let dir = tempfile::tempdir().unwrap();
+        let (storage, user) = storage(dir.path()).await;
+        storage
+            .put(&user, &b"1"[..], "/data/a.txt", 0)
+            .await
+            .unwrap();
+
+        let reload = async |config: String| {
+            let config: ConfigFromFile = toml::from_str(&config).unwrap();
+            let config = Config::from_config_file(&config).await.unwrap();
+            storage.app.replace_config(config).await;
+        };
+        reload(config(dir.path(), 0b101)).await;
+        let err = storage
+            .put(&user, &b"2"[..], "/data/a.txt", 0)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert_eq!(get(&storage, &user, "/data/a.txt").await.unwrap(), b"1");
+
+        // 用户被删除后什么都看不到
+        reload(config(dir.path(), 0b111).replace("alice", "bob")).await;
+        assert!(get(&storage, &user, "/data/a.txt").await.is_err());
+        assert!(storage.list(&user, "/").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn operations_are_audited() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["data", "ro"] {
+            std::fs::create_dir_all(dir.path().join(name)).unwrap();
+        }
+        let audit_dir = dir.path().join("audit");
+        let config = format!(
+            "{}\n[audit]\nenable = true\ndir = \"{}\"\n",
+            config(dir.path(), 0b111),
+            audit_dir.display()
+        );
+        let app = AppState::for_test(&config, dir.path()).await;
+        let storage = FtpStorage::new(app.clone());
+        let user = FtpUser {
+            username: "alice".to_string(),
+        };
+        storage
+            .put(&user, &b"hello"[..], "/data/a.txt", 0)
+            .await
+            .unwrap();
+        get(&storage, &user, "/data/a.txt").await.unwrap();
+        storage
+            .rename(&user, "/data/a.txt", "/data/b.txt")
+            .await
+            .unwrap();
+        storage.del(&user, "/ro/c.txt").await.unwrap_err();
+        // 丢弃所有引用后审计日志才会写完
+        drop((storage, app));
+
+        let config = format!(
+            "users = []\npaths = []\n[audit]\ndir = \"{}\"\n",
+            audit_dir.display()
+        );
+        let records = crate::audit::Audit::open(&toml::from_str(&config).unwrap())
+            .unwrap()
+            .query(&AuditQuery::default())
+            .await
+            .unwrap();
+        let summary: Vec<String> = records
+            .iter()
+            .map(|r| serde_json::to_string(&(r.action, &r.root, &r.path, r.bytes, r.result)))
+            .collect::<serde_json::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            summary,
+            [
+                r#"["upload","data","a.txt",5,"ok"]"#,
+                r#"["download","data","a.txt",5,"ok"]"#,
+                r#"["rename","data","a.txt",null,"ok"]"#,
+                r#"["delete","ro","c.txt",null,"denied"]"#,
+            ]
+        );
+        assert_eq!(records[2].new_path.as_deref(), Some("b.txt"));
+        assert!(
+            records
+                .iter()
+                .all(|r| r.protocol == "ftp" && r.client_ip.is_none())
+        );
+    }
+}
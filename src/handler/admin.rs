@@ -0,0 +1,64 @@
+//! 管理接口，只有 `admin = true` 的用户可以使用
+
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{audit::AuditQuery, extractors::AdminUser, logging, model::AppState};
+
+#[derive(Serialize, Deserialize)]
+pub struct LogLevel {
+    /// `EnvFilter` 过滤规则，例如 `debug` 或 `info,simple_file_manager::s3=trace`
+    pub level: String,
+}
+
+fn current() -> Response {
+    match logging::current_filter() {
+        Some(level) => Json(LogLevel { level }).into_response(),
+        None => (StatusCode::INTERNAL_SERVER_ERROR, "无法读取日志级别").into_response(),
+    }
+}
+
+pub async fn get_log_level(AdminUser(_): AdminUser) -> Response {
+    current()
+}
+
+/// 修改只在本次运行期间有效，重启后恢复为配置中的 `log_level`
+pub async fn set_log_level(AdminUser(user): AdminUser, Json(body): Json<LogLevel>) -> Response {
+    match logging::set_filter(&body.level) {
+        Ok(()) => {
+            info!("管理员 {} 把日志级别修改为 {}", user.username, body.level);
+            current()
+        }
+        Err(e) => {
+            warn!(
+                "管理员 {} 设置日志级别 {} 失败: {}",
+                user.username, body.level, e
+            );
+            (StatusCode::BAD_REQUEST, format!("无效的日志级别: {}", e)).into_response()
+        }
+    }
+}
+
+/// 按用户、操作、根目录、路径前缀和时间范围查询审计日志，返回最近的记录
+pub async fn query_audit(
+    State(state): State<AppState>,
+    AdminUser(_): AdminUser,
+    Query(query): Query<AuditQuery>,
+) -> Response {
+    if !state.audit.enabled() {
+        return (StatusCode::NOT_FOUND, "未启用审计日志").into_response();
+    }
+    match state.audit.query(&query).await {
+        Ok(records) => Json(records).into_response(),
+        Err(e) => {
+            warn!("读取审计日志失败: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "读取审计日志失败").into_response()
+        }
+    }
+}
@@ -1,13 +1,18 @@
-use crate::{extractors::AuthUser, handler::list::FileRequest, model::AppState};
+use crate::{
+    extractors::AuthUser,
+    handler::{list::FileRequest, share::clean_relative_path},
+    model::{AppState, READ_MASK},
+    utils::{check_permission, content_disposition},
+};
 use axum::{
     extract::Query,
     extract::State,
     http::StatusCode,
     response::{IntoResponse, Response},
 };
-use std::path::Path;
+use std::path::PathBuf;
 use tokio::fs;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 pub async fn download(
     State(state): State<AppState>,
@@ -18,16 +23,29 @@ pub async fn download(
     let path = params.path.unwrap_or("".to_string());
     info!("用户 '{}' 请求下载: {}/{}", &user.username, &root, &path);
 
-    let full_path = state.path.get(&root).map(|p| format!("{}{}", p.path, path));
+    let Some(relative) = clean_relative_path(&path) else {
+        return (StatusCode::BAD_REQUEST, "非法路径").into_response();
+    };
+    let full_path = state
+        .get_path(&root)
+        .map(|p| PathBuf::from(&p.path).join(&relative));
 
     if full_path.is_none() {
         return (StatusCode::NOT_FOUND, "路径不存在".to_string()).into_response();
     }
 
     // 从查询参数中获取路径
-    let path_str = full_path.unwrap();
-    info!("用户 '{}' 下载文件: {}", &user.username, &path_str);
-    let file_path = Path::new(&path_str);
+    let file_path = full_path.unwrap();
+    info!("用户 '{}' 下载文件: {:?}", &user.username, &file_path);
+
+    if !check_permission(
+        &user.permissions_tree,
+        &file_path.to_string_lossy(),
+        READ_MASK,
+    ) {
+        warn!("用户 '{}' 没有读取 {:?} 的权限", &user.username, file_path);
+        return (StatusCode::FORBIDDEN, "没有读取权限").into_response();
+    }
 
     // 检查文件是否存在
     if !file_path.exists() {
@@ -56,13 +74,10 @@ pub async fn download(
     (
         StatusCode::OK,
         [
-            (
-                "Content-Disposition",
-                format!("attachment; filename=\"{}\"", file_name),
-            ),
+            ("Content-Disposition", content_disposition(&file_name)),
             (
                 "Content-Type",
-                mime_guess::from_path(&file_path)
+                mime_guess::from_path(file_path)
                     .first_or_octet_stream()
                     .to_string(),
             ),
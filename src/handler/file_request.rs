@@ -0,0 +1,656 @@
+use crate::{
+    audit::AuditContext,
+    extractors::AuthUser,
+    handler::share::{clean_relative_path, page},
+    model::{AppState, WRITE_MASK, file_request::FileRequest},
+    proxy::{Client, Proxy},
+    utils::{AtomicFile, check_permission},
+    vfs,
+};
+use axum::{
+    Extension, Json,
+    extract::{Multipart, Path as UrlPath, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::{TimeDelta, Utc};
+use futures_util::TryStreamExt;
+use quick_xml::escape::escape;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tokio::io::AsyncReadExt;
+use tokio_util::io::StreamReader;
+use tracing::{error, info, warn};
+
+/// 上传者姓名和邮箱的最大长度
+const MAX_CONTACT_LEN: usize = 200;
+
+#[derive(Deserialize)]
+pub struct CreateFileRequestRequest {
+    pub root: String,
+    pub path: String,
+    pub title: Option<String>,
+    /// 有效期（秒），不填表示永久有效
+    pub expires_in: Option<i64>,
+    pub max_file_size: Option<u64>,
+    #[serde(default)]
+    pub allowed_extensions: Vec<String>,
+    pub max_files: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct FileRequestInfo {
+    pub token: String,
+    pub url: String,
+    pub root: String,
+    pub path: String,
+    pub title: Option<String>,
+    pub created_at: String,
+    pub expires_at: Option<String>,
+    pub max_file_size: Option<u64>,
+    pub allowed_extensions: Vec<String>,
+    pub max_files: Option<u32>,
+    pub uploads: u32,
+}
+
+impl FileRequestInfo {
+    /// `url` 带上 `misc.base_path`
+    fn new(request: &FileRequest, proxy: &Proxy) -> Self {
+        FileRequestInfo {
+            token: request.token.clone(),
+            url: proxy.url(&format!("/r/{}", request.token)),
+            root: request.root.clone(),
+            path: request.path.clone(),
+            title: request.title.clone(),
+            created_at: request.created_at.to_rfc3339(),
+            expires_at: request.expires_at.map(|t| t.to_rfc3339()),
+            max_file_size: request.max_file_size,
+            allowed_extensions: request.allowed_extensions.clone(),
+            max_files: request.max_files,
+            uploads: request.uploads,
+        }
+    }
+}
+
+/// 写入上传文件旁边的 `*.upload.json`，记录上传者填写的信息
+#[derive(Serialize)]
+struct UploadRecord<'a> {
+    request: &'a str,
+    original_name: &'a str,
+    size: u64,
+    uploader_name: Option<&'a str>,
+    uploader_email: Option<&'a str>,
+    ip: String,
+    uploaded_at: String,
+}
+
+pub async fn create_file_request(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    audit: Option<Extension<AuditContext>>,
+    Json(payload): Json<CreateFileRequestRequest>,
+) -> Response {
+    if let Some(Extension(audit)) = &audit {
+        audit.set_target(&payload.root, &payload.path);
+    }
+    let Some(root) = state.get_path(&payload.root) else {
+        return (StatusCode::NOT_FOUND, "Root不存在").into_response();
+    };
+    let Some(path) = clean_relative_path(&payload.path) else {
+        return (StatusCode::BAD_REQUEST, "非法路径").into_response();
+    };
+    let full_path = PathBuf::from(&root.path).join(&path);
+
+    if !check_permission(
+        &user.permissions_tree,
+        &full_path.to_string_lossy(),
+        WRITE_MASK,
+    ) {
+        warn!(
+            "用户 '{}' 没有在 {:?} 收集文件的权限",
+            &user.username, full_path
+        );
+        return (StatusCode::FORBIDDEN, "没有写入权限").into_response();
+    }
+    if !tokio::fs::metadata(&full_path)
+        .await
+        .is_ok_and(|m| m.is_dir())
+    {
+        return (StatusCode::NOT_FOUND, "文件夹不存在").into_response();
+    }
+    if payload.expires_in.is_some_and(|s| s <= 0) {
+        return (StatusCode::BAD_REQUEST, "有效期必须大于 0").into_response();
+    }
+
+    let now = Utc::now();
+    let expires_at = match payload.expires_in {
+        Some(seconds) => {
+            match TimeDelta::try_seconds(seconds).and_then(|d| now.checked_add_signed(d)) {
+                Some(expires_at) => Some(expires_at),
+                None => return (StatusCode::BAD_REQUEST, "有效期过长").into_response(),
+            }
+        }
+        None => None,
+    };
+    let request = FileRequest {
+        token: uuid::Uuid::new_v4().simple().to_string(),
+        owner: user.username.clone(),
+        root: payload.root,
+        path,
+        title: payload.title.filter(|t| !t.is_empty()),
+        created_at: now,
+        expires_at,
+        max_file_size: payload.max_file_size,
+        allowed_extensions: payload
+            .allowed_extensions
+            .iter()
+            .map(|e| e.trim().trim_start_matches('.').to_lowercase())
+            .filter(|e| !e.is_empty())
+            .collect(),
+        max_files: payload.max_files,
+        uploads: 0,
+    };
+    let info = FileRequestInfo::new(&request, &state.proxy);
+    if let Err(e) = state.file_requests.insert(request).await {
+        error!("保存文件收集链接失败: {}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "保存文件收集链接失败").into_response();
+    }
+    info!(
+        "用户 '{}' 创建文件收集链接 {}: {}/{}",
+        &user.username, &info.token, &info.root, &info.path
+    );
+    (StatusCode::OK, Json(info)).into_response()
+}
+
+pub async fn list_file_requests(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+) -> Json<Vec<FileRequestInfo>> {
+    let requests = state.file_requests.list_by_owner(&user.username).await;
+    Json(
+        requests
+            .iter()
+            .map(|r| FileRequestInfo::new(r, &state.proxy))
+            .collect(),
+    )
+}
+
+pub async fn revoke_file_request(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    UrlPath(token): UrlPath<String>,
+) -> Response {
+    match state.file_requests.revoke(&token, &user.username).await {
+        Ok(true) => {
+            info!("用户 '{}' 撤销文件收集链接 {}", &user.username, &token);
+            (StatusCode::OK, "已撤销").into_response()
+        }
+        Ok(false) => (StatusCode::NOT_FOUND, "文件收集链接不存在").into_response(),
+        Err(e) => {
+            error!("保存文件收集链接失败: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "保存文件收集链接失败").into_response()
+        }
+    }
+}
+
+/// 检查链接是否可用，返回接收上传的文件夹和创建者的权限树
+async fn open_request(
+    state: &AppState,
+    token: &str,
+) -> Result<(FileRequest, PathBuf, crate::model::Path), Response> {
+    let not_found = || page(StatusCode::NOT_FOUND, "链接不存在", "");
+    let request = state.file_requests.get(token).await.ok_or_else(not_found)?;
+    if request.is_expired() {
+        return Err(page(StatusCode::GONE, "链接已过期", ""));
+    }
+    if request.is_full() {
+        return Err(page(StatusCode::GONE, "已达到文件数量上限", ""));
+    }
+
+    // 创建者被删除或失去写入权限后，链接随之失效
+    let (Some(owner), Some(root)) = (
+        state.get_user_config(&request.owner),
+        state.get_path(&request.root),
+    ) else {
+        return Err(not_found());
+    };
+    let root = PathBuf::from(&root.path);
+    let folder = root.join(&request.path);
+    if !check_permission(
+        &owner.permissions_tree,
+        &folder.to_string_lossy(),
+        WRITE_MASK,
+    ) || vfs::ensure_contained(&root, &folder).await.is_err()
+        || !tokio::fs::metadata(&folder).await.is_ok_and(|m| m.is_dir())
+    {
+        return Err(not_found());
+    }
+    Ok((request, folder, owner.permissions_tree.clone()))
+}
+
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", size, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// `GET /r/{token}`：只显示上传表单，不透露文件夹中的任何内容
+pub async fn file_request_page(
+    State(state): State<AppState>,
+    UrlPath(token): UrlPath<String>,
+) -> Response {
+    let request = match open_request(&state, &token).await {
+        Ok((request, _, _)) => request,
+        Err(response) => return response,
+    };
+
+    let mut limits = Vec::new();
+    if let Some(max) = request.max_file_size {
+        limits.push(format!("单个文件不超过 {}", format_size(max)));
+    }
+    if !request.allowed_extensions.is_empty() {
+        limits.push(format!(
+            "允许的类型：{}",
+            escape(request.allowed_extensions.join(", "))
+        ));
+    }
+    if let Some(max) = request.max_files {
+        limits.push(format!(
+            "还可以上传 {} 个文件",
+            max.saturating_sub(request.uploads)
+        ));
+    }
+    let accept = request
+        .allowed_extensions
+        .iter()
+        .map(|e| format!(".{}", e))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let body = format!(
+        "<p>{}</p>\
+         <form method=\"post\" enctype=\"multipart/form-data\">\
+         <p><label>姓名（可选） <input name=\"name\" maxlength=\"{max}\"></label></p>\
+         <p><label>邮箱（可选） <input name=\"email\" type=\"email\" maxlength=\"{max}\"></label></p>\
+         <p><input name=\"file\" type=\"file\" multiple required accept=\"{}\"></p>\
+         <p><button type=\"submit\">上传</button></p></form>",
+        limits.join("；"),
+        escape(&accept),
+        max = MAX_CONTACT_LEN
+    );
+    page(
+        StatusCode::OK,
+        request.title.as_deref().unwrap_or("上传文件"),
+        &body,
+    )
+}
+
+/// `POST /r/{token}`：接收上传，同名文件不会被覆盖，返回的回执只包含本次上传的文件
+pub async fn file_request_upload(
+    State(state): State<AppState>,
+    UrlPath(token): UrlPath<String>,
+    client: Client,
+    audit: Option<Extension<AuditContext>>,
+    mut multipart: Multipart,
+) -> Response {
+    let (request, folder, tree) = match open_request(&state, &token).await {
+        Ok(opened) => opened,
+        Err(response) => return response,
+    };
+    if let Some(Extension(audit)) = &audit {
+        audit.set_target(&request.root, &request.path);
+    }
+
+    let mut uploader_name: Option<String> = None;
+    let mut uploader_email: Option<String> = None;
+    // (原始文件名, 实际保存路径, 大小)
+    let mut received: Vec<(String, PathBuf, u64)> = Vec::new();
+
+    // 中途失败时已经保存的文件仍然写入上传记录
+    let mut failure: Option<(StatusCode, &str, String)> = None;
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => {
+                warn!("文件收集链接 {} 读取上传失败: {}", &token, e);
+                failure = Some((StatusCode::BAD_REQUEST, "上传失败", String::new()));
+                break;
+            }
+        };
+        let name = field.name().unwrap_or("unknown").to_string();
+        match name.as_str() {
+            "name" | "email" => {
+                let value: String = field
+                    .text()
+                    .await
+                    .unwrap_or_default()
+                    .trim()
+                    .chars()
+                    .take(MAX_CONTACT_LEN)
+                    .collect();
+                if !value.is_empty() {
+                    if name == "name" {
+                        uploader_name = Some(value);
+                    } else {
+                        uploader_email = Some(value);
+                    }
+                }
+            }
+            "file" => {
+                let original_name = field.file_name().unwrap_or("unknown").to_string();
+                let mut clean_name = sanitize_filename::sanitize(&original_name);
+                if clean_name.is_empty() || clean_name.starts_with('.') {
+                    clean_name = format!("upload{}", clean_name);
+                }
+                if !request.extension_allowed(&clean_name) {
+                    failure = Some((
+                        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                        "不允许上传此类型的文件",
+                        format!("<p>{}</p>", escape(&original_name)),
+                    ));
+                    break;
+                }
+                let target = folder.join(&clean_name);
+                if !check_permission(&tree, &target.to_string_lossy(), WRITE_MASK) {
+                    failure = Some((StatusCode::FORBIDDEN, "没有写入权限", String::new()));
+                    break;
+                }
+
+                match state.file_requests.reserve_upload(&token).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        failure = Some((StatusCode::GONE, "已达到文件数量上限", String::new()));
+                        break;
+                    }
+                    Err(e) => {
+                        error!("保存文件收集链接失败: {}", e);
+                        failure =
+                            Some((StatusCode::INTERNAL_SERVER_ERROR, "上传失败", String::new()));
+                        break;
+                    }
+                }
+
+                let reader = StreamReader::new(field.map_err(std::io::Error::other));
+                let result = match request.max_file_size {
+                    // 多读一个字节用来判断是否超过上限
+                    Some(max) => write_new_file(&target, reader.take(max + 1), Some(max)).await,
+                    None => write_new_file(&target, reader, None).await,
+                };
+                match result {
+                    Ok(Some((path, size))) => received.push((original_name, path, size)),
+                    Ok(None) => {
+                        let _ = state.file_requests.release_upload(&token).await;
+                        failure = Some((
+                            StatusCode::PAYLOAD_TOO_LARGE,
+                            "文件太大",
+                            format!("<p>{}</p>", escape(&original_name)),
+                        ));
+                        break;
+                    }
+                    Err(e) => {
+                        let _ = state.file_requests.release_upload(&token).await;
+                        error!("写入文件 {:?} 失败: {}", target, e);
+                        failure =
+                            Some((StatusCode::INTERNAL_SERVER_ERROR, "上传失败", String::new()));
+                        break;
+                    }
+                }
+            }
+            _ => {
+                warn!("unknown field: {}", name);
+            }
+        }
+    }
+
+    if let Some(Extension(audit)) = &audit {
+        audit.set_bytes(received.iter().map(|(_, _, size)| size).sum());
+    }
+    if received.is_empty() {
+        let (status, title, detail) =
+            failure.unwrap_or((StatusCode::BAD_REQUEST, "没有收到文件", String::new()));
+        return page(status, title, &detail);
+    }
+
+    let uploaded_at = Utc::now();
+    for (original_name, path, size) in &received {
+        let record = UploadRecord {
+            request: &token,
+            original_name,
+            size: *size,
+            uploader_name: uploader_name.as_deref(),
+            uploader_email: uploader_email.as_deref(),
+            ip: client.ip.to_string(),
+            uploaded_at: uploaded_at.to_rfc3339(),
+        };
+        if let Err(e) = write_sidecar(path, &record).await {
+            error!("写入上传记录 {:?} 失败: {}", path, e);
+        }
+        info!(
+            "文件收集链接 {} 收到来自 {} 的文件: {:?}",
+            &token, client.ip, path
+        );
+    }
+
+    let items: String = received
+        .iter()
+        .map(|(name, _, size)| format!("<li>{} ({})</li>", escape(name), format_size(*size)))
+        .collect();
+    let receipt = format!(
+        "<p>已收到以下文件（{}）：</p><ul>{}</ul>",
+        uploaded_at.format("%Y-%m-%d %H:%M:%S UTC"),
+        items
+    );
+    match failure {
+        Some((status, title, detail)) => page(status, title, &format!("{}{}", detail, receipt)),
+        None => page(StatusCode::OK, "上传成功", &receipt),
+    }
+}
+
+/// 写入新文件，不覆盖已有文件；超过 `max_size` 时丢弃并返回 `None`
+async fn write_new_file<R>(
+    target: &std::path::Path,
+    mut reader: R,
+    max_size: Option<u64>,
+) -> std::io::Result<Option<(PathBuf, u64)>>
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    let mut file = AtomicFile::create(target).await?;
+    let written = tokio::io::copy(&mut reader, file.as_file_mut()).await?;
+    if max_size.is_some_and(|max| written > max) {
+        return Ok(None);
+    }
+    let path = file.commit_unique().await?;
+    Ok(Some((path, written)))
+}
+
+async fn write_sidecar(path: &std::path::Path, record: &UploadRecord<'_>) -> std::io::Result<()> {
+    let mut sidecar_name = path.file_name().unwrap_or_default().to_os_string();
+    sidecar_name.push(".upload.json");
+    let data = serde_json::to_vec_pretty(record).map_err(std::io::Error::other)?;
+    let mut file = AtomicFile::create(&path.with_file_name(sidecar_name)).await?;
+    file.write_all(&data).await?;
+    file.commit_unique().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use axum::{
+        Router,
+        body::{Body, to_bytes},
+        extract::{ConnectInfo, Request},
+        http::header,
+        routing::get,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+
+    const BOUNDARY: &str = "test-boundary";
+
+    async fn app(dir: &std::path::Path) -> (AppState, Router) {
+        std::fs::create_dir_all(dir.join("data").join("inbox")).unwrap();
+        std::fs::write(dir.join("data").join("inbox").join("secret.txt"), "secret").unwrap();
+        let config = format!(
+            r#"
+            [[paths]]
+            name = "data"
+            path = "{}/data"
+            permission = 0b111
+
+            [[users]]
+            username = "alice"
+            password = "x"
+            permissions = [{{ path_name = "data", permission = 0b111 }}]
+            "#,
+            dir.display()
+        );
+        let state = AppState::for_test(&config, dir).await;
+        let router = Router::new()
+            .route(
+                "/r/{token}",
+                get(file_request_page).post(file_request_upload),
+            )
+            .with_state(state.clone());
+        (state, router)
+    }
+
+    fn file_request(token: &str) -> FileRequest {
+        FileRequest {
+            token: token.to_string(),
+            owner: "alice".to_string(),
+            root: "data".to_string(),
+            path: "inbox".to_string(),
+            title: None,
+            created_at: Utc::now(),
+            expires_at: None,
+            max_file_size: None,
+            allowed_extensions: Vec::new(),
+            max_files: None,
+            uploads: 0,
+        }
+    }
+
+    async fn send(router: &Router, mut request: Request) -> Response {
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+        router.clone().oneshot(request).await.unwrap()
+    }
+
+    async fn upload(router: &Router, token: &str, files: &[(&str, &str)]) -> Response {
+        let mut body =
+            format!("--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"name\"\r\n\r\nBob\r\n");
+        for (name, content) in files {
+            body.push_str(&format!(
+                "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{name}\"\r\n\r\n{content}\r\n"
+            ));
+        }
+        body.push_str(&format!("--{BOUNDARY}--\r\n"));
+        let request = Request::post(format!("/r/{token}"))
+            .header(
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={BOUNDARY}"),
+            )
+            .body(Body::from(body))
+            .unwrap();
+        send(router, request).await
+    }
+
+    async fn text(response: Response) -> String {
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn page_does_not_reveal_folder_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, router) = app(dir.path()).await;
+        state.file_requests.insert(file_request("t")).await.unwrap();
+
+        let request = Request::get("/r/t").body(Body::empty()).unwrap();
+        let response = send(&router, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!text(response).await.contains("secret.txt"));
+
+        let request = Request::get("/r/missing").body(Body::empty()).unwrap();
+        assert_eq!(send(&router, request).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn uploads_never_overwrite_existing_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, router) = app(dir.path()).await;
+        state.file_requests.insert(file_request("t")).await.unwrap();
+
+        let response = upload(&router, "t", &[("secret.txt", "new")]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let inbox = dir.path().join("data").join("inbox");
+        assert_eq!(
+            std::fs::read_to_string(inbox.join("secret.txt")).unwrap(),
+            "secret"
+        );
+        let saved: Vec<_> = std::fs::read_dir(&inbox)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name != "secret.txt")
+            .collect();
+        assert_eq!(saved.len(), 2, "{:?}", saved);
+        let sidecar = saved.iter().find(|n| n.ends_with(".upload.json")).unwrap();
+        let record: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(inbox.join(sidecar)).unwrap()).unwrap();
+        assert_eq!(record["original_name"], "secret.txt");
+        assert_eq!(record["uploader_name"], "Bob");
+        assert_eq!(record["size"], 3);
+        assert_eq!(state.file_requests.get("t").await.unwrap().uploads, 1);
+    }
+
+    #[tokio::test]
+    async fn limits_are_enforced() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, router) = app(dir.path()).await;
+        let mut limited = file_request("t");
+        limited.allowed_extensions = vec!["pdf".to_string()];
+        limited.max_file_size = Some(4);
+        limited.max_files = Some(1);
+        state.file_requests.insert(limited).await.unwrap();
+
+        let response = upload(&router, "t", &[("a.exe", "x")]).await;
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let response = upload(&router, "t", &[("a.pdf", "too large")]).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        // 失败的上传不占用名额
+        assert_eq!(state.file_requests.get("t").await.unwrap().uploads, 0);
+
+        let response = upload(&router, "t", &[("a.pdf", "ok"), ("b.pdf", "ok")]).await;
+        assert_eq!(response.status(), StatusCode::GONE);
+        assert!(text(response).await.contains("a.pdf"));
+        let response = upload(&router, "t", &[("c.pdf", "ok")]).await;
+        assert_eq!(response.status(), StatusCode::GONE);
+    }
+
+    #[tokio::test]
+    async fn expired_request_is_gone() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, router) = app(dir.path()).await;
+        let mut expired = file_request("t");
+        expired.expires_at = Some(Utc::now() - TimeDelta::seconds(1));
+        state.file_requests.insert(expired).await.unwrap();
+
+        let response = upload(&router, "t", &[("a.txt", "x")]).await;
+        assert_eq!(response.status(), StatusCode::GONE);
+    }
+}
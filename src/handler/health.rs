@@ -0,0 +1,207 @@
+//! 存活和就绪探针，不需要登录
+
+use axum::{Json, extract::State, http::StatusCode};
+use serde::Serialize;
+
+use crate::{
+    model::{AppState, WRITE_MASK},
+    utils::AtomicFile,
+};
+
+#[derive(Serialize)]
+pub struct Health {
+    pub status: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct RootStatus {
+    /// `[[paths]]` 的名称，不返回服务器上的实际路径
+    pub name: String,
+    pub ok: bool,
+    pub readable: bool,
+    /// 没有用户对该根目录有写权限时不检查
+    pub writable: Option<bool>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Readiness {
+    pub status: &'static str,
+    pub roots: Vec<RootStatus>,
+}
+
+/// `GET /healthz`：进程能处理请求就返回 200
+pub async fn healthz() -> Json<Health> {
+    Json(Health { status: "ok" })
+}
+
+/// `GET /readyz`：每个根目录都存在且可读，有用户可以写入的根目录还要可写，否则返回 503
+pub async fn readyz(State(state): State<AppState>) -> (StatusCode, Json<Readiness>) {
+    let config = state.config();
+    let mut roots = Vec::new();
+    for (name, root) in &config.paths {
+        let needs_write = config.users.values().any(|u| {
+            u.permissions
+                .iter()
+                .any(|p| &p.path_name == name && p.permission & WRITE_MASK != 0)
+        });
+        roots.push(check_root(name, std::path::Path::new(&root.path), needs_write).await);
+    }
+
+    let ready = roots.iter().all(|r| r.ok);
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let body = Readiness {
+        status: if ready { "ready" } else { "not_ready" },
+        roots,
+    };
+    (status, Json(body))
+}
+
+async fn check_root(name: &str, path: &std::path::Path, needs_write: bool) -> RootStatus {
+    let mut status = RootStatus {
+        name: name.to_string(),
+        ok: false,
+        readable: false,
+        writable: None,
+        error: None,
+    };
+    match tokio::fs::metadata(path).await {
+        Ok(m) if m.is_dir() => {}
+        Ok(_) => {
+            status.error = Some("不是目录".to_string());
+            return status;
+        }
+        Err(e) => {
+            status.error = Some(e.to_string());
+            return status;
+        }
+    }
+    if let Err(e) = tokio::fs::read_dir(path).await {
+        status.error = Some(e.to_string());
+        return status;
+    }
+    status.readable = true;
+
+    if needs_write {
+        // 创建一个临时文件再丢弃，丢弃时会被删除
+        match AtomicFile::create(&path.join(".readyz")).await {
+            Ok(_) => status.writable = Some(true),
+            Err(e) => {
+                status.writable = Some(false);
+                status.error = Some(e.to_string());
+                return status;
+            }
+        }
+    }
+    status.ok = true;
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Router,
+        body::{Body, to_bytes},
+        extract::Request,
+        routing::get,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+
+    /// `rw` 可写，`ro` 只读，`extra` 为追加的 `[[paths]]`
+    async fn probe(dir: &std::path::Path, extra: &str) -> (StatusCode, serde_json::Value) {
+        for name in ["rw", "ro"] {
+            std::fs::create_dir_all(dir.join(name)).unwrap();
+        }
+        let config = format!(
+            r#"
+            [[paths]]
+            name = "rw"
+            path = "{dir}/rw"
+            permission = 0b111
+
+            [[paths]]
+            name = "ro"
+            path = "{dir}/ro"
+            permission = 0b111
+            {extra}
+
+            [[users]]
+            username = "alice"
+            password = "x"
+            permissions = [
+                {{ path_name = "rw", permission = 0b111 }},
+                {{ path_name = "ro", permission = 0b001 }},
+            ]
+            "#,
+            dir = dir.display()
+        );
+        let state = AppState::for_test(&config, dir).await;
+        let router = Router::new()
+            .route("/readyz", get(readyz))
+            .with_state(state);
+        let request = Request::get("/readyz").body(Body::empty()).unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn root<'a>(body: &'a serde_json::Value, name: &str) -> &'a serde_json::Value {
+        body["roots"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|r| r["name"] == name)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn ready_when_all_roots_are_usable() {
+        let dir = tempfile::tempdir().unwrap();
+        let (status, body) = probe(dir.path(), "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ready");
+        assert_eq!(root(&body, "rw")["writable"], true);
+        // 没有用户可以写入时不检查
+        assert!(root(&body, "ro")["writable"].is_null());
+        // 检查写入时创建的文件不会留下
+        assert_eq!(std::fs::read_dir(dir.path().join("rw")).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn missing_or_invalid_roots_are_not_ready() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("file"), "").unwrap();
+        let extra = format!(
+            r#"
+            [[paths]]
+            name = "missing"
+            path = "{dir}/missing"
+            permission = 0b111
+
+            [[paths]]
+            name = "file"
+            path = "{dir}/file"
+            permission = 0b111
+            "#,
+            dir = dir.path().display()
+        );
+        let (status, body) = probe(dir.path(), &extra).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "not_ready");
+        assert_eq!(root(&body, "rw")["ok"], true);
+        let missing = root(&body, "missing");
+        assert_eq!(missing["ok"], false);
+        assert_eq!(missing["readable"], false);
+        assert!(missing["error"].is_string());
+        assert_eq!(root(&body, "file")["error"], "不是目录");
+        // 不返回服务器上的实际路径
+        assert!(!body.to_string().contains(&dir.path().display().to_string()));
+    }
+}
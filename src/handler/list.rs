@@ -1,6 +1,9 @@
-use std::os::windows::fs::MetadataExt;
-
-use crate::{extractors::AuthUser, model::AppState};
+use crate::{
+    extractors::AuthUser,
+    handler::share::clean_relative_path,
+    model::{AppState, VIEW_MASK},
+    utils::check_permission,
+};
 use axum::{
     Json,
     extract::{Query, State},
@@ -8,7 +11,8 @@ use axum::{
     response::IntoResponse,
 };
 use serde::{Deserialize, Serialize};
-use tracing::{error, info};
+use std::path::PathBuf;
+use tracing::{error, info, warn};
 
 const DEFAULT_PERMISSIONS: u8 = 0b111;
 
@@ -45,8 +49,10 @@ pub async fn list_files(
             StatusCode::OK,
             Json(FileListResponse {
                 files: state
-                    .path
+                    .config()
+                    .paths
                     .values()
+                    .filter(|f| check_permission(&user.permissions_tree, &f.path, VIEW_MASK))
                     .map(|f| File {
                         name: f.name.clone(),
                         is_dir: true,
@@ -58,27 +64,46 @@ pub async fn list_files(
         )
             .into_response();
     }
-    if let Some(file_list) = state.path.get(&root).and_then(|p| {
-        let full_path = match path.len() {
-            0 => p.path.clone(),
-            _ => format!("{}{}", p.path, path),
-        };
-        info!("用户 '{}' 读取目录: {}", &user.username, &full_path);
+    let Some(relative) = clean_relative_path(&path) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(FileListResponse { files: vec![] }),
+        )
+            .into_response();
+    };
+    let full_path = state
+        .get_path(&root)
+        .map(|p| PathBuf::from(&p.path).join(&relative));
+    if let Some(full_path) = &full_path
+        && !check_permission(
+            &user.permissions_tree,
+            &full_path.to_string_lossy(),
+            VIEW_MASK,
+        )
+    {
+        warn!("用户 '{}' 没有查看 {:?} 的权限", &user.username, full_path);
+        return (
+            StatusCode::FORBIDDEN,
+            Json(FileListResponse { files: vec![] }),
+        )
+            .into_response();
+    }
+    if let Some(file_list) = full_path.and_then(|full_path| {
+        info!("用户 '{}' 读取目录: {:?}", &user.username, &full_path);
         let dir_entries = std::fs::read_dir(&full_path).ok()?;
 
         let files: Vec<File> = dir_entries
             .filter_map(|entry| entry.ok())
-            .map(|entry| {
+            .filter_map(|entry| {
                 let metadata = entry.metadata().ok()?;
                 let is_dir = metadata.is_dir();
                 Some(File {
                     name: entry.file_name().to_string_lossy().to_string(),
-                    is_dir: is_dir,
+                    is_dir,
                     permissions: DEFAULT_PERMISSIONS,
-                    size: if is_dir { 0 } else { metadata.file_size() },
+                    size: if is_dir { 0 } else { metadata.len() },
                 })
             })
-            .filter_map(|f| f)
             .collect();
         Some(files)
     }) {
@@ -1,5 +1,14 @@
-use crate::model::AppState;
-use axum::{Json, extract::State};
+use crate::{
+    audit::{AuditContext, AuditResult},
+    extractors::read_token_from_req,
+    model::AppState,
+    telemetry,
+};
+use axum::{
+    Extension, Json,
+    extract::{FromRequestParts, State},
+    http::{StatusCode, request::Parts},
+};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use tracing::{error, info};
@@ -18,20 +27,23 @@ pub struct LoginResponse {
 
 pub async fn login(
     State(state): State<AppState>,
+    audit: Option<Extension<AuditContext>>,
     Json(payload): Json<LoginRequest>,
 ) -> Json<LoginResponse> {
-    let valid = state
-        .user_config
-        .get(&payload.username)
-        .filter(|u| u.password == payload.password)
-        .is_some();
-
-    let session_id = Uuid::new_v4().to_string();
-    state
-        .add_session(session_id.clone(), payload.username.clone())
-        .await;
+    if let Some(Extension(audit)) = &audit {
+        audit.set_user(&payload.username);
+    }
+    let valid = match state.get_user_config(&payload.username) {
+        Some(user) => user.verify_password(&payload.password).await,
+        None => false,
+    };
 
     if valid {
+        // 只为登录成功的用户创建会话，失败的尝试不占用会话
+        let session_id = Uuid::new_v4().to_string();
+        state
+            .add_session(session_id.clone(), payload.username.clone())
+            .await;
         info!("用户 '{}' 登录成功, session: {}", payload.username, &session_id);
         Json(LoginResponse {
             success: true,
@@ -39,9 +51,41 @@ pub async fn login(
         })
     } else {
         error!("用户 '{}' 登录失败", payload.username);
+        telemetry::login_failed("http");
+        if let Some(Extension(audit)) = &audit {
+            audit.set_result(AuditResult::Denied);
+        }
         Json(LoginResponse {
             success: false,
             token: None,
         })
     }
 }
+
+/// 会话 token，与 [`crate::extractors::AuthUser`] 从同样的位置读取
+pub struct SessionToken(String);
+
+impl<S: Send + Sync> FromRequestParts<S> for SessionToken {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        read_token_from_req(parts)
+            .await
+            .map(|t| SessionToken(t.to_string()))
+            .ok_or(StatusCode::UNAUTHORIZED)
+    }
+}
+
+/// 删除当前会话，之后同一个 token 不能再使用
+pub async fn logout(
+    State(state): State<AppState>,
+    SessionToken(token): SessionToken,
+) -> StatusCode {
+    match state.remove_session(&token).await {
+        Some(username) => {
+            info!("用户 '{}' 退出登录", username);
+            StatusCode::NO_CONTENT
+        }
+        None => StatusCode::UNAUTHORIZED,
+    }
+}
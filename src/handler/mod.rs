@@ -1,9 +1,20 @@
+pub mod admin;
+pub mod health;
 pub mod login;
 pub mod list;
 pub mod upload;
 pub mod download;
+pub mod file_request;
+pub mod share;
 
-pub use login::login;
+pub use admin::{get_log_level, query_audit, set_log_level};
+pub use health::{healthz, readyz};
+pub use login::{login, logout};
 pub use list::list_files;
 pub use upload::upload;
-pub use download::download;
\ No newline at end of file
+pub use download::download;
+pub use file_request::{
+    create_file_request, file_request_page, file_request_upload, list_file_requests,
+    revoke_file_request,
+};
+pub use share::{create_share, list_shares, public_share, revoke_share, unlock_share};
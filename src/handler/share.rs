@@ -0,0 +1,721 @@
+use crate::{
+    audit::AuditContext,
+    extractors::AuthUser,
+    model::{
+        AppState, READ_MASK,
+        auth::hash_password,
+        share::{SHARE_UNLOCK_SECONDS, Share, ShareDenied, is_valid_ip_range},
+    },
+    proxy::{Client, Proxy},
+    utils::{check_permission, content_disposition, is_temp_file},
+    vfs,
+};
+use axum::{
+    Extension, Json,
+    body::Body,
+    extract::{Form, Path as UrlPath, Query, State},
+    http::{HeaderMap, StatusCode, Uri, header},
+    response::{Html, IntoResponse, Response},
+};
+use chrono::{TimeDelta, Utc};
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use quick_xml::escape::escape;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+use tokio::io::AsyncSeekExt;
+use tokio_util::io::ReaderStream;
+use tracing::{error, info, warn};
+
+#[derive(Deserialize)]
+pub struct CreateShareRequest {
+    pub root: String,
+    pub path: String,
+    /// 有效期（秒），不填表示永久有效
+    pub expires_in: Option<i64>,
+    pub password: Option<String>,
+    pub max_downloads: Option<u32>,
+    #[serde(default)]
+    pub allowed_ips: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct ShareInfo {
+    pub token: String,
+    pub url: String,
+    pub root: String,
+    pub path: String,
+    pub is_dir: bool,
+    pub created_at: String,
+    pub expires_at: Option<String>,
+    pub has_password: bool,
+    pub max_downloads: Option<u32>,
+    pub downloads: u32,
+    pub allowed_ips: Vec<String>,
+}
+
+impl ShareInfo {
+    /// `url` 带上 `misc.base_path`
+    fn new(share: &Share, proxy: &Proxy) -> Self {
+        ShareInfo {
+            token: share.token.clone(),
+            url: proxy.url(&format!("/s/{}", share.token)),
+            root: share.root.clone(),
+            path: share.path.clone(),
+            is_dir: share.is_dir,
+            created_at: share.created_at.to_rfc3339(),
+            expires_at: share.expires_at.map(|t| t.to_rfc3339()),
+            has_password: share.password_hash.is_some(),
+            max_downloads: share.max_downloads,
+            downloads: share.downloads,
+            allowed_ips: share.allowed_ips.clone(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ShareQuery {
+    /// 文件夹分享中的子路径
+    pub path: Option<String>,
+    /// 为 true 时把文件夹打包为 ZIP 下载
+    pub zip: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct SharePasswordForm {
+    pub password: String,
+}
+
+/// 保存访问凭据的 Cookie，`Path` 限定在单个分享下
+const UNLOCK_COOKIE: &str = "share_unlock";
+
+/// 把用户给出的相对路径规范化，拒绝 `..`
+pub(super) fn clean_relative_path(path: &str) -> Option<String> {
+    let parts: Vec<&str> = path
+        .split(['/', '\\'])
+        .filter(|p| !p.is_empty() && *p != ".")
+        .collect();
+    if parts.contains(&"..") {
+        return None;
+    }
+    Some(parts.join("/"))
+}
+
+pub async fn create_share(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    audit: Option<Extension<AuditContext>>,
+    Json(payload): Json<CreateShareRequest>,
+) -> Response {
+    if let Some(Extension(audit)) = &audit {
+        audit.set_target(&payload.root, &payload.path);
+    }
+    let Some(root) = state.get_path(&payload.root) else {
+        return (StatusCode::NOT_FOUND, "Root不存在").into_response();
+    };
+    let Some(path) = clean_relative_path(&payload.path) else {
+        return (StatusCode::BAD_REQUEST, "非法路径").into_response();
+    };
+    let full_path = PathBuf::from(&root.path).join(&path);
+
+    if !check_permission(
+        &user.permissions_tree,
+        &full_path.to_string_lossy(),
+        READ_MASK,
+    ) {
+        warn!("用户 '{}' 没有分享 {:?} 的权限", &user.username, full_path);
+        return (StatusCode::FORBIDDEN, "没有读取权限").into_response();
+    }
+    let metadata = match tokio::fs::metadata(&full_path).await {
+        Ok(m) => m,
+        Err(_) => return (StatusCode::NOT_FOUND, "文件不存在").into_response(),
+    };
+    if let Some(ip) = payload.allowed_ips.iter().find(|r| !is_valid_ip_range(r)) {
+        return (StatusCode::BAD_REQUEST, format!("无效的 IP 范围: {}", ip)).into_response();
+    }
+    if payload.expires_in.is_some_and(|s| s <= 0) {
+        return (StatusCode::BAD_REQUEST, "有效期必须大于 0").into_response();
+    }
+
+    let now = Utc::now();
+    let expires_at = match payload.expires_in {
+        Some(seconds) => {
+            match TimeDelta::try_seconds(seconds).and_then(|d| now.checked_add_signed(d)) {
+                Some(expires_at) => Some(expires_at),
+                None => return (StatusCode::BAD_REQUEST, "有效期过长").into_response(),
+            }
+        }
+        None => None,
+    };
+    let share = Share {
+        token: uuid::Uuid::new_v4().simple().to_string(),
+        owner: user.username.clone(),
+        root: payload.root,
+        path,
+        is_dir: metadata.is_dir(),
+        created_at: now,
+        expires_at,
+        password_hash: payload
+            .password
+            .filter(|p| !p.is_empty())
+            .map(|p| hash_password(&p)),
+        max_downloads: payload.max_downloads,
+        downloads: 0,
+        allowed_ips: payload.allowed_ips,
+    };
+    let info = ShareInfo::new(&share, &state.proxy);
+    if let Err(e) = state.shares.insert(share).await {
+        error!("保存分享链接失败: {}", e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "保存分享链接失败").into_response();
+    }
+    info!(
+        "用户 '{}' 创建分享 {}: {}/{}",
+        &user.username, &info.token, &info.root, &info.path
+    );
+    (StatusCode::OK, Json(info)).into_response()
+}
+
+pub async fn list_shares(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+) -> Json<Vec<ShareInfo>> {
+    let shares = state.shares.list_by_owner(&user.username).await;
+    Json(
+        shares
+            .iter()
+            .map(|s| ShareInfo::new(s, &state.proxy))
+            .collect(),
+    )
+}
+
+pub async fn revoke_share(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    UrlPath(token): UrlPath<String>,
+) -> Response {
+    match state.shares.revoke(&token, &user.username).await {
+        Ok(true) => {
+            info!("用户 '{}' 撤销分享 {}", &user.username, &token);
+            (StatusCode::OK, "已撤销").into_response()
+        }
+        Ok(false) => (StatusCode::NOT_FOUND, "分享不存在").into_response(),
+        Err(e) => {
+            error!("保存分享链接失败: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "保存分享链接失败").into_response()
+        }
+    }
+}
+
+pub(super) fn page(status: StatusCode, title: &str, body: &str) -> Response {
+    let html = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{0}</title></head><body><h2>{0}</h2>{1}</body></html>",
+        escape(title),
+        body
+    );
+    (status, Html(html)).into_response()
+}
+
+fn denied_response(denied: ShareDenied) -> Response {
+    match denied {
+        ShareDenied::NotFound => page(StatusCode::NOT_FOUND, "分享不存在", ""),
+        ShareDenied::Expired => page(StatusCode::GONE, "分享已过期", ""),
+        ShareDenied::LimitReached => page(StatusCode::GONE, "分享已达到下载次数上限", ""),
+        ShareDenied::IpNotAllowed => page(StatusCode::FORBIDDEN, "当前网络不允许访问此分享", ""),
+        ShareDenied::PasswordRequired => page(
+            StatusCode::UNAUTHORIZED,
+            "请输入访问密码",
+            "<form method=\"post\"><input type=\"password\" name=\"password\" autofocus> <button type=\"submit\">访问</button></form>",
+        ),
+    }
+}
+
+fn attachment(file_name: &str, content_type: String, body: Body) -> Response {
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_DISPOSITION, content_disposition(file_name)),
+            (header::CONTENT_TYPE, content_type),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// `GET /s/{token}`：无需登录，文件直接下载，文件夹显示列表或打包为 ZIP
+pub async fn public_share(
+    State(state): State<AppState>,
+    UrlPath(token): UrlPath<String>,
+    Query(query): Query<ShareQuery>,
+    client: Client,
+    audit: Option<Extension<AuditContext>>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(share) = state.shares.get(&token).await else {
+        return denied_response(ShareDenied::NotFound);
+    };
+    let unlocked = is_unlocked(&state, &share, &headers).await;
+    if let Err(denied) = share.check_access(client.ip, unlocked) {
+        return denied_response(denied);
+    }
+
+    // 创建者被删除或失去读取权限后，分享随之失效
+    let (Some(owner), Some(root)) = (
+        state.get_user_config(&share.owner),
+        state.get_path(&share.root),
+    ) else {
+        return denied_response(ShareDenied::NotFound);
+    };
+    let base = PathBuf::from(&root.path).join(&share.path);
+    let sub_path = match (share.is_dir, query.path.as_deref()) {
+        (true, Some(p)) => match clean_relative_path(p) {
+            Some(p) => p,
+            None => return (StatusCode::BAD_REQUEST, "非法路径").into_response(),
+        },
+        _ => String::new(),
+    };
+    let target = if sub_path.is_empty() {
+        base.clone()
+    } else {
+        base.join(&sub_path)
+    };
+    if let Some(Extension(audit)) = &audit {
+        let path = if sub_path.is_empty() {
+            share.path.clone()
+        } else {
+            Path::new(&share.path)
+                .join(&sub_path)
+                .to_string_lossy()
+                .into_owned()
+        };
+        audit.set_target(&share.root, &path);
+    }
+    if !check_permission(
+        &owner.permissions_tree,
+        &target.to_string_lossy(),
+        READ_MASK,
+    ) || vfs::ensure_contained(&base, &target).await.is_err()
+    {
+        return denied_response(ShareDenied::NotFound);
+    }
+    let metadata = match tokio::fs::metadata(&target).await {
+        Ok(m) => m,
+        Err(_) => return denied_response(ShareDenied::NotFound),
+    };
+
+    if metadata.is_dir() && !query.zip.unwrap_or(false) {
+        return listing(&state, &share, &target, &sub_path).await;
+    }
+
+    match state.shares.record_download(&share.token).await {
+        Ok(true) => {}
+        Ok(false) => return denied_response(ShareDenied::LimitReached),
+        Err(e) => {
+            error!("保存分享链接失败: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "保存分享链接失败").into_response();
+        }
+    }
+    let name = target
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or("download".to_string());
+    info!("分享 {} 被 {} 下载: {:?}", &share.token, client.ip, &target);
+
+    if metadata.is_dir() {
+        let tree = owner.permissions_tree.clone();
+        let dir = target.clone();
+        let zip_file = match tokio::task::spawn_blocking(move || build_zip(&dir, &tree)).await {
+            Ok(Ok(file)) => file,
+            Ok(Err(e)) => {
+                error!("打包 ZIP 失败: {}", e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, "打包失败").into_response();
+            }
+            Err(e) => {
+                error!("打包 ZIP 任务失败: {}", e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, "打包失败").into_response();
+            }
+        };
+        let mut file = tokio::fs::File::from_std(zip_file);
+        if let Err(e) = file.seek(std::io::SeekFrom::Start(0)).await {
+            error!("读取 ZIP 失败: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "打包失败").into_response();
+        }
+        return attachment(
+            &format!("{}.zip", name),
+            "application/zip".to_string(),
+            Body::from_stream(ReaderStream::new(file)),
+        );
+    }
+
+    match tokio::fs::File::open(&target).await {
+        Ok(file) => attachment(
+            &name,
+            mime_guess::from_path(&target)
+                .first_or_octet_stream()
+                .to_string(),
+            Body::from_stream(ReaderStream::new(file)),
+        ),
+        Err(e) => {
+            error!("读取文件失败: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "读取文件失败").into_response()
+        }
+    }
+}
+
+/// `X-Share-Password` 请求头中的密码正确，或者带有输入密码后得到的 Cookie
+async fn is_unlocked(state: &AppState, share: &Share, headers: &HeaderMap) -> bool {
+    if let Some(password) = headers
+        .get("x-share-password")
+        .and_then(|h| h.to_str().ok())
+    {
+        return share.check_password(password).await;
+    }
+    for id in headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(';'))
+        .filter_map(|c| c.trim().strip_prefix(UNLOCK_COOKIE)?.strip_prefix('='))
+    {
+        if state.shares.is_unlocked(&share.token, id).await {
+            return true;
+        }
+    }
+    false
+}
+
+/// `POST /s/{token}`：密码输入框提交到这里，密码正确时用 Cookie 保存访问凭据，
+/// 再跳转回原来的地址，密码不会出现在链接和访问日志中
+pub async fn unlock_share(
+    State(state): State<AppState>,
+    UrlPath(token): UrlPath<String>,
+    uri: Uri,
+    client: Client,
+    Form(form): Form<SharePasswordForm>,
+) -> Response {
+    let Some(share) = state.shares.get(&token).await else {
+        return denied_response(ShareDenied::NotFound);
+    };
+    let unlocked = share.check_password(&form.password).await;
+    if let Err(denied) = share.check_access(client.ip, unlocked) {
+        if matches!(denied, ShareDenied::PasswordRequired) {
+            warn!("分享 {} 的密码错误: {}", &share.token, client.ip);
+        }
+        return denied_response(denied);
+    }
+
+    let path = state.proxy.url(&format!("/s/{}", share.token));
+    let location = match uri.query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path.clone(),
+    };
+    let cookie = format!(
+        "{}={}; Path={}; Max-Age={}; HttpOnly; SameSite=Lax{}",
+        UNLOCK_COOKIE,
+        state.shares.unlock(&share.token).await,
+        path,
+        SHARE_UNLOCK_SECONDS,
+        if client.https { "; Secure" } else { "" }
+    );
+    (
+        StatusCode::SEE_OTHER,
+        [(header::LOCATION, location), (header::SET_COOKIE, cookie)],
+    )
+        .into_response()
+}
+
+async fn listing(state: &AppState, share: &Share, dir: &Path, sub_path: &str) -> Response {
+    let Some(owner) = state.get_user_config(&share.owner) else {
+        return denied_response(ShareDenied::NotFound);
+    };
+    let mut read_dir = match tokio::fs::read_dir(dir).await {
+        Ok(r) => r,
+        Err(_) => return denied_response(ShareDenied::NotFound),
+    };
+    let mut entries = Vec::new();
+    while let Ok(Some(entry)) = read_dir.next_entry().await {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if is_temp_file(&name)
+            || !check_permission(
+                &owner.permissions_tree,
+                &entry.path().to_string_lossy(),
+                READ_MASK,
+            )
+        {
+            continue;
+        }
+        let Ok(metadata) = entry.metadata().await else {
+            continue;
+        };
+        entries.push((name, metadata.is_dir(), metadata.len()));
+    }
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    let link = |path: &str, zip: bool| {
+        format!(
+            "?path={}{}",
+            utf8_percent_encode(path, NON_ALPHANUMERIC),
+            if zip { "&zip=true" } else { "" }
+        )
+    };
+
+    let mut body = format!(
+        "<p><a href=\"{}\">下载全部（ZIP）</a></p><ul>",
+        escape(link(sub_path, true))
+    );
+    if !sub_path.is_empty() {
+        let parent = sub_path.rsplit_once('/').map(|(p, _)| p).unwrap_or("");
+        body.push_str(&format!(
+            "<li><a href=\"{}\">..</a></li>",
+            escape(link(parent, false))
+        ));
+    }
+    for (name, is_dir, size) in entries {
+        let path = if sub_path.is_empty() {
+            name.clone()
+        } else {
+            format!("{}/{}", sub_path, name)
+        };
+        if is_dir {
+            body.push_str(&format!(
+                "<li><a href=\"{}\">{}/</a></li>",
+                escape(link(&path, false)),
+                escape(&name)
+            ));
+        } else {
+            body.push_str(&format!(
+                "<li><a href=\"{}\">{}</a> ({} 字节)</li>",
+                escape(link(&path, false)),
+                escape(&name),
+                size
+            ));
+        }
+    }
+    body.push_str("</ul>");
+
+    let title = if sub_path.is_empty() {
+        Path::new(&share.path)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or(share.root.clone())
+    } else {
+        sub_path.to_string()
+    };
+    page(StatusCode::OK, &title, &body)
+}
+
+/// 把目录打包到匿名临时文件中，跳过创建者没有读取权限的文件
+fn build_zip(dir: &Path, tree: &crate::model::Path) -> std::io::Result<std::fs::File> {
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Stored)
+        .large_file(true);
+    let mut writer = zip::ZipWriter::new(tempfile::tempfile()?);
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(current) = stack.pop() {
+        for entry in std::fs::read_dir(&current)? {
+            let entry = entry?;
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+            if is_temp_file(&name) || !check_permission(tree, &path.to_string_lossy(), READ_MASK) {
+                continue;
+            }
+            let relative = path
+                .strip_prefix(dir)
+                .map_err(std::io::Error::other)?
+                .to_string_lossy()
+                .replace('\\', "/");
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                writer
+                    .add_directory(relative, options)
+                    .map_err(std::io::Error::other)?;
+                stack.push(path);
+            } else if file_type.is_file() {
+                writer
+                    .start_file(relative, options)
+                    .map_err(std::io::Error::other)?;
+                let mut file = std::fs::File::open(&path)?;
+                std::io::copy(&mut file, &mut writer)?;
+            }
+        }
+    }
+    let mut file = writer.finish().map_err(std::io::Error::other)?;
+    file.flush()?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use axum::{
+        Router,
+        body::to_bytes,
+        extract::{ConnectInfo, Request},
+        routing::get,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+
+    const FILE_NAME: &str = "报告 \"1\".txt";
+
+    async fn app(dir: &Path) -> (AppState, Router) {
+        std::fs::create_dir_all(dir.join("data")).unwrap();
+        std::fs::write(dir.join("data").join(FILE_NAME), "hello").unwrap();
+        let config = format!(
+            r#"
+            [[paths]]
+            name = "data"
+            path = "{}/data"
+            permission = 0b111
+
+            [[users]]
+            username = "alice"
+            password = "x"
+            permissions = [{{ path_name = "data", permission = 0b111 }}]
+            "#,
+            dir.display()
+        );
+        let state = AppState::for_test(&config, dir).await;
+        let router = Router::new()
+            .route("/s/{token}", get(public_share).post(unlock_share))
+            .with_state(state.clone());
+        (state, router)
+    }
+
+    fn share(token: &str) -> Share {
+        Share {
+            token: token.to_string(),
+            owner: "alice".to_string(),
+            root: "data".to_string(),
+            path: FILE_NAME.to_string(),
+            is_dir: false,
+            created_at: Utc::now(),
+            expires_at: None,
+            password_hash: None,
+            max_downloads: None,
+            downloads: 0,
+            allowed_ips: Vec::new(),
+        }
+    }
+
+    async fn send(router: &Router, mut request: Request) -> Response {
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+        router.clone().oneshot(request).await.unwrap()
+    }
+
+    async fn download(router: &Router, token: &str, headers: &[(&str, &str)]) -> Response {
+        let mut request = Request::get(format!("/s/{token}"));
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        send(router, request.body(Body::empty()).unwrap()).await
+    }
+
+    async fn unlock(router: &Router, token: &str, password: &str) -> Response {
+        let request = Request::post(format!("/s/{token}"))
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(format!("password={password}")))
+            .unwrap();
+        send(router, request).await
+    }
+
+    #[tokio::test]
+    async fn downloads_with_escaped_file_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, router) = app(dir.path()).await;
+        state.shares.insert(share("t")).await.unwrap();
+
+        let response = download(&router, "t", &[]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"__ \\\"1\\\".txt\"; filename*=UTF-8''%E6%8A%A5%E5%91%8A%20%221%22.txt"
+        );
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"hello");
+    }
+
+    #[tokio::test]
+    async fn expired_share_is_gone() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, router) = app(dir.path()).await;
+        let mut expired = share("old");
+        expired.expires_at = Some(Utc::now() - TimeDelta::seconds(1));
+        state.shares.insert(expired).await.unwrap();
+        let mut valid = share("new");
+        valid.expires_at = Some(Utc::now() + TimeDelta::hours(1));
+        state.shares.insert(valid).await.unwrap();
+
+        assert_eq!(
+            download(&router, "old", &[]).await.status(),
+            StatusCode::GONE
+        );
+        assert_eq!(download(&router, "new", &[]).await.status(), StatusCode::OK);
+        assert_eq!(
+            download(&router, "missing", &[]).await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn download_limit_is_enforced() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, router) = app(dir.path()).await;
+        let mut limited = share("t");
+        limited.max_downloads = Some(2);
+        state.shares.insert(limited).await.unwrap();
+
+        for _ in 0..2 {
+            assert_eq!(download(&router, "t", &[]).await.status(), StatusCode::OK);
+        }
+        assert_eq!(download(&router, "t", &[]).await.status(), StatusCode::GONE);
+        assert_eq!(state.shares.get("t").await.unwrap().downloads, 2);
+    }
+
+    #[tokio::test]
+    async fn password_unlocks_with_a_cookie() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, router) = app(dir.path()).await;
+        let mut locked = share("t");
+        locked.password_hash = Some(hash_password("secret"));
+        state.shares.insert(locked).await.unwrap();
+
+        assert_eq!(
+            download(&router, "t", &[]).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            unlock(&router, "t", "wrong").await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let response = unlock(&router, "t", "secret").await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()[header::LOCATION], "/s/t");
+        let cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+        assert!(cookie.contains("Path=/s/t;"));
+        assert!(cookie.contains("HttpOnly"));
+        let cookie = cookie.split(';').next().unwrap().to_string();
+
+        let with_cookie = [("cookie", cookie.as_str())];
+        assert_eq!(
+            download(&router, "t", &with_cookie).await.status(),
+            StatusCode::OK
+        );
+        let forged = [("cookie", "share_unlock=forged")];
+        assert_eq!(
+            download(&router, "t", &forged).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+        let header = [("x-share-password", "secret")];
+        assert_eq!(
+            download(&router, "t", &header).await.status(),
+            StatusCode::OK
+        );
+    }
+}
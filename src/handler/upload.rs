@@ -1,20 +1,19 @@
-use crate::{
-    extractors::AuthUser,
-    model::AppState,
-};
+use crate::{audit::AuditContext, extractors::AuthUser, model::AppState, utils::write_upload};
 use axum::{
+    Extension,
     extract::{Multipart, State},
     http::StatusCode,
     response::{IntoResponse, Response},
 };
+use futures_util::TryStreamExt;
 use std::path::PathBuf;
-use tokio::fs::File;
-use tokio::io::AsyncWriteExt; // 引入 write_all_buf
+use tokio_util::io::StreamReader;
 use tracing::{error, info, warn};
 
 pub async fn upload(
     State(state): State<AppState>,
     AuthUser(user): AuthUser,
+    audit: Option<Extension<AuditContext>>,
     mut multipart: Multipart,
 ) -> Response {
     let mut file_name: Option<String> = None;
@@ -23,12 +22,16 @@ pub async fn upload(
     let mut full_path: Option<PathBuf> = None;
 
     // 遍历字段
-    while let Some(mut field) = multipart.next_field().await.unwrap() {
+    while let Some(field) = multipart.next_field().await.unwrap() {
         let name = field.name().unwrap_or("unknown");
         match name {
             "root" => {
-                if let Some(root_config) = state.path.get(&field.text().await.unwrap_or_default()) {
+                let root_name = field.text().await.unwrap_or_default();
+                if let Some(root_config) = state.get_path(&root_name) {
                     root = Some(root_config.path.clone());
+                    if let Some(Extension(audit)) = &audit {
+                        audit.set_root(&root_name);
+                    }
                 } else {
                     return (StatusCode::NOT_FOUND, "Root不存在").into_response();
                 }
@@ -43,51 +46,35 @@ pub async fn upload(
                 file_name = Some(field.file_name().unwrap_or("unknown").to_string());
                 
                 // 在这里我们开始处理文件流
-                let clean_file_name = sanitize_filename::sanitize(&file_name.as_ref().unwrap());
-                let tentative_path = PathBuf::from(&root.as_ref().unwrap())
-                    .join(path.as_ref().unwrap())
-                    .join(clean_file_name);
+                let clean_file_name = sanitize_filename::sanitize(file_name.as_ref().unwrap());
+                let relative_path =
+                    PathBuf::from(path.as_ref().unwrap().trim_start_matches(['/', '\\']))
+                        .join(clean_file_name);
+                let tentative_path = PathBuf::from(&root.as_ref().unwrap()).join(&relative_path);
 
-                // 创建目录
-                if let Some(parent) = tentative_path.parent() {
-                    if let Err(e) = tokio::fs::create_dir_all(parent).await {
-                        error!("Failed to create directory {:?}: {}", parent, e);
-                        return (StatusCode::INTERNAL_SERVER_ERROR, "创建目录失败").into_response();
-                    }
+                // 路径中不允许出现 ..，避免写到根目录之外
+                if path.as_ref().unwrap().split(['/', '\\']).any(|p| p == "..") {
+                    return (StatusCode::BAD_REQUEST, "非法路径").into_response();
                 }
 
-                // 创建文件句柄
-                match File::create(&tentative_path).await {
-                    Ok(mut file) => {
-                        // field 本身是一个 Stream，我们不需要把整个文件读入内存
-                        use futures_util::StreamExt; // 需要 futures 库
-                        
-                        while let Some(chunk_result) = field.next().await {
-                            match chunk_result {
-                                Ok(chunk) => {
-                                    if let Err(e) = file.write_all(&chunk).await {
-                                        error!("写入文件块失败: {}", e);
-                                        return (StatusCode::INTERNAL_SERVER_ERROR, "文件写入失败").into_response();
-                                    }
-                                }
-                                Err(e) => {
-                                    error!("读取数据流失败: {}", e);
-                                    return (StatusCode::INTERNAL_SERVER_ERROR, "读取数据流失败").into_response();
-                                }
-                            }
+                // field 本身是一个 Stream，我们不需要把整个文件读入内存；
+                // 与 FTP 共用同一条写入路径：检查写权限并原子地落盘
+                let mut reader = StreamReader::new(field.map_err(std::io::Error::other));
+                match write_upload(&user.permissions_tree, &tentative_path, &mut reader).await {
+                    Ok(written) => {
+                        if let Some(Extension(audit)) = &audit {
+                            audit.set_path(&relative_path.to_string_lossy());
+                            audit.set_bytes(written);
                         }
-                        
-                        // 确保所有数据刷入磁盘
-                        if let Err(e) = file.flush().await {
-                            error!("Flush 失败: {}", e);
-                            return (StatusCode::INTERNAL_SERVER_ERROR, "Flush 失败").into_response();
-                        }
-                        
                         full_path = Some(tentative_path);
                     }
+                    Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                        warn!("用户 '{}' 没有写入 {:?} 的权限", &user.username, tentative_path);
+                        return (StatusCode::FORBIDDEN, "没有写入权限").into_response();
+                    }
                     Err(e) => {
-                        error!("无法创建文件 {:?}: {}", tentative_path, e);
-                        return (StatusCode::INTERNAL_SERVER_ERROR, "无法创建文件").into_response();
+                        error!("写入文件 {:?} 失败: {}", tentative_path, e);
+                        return (StatusCode::INTERNAL_SERVER_ERROR, "文件写入失败").into_response();
                     }
                 }
             }
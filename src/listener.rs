@@ -0,0 +1,164 @@
+//! 文件服务的 Unix 套接字监听：绑定前删除上次留下的套接字文件，绑定后按配置设置权限和所有者。
+//! Unix 套接字上的客户端没有 IP 地址，记为 `127.0.0.1`，这样处理函数和中间件可以
+//! 统一使用 `ConnectInfo<SocketAddr>`。
+
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr},
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::Path,
+};
+
+use axum::serve::Listener;
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::model::ListenerFromFile;
+
+/// Unix 套接字上的连接对应的客户端地址
+const UNIX_PEER_ADDR: SocketAddr = SocketAddr::new(std::net::IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+
+/// 以 `127.0.0.1` 作为客户端地址的 Unix 套接字监听器
+pub struct UnixSocketListener(UnixListener);
+
+impl Listener for UnixSocketListener {
+    type Io = UnixStream;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        let (stream, _) = Listener::accept(&mut self.0).await;
+        (stream, UNIX_PEER_ADDR)
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(UNIX_PEER_ADDR)
+    }
+}
+
+/// 绑定 Unix 套接字，路径上已有的套接字文件视为上次运行留下的，会先删除；
+/// 已存在的其他类型的文件不会被覆盖
+pub fn bind_unix(path: &Path, config: &ListenerFromFile) -> io::Result<UnixSocketListener> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} 已存在且不是套接字", path.display()),
+            ));
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let listener = UnixListener::bind(path)?;
+
+    if let Some(mode) = config.mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    let owner = config.owner.as_deref().map(uid).transpose()?;
+    let group = config.group.as_deref().map(gid).transpose()?;
+    if owner.is_some() || group.is_some() {
+        std::os::unix::fs::chown(path, owner, group)?;
+    }
+    Ok(UnixSocketListener(listener))
+}
+
+/// 用户名或数字 UID
+fn uid(owner: &str) -> io::Result<u32> {
+    if let Ok(uid) = owner.parse() {
+        return Ok(uid);
+    }
+    match nix::unistd::User::from_name(owner) {
+        Ok(Some(user)) => Ok(user.uid.as_raw()),
+        Ok(None) => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("找不到用户 {}", owner),
+        )),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// 组名或数字 GID
+fn gid(group: &str) -> io::Result<u32> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    match nix::unistd::Group::from_name(group) {
+        Ok(Some(group)) => Ok(group.gid.as_raw()),
+        Ok(None) => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("找不到用户组 {}", group),
+        )),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::MetadataExt;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    fn unix(path: &Path) -> ListenerFromFile {
+        ListenerFromFile {
+            unix: Some(path.display().to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn stale_socket_is_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run").join("sfm.sock");
+        let config = unix(&path);
+        // 上次运行留下的套接字文件
+        drop(bind_unix(&path, &config).unwrap());
+        assert!(path.exists());
+
+        let mut listener = bind_unix(&path, &config).unwrap();
+        let mut client = UnixStream::connect(&path).await.unwrap();
+        let (mut stream, addr) = listener.accept().await;
+        assert_eq!(addr, UNIX_PEER_ADDR);
+        client.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[tokio::test]
+    async fn other_files_are_not_overwritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sfm.sock");
+        std::fs::write(&path, "data").unwrap();
+        let error = bind_unix(&path, &unix(&path)).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "data");
+    }
+
+    #[tokio::test]
+    async fn mode_and_owner_are_applied() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sfm.sock");
+        let metadata = std::fs::metadata(dir.path()).unwrap();
+        let config = ListenerFromFile {
+            mode: Some(0o600),
+            // 改成当前的所有者，不需要额外的权限
+            owner: Some(metadata.uid().to_string()),
+            group: Some(metadata.gid().to_string()),
+            ..unix(&path)
+        };
+        let _listener = bind_unix(&path, &config).unwrap();
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o600);
+
+        let config = ListenerFromFile {
+            owner: Some("no-such-user-for-sfm-tests".to_string()),
+            ..unix(&path)
+        };
+        let error = bind_unix(&path, &config).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+}
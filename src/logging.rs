@@ -0,0 +1,223 @@
+//! 日志：过滤规则、文本或 JSON 格式、日志文件的滚动和保留数量以及是否输出到标准输出都由
+//! `[misc]` 配置，运行时可以通过 `/api/admin/log-level` 修改过滤规则。
+
+use std::{error::Error, path::Path, sync::OnceLock};
+
+use tracing::Subscriber;
+use tracing_appender::{
+    non_blocking::WorkerGuard,
+    rolling::{InitError, RollingFileAppender, Rotation},
+};
+use tracing_subscriber::{
+    EnvFilter, Layer, Registry, fmt::MakeWriter, layer::SubscriberExt, registry::LookupSpan,
+    reload, util::SubscriberInitExt,
+};
+
+use crate::model::{ConfigFromFile, LogFormat, LogRotation};
+
+const DEFAULT_LOG_LEVEL: &str = "info";
+const LOG_FILE_NAME: &str = "app.log";
+
+/// 用于在运行时替换过滤规则
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// 日志文件写入线程的 guard，丢弃时写完缓冲中的日志，需要一直持有到退出
+pub struct LogGuard {
+    _guard: Option<WorkerGuard>,
+}
+
+type BoxedLayer<S> = Box<dyn Layer<S> + Send + Sync>;
+
+fn fmt_layer<S, W>(format: LogFormat, writer: W, ansi: bool) -> BoxedLayer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_target(true)
+        .with_level(true);
+    match format {
+        LogFormat::Text => layer.with_ansi(ansi).boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    }
+}
+
+/// 按滚动周期（默认每天）和保留数量写入 `{dir}/{prefix}.{日期}` 的日志文件，
+/// 应用日志、审计日志和访问日志共用
+pub fn rolling_appender(
+    dir: impl AsRef<Path>,
+    prefix: &str,
+    rotation: Option<LogRotation>,
+    max_files: Option<usize>,
+) -> Result<RollingFileAppender, InitError> {
+    let rotation = match rotation.unwrap_or(LogRotation::Daily) {
+        LogRotation::Minutely => Rotation::MINUTELY,
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Never => Rotation::NEVER,
+    };
+    let mut builder = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(prefix);
+    if let Some(max_files) = max_files {
+        builder = builder.max_log_files(max_files);
+    }
+    builder.build(dir)
+}
+
+/// misc.log_level 优先，其次是 RUST_LOG，默认 info
+fn env_filter(config: &ConfigFromFile) -> Result<EnvFilter, Box<dyn Error>> {
+    match config.misc.as_ref().and_then(|m| m.log_level.as_deref()) {
+        Some(level) => Ok(EnvFilter::try_new(level)?),
+        None => {
+            Ok(EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_LEVEL)))
+        }
+    }
+}
+
+/// 按配置创建日志文件和标准输出的输出层
+fn output_layers<S>(config: &ConfigFromFile) -> Result<(Vec<BoxedLayer<S>>, LogGuard), InitError>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let misc = config.misc.as_ref();
+    let format = misc.and_then(|m| m.log_format).unwrap_or(LogFormat::Text);
+    let mut layers = Vec::new();
+    let mut guard = None;
+
+    // 文件层 - 不使用颜色格式
+    if misc.and_then(|m| m.log_file).unwrap_or(true) {
+        let file_appender = rolling_appender(
+            config.log_dir(),
+            LOG_FILE_NAME,
+            misc.and_then(|m| m.log_rotation),
+            misc.and_then(|m| m.log_max_files),
+        )?;
+        let (non_blocking_file, file_guard) = tracing_appender::non_blocking(file_appender);
+        guard = Some(file_guard);
+        layers.push(fmt_layer(format, non_blocking_file, false));
+    }
+
+    // 控制台层
+    if misc.and_then(|m| m.log_stdout).unwrap_or(true) {
+        layers.push(fmt_layer(format, std::io::stdout, true));
+    }
+    Ok((layers, LogGuard { _guard: guard }))
+}
+
+/// 按配置初始化全局日志
+pub fn init(config: &ConfigFromFile) -> Result<LogGuard, Box<dyn Error>> {
+    let (filter, handle) = reload::Layer::new(env_filter(config)?);
+    let (layers, guard) = output_layers(config)?;
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(layers)
+        .try_init()?;
+    let _ = FILTER.set(handle);
+    Ok(guard)
+}
+
+/// 当前的过滤规则
+pub fn current_filter() -> Option<String> {
+    FILTER.get()?.with_current(|f| f.to_string()).ok()
+}
+
+/// 在运行时替换过滤规则，例如 `debug` 或 `info,simple_file_manager::s3=trace`
+pub fn set_filter(directives: &str) -> Result<(), String> {
+    let filter = EnvFilter::try_new(directives).map_err(|e| e.to_string())?;
+    FILTER
+        .get()
+        .ok_or("日志尚未初始化")?
+        .reload(filter)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::info;
+
+    use super::*;
+
+    fn config(dir: &Path, misc: &str) -> ConfigFromFile {
+        toml::from_str(&format!(
+            "users = []\npaths = []\n[misc]\nlog_dir = {:?}\nlog_stdout = false\n{}",
+            dir.display().to_string(),
+            misc
+        ))
+        .unwrap()
+    }
+
+    /// 用按配置创建的输出层写一条日志，返回日志目录中的文件名和内容
+    fn log_once(config: &ConfigFromFile) -> Vec<(String, String)> {
+        let (layers, guard) = output_layers(config).unwrap();
+        let subscriber = tracing_subscriber::registry().with(layers);
+        tracing::subscriber::with_default(subscriber, || info!(user = "alice", "登录成功"));
+        // 丢弃 guard 时写完缓冲中的日志
+        drop(guard);
+        let mut files: Vec<_> = std::fs::read_dir(config.log_dir())
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .map(|p| {
+                let name = p.file_name().unwrap().to_string_lossy().into_owned();
+                (name, std::fs::read_to_string(p).unwrap())
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn log_level_takes_precedence_and_is_validated() {
+        let dir = tempfile::tempdir().unwrap();
+        let filter = env_filter(&config(
+            dir.path(),
+            "log_level = \"warn,simple_file_manager=debug\"",
+        ));
+        assert_eq!(
+            filter.unwrap().to_string(),
+            "simple_file_manager=debug,warn"
+        );
+        assert!(env_filter(&config(dir.path(), "log_level = \"=\"")).is_err());
+    }
+
+    #[test]
+    fn text_logs_are_written_without_colors() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = log_once(&config(dir.path(), "log_rotation = \"never\""));
+        assert_eq!(files.len(), 1);
+        let (name, content) = &files[0];
+        assert_eq!(name, LOG_FILE_NAME);
+        assert!(content.contains("INFO"), "{}", content);
+        assert!(content.contains("登录成功 user=\"alice\""), "{}", content);
+        assert!(!content.contains('\x1b'), "{}", content);
+    }
+
+    #[test]
+    fn json_logs_have_one_object_per_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = log_once(&config(dir.path(), "log_format = \"json\""));
+        let (name, content) = &files[0];
+        assert!(name.starts_with(&format!("{}.", LOG_FILE_NAME)), "{}", name);
+        let line: serde_json::Value = serde_json::from_str(content.trim()).unwrap();
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["fields"]["message"], "登录成功");
+        assert_eq!(line["fields"]["user"], "alice");
+        assert_eq!(line["target"], module_path!());
+    }
+
+    #[test]
+    fn log_file_can_be_disabled() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config(dir.path(), "log_file = false");
+        let (layers, _guard) = output_layers::<Registry>(&config).unwrap();
+        assert!(layers.is_empty());
+        assert!(!dir.path().join(LOG_FILE_NAME).exists());
+    }
+
+    #[test]
+    fn invalid_runtime_filters_are_rejected() {
+        assert!(set_filter("=").is_err());
+    }
+}
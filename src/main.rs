@@ -1,172 +1,391 @@
+mod access_log;
+mod audit;
+mod cli;
+mod compression;
+mod config_reload;
+mod cors;
 mod extractors;
+mod ftp;
 mod handler;
+#[cfg(unix)]
+mod listener;
+mod logging;
 mod model;
+mod proxy;
 mod router;
+mod s3;
+mod security_headers;
+mod sftp;
+mod shutdown;
+mod static_files;
+mod telemetry;
+mod tls;
 mod utils;
+mod vfs;
 
+use axum::Router;
+use axum::http::header;
+use clap::Parser;
+use model::{AppState, ConfigFromFile, ListenAddress, ListenerFromFile};
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
 use std::sync::Arc;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tower_http::set_header::SetResponseHeaderLayer;
 use tracing::{error, info};
-use tracing_subscriber::fmt::format::FmtSpan;
-use tracing_subscriber::layer::SubscriberExt;
-use tracing_subscriber::util::SubscriberInitExt;
-
-// 全局变量保存 tracing 的 guard，防止其被丢弃
-static mut LOG_GUARD: Option<tracing_appender::non_blocking::WorkerGuard> = None;
 
 #[tokio::main]
-async fn main() {
-    before_start();
+async fn main() -> ExitCode {
+    let cli = cli::Cli::parse();
+    if let Some(command) = cli
+        .command
+        .as_ref()
+        .filter(|c| !matches!(c, cli::Command::Serve))
+    {
+        std::process::exit(cli::run(&cli, command).await);
+    }
 
-    // 加载配置
-    let config = match model::ConfigFromFile::from_toml("config.toml").await {
-        Ok(c) => Arc::new(c),
+    // 加载配置，日志目录和级别来自配置，所以先读取配置再初始化日志
+    let overrides = cli.overrides.to_misc();
+    let (config, issues) = match model::ConfigFromFile::check(&cli.config, &overrides).await {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("配置文件加载失败: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    // 持有到退出，保证日志写入文件
+    let _log_guard = match logging::init(&config) {
+        Ok(guard) => guard,
         Err(e) => {
-            error!("配置文件加载失败: {}", e);
-            std::process::exit(1);
+            eprintln!("日志初始化失败: {}", e);
+            return ExitCode::FAILURE;
         }
     };
+    if let Err(e) = model::ConfigFromFile::check_issues(&cli.config, issues) {
+        error!("配置文件加载失败: {}", e);
+        return ExitCode::FAILURE;
+    }
+    if let Err(e) = telemetry::init(&config) {
+        error!("指标初始化失败: {}", e);
+        return ExitCode::FAILURE;
+    }
+    let config = Arc::new(config);
 
     let state = model::AppState::new_form_config(config.as_ref()).await;
-    let app = router::create_router(state, config.as_ref());
-
-    let http_port = config
-        .as_ref()
-        .misc
-        .as_ref()
-        .map(|e| e.port.clone())
-        .unwrap()
-        .unwrap();
-
-    let host = config
-        .as_ref()
-        .misc
-        .as_ref()
-        .map(|e| e.host.clone())
-        .unwrap()
-        .unwrap();
+    // 修改用户和路径后不需要重启
+    config_reload::spawn_config_reloader(
+        std::path::Path::new(&cli.config),
+        overrides,
+        state.clone(),
+        config.as_ref().clone(),
+    );
+    let app = router::create_router(state.clone(), config.as_ref());
 
-    let http_addr = SocketAddr::new(host.parse().unwrap(), http_port);
+    // 各个服务在收到退出信号后停止接受新连接，等进行中的请求完成后结束
+    let shutdown = shutdown::listen();
+    let mut servers = JoinSet::new();
 
-    // 检查是否有证书文件，如果有则启动 HTTPS 服务器
-    if std::path::Path::new("certs/cert.pem").exists()
-        && std::path::Path::new("certs/key.pem").exists()
+    let mut unix_sockets = Vec::new();
+    // 启动失败或某个服务出错时通知其他服务退出，仍然走完下面的退出流程
+    let mut failed = false;
+    if let Err(e) = start_servers(
+        &config,
+        &state,
+        app,
+        &shutdown,
+        &mut servers,
+        &mut unix_sockets,
+    )
+    .await
     {
-        info!("检测到证书文件，启动 HTTPS 服务器...");
-
-        // // 创建 TLS 配置
-        // let rustls_config =
-        //     axum_server::tls_rustls::RustlsConfig::from_pem_file("certs/cert.pem", "certs/key.pem")
-        //         .await
-        //         .expect("无法加载 TLS 证书/私钥");
-
-        // // 启动 HTTPS 服务器
-        // info!("HTTPS 服务器运行在 https://{}", https_addr);
-        // let https_listener = match tokio::net::TcpListener::bind(&https_addr).await {
-        //     Ok(l) => l,
-        //     Err(e) => {
-        //         error!("HTTPS 端口绑定失败: {}", e);
-        //         std::process::exit(1);
-        //     }
-        // };
-
-        // let https_server = axum_server::from_tcp_rustls(
-        //     https_listener.into_std().expect("转换 TcpListener 失败"),
-        //     rustls_config,
-        // )
-        // .serve(app.clone().into_make_service());
-
-        // // 同时启动 HTTP 服务器作为备选
-        // info!("HTTP 服务器运行在 http://{}", http_addr);
-        // let http_listener = match tokio::net::TcpListener::bind(&http_addr).await {
-        //     Ok(l) => l,
-        //     Err(e) => {
-        //         error!("HTTP 端口绑定失败: {}", e);
-        //         std::process::exit(1);
-        //     }
-        // };
-
-        // let http_server = axum::serve(http_listener, app.into_make_service());
-
-        // // 并行运行 HTTP 和 HTTPS 服务器
-        // tokio::select! {
-        //     result = https_server => {
-        //         if let Err(e) = result {
-        //             error!("HTTPS 服务器错误: {}", e);
-        //             std::process::exit(1);
-        //         }
-        //     },
-        //     result = http_server => {
-        //         if let Err(e) = result {
-        //             error!("HTTP 服务器错误: {}", e);
-        //             std::process::exit(1);
-        //         }
-        //     }
-        // }
-    } else {
-        info!("未找到证书文件，仅启动 HTTP 服务器");
-        info!("服务器运行在 http://{}", http_addr);
-        let listener = match tokio::net::TcpListener::bind(&http_addr).await {
-            Ok(l) => l,
-            Err(e) => {
-                error!("端口绑定失败: {}", e);
-                std::process::exit(1);
+        error!("{}", e);
+        failed = true;
+        shutdown.cancel();
+    }
+    shutdown::drain(
+        async {
+            while let Some(result) = servers.join_next().await {
+                if let Ok(Err(e)) = result {
+                    error!("{}", e);
+                    failed = true;
+                    shutdown.cancel();
+                }
             }
-        };
-
-        if let Err(e) = axum::serve(listener, app).await {
-            error!("服务器错误: {}", e);
-            std::process::exit(1);
-        }
+        },
+        &shutdown,
+        config.shutdown_timeout(),
+    )
+    .await;
+    match state.save_sessions(config.as_ref()).await {
+        Ok(count) => info!("已保存 {} 个登录会话", count),
+        Err(e) => error!("保存登录会话失败: {}", e),
+    }
+    for path in unix_sockets {
+        let _ = std::fs::remove_file(path);
+    }
+    // 返回后日志、审计日志和访问日志的写入线程会写完缓冲的内容
+    info!("服务已退出");
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
     }
 }
 
-fn before_start() {
-    init_tracing();
-}
+/// 按配置启动各个服务，绑定失败时返回错误，已启动的服务由调用方负责停止
+async fn start_servers(
+    config: &Arc<ConfigFromFile>,
+    state: &AppState,
+    app: Router,
+    shutdown: &CancellationToken,
+    servers: &mut JoinSet<Result<(), String>>,
+    unix_sockets: &mut Vec<PathBuf>,
+) -> Result<(), String> {
+    // 监听地址已在加载配置时校验过
+    let host = config.host().to_string();
 
-fn init_tracing() {
-    // 创建日志目录
-    let log_dir = "log";
-    if !std::path::Path::new(log_dir).exists() {
-        std::fs::create_dir(log_dir).expect("创建日志目录失败");
+    // 启用时在独立端口上运行 S3 兼容网关
+    if let Some(s3_config) = config.s3.as_ref().filter(|s| s.enable) {
+        let s3_app = s3::create_router(state.clone(), s3_config)
+            .await
+            .map_err(|e| format!("S3 网关初始化失败: {}", e))?;
+        let s3_host = s3_config.host.clone().unwrap_or(host.clone());
+        let s3_addr = SocketAddr::new(
+            s3_host.parse().unwrap(),
+            s3_config.port.unwrap_or(s3::DEFAULT_PORT),
+        );
+        let s3_listener = tokio::net::TcpListener::bind(&s3_addr)
+            .await
+            .map_err(|e| format!("S3 网关端口绑定失败: {}", e))?;
+        info!("S3 网关运行在 http://{}", s3_addr);
+        let shutdown = shutdown.clone();
+        servers.spawn(async move {
+            axum::serve(
+                s3_listener,
+                s3_app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(shutdown.cancelled_owned())
+            .await
+            .map_err(|e| format!("S3 网关错误: {}", e))
+        });
     }
 
-    // 文件层 - 不使用颜色格式
-    let file_appender = tracing_appender::rolling::RollingFileAppender::new(
-        tracing_appender::rolling::Rotation::DAILY,
-        log_dir,
-        "app.log",
-    );
-    let (non_blocking_file, guard) = tracing_appender::non_blocking(file_appender);
+    // 设置了端口时在独立端口上提供指标，不经过文件服务的路由
+    if let Some(metrics_config) = config.metrics.as_ref().filter(|m| m.enable)
+        && let Some(port) = metrics_config.port
+    {
+        let metrics_host = metrics_config.host.clone().unwrap_or(host.clone());
+        let metrics_addr = SocketAddr::new(metrics_host.parse().unwrap(), port);
+        let metrics_listener = tokio::net::TcpListener::bind(&metrics_addr)
+            .await
+            .map_err(|e| format!("指标端口绑定失败: {}", e))?;
+        let metrics_app = telemetry::router(state.clone(), metrics_config);
+        info!(
+            "指标运行在 http://{}{}",
+            metrics_addr,
+            telemetry::METRICS_PATH
+        );
+        let shutdown = shutdown.clone();
+        servers.spawn(async move {
+            axum::serve(metrics_listener, metrics_app)
+                .with_graceful_shutdown(shutdown.cancelled_owned())
+                .await
+                .map_err(|e| format!("指标服务错误: {}", e))
+        });
+    }
+
+    // 启用时在独立端口上运行 SFTP 服务
+    if let Some(sftp_config) = config.sftp.as_ref().filter(|s| s.enable) {
+        let sftp_server = sftp::SftpServer::new(state.clone(), sftp_config)
+            .await
+            .map_err(|e| format!("SFTP 服务初始化失败: {}", e))?;
+        let sftp_host = sftp_config.host.clone().unwrap_or(host.clone());
+        let sftp_addr = SocketAddr::new(
+            sftp_host.parse().unwrap(),
+            sftp_config.port.unwrap_or(sftp::DEFAULT_PORT),
+        );
+        let sftp_listener = tokio::net::TcpListener::bind(&sftp_addr)
+            .await
+            .map_err(|e| format!("SFTP 端口绑定失败: {}", e))?;
+        info!("SFTP 服务运行在 sftp://{}", sftp_addr);
+        let shutdown = shutdown.clone();
+        servers.spawn(async move {
+            sftp_server
+                .serve(sftp_listener, shutdown)
+                .await
+                .map_err(|e| format!("SFTP 服务错误: {}", e))
+        });
+    }
+
+    // 启用 ACME 时证书由后台任务申请，没有证书时先生成临时证书，HTTPS 和 FTPS 都会使用它
+    tls::prepare_acme(config.as_ref()).map_err(|e| format!("ACME 初始化失败: {}", e))?;
 
-    // 将 guard 存储到全局变量中，防止其被丢弃
-    unsafe {
-        LOG_GUARD = Some(guard);
+    // 未设置 listeners 且未配置 enable_https 时，有证书文件就启动 HTTPS 服务器
+    let (cert_file, key_file) = config.tls_files();
+    let certs_exist = cert_file.exists() && key_file.exists();
+    let misc = config.misc.as_ref();
+    let listeners = config.listeners(misc.and_then(|m| m.enable_https).unwrap_or(certs_exist));
+    let enable_https = listeners.iter().any(|l| l.tls());
+    if enable_https && !certs_exist {
+        return Err(format!(
+            "已启用 HTTPS，但找不到证书文件 {} 或私钥文件 {}",
+            cert_file.display(),
+            key_file.display()
+        ));
     }
+    // 监听地址已在加载配置时校验过
+    let listeners: Vec<(ListenAddress, &ListenerFromFile)> = listeners
+        .iter()
+        .map(|l| (l.listen_address().unwrap(), l))
+        .collect();
 
-    let file_layer = tracing_subscriber::fmt::layer()
-        .with_writer(non_blocking_file)
-        .with_span_events(FmtSpan::CLOSE)
-        .with_target(true)
-        .with_level(true)
-        .with_ansi(false);
-
-    // 控制台层 - 可以选择是否显示颜色
-    let stdout_layer = tracing_subscriber::fmt::layer()
-        .with_writer(std::io::stdout)
-        .with_span_events(FmtSpan::CLOSE)
-        .with_target(true)
-        .with_level(true)
-        .with_ansi(true);
-
-    // 组合两个层
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+    // HTTPS 和 FTPS 共用证书，热重载对两者同时生效，ACME 只在启用 HTTPS 时运行
+    let ftp_config = config.ftp.as_ref().filter(|f| f.enable);
+    let rustls_config = if enable_https || (ftp_config.is_some() && certs_exist) {
+        let rustls_config = tls::rustls_config(config.as_ref())
+            .map_err(|e| format!("无法加载 TLS 证书/私钥: {}", e))?;
+        tls::spawn_reloader(config.clone(), rustls_config.clone());
+        if enable_https {
+            tls::spawn_acme(config.clone(), rustls_config.clone());
+        }
+        Some(rustls_config)
+    } else {
+        None
+    };
+
+    // 启用时在独立端口上运行 FTP 服务，证书存在时支持 AUTH TLS
+    if let Some(ftp_config) = ftp_config {
+        let ftp_server = ftp::create_server(
+            state.clone(),
+            config.as_ref(),
+            ftp_config,
+            rustls_config.as_ref(),
+            shutdown.clone(),
         )
-        .with(stdout_layer)
-        .with(file_layer)
-        .init();
+        .map_err(|e| format!("FTP 服务初始化失败: {}", e))?;
+        let ftp_host = ftp_config.host.clone().unwrap_or(host.clone());
+        let ftp_addr = SocketAddr::new(
+            ftp_host.parse().unwrap(),
+            ftp_config.port.unwrap_or(ftp::DEFAULT_PORT),
+        );
+        info!("FTP 服务运行在 ftp://{}", ftp_addr);
+        servers.spawn(async move {
+            ftp_server
+                .listen(ftp_addr.to_string())
+                .await
+                .map_err(|e| format!("FTP 服务错误: {}", e))
+        });
+    }
+
+    let https = if let Some(rustls_config) = rustls_config.filter(|_| enable_https) {
+        info!("检测到证书文件，启动 HTTPS 服务器...");
+        let mut https_app = app.clone();
+        if let Some(hsts) = misc.and_then(tls::hsts_header) {
+            info!("HTTPS 响应添加 Strict-Transport-Security: {:?}", hsts);
+            https_app = https_app.layer(SetResponseHeaderLayer::overriding(
+                header::STRICT_TRANSPORT_SECURITY,
+                hsts,
+            ));
+        }
+        Some((rustls_config, https_app))
+    } else {
+        info!("未启用 HTTPS，仅启动 HTTP 服务器");
+        None
+    };
+    // 启用 HTTPS 时，HTTP 地址按 http_mode 提供完整服务、重定向到 HTTPS 或只提供 ACME 验证
+    let http_app = if enable_https {
+        let https_port = listeners
+            .iter()
+            .find_map(|(address, l)| match address {
+                ListenAddress::Tcp(addr) if l.tls() => Some(addr.port()),
+                _ => None,
+            })
+            .unwrap_or(tls::DEFAULT_HTTPS_PORT);
+        tls::http_router(misc, https_port, app)
+    } else {
+        app
+    };
+
+    for (address, listener) in listeners {
+        match address {
+            ListenAddress::Tcp(addr) if listener.tls() => {
+                let (rustls_config, https_app) = https.as_ref().expect("有 tls 地址时已加载证书");
+                let https_listener = tokio::net::TcpListener::bind(&addr)
+                    .await
+                    .and_then(|l| l.into_std())
+                    .map_err(|e| format!("HTTPS 端口 {} 绑定失败: {}", addr, e))?;
+                // 握手后读取客户端证书，供 AuthUser 使用
+                let acceptor = tls::ClientCertAcceptor::new(
+                    axum_server::tls_rustls::RustlsAcceptor::new(rustls_config.clone()),
+                );
+                let https_handle = axum_server::Handle::new();
+                let https_server = axum_server::from_tcp(https_listener)
+                    .map_err(|e| format!("HTTPS 服务器初始化失败: {}", e))?
+                    .acceptor(acceptor)
+                    .handle(https_handle.clone());
+                let token = shutdown.clone();
+                tokio::spawn(async move {
+                    token.cancelled().await;
+                    // 超时由 shutdown::drain 控制
+                    https_handle.graceful_shutdown(None);
+                });
+                info!("HTTPS 服务器运行在 https://{}", addr);
+                let https_server = https_server.serve(
+                    https_app
+                        .clone()
+                        .into_make_service_with_connect_info::<SocketAddr>(),
+                );
+                servers.spawn(async move {
+                    https_server
+                        .await
+                        .map_err(|e| format!("HTTPS 服务器错误: {}", e))
+                });
+            }
+            ListenAddress::Tcp(addr) => {
+                let http_listener = tokio::net::TcpListener::bind(&addr)
+                    .await
+                    .map_err(|e| format!("端口 {} 绑定失败: {}", addr, e))?;
+                info!("HTTP 服务器运行在 http://{}", addr);
+                let http_server = axum::serve(
+                    http_listener,
+                    http_app
+                        .clone()
+                        .into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .with_graceful_shutdown(shutdown.clone().cancelled_owned());
+                servers.spawn(async move {
+                    http_server
+                        .await
+                        .map_err(|e| format!("HTTP 服务器错误: {}", e))
+                });
+            }
+            #[cfg(unix)]
+            ListenAddress::Unix(path) => {
+                use axum::serve::ListenerExt;
+                let unix_listener = listener::bind_unix(&path, listener)
+                    .map_err(|e| format!("Unix 套接字 {} 绑定失败: {}", path.display(), e))?;
+                info!("HTTP 服务器运行在 unix:{}", path.display());
+                // 经过 tap_io 才能取得 ConnectInfo<SocketAddr>
+                let http_server = axum::serve(
+                    unix_listener.tap_io(|_| {}),
+                    http_app
+                        .clone()
+                        .into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .with_graceful_shutdown(shutdown.clone().cancelled_owned());
+                servers.spawn(async move {
+                    http_server
+                        .await
+                        .map_err(|e| format!("HTTP 服务器错误: {}", e))
+                });
+                unix_sockets.push(path);
+            }
+            #[cfg(not(unix))]
+            ListenAddress::Unix(_) => unreachable!("只在 Unix 平台上允许配置 Unix 套接字"),
+        }
+    }
+    Ok(())
 }
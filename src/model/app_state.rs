@@ -1,14 +1,33 @@
-use crate::model::{Config, ConfigFromFile, Path, UserConfig};
-use std::{
-    collections::{BTreeMap, HashMap},
-    sync::Arc,
+use crate::access_log::AccessLog;
+use crate::audit::Audit;
+use crate::model::{
+    AccessKeyFromFile, Config, ConfigFromFile, Path, UserConfig,
+    file_request::{DEFAULT_FILE_REQUEST_STORE, FileRequestStore},
+    share::{DEFAULT_SHARE_STORE, ShareStore, load_json_list},
 };
+use crate::proxy::Proxy;
+use crate::utils::AtomicFile;
+use arc_swap::ArcSwap;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc};
+
+/// 退出时保存的网页登录会话
+#[derive(Serialize, Deserialize)]
+struct StoredSession {
+    token: String,
+    username: String,
+}
 
 #[derive(Clone)]
 pub struct AppState {
-    pub path: Arc<BTreeMap<String, Path>>,
-    pub user_config: Arc<BTreeMap<String, UserConfig>>,
+    /// 用户和 `[[paths]]`，重新加载配置时整体替换，读取时拿到的是同一份快照
+    pub config: Arc<ArcSwap<Config>>,
     pub user_sessions: Arc<tokio::sync::Mutex<HashMap<String, String>>>,
+    pub shares: ShareStore,
+    pub file_requests: FileRequestStore,
+    pub audit: Audit,
+    pub access_log: AccessLog,
+    pub proxy: Proxy,
 }
 
 impl AsRef<AppState> for AppState {
@@ -19,34 +38,128 @@ impl AsRef<AppState> for AppState {
 
 impl AppState {
     pub async fn new_form_config(config_from_file: &ConfigFromFile) -> Self {
-        let config = Config::from_config_file(config_from_file)
-            .await
-            .unwrap();
+        let config = match Config::from_config_file(config_from_file).await {
+            Ok(config) => config,
+            Err(e) => {
+                tracing::error!("配置无效: {}", e);
+                std::process::exit(1);
+            }
+        };
+
+        let share_store = config_from_file
+            .share
+            .as_ref()
+            .and_then(|s| s.store_path.clone())
+            .unwrap_or(DEFAULT_SHARE_STORE.to_string());
+        let shares = match ShareStore::load(std::path::Path::new(&share_store)).await {
+            Ok(shares) => shares,
+            Err(e) => {
+                tracing::error!("分享链接加载失败 {}: {}", share_store, e);
+                std::process::exit(1);
+            }
+        };
+        let request_store = config_from_file
+            .share
+            .as_ref()
+            .and_then(|s| s.request_store_path.clone())
+            .unwrap_or(DEFAULT_FILE_REQUEST_STORE.to_string());
+        let file_requests = match FileRequestStore::load(std::path::Path::new(&request_store)).await
+        {
+            Ok(requests) => requests,
+            Err(e) => {
+                tracing::error!("文件收集链接加载失败 {}: {}", request_store, e);
+                std::process::exit(1);
+            }
+        };
+
+        let audit = match Audit::open(config_from_file) {
+            Ok(audit) => audit,
+            Err(e) => {
+                tracing::error!("审计日志打开失败: {}", e);
+                std::process::exit(1);
+            }
+        };
+
+        let access_log = match AccessLog::open(config_from_file) {
+            Ok(access_log) => access_log,
+            Err(e) => {
+                tracing::error!("访问日志打开失败: {}", e);
+                std::process::exit(1);
+            }
+        };
+
+        let user_sessions = load_sessions(config_from_file, &config).await;
 
         let app_state  = AppState {
-            path: Arc::new(config.paths),
-            user_config: Arc::new(config.users),
-            user_sessions: Arc::new(tokio::sync::Mutex::new(HashMap::new())),
+            config: Arc::new(ArcSwap::from_pointee(config)),
+            user_sessions: Arc::new(tokio::sync::Mutex::new(user_sessions)),
+            shares,
+            file_requests,
+            audit,
+            access_log,
+            proxy: Proxy::new(config_from_file.misc.as_ref()),
         };
 
         if let Some(debug) = &config_from_file.debug
             && debug.enable
+            && let Some(debug_session) = &debug.debug_session
         {
-            if let Some(debug_session) = &debug.debug_session
-            {
-                let username = debug_session.username.clone();
-                let session = debug_session.token.clone();
-                app_state.add_session(session, username).await;
-            }
+            let username = debug_session.username.clone();
+            let session = debug_session.token.clone();
+            app_state.add_session(session, username).await;
         }
         app_state
     }
 
-    pub fn get_user_config(&self, username: &str) -> Option<&UserConfig> {
-        self.user_config.get(username)
+    /// 当前配置的快照
+    pub fn config(&self) -> Arc<Config> {
+        self.config.load_full()
+    }
+
+    /// 替换用户和 `[[paths]]`，被删除的用户的会话随之失效，其余会话保持不变
+    pub async fn replace_config(&self, config: Config) {
+        let mut sessions = self.user_sessions.lock().await;
+        sessions.retain(|_, username| config.users.contains_key(username));
+        self.config.store(Arc::new(config));
+    }
+
+    pub fn get_user_config(&self, username: &str) -> Option<UserConfig> {
+        self.config.load().users.get(username).cloned()
+    }
+
+    pub fn get_path(&self, name: &str) -> Option<Path> {
+        self.config.load().paths.get(name).cloned()
     }
 
-    pub async fn get_user_by_token(&self, session_token: &str) -> Option<&UserConfig> {
+    pub fn get_user_by_access_key(
+        &self,
+        access_key_id: &str,
+    ) -> Option<(UserConfig, AccessKeyFromFile)> {
+        self.config.load().users.values().find_map(|u| {
+            u.access_keys
+                .iter()
+                .find(|k| k.access_key_id == access_key_id)
+                .map(|k| (u.clone(), k.clone()))
+        })
+    }
+
+    /// 按客户端证书中的名称查找用户：优先匹配 `client_cert_names`，未配置时匹配用户名
+    pub fn get_user_by_cert_names(&self, names: &[String]) -> Option<UserConfig> {
+        self.config
+            .load()
+            .users
+            .values()
+            .find(|u| {
+                if u.client_cert_names.is_empty() {
+                    names.contains(&u.username)
+                } else {
+                    u.client_cert_names.iter().any(|n| names.contains(n))
+                }
+            })
+            .cloned()
+    }
+
+    pub async fn get_user_by_token(&self, session_token: &str) -> Option<UserConfig> {
         self.get_user_config(self.get_username_by_session(session_token).await?.as_str())
     }
 
@@ -54,10 +167,106 @@ impl AppState {
         self.user_sessions.lock().await.get(session_token).cloned()
     }
 
+    /// 删除会话，返回其用户名
+    pub async fn remove_session(&self, session_token: &str) -> Option<String> {
+        self.user_sessions.lock().await.remove(session_token)
+    }
+
     pub async fn add_session(& self, session_token: String, username: String) {
         self.user_sessions
             .lock()
             .await
             .insert(session_token, username);
     }
+
+    /// 把会话保存到 `misc.session_store`，调试会话除外，返回保存的数量
+    pub async fn save_sessions(&self, config_from_file: &ConfigFromFile) -> std::io::Result<usize> {
+        let debug_token = config_from_file
+            .debug
+            .as_ref()
+            .and_then(|d| d.debug_session.as_ref())
+            .map(|s| s.token.as_str());
+        let sessions: Vec<StoredSession> = self
+            .user_sessions
+            .lock()
+            .await
+            .iter()
+            .filter(|(token, _)| Some(token.as_str()) != debug_token)
+            .map(|(token, username)| StoredSession {
+                token: token.clone(),
+                username: username.clone(),
+            })
+            .collect();
+        let data = serde_json::to_vec_pretty(&sessions).map_err(std::io::Error::other)?;
+        let mut file =
+            AtomicFile::create(std::path::Path::new(config_from_file.session_store())).await?;
+        // 文件中是可以直接使用的会话 token
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.as_file_mut()
+                .set_permissions(std::fs::Permissions::from_mode(0o600))
+                .await?;
+        }
+        file.write_all(&data).await?;
+        file.commit().await?;
+        Ok(sessions.len())
+    }
+}
+
+/// 恢复上次退出时保存的会话，丢弃已不存在的用户的会话。读取后删除文件，
+/// 这样异常退出后不会恢复在这之后已经退出登录的会话
+async fn load_sessions(
+    config_from_file: &ConfigFromFile,
+    config: &Config,
+) -> HashMap<String, String> {
+    let file = std::path::Path::new(config_from_file.session_store());
+    let sessions = match load_json_list::<StoredSession>(file).await {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            tracing::warn!("会话恢复失败 {}: {}", file.display(), e);
+            return HashMap::new();
+        }
+    };
+    if let Err(e) = tokio::fs::remove_file(file).await
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        tracing::warn!("删除会话文件失败 {}: {}", file.display(), e);
+    }
+    let sessions: HashMap<String, String> = sessions
+        .into_iter()
+        .filter(|s| config.users.contains_key(&s.username))
+        .map(|s| (s.token, s.username))
+        .collect();
+    if !sessions.is_empty() {
+        tracing::info!("已恢复 {} 个登录会话", sessions.len());
+    }
+    sessions
+}
+
+#[cfg(test)]
+impl AppState {
+    /// 测试用：会话、分享和文件收集链接保存在 `dir` 中，没有特别配置时关闭审计和访问日志
+    pub async fn for_test(config: &str, dir: &std::path::Path) -> Self {
+        fn section<'a>(table: &'a mut toml::Table, name: &str) -> &'a mut toml::Table {
+            table
+                .entry(name)
+                .or_insert_with(|| toml::Table::new().into())
+                .as_table_mut()
+                .expect("测试配置无效")
+        }
+        let file = |name: &str| toml::Value::from(dir.join(name).to_string_lossy().as_ref());
+
+        let mut table: toml::Table = toml::from_str(config).expect("测试配置无效");
+        section(&mut table, "misc").insert("session_store".into(), file("sessions.json"));
+        let share = section(&mut table, "share");
+        share.insert("store_path".into(), file("shares.json"));
+        share.insert("request_store_path".into(), file("file_requests.json"));
+        for name in ["audit", "access_log"] {
+            section(&mut table, name)
+                .entry("enable")
+                .or_insert(false.into());
+        }
+        Self::new_form_config(&table.try_into().expect("测试配置无效")).await
+    }
 }
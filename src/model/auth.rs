@@ -1,13 +1,70 @@
-pub struct Session {
-    pub username: String,
-    pub token: String,
-    pub expires_at: i64,
-    pub created_at: i64,
-    pub last_used_at: i64,
-    pub max_age: i64,
-    pub max_idle_time: i64,
-    pub max_requests: i64,
-    pub requests: i64,
-    pub ip: String,
-    pub user_agent: String,
-}
\ No newline at end of file
+//! 登录密码和分享密码的哈希
+
+use argon2::{
+    Argon2,
+    password_hash::{PasswordHasher, PasswordVerifier, phc::PasswordHash},
+};
+
+/// PHC 格式的 argon2id 哈希，例如 `$argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>`，
+/// 算法和参数随哈希一起保存，以后调整参数不影响已有的哈希
+pub fn hash_password(password: &str) -> String {
+    Argon2::default()
+        .hash_password(password.as_bytes())
+        .expect("系统随机数生成器不可用")
+        .to_string()
+}
+
+/// 一次 argon2 校验要占用几十毫秒 CPU，放到阻塞线程池中执行，不卡住异步运行时
+pub async fn verify_password(hash: &str, password: &str) -> bool {
+    let (hash, password) = (hash.to_string(), password.to_string());
+    tokio::task::spawn_blocking(move || verify_password_sync(&hash, &password))
+        .await
+        .unwrap_or(false)
+}
+
+fn verify_password_sync(hash: &str, password: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|h| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &h)
+            .is_ok()
+    })
+}
+
+/// 可以被 [`verify_password`] 校验的 argon2 哈希
+pub fn is_argon2_hash(hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|h| argon2::Algorithm::new(h.algorithm.as_str()).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn hash_round_trip() {
+        let hash = hash_password("correct horse");
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(is_argon2_hash(&hash));
+        assert!(verify_password(&hash, "correct horse").await);
+        assert!(!verify_password(&hash, "correct horse ").await);
+        assert!(!verify_password(&hash, "").await);
+    }
+
+    #[test]
+    fn hashes_are_salted() {
+        assert_ne!(hash_password("x"), hash_password("x"));
+    }
+
+    #[tokio::test]
+    async fn rejects_other_formats() {
+        let sha256 = "2d711642b726b04401627ca9fbac32f5c8530fb1903cc4db02258717921a4881";
+        for hash in [
+            sha256,
+            "salt$abcdef",
+            "",
+            "$scrypt$ln=15,r=8,p=1$c2FsdA$aGFzaA",
+        ] {
+            assert!(!is_argon2_hash(hash), "{hash}");
+            assert!(!verify_password(hash, "x").await, "{hash}");
+        }
+    }
+}
@@ -1,15 +1,56 @@
-use std::collections::BTreeMap;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    time::Duration,
+};
 
 use serde::{Deserialize, Serialize};
 
-pub use crate::model::{Path, config::file_configs::ConfigFromFile};
+use crate::model::{
+    layers::Layers,
+    validate::{ConfigError, ConfigIssue, Severity, validate},
+};
+
+pub const DEFAULT_HOST: &str = "0.0.0.0";
+pub const DEFAULT_PORT: u16 = 8080;
+pub const DEFAULT_LOG_DIR: &str = "log";
+pub const DEFAULT_SHUTDOWN_TIMEOUT: u64 = 30;
+pub const DEFAULT_SESSION_STORE: &str = "data/sessions.json";
+
+pub use crate::model::{
+    Path,
+    config::file_configs::{
+        AccessKeyFromFile, AccessLogFormat, AcmeChallenge, AcmeFromFile, ClientAuthMode,
+        ConfigFromFile, CorsFromFile, FtpFromFile, HttpMode, ListenerFromFile, LogFormat,
+        LogRotation, MetricsFromFile, MiscFromFile, PolicyEffect, S3FromFile,
+        SecurityHeadersFromFile, SftpFromFile,
+    },
+};
 
 #[derive(Clone)]
 pub struct UserConfig {
     pub username: String,
-    pub password: String,
+    pub password: Option<String>,
+    pub password_hash: Option<String>,
+    pub admin: bool,
     pub permissions_tree: Path,
     pub permissions: Vec<file_configs::UserPermissionFromFile>,
+    pub access_keys: Vec<AccessKeyFromFile>,
+    pub authorized_keys: Vec<String>,
+    pub client_cert_names: Vec<String>,
+}
+
+impl UserConfig {
+    /// 校验明文密码或 `hash-password` 生成的哈希
+    pub async fn verify_password(&self, password: &str) -> bool {
+        match (&self.password, &self.password_hash) {
+            (_, Some(hash)) => crate::model::auth::verify_password(hash, password).await,
+            (Some(p), None) => crate::utils::constant_time_eq(p.as_bytes(), password.as_bytes()),
+            (None, None) => false,
+        }
+    }
 }
 
 pub struct Config {
@@ -17,6 +58,22 @@ pub struct Config {
     pub paths: BTreeMap<String, Path>,
 }
 
+/// 解析后的监听地址
+#[derive(Clone, PartialEq, Eq)]
+pub enum ListenAddress {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddress::Tcp(addr) => write!(f, "{}", addr),
+            ListenAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
 mod file_configs {
     pub use super::*;
     #[derive(Clone, Deserialize, Serialize)]
@@ -25,6 +82,16 @@ mod file_configs {
         pub paths: Vec<PathFromFile>,
         pub misc: Option<MiscFromFile>,
         pub debug: Option<DebugFromFile>,
+        pub s3: Option<S3FromFile>,
+        pub sftp: Option<SftpFromFile>,
+        pub ftp: Option<FtpFromFile>,
+        pub share: Option<ShareFromFile>,
+        pub acme: Option<AcmeFromFile>,
+        pub audit: Option<AuditFromFile>,
+        pub access_log: Option<AccessLogFromFile>,
+        pub metrics: Option<MetricsFromFile>,
+        pub cors: Option<CorsFromFile>,
+        pub security_headers: Option<SecurityHeadersFromFile>,
     }
 
     impl ConfigFromFile {
@@ -34,32 +101,307 @@ mod file_configs {
                     .users
                     .clone()
                     .into_iter()
-                    .map(|u| (u.username.clone(), u.to_user_config()))
+                    .map(|u| (u.username.clone(), u.into_user_config()))
                     .collect(),
                 paths: self
                     .paths
                     .clone()
                     .into_iter()
-                    .map(|p| (p.name.clone(), p.to_path_config()))
+                    .map(|p| (p.name.clone(), p.into_path_config()))
                     .collect(),
             }
         }
 
-        pub async fn from_toml(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
-            let config_str = tokio::fs::read_to_string(path).await?;
-            let config_from_file: Self = toml::from_str(&config_str)?;
+        /// HTTPS 与 FTPS 共用的证书和私钥，默认为 `misc.cert_path` 目录（默认 `certs`）下的
+        /// `cert.pem` 和 `key.pem`，可以用 `misc.cert_file`、`misc.key_file` 分别指定；
+        /// 启用 ACME 时使用 `acme.cache_dir` 中申请到的证书
+        pub fn tls_files(&self) -> (PathBuf, PathBuf) {
+            if let Some(acme) = self.acme.as_ref().filter(|a| a.enable) {
+                let dir = acme.cache_dir();
+                return (dir.join("cert.pem"), dir.join("key.pem"));
+            }
+            let misc = self.misc.as_ref();
+            let dir = PathBuf::from(
+                misc.and_then(|m| m.cert_path.clone())
+                    .unwrap_or("certs".to_string()),
+            );
+            let cert = misc
+                .and_then(|m| m.cert_file.as_ref())
+                .map(PathBuf::from)
+                .unwrap_or(dir.join("cert.pem"));
+            let key = misc
+                .and_then(|m| m.key_file.as_ref())
+                .map(PathBuf::from)
+                .unwrap_or(dir.join("key.pem"));
+            (cert, key)
+        }
+
+        /// `misc.host`，默认 `0.0.0.0`
+        pub fn host(&self) -> &str {
+            self.misc
+                .as_ref()
+                .and_then(|m| m.host.as_deref())
+                .unwrap_or(DEFAULT_HOST)
+        }
+
+        /// `misc.port`，默认 8080
+        pub fn port(&self) -> u16 {
+            self.misc
+                .as_ref()
+                .and_then(|m| m.port)
+                .unwrap_or(DEFAULT_PORT)
+        }
+
+        /// `misc.static_dir`，不设置时使用编译进程序的前端文件
+        pub fn static_dir(&self) -> Option<&str> {
+            self.misc.as_ref().and_then(|m| m.static_dir.as_deref())
+        }
+
+        /// `misc.log_dir`，默认 `log`
+        pub fn log_dir(&self) -> &str {
+            self.misc
+                .as_ref()
+                .and_then(|m| m.log_dir.as_deref())
+                .unwrap_or(DEFAULT_LOG_DIR)
+        }
+
+        /// 文件服务的监听地址。未设置 `misc.listeners` 时由 `host`、`port`、`https_port` 和
+        /// `http_listener` 得到：`enable_https` 为 true 时包含 HTTPS 地址
+        pub fn listeners(&self, enable_https: bool) -> Vec<ListenerFromFile> {
+            let misc = self.misc.as_ref();
+            if let Some(listeners) = misc.and_then(|m| m.listeners.clone()) {
+                return listeners;
+            }
+            let address = |port: u16| match self.host().parse::<IpAddr>() {
+                Ok(ip) => SocketAddr::new(ip, port).to_string(),
+                Err(_) => format!("{}:{}", self.host(), port),
+            };
+            let mut listeners = Vec::new();
+            if !enable_https || misc.and_then(|m| m.http_listener).unwrap_or(true) {
+                listeners.push(ListenerFromFile {
+                    address: Some(address(self.port())),
+                    ..Default::default()
+                });
+            }
+            if enable_https {
+                listeners.push(ListenerFromFile {
+                    address: Some(address(
+                        misc.and_then(|m| m.https_port)
+                            .unwrap_or(crate::tls::DEFAULT_HTTPS_PORT),
+                    )),
+                    tls: Some(true),
+                    ..Default::default()
+                });
+            }
+            listeners
+        }
+
+        /// `misc.shutdown_timeout`，默认 30 秒
+        pub fn shutdown_timeout(&self) -> Duration {
+            Duration::from_secs(
+                self.misc
+                    .as_ref()
+                    .and_then(|m| m.shutdown_timeout)
+                    .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT),
+            )
+        }
+
+        /// `misc.session_store`，默认 `data/sessions.json`
+        pub fn session_store(&self) -> &str {
+            self.misc
+                .as_ref()
+                .and_then(|m| m.session_store.as_deref())
+                .unwrap_or(DEFAULT_SESSION_STORE)
+        }
+
+        /// 读取并校验配置文件，警告写入日志，存在错误时返回列出全部错误的 [`ConfigError`]
+        pub async fn from_toml(
+            path: &str,
+            overrides: &MiscFromFile,
+        ) -> Result<Self, Box<dyn std::error::Error>> {
+            let (config_from_file, issues) = Self::check(path, overrides).await?;
+            Self::check_issues(path, issues)?;
             Ok(config_from_file)
         }
+
+        /// 把警告写入日志，存在错误时返回列出全部错误的 [`ConfigError`]
+        pub fn check_issues(path: &str, issues: Vec<ConfigIssue>) -> Result<(), ConfigError> {
+            let mut errors = Vec::new();
+            for issue in issues {
+                match issue.severity {
+                    Severity::Error => errors.push(issue.report(path)),
+                    Severity::Warning => tracing::warn!("{}", issue.report(path)),
+                }
+            }
+            if !errors.is_empty() {
+                return Err(ConfigError {
+                    file: path.to_string(),
+                    errors,
+                });
+            }
+            Ok(())
+        }
+
+        /// 读取并合并各层配置（见 [`Layers`]）后列出所有问题，无法解析时返回解析错误
+        pub async fn check(
+            path: &str,
+            overrides: &MiscFromFile,
+        ) -> Result<(Self, Vec<ConfigIssue>), Box<dyn std::error::Error>> {
+            let layers = Layers::load(path, overrides)?;
+            let config_from_file: Self = layers.deserialize()?;
+            let issues = validate(&layers, &config_from_file);
+            Ok((config_from_file, issues))
+        }
     }
 
-    #[derive(Clone, Deserialize, Serialize)]
+    #[derive(Clone, Default, Deserialize, Serialize)]
     pub struct MiscFromFile {
+        /// 未设置 `listeners` 时 HTTP 服务的端口，默认 8080
         pub port: Option<u16>,
+        /// 未设置 `listeners` 时 HTTP 和 HTTPS 的监听地址，也是 S3、SFTP 等服务的默认监听地址
         pub host: Option<String>,
+        /// 文件服务的监听地址，设置后忽略 `port`、`https_port` 和 `http_listener`
+        pub listeners: Option<Vec<ListenerFromFile>>,
+        /// 不填时有证书就启用 HTTPS；true 时缺少证书会报错；false 时不启用
         pub enable_https: Option<bool>,
         pub cert_path: Option<String>,
+        /// 日志级别或过滤规则，例如 `debug`、`info,simple_file_manager=debug`，
+        /// 不填时使用环境变量 `RUST_LOG`，默认 `info`
         pub log_level: Option<String>,
+        /// 日志目录，默认 `log`
+        pub log_dir: Option<String>,
+        /// 日志格式，默认 `text`
+        pub log_format: Option<LogFormat>,
+        /// 日志文件的滚动周期，默认 `daily`
+        pub log_rotation: Option<LogRotation>,
+        /// 最多保留的日志文件数量，不填时不删除旧日志
+        pub log_max_files: Option<usize>,
+        /// 是否输出到标准输出，默认 true
+        pub log_stdout: Option<bool>,
+        /// 是否写入 `log_dir` 下的日志文件，默认 true
+        pub log_file: Option<bool>,
+        /// 从磁盘读取前端文件的目录，用于开发时修改页面，默认使用编译进程序的 `static/`
+        pub static_dir: Option<String>,
         pub max_upload_size: Option<usize>,
+        /// HTTPS 端口，默认 8443
+        pub https_port: Option<u16>,
+        /// 证书链文件，默认 `{cert_path}/cert.pem`
+        pub cert_file: Option<String>,
+        /// 私钥文件，默认 `{cert_path}/key.pem`
+        pub key_file: Option<String>,
+        /// 最低 TLS 版本，`"1.2"`（默认）或 `"1.3"`
+        pub tls_min_version: Option<String>,
+        /// 是否通过 ALPN 协商 HTTP/2，默认 true
+        pub http2: Option<bool>,
+        /// 启用 HTTPS 时是否继续在 `port` 上提供 HTTP，默认 true
+        pub http_listener: Option<bool>,
+        /// 启用 HTTPS 后 HTTP 端口的行为，默认 `serve`
+        pub http_mode: Option<HttpMode>,
+        /// HTTP 端口上 `/.well-known/acme-challenge/` 对应的目录，默认 `data/acme-challenge`
+        pub acme_challenge_dir: Option<String>,
+        /// `Strict-Transport-Security` 的 max-age（秒），不填或为 0 时不发送
+        pub hsts_max_age: Option<u64>,
+        pub hsts_include_subdomains: Option<bool>,
+        pub hsts_preload: Option<bool>,
+        /// 检查证书文件是否变化的间隔（秒），默认 60，为 0 时只在收到 SIGHUP 时重新加载
+        pub tls_reload_interval: Option<u64>,
+        /// 检查配置文件是否变化的间隔（秒），默认 10，为 0 时只在收到 SIGHUP 时重新加载
+        pub config_reload_interval: Option<u64>,
+        /// 证书剩余有效期少于这个天数时输出警告，默认 14
+        pub cert_expiry_warn_days: Option<i64>,
+        /// HTTPS 客户端证书认证，默认 `ignore`
+        pub client_auth: Option<ClientAuthMode>,
+        /// 用于验证客户端证书的 CA 证书（PEM，可以包含多个）
+        pub client_ca_file: Option<String>,
+        /// 收到 SIGTERM 或 SIGINT 后等待进行中的请求完成的秒数，默认 30
+        pub shutdown_timeout: Option<u64>,
+        /// 退出时保存网页登录会话的文件，下次启动时恢复，默认 `data/sessions.json`
+        pub session_store: Option<String>,
+        /// 所有页面和接口的路径前缀，例如 `/files`，默认挂在根路径
+        pub base_path: Option<String>,
+        /// 可信的反向代理地址或网段（CIDR），只有来自这些地址的请求才使用
+        /// `Forwarded`、`X-Forwarded-For` 和 `X-Forwarded-Proto`
+        pub trusted_proxies: Option<Vec<String>>,
+        /// 是否按 `Accept-Encoding` 压缩响应，默认 true
+        pub compression: Option<bool>,
+    }
+
+    /// 一个 TCP 地址或 Unix 套接字，`address` 和 `unix` 二选一
+    #[derive(Clone, Default, Deserialize, Serialize)]
+    pub struct ListenerFromFile {
+        /// `IP:端口`，IPv6 写作 `[::]:8080`
+        pub address: Option<String>,
+        /// Unix 套接字的路径
+        pub unix: Option<String>,
+        /// 是否在这个地址上提供 HTTPS，默认 false，Unix 套接字不支持
+        pub tls: Option<bool>,
+        /// Unix 套接字文件的权限，例如 `0o660`
+        pub mode: Option<u32>,
+        /// Unix 套接字文件的所有者，用户名或 UID
+        pub owner: Option<String>,
+        /// Unix 套接字文件的所属组，组名或 GID
+        pub group: Option<String>,
+    }
+
+    impl ListenerFromFile {
+        pub fn listen_address(&self) -> Result<ListenAddress, String> {
+            match (&self.address, &self.unix) {
+                (Some(address), None) => address.parse().map(ListenAddress::Tcp).map_err(|_| {
+                    format!(
+                        "无法解析的监听地址 `{}`，需要 `IP:端口`，IPv6 写作 `[::]:8080`",
+                        address
+                    )
+                }),
+                (None, Some(path)) => Ok(ListenAddress::Unix(PathBuf::from(path))),
+                (Some(_), Some(_)) => Err("address 和 unix 只能设置其中一个".to_string()),
+                (None, None) => Err("需要设置 address 或 unix".to_string()),
+            }
+        }
+
+        pub fn tls(&self) -> bool {
+            self.tls.unwrap_or(false)
+        }
+    }
+
+    #[derive(Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum LogFormat {
+        /// 每条日志一行文本
+        Text,
+        /// 每条日志一个 JSON 对象
+        Json,
+    }
+
+    #[derive(Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum LogRotation {
+        Minutely,
+        Hourly,
+        Daily,
+        /// 始终写入同一个文件
+        Never,
+    }
+
+    #[derive(Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum ClientAuthMode {
+        /// 不请求客户端证书
+        Ignore,
+        /// 客户端可以提供证书，验证通过后可代替登录
+        Allow,
+        /// TLS 握手时必须提供有效的客户端证书
+        Require,
+    }
+
+    #[derive(Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum HttpMode {
+        /// 与 HTTPS 提供相同的服务
+        Serve,
+        /// 用 308 重定向到 HTTPS，保留路径和查询参数
+        Redirect,
+        /// 只提供 ACME HTTP-01 验证文件
+        AcmeOnly,
     }
 
     #[derive(Clone, Deserialize, Serialize)]
@@ -74,11 +416,213 @@ mod file_configs {
         pub debug_session: Option<DebugSession>,
     }
 
+    /// S3 兼容网关配置，每个 `[[paths]]` 作为一个 bucket 暴露
+    #[derive(Clone, Deserialize, Serialize)]
+    pub struct S3FromFile {
+        pub enable: bool,
+        pub host: Option<String>,
+        pub port: Option<u16>,
+        /// 分片上传的暂存目录，默认 `data/s3-multipart`
+        pub multipart_dir: Option<String>,
+    }
+
+    /// 内嵌 SFTP 服务配置，每个用户只能看到自己有权限的 `[[paths]]`
+    #[derive(Clone, Deserialize, Serialize)]
+    pub struct SftpFromFile {
+        pub enable: bool,
+        pub host: Option<String>,
+        pub port: Option<u16>,
+        /// 主机私钥（OpenSSH 格式），不存在时自动生成 Ed25519 密钥，默认 `data/ssh_host_ed25519_key`
+        pub host_key_path: Option<String>,
+    }
+
+    /// FTP 服务配置，只支持被动模式；证书存在时提供显式 TLS（AUTH TLS）
+    #[derive(Clone, Deserialize, Serialize)]
+    pub struct FtpFromFile {
+        pub enable: bool,
+        pub host: Option<String>,
+        pub port: Option<u16>,
+        /// 被动模式数据连接的端口范围，默认 `[50000, 50100]`
+        pub passive_ports: Option<[u16; 2]>,
+        /// 被动模式下告诉客户端的 IPv4 地址，默认使用控制连接的地址
+        pub passive_host: Option<String>,
+        /// 要求所有连接使用 TLS，默认 false
+        pub require_tls: Option<bool>,
+    }
+
+    /// 公开分享链接与文件收集链接
+    #[derive(Clone, Deserialize, Serialize)]
+    pub struct ShareFromFile {
+        /// 分享记录的保存位置，默认 `data/shares.json`
+        pub store_path: Option<String>,
+        /// 文件收集链接的保存位置，默认 `data/file_requests.json`
+        pub request_store_path: Option<String>,
+    }
+
+    /// HTTP 访问日志，与普通日志分开保存
+    #[derive(Clone, Deserialize, Serialize)]
+    pub struct AccessLogFromFile {
+        /// 默认 true
+        pub enable: Option<bool>,
+        /// 默认 combined
+        pub format: Option<AccessLogFormat>,
+        /// 访问日志目录，默认与 `log_dir` 相同
+        pub dir: Option<String>,
+        /// 滚动周期，默认 daily
+        pub rotation: Option<LogRotation>,
+        /// 最多保留的访问日志文件数，默认不删除
+        pub max_files: Option<usize>,
+    }
+
+    #[derive(Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum AccessLogFormat {
+        /// Common Log Format
+        Common,
+        /// Combined Log Format（Common 加上 Referer 和 User-Agent）
+        Combined,
+        /// Combined 末尾追加请求 ID 和耗时
+        Extended,
+        /// 每个请求一个 JSON 对象
+        Json,
+    }
+
+    /// 审计日志，与普通日志分开保存
+    #[derive(Clone, Deserialize, Serialize)]
+    pub struct AuditFromFile {
+        /// 默认 true
+        pub enable: Option<bool>,
+        /// 审计日志目录，默认 `{log_dir}/audit`
+        pub dir: Option<String>,
+        /// 滚动周期，默认 daily
+        pub rotation: Option<LogRotation>,
+        /// 最多保留的审计日志文件数，默认不删除
+        pub max_files: Option<usize>,
+    }
+
+    /// Prometheus 指标，设置 `port` 时在独立端口上提供，否则为 HTTP 服务的 `/metrics`
+    #[derive(Clone, Deserialize, Serialize)]
+    pub struct MetricsFromFile {
+        pub enable: bool,
+        pub host: Option<String>,
+        pub port: Option<u16>,
+        /// 抓取时需要提供的 `Authorization: Bearer` 令牌，也可以用 `token_file` 从文件读取
+        pub token: Option<String>,
+    }
+
+    /// 跨域访问（CORS），不设置时浏览器不允许其他来源的页面读取接口的响应
+    #[derive(Clone, Deserialize, Serialize)]
+    pub struct CorsFromFile {
+        /// 允许的来源，例如 `https://portal.example.com`，`"*"` 表示任意来源
+        pub allowed_origins: Vec<String>,
+        /// 允许的方法，默认 `GET`、`POST`、`PUT`、`DELETE`，`"*"` 表示任意方法
+        pub allowed_methods: Option<Vec<String>>,
+        /// 允许的请求头，默认 `Authorization` 和 `Content-Type`，`"*"` 表示任意请求头
+        pub allowed_headers: Option<Vec<String>>,
+        /// 允许页面读取的响应头，默认 `Content-Disposition` 和 `X-Request-Id`
+        pub exposed_headers: Option<Vec<String>>,
+        /// 是否允许携带 Cookie 等凭据，默认 false，为 true 时以上各项都不能使用 `"*"`
+        pub allow_credentials: Option<bool>,
+        /// 浏览器缓存预检结果的秒数，默认 600
+        pub max_age: Option<u64>,
+    }
+
+    /// 所有 HTTP 响应附带的安全响应头，已经由处理函数设置的不会被覆盖
+    #[derive(Clone, Deserialize, Serialize)]
+    pub struct SecurityHeadersFromFile {
+        /// 默认 true
+        pub enable: Option<bool>,
+        /// 代替默认的 `Content-Security-Policy`，为空字符串时不发送
+        pub content_security_policy: Option<String>,
+        /// 允许嵌入页面的来源，写进 CSP 的 `frame-ancestors`，默认 `'self'`
+        pub frame_ancestors: Option<String>,
+        /// 默认 `same-origin`，为空字符串时不发送
+        pub referrer_policy: Option<String>,
+    }
+
+    /// ACME 自动申请证书，启用后忽略 `cert_path`、`cert_file` 和 `key_file`
+    #[derive(Clone, Deserialize, Serialize)]
+    pub struct AcmeFromFile {
+        pub enable: bool,
+        /// 要申请证书的域名
+        pub domains: Vec<String>,
+        /// 联系方式，例如 `mailto:admin@example.com`，只写邮箱时自动加上 `mailto:`
+        #[serde(default)]
+        pub contact: Vec<String>,
+        /// ACME 目录地址，默认 Let's Encrypt 正式环境
+        pub directory_url: Option<String>,
+        /// 只信任这个根证书访问 ACME 服务器（PEM），用于 Pebble 等测试环境
+        pub directory_ca_file: Option<String>,
+        /// 验证方式，默认 `http-01`
+        pub challenge: Option<AcmeChallenge>,
+        /// 账户、证书和私钥的缓存目录，默认 `data/acme`
+        pub cache_dir: Option<String>,
+        /// 证书剩余有效期少于这个天数时续期，默认 30
+        pub renew_before_days: Option<i64>,
+    }
+
+    impl AcmeFromFile {
+        pub fn cache_dir(&self) -> PathBuf {
+            PathBuf::from(self.cache_dir.clone().unwrap_or("data/acme".to_string()))
+        }
+    }
+
+    #[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize)]
+    pub enum AcmeChallenge {
+        /// 验证文件由 HTTP 端口的 `/.well-known/acme-challenge/` 提供，需要 HTTP 监听器
+        #[serde(rename = "http-01")]
+        Http01,
+        /// 在 HTTPS 端口上通过 ALPN `acme-tls/1` 返回验证证书
+        #[serde(rename = "tls-alpn-01")]
+        TlsAlpn01,
+    }
+
     #[derive(Clone, Deserialize, Serialize)]
     pub struct UserFromFile {
         pub username: String,
-        pub password: String,
+        /// 明文密码，与 `password_hash` 二选一
+        pub password: Option<String>,
+        /// `hash-password` 子命令生成的密码哈希
+        pub password_hash: Option<String>,
+        /// 可以使用 `/api/admin/` 下的管理接口，默认 false
+        #[serde(default)]
+        pub admin: bool,
         pub permissions: Vec<UserPermissionFromFile>,
+        #[serde(default)]
+        pub access_keys: Vec<AccessKeyFromFile>,
+        /// SFTP 公钥登录使用的公钥，每项为一行 OpenSSH 格式（`ssh-ed25519 AAAA... comment`）
+        #[serde(default)]
+        pub authorized_keys: Vec<String>,
+        /// 可以映射到该用户的客户端证书 CN 或 SAN，为空时与用户名相同即可
+        #[serde(default)]
+        pub client_cert_names: Vec<String>,
+    }
+
+    /// S3 访问密钥，签名使用 SigV4
+    #[derive(Clone, Deserialize, Serialize)]
+    pub struct AccessKeyFromFile {
+        pub access_key_id: String,
+        pub secret_access_key: String,
+        /// 类 IAM 的策略语句，为空时仅受 rwv 权限约束
+        #[serde(default)]
+        pub policy: Vec<PolicyStatementFromFile>,
+    }
+
+    #[derive(Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum PolicyEffect {
+        Allow,
+        Deny,
+    }
+
+    #[derive(Clone, Deserialize, Serialize)]
+    pub struct PolicyStatementFromFile {
+        pub effect: PolicyEffect,
+        /// 例如 `s3:GetObject`、`s3:*`
+        pub actions: Vec<String>,
+        /// `bucket` 或 `bucket/key` 的通配模式，为空表示全部
+        #[serde(default)]
+        pub resources: Vec<String>,
     }
 
     #[derive(Clone, Deserialize, Serialize)]
@@ -88,10 +632,12 @@ mod file_configs {
     }
 
     impl UserFromFile {
-        fn to_user_config(self) -> UserConfig {
+        fn into_user_config(self) -> UserConfig {
             UserConfig {
                 username: self.username,
                 password: self.password,
+                password_hash: self.password_hash,
+                admin: self.admin,
                 permissions_tree: Path {
                     path: "/".to_string(),
                     name: "root".to_string(),
@@ -99,6 +645,9 @@ mod file_configs {
                     sub_path: BTreeMap::new(),
                 },
                 permissions: self.permissions,
+                access_keys: self.access_keys,
+                authorized_keys: self.authorized_keys,
+                client_cert_names: self.client_cert_names,
             }
         }
     }
@@ -111,7 +660,7 @@ mod file_configs {
     }
 
     impl PathFromFile {
-        fn to_path_config(self) -> Path {
+        fn into_path_config(self) -> Path {
             Path {
                 path: self.path,
                 name: self.name,
@@ -145,3 +694,55 @@ impl Config {
         Ok(config)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addresses(misc: &str, enable_https: bool) -> Vec<(String, bool)> {
+        let config: ConfigFromFile =
+            toml::from_str(&format!("users = []\npaths = []\n[misc]\n{}", misc)).unwrap();
+        config
+            .listeners(enable_https)
+            .iter()
+            .map(|l| (l.listen_address().unwrap().to_string(), l.tls()))
+            .collect()
+    }
+
+    #[test]
+    fn listeners_default_to_host_and_ports() {
+        assert_eq!(
+            addresses("host = \"::1\"\nport = 8000", false),
+            [("[::1]:8000".to_string(), false)]
+        );
+        assert_eq!(
+            addresses("https_port = 9443", true),
+            [
+                ("0.0.0.0:8080".to_string(), false),
+                ("0.0.0.0:9443".to_string(), true)
+            ]
+        );
+        assert_eq!(
+            addresses("http_listener = false", true),
+            [("0.0.0.0:8443".to_string(), true)]
+        );
+    }
+
+    #[test]
+    fn configured_listeners_replace_the_defaults() {
+        let misc = r#"port = 8000
+listeners = [
+    { address = "127.0.0.1:8080" },
+    { address = "[::]:8443", tls = true },
+    { unix = "/run/sfm/sfm.sock" },
+]"#;
+        assert_eq!(
+            addresses(misc, true),
+            [
+                ("127.0.0.1:8080".to_string(), false),
+                ("[::]:8443".to_string(), true),
+                ("unix:/run/sfm/sfm.sock".to_string(), false)
+            ]
+        );
+    }
+}
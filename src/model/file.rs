@@ -1,8 +1,8 @@
 use std::collections::BTreeMap;
 
-const READ_MASK: u8 = 0b100u8;
-const WRITE_MASK: u8 = 0b010u8;
-const VIEW_MASK: u8 = 0b001u8;
+pub const READ_MASK: u8 = 0b100u8;
+pub const WRITE_MASK: u8 = 0b010u8;
+pub const VIEW_MASK: u8 = 0b001u8;
 
 #[derive(Eq, Hash, PartialEq, Clone)]
 pub struct Path {
@@ -13,18 +13,6 @@ pub struct Path {
 }
 
 impl Path {
-    pub fn can_read(&self) -> bool {
-        (self.permission & READ_MASK) == READ_MASK
-    }
-
-    pub fn can_write(&self) -> bool {
-        (self.permission & WRITE_MASK) == WRITE_MASK
-    }
-
-    pub fn can_view(&self) -> bool {
-        (self.permission & VIEW_MASK) == VIEW_MASK
-    }
-
     pub fn extract_sub_paths(&mut self) {
         let permission = self.permission;
         self.permission = 0;
@@ -77,12 +65,21 @@ impl Path {
         }
     }
 
+    /// 把配置的路径树合并到用户的权限树上。只有配置了权限的节点才会获得权限，
+    /// 取值为路径权限与用户授权的交集；中间节点保持 0，由更深的节点决定
     pub fn merge_path(&mut self, from: &Path, permission: u8) {
-        self.permission = from.permission | permission;
+        if from.permission != 0 {
+            self.permission |= from.permission & permission;
+        }
         from.sub_path.iter().for_each(|(name, other_sub_path)| {
             self.sub_path
                 .entry(name.clone())
-                .or_insert_with(|| other_sub_path.clone())
+                .or_insert_with(|| Path {
+                    path: other_sub_path.path.clone(),
+                    name: other_sub_path.name.clone(),
+                    permission: 0,
+                    sub_path: BTreeMap::new(),
+                })
                 .merge_path(other_sub_path, permission);
         });
     }
@@ -0,0 +1,136 @@
+//! 文件收集链接：匿名访客只能向绑定的文件夹上传，不能列目录或下载
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::model::share::{load_json_list, save_json_list};
+
+pub const DEFAULT_FILE_REQUEST_STORE: &str = "data/file_requests.json";
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FileRequest {
+    pub token: String,
+    pub owner: String,
+    /// `[[paths]]` 的名称
+    pub root: String,
+    /// 接收上传的文件夹，相对于 root，为空表示整个 root
+    pub path: String,
+    /// 显示在上传页面上的标题
+    pub title: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// 单个文件的最大字节数
+    pub max_file_size: Option<u64>,
+    /// 允许的扩展名（小写，不带 `.`），为空表示不限制
+    #[serde(default)]
+    pub allowed_extensions: Vec<String>,
+    /// 整个链接最多接收的文件数
+    pub max_files: Option<u32>,
+    #[serde(default)]
+    pub uploads: u32,
+}
+
+impl FileRequest {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|t| t <= Utc::now())
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.max_files.is_some_and(|max| self.uploads >= max)
+    }
+
+    pub fn extension_allowed(&self, file_name: &str) -> bool {
+        if self.allowed_extensions.is_empty() {
+            return true;
+        }
+        Path::new(file_name)
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .is_some_and(|e| self.allowed_extensions.contains(&e))
+    }
+}
+
+#[derive(Clone)]
+pub struct FileRequestStore {
+    file: PathBuf,
+    requests: Arc<Mutex<BTreeMap<String, FileRequest>>>,
+}
+
+impl FileRequestStore {
+    pub async fn load(file: &Path) -> std::io::Result<Self> {
+        let requests = load_json_list::<FileRequest>(file)
+            .await?
+            .into_iter()
+            .map(|r| (r.token.clone(), r))
+            .collect();
+        Ok(FileRequestStore {
+            file: file.to_path_buf(),
+            requests: Arc::new(Mutex::new(requests)),
+        })
+    }
+
+    async fn save(&self, requests: &BTreeMap<String, FileRequest>) -> std::io::Result<()> {
+        save_json_list(&self.file, requests.values()).await
+    }
+
+    pub async fn insert(&self, request: FileRequest) -> std::io::Result<()> {
+        let mut requests = self.requests.lock().await;
+        requests.insert(request.token.clone(), request);
+        self.save(&requests).await
+    }
+
+    pub async fn get(&self, token: &str) -> Option<FileRequest> {
+        self.requests.lock().await.get(token).cloned()
+    }
+
+    pub async fn list_by_owner(&self, owner: &str) -> Vec<FileRequest> {
+        self.requests
+            .lock()
+            .await
+            .values()
+            .filter(|r| r.owner == owner)
+            .cloned()
+            .collect()
+    }
+
+    /// 只有创建者可以撤销，返回是否删除了链接
+    pub async fn revoke(&self, token: &str, owner: &str) -> std::io::Result<bool> {
+        let mut requests = self.requests.lock().await;
+        if requests.get(token).is_none_or(|r| r.owner != owner) {
+            return Ok(false);
+        }
+        requests.remove(token);
+        self.save(&requests).await?;
+        Ok(true)
+    }
+
+    /// 上传前占用一个名额，超过 `max_files` 时返回 false；上传失败后用 `release_upload` 归还
+    pub async fn reserve_upload(&self, token: &str) -> std::io::Result<bool> {
+        let mut requests = self.requests.lock().await;
+        let Some(request) = requests.get_mut(token) else {
+            return Ok(false);
+        };
+        if request.is_full() {
+            return Ok(false);
+        }
+        request.uploads += 1;
+        self.save(&requests).await?;
+        Ok(true)
+    }
+
+    pub async fn release_upload(&self, token: &str) -> std::io::Result<()> {
+        let mut requests = self.requests.lock().await;
+        if let Some(request) = requests.get_mut(token) {
+            request.uploads = request.uploads.saturating_sub(1);
+            self.save(&requests).await?;
+        }
+        Ok(())
+    }
+}
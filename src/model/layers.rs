@@ -0,0 +1,567 @@
+//! 分层配置：依次合并主配置文件、同目录 `conf.d/` 下的 `*.toml`（按文件名排序）、
+//! `SFM_` 开头并用 `__` 分隔层级的环境变量以及命令行参数，后面的层覆盖前面的层。
+//! 表按键合并，`[[users]]` 这样的表数组追加到前面的层之后，其余数组整体替换。
+//! 每个值来自哪一层都会记录下来，用于报告错误位置和 `check-config --show-origin`。
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+use serde::de::DeserializeOwned;
+use toml::{
+    Table, Value,
+    de::{DeTable, DeValue},
+};
+
+use crate::model::{
+    MiscFromFile,
+    validate::{ConfigError, ConfigIssue, Severity},
+};
+
+const ENV_PREFIX: &str = "SFM_";
+const ENV_SEPARATOR: &str = "__";
+const CONF_DIR: &str = "conf.d";
+/// 可以改用 `<键>_file` 从文件读取的敏感配置，例如 `password_file = "/run/secrets/x"`
+const SECRET_KEYS: [&str; 4] = ["password", "password_hash", "secret_access_key", "token"];
+
+/// 配置项路径中的一段，例如 `users[0].username` 为 `users`、`0`、`username`
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Seg {
+    Key(String),
+    Index(usize),
+}
+
+/// 形如 `users[0].permissions[1].path_name`
+pub fn format_path(path: &[Seg]) -> String {
+    let mut s = String::new();
+    for seg in path {
+        match seg {
+            Seg::Key(key) => {
+                if !s.is_empty() {
+                    s.push('.');
+                }
+                s.push_str(key);
+            }
+            Seg::Index(i) => s.push_str(&format!("[{}]", i)),
+        }
+    }
+    s
+}
+
+/// 配置项所在的层，以及在该层文件中的行号和列号（从 1 开始）
+#[derive(Clone)]
+pub struct Location {
+    pub layer: usize,
+    pub source: String,
+    pub position: Option<(usize, usize)>,
+}
+
+struct Layer {
+    /// 文件路径、`环境变量 SFM_...` 或 `命令行参数`
+    name: String,
+    /// 文件原文，用于定位行号和列号
+    source: Option<String>,
+}
+
+impl Layer {
+    /// 按该层中的路径查找行号和列号，找不到时退回到最近的上级
+    fn position(&self, path: &[Seg]) -> Option<(usize, usize)> {
+        let source = self.source.as_deref()?;
+        let root = DeValue::Table(DeTable::parse(source).ok()?.into_inner());
+        let mut value = &root;
+        let mut offset = None;
+        for seg in path {
+            let next = match seg {
+                // 从文件读取的敏感配置定位到 `<键>_file`
+                Seg::Key(key) => value
+                    .get(key.as_str())
+                    .or_else(|| value.get(format!("{}_file", key).as_str())),
+                Seg::Index(i) => value.get(*i),
+            };
+            let Some(next) = next else {
+                break;
+            };
+            offset = Some(next.span().start);
+            value = next.get_ref();
+        }
+        let offset = offset?;
+        let before = &source[..offset];
+        let line = before.matches('\n').count() + 1;
+        let column = before
+            .rsplit('\n')
+            .next()
+            .map(|l| l.chars().count())
+            .unwrap_or(0)
+            + 1;
+        Some((line, column))
+    }
+}
+
+pub struct Layers {
+    /// 主配置文件，无法定位的问题报告在这里
+    file: String,
+    layers: Vec<Layer>,
+    merged: Table,
+    /// 合并后的配置项路径 -> (层, 在该层中的路径)
+    origins: BTreeMap<Vec<Seg>, (usize, Vec<Seg>)>,
+}
+
+/// 主配置文件以及同目录 `conf.d/` 下按文件名排序的 `*.toml`
+pub fn config_files(path: &Path) -> Vec<PathBuf> {
+    let mut files = vec![path.to_path_buf()];
+    let conf_dir = path.parent().unwrap_or(Path::new("")).join(CONF_DIR);
+    if let Ok(entries) = std::fs::read_dir(conf_dir) {
+        let mut extra: Vec<PathBuf> = entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_file() && p.extension().is_some_and(|e| e == "toml"))
+            .collect();
+        extra.sort();
+        files.extend(extra);
+    }
+    files
+}
+
+/// 环境变量的值按 TOML 值解析（`9000`、`true`、`[1, 2]`），无法解析时作为字符串
+fn parse_env_value(raw: &str) -> Value {
+    toml::from_str::<Table>(&format!("v = {}", raw))
+        .ok()
+        .and_then(|mut t| t.remove("v"))
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
+
+fn is_table_array(value: &Value) -> bool {
+    value
+        .as_array()
+        .is_some_and(|a| !a.is_empty() && a.iter().all(Value::is_table))
+}
+
+fn child(path: &[Seg], seg: Seg) -> Vec<Seg> {
+    let mut path = path.to_vec();
+    path.push(seg);
+    path
+}
+
+/// 把 `password_file` 这样的键替换为文件内容（去掉结尾的换行）
+fn resolve_secret_files(table: &mut Table, path: &[Seg]) -> Result<(), String> {
+    for key in SECRET_KEYS {
+        let file_key = format!("{}_file", key);
+        let Some(file) = table.remove(&file_key) else {
+            continue;
+        };
+        let at = format_path(&child(path, Seg::Key(file_key.clone())));
+        if table.contains_key(key) {
+            return Err(format!("{} 不能和 {} 同时设置", at, key));
+        }
+        let Some(file) = file.as_str() else {
+            return Err(format!("{} 需要是文件路径", at));
+        };
+        let secret = std::fs::read_to_string(file)
+            .map_err(|e| format!("无法读取 {} 指定的文件 {}: {}", at, file, e))?;
+        let secret = secret.trim_end_matches(['\r', '\n']).to_string();
+        table.insert(key.to_string(), Value::String(secret));
+    }
+    for (key, value) in table.iter_mut() {
+        let path = child(path, Seg::Key(key.clone()));
+        match value {
+            Value::Table(t) => resolve_secret_files(t, &path)?,
+            Value::Array(items) => {
+                for (i, item) in items.iter_mut().enumerate() {
+                    if let Value::Table(t) = item {
+                        resolve_secret_files(t, &child(&path, Seg::Index(i)))?;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+impl Layers {
+    /// 读取并合并所有层，`overrides` 是命令行参数中设置了的 `[misc]` 项
+    pub fn load(path: &str, overrides: &MiscFromFile) -> Result<Self, Box<dyn Error>> {
+        Self::load_with_env(path, overrides, std::env::vars())
+    }
+
+    fn load_with_env(
+        path: &str,
+        overrides: &MiscFromFile,
+        env: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut layers = Layers {
+            file: path.to_string(),
+            layers: Vec::new(),
+            merged: Table::new(),
+            origins: BTreeMap::new(),
+        };
+
+        for file in config_files(Path::new(path)) {
+            let name = file.to_string_lossy().to_string();
+            let source = std::fs::read_to_string(&file)
+                .map_err(|e| format!("无法读取配置文件 {}: {}", name, e))?;
+            let mut table: Table =
+                toml::from_str(&source).map_err(|e| format!("{}: {}", name, e))?;
+            resolve_secret_files(&mut table, &[]).map_err(|e| format!("{}: {}", name, e))?;
+            layers.push(name, Some(source), table);
+        }
+
+        let mut vars: Vec<(String, String)> = env
+            .into_iter()
+            .filter(|(k, _)| k.starts_with(ENV_PREFIX) && k.contains(ENV_SEPARATOR))
+            .collect();
+        vars.sort();
+        for (name, raw) in vars {
+            let keys: Vec<String> = name[ENV_PREFIX.len()..]
+                .split(ENV_SEPARATOR)
+                .map(|k| k.to_lowercase())
+                .collect();
+            if keys.iter().any(|k| k.is_empty()) {
+                return Err(format!("无法解析的环境变量 {}", name).into());
+            }
+            let mut value = parse_env_value(&raw);
+            for key in keys.into_iter().rev() {
+                value = Value::Table(Table::from_iter([(key, value)]));
+            }
+            if let Value::Table(table) = value {
+                layers.push(format!("环境变量 {}", name), None, table);
+            }
+        }
+
+        let misc = Table::try_from(overrides)?;
+        if !misc.is_empty() {
+            let table = Table::from_iter([("misc".to_string(), Value::Table(misc))]);
+            layers.push("命令行参数".to_string(), None, table);
+        }
+        Ok(layers)
+    }
+
+    fn push(&mut self, name: String, source: Option<String>, table: Table) {
+        let layer = self.layers.len();
+        self.layers.push(Layer { name, source });
+        let mut merged = std::mem::take(&mut self.merged);
+        self.merge(layer, &mut merged, table, &[], &[]);
+        self.merged = merged;
+    }
+
+    fn record(&mut self, layer: usize, value: &Value, path: Vec<Seg>, layer_path: Vec<Seg>) {
+        match value {
+            Value::Table(t) => {
+                for (key, v) in t {
+                    let seg = Seg::Key(key.clone());
+                    self.record(layer, v, child(&path, seg.clone()), child(&layer_path, seg));
+                }
+            }
+            Value::Array(items) => {
+                for (i, v) in items.iter().enumerate() {
+                    self.record(
+                        layer,
+                        v,
+                        child(&path, Seg::Index(i)),
+                        child(&layer_path, Seg::Index(i)),
+                    );
+                }
+            }
+            _ => {}
+        }
+        self.origins.insert(path, (layer, layer_path));
+    }
+
+    fn merge(
+        &mut self,
+        layer: usize,
+        into: &mut Table,
+        from: Table,
+        path: &[Seg],
+        layer_path: &[Seg],
+    ) {
+        for (key, value) in from {
+            let at = child(path, Seg::Key(key.clone()));
+            let layer_at = child(layer_path, Seg::Key(key.clone()));
+            match (into.get_mut(&key), value) {
+                (Some(Value::Table(existing)), Value::Table(table)) => {
+                    self.merge(layer, existing, table, &at, &layer_at);
+                }
+                (Some(existing), Value::Array(items))
+                    if is_table_array(existing) && items.iter().all(Value::is_table) =>
+                {
+                    let existing = existing.as_array_mut().expect("表数组");
+                    let base = existing.len();
+                    for (i, item) in items.into_iter().enumerate() {
+                        self.record(
+                            layer,
+                            &item,
+                            child(&at, Seg::Index(base + i)),
+                            child(&layer_at, Seg::Index(i)),
+                        );
+                        existing.push(item);
+                    }
+                }
+                (_, value) => {
+                    self.origins.retain(|k, _| !k.starts_with(&at));
+                    self.record(layer, &value, at, layer_at);
+                    into.insert(key, value);
+                }
+            }
+        }
+    }
+
+    /// 查找配置项来自哪一层，以及在该层文件中的位置
+    pub fn locate(&self, path: &[Seg]) -> Option<Location> {
+        let (len, (layer, layer_path)) = (0..=path.len())
+            .rev()
+            .find_map(|len| self.origins.get(&path[..len]).map(|o| (len, o)))?;
+        let mut full = layer_path.clone();
+        full.extend_from_slice(&path[len..]);
+        let layer_info = &self.layers[*layer];
+        Some(Location {
+            layer: *layer,
+            source: layer_info.name.clone(),
+            position: layer_info.position(&full),
+        })
+    }
+
+    /// 把合并后的配置反序列化，类型错误会指出配置项所在的层和位置
+    pub fn deserialize<T: DeserializeOwned>(&self) -> Result<T, ConfigError> {
+        serde_path_to_error::deserialize(Value::Table(self.merged.clone())).map_err(|e| {
+            let path: Vec<Seg> = e
+                .path()
+                .iter()
+                .filter_map(|s| match s {
+                    serde_path_to_error::Segment::Map { key } => Some(Seg::Key(key.clone())),
+                    serde_path_to_error::Segment::Seq { index } => Some(Seg::Index(*index)),
+                    _ => None,
+                })
+                .collect();
+            let issue = ConfigIssue {
+                severity: Severity::Error,
+                location: self.locate(&path),
+                message: format!("{}: {}", e.path(), e.inner().message()),
+            };
+            ConfigError {
+                file: self.file.clone(),
+                errors: vec![issue.report(&self.file)],
+            }
+        })
+    }
+
+    /// 合并后的每个值及其来源，敏感配置显示为 `***`
+    pub fn origins(&self) -> Vec<(String, String, String)> {
+        let mut entries = Vec::new();
+        self.collect(&self.merged, &[], &mut entries);
+        entries
+    }
+
+    fn collect(&self, table: &Table, path: &[Seg], out: &mut Vec<(String, String, String)>) {
+        for (key, value) in table {
+            let at = child(path, Seg::Key(key.clone()));
+            match value {
+                Value::Table(t) => self.collect(t, &at, out),
+                Value::Array(items) if is_table_array(value) => {
+                    for (i, item) in items.iter().enumerate() {
+                        if let Value::Table(t) = item {
+                            self.collect(t, &child(&at, Seg::Index(i)), out);
+                        }
+                    }
+                }
+                _ => {
+                    let shown = if SECRET_KEYS.contains(&key.as_str()) {
+                        "\"***\"".to_string()
+                    } else {
+                        value.to_string()
+                    };
+                    let source = self
+                        .locate(&at)
+                        .map(|l| match l.position {
+                            Some((line, column)) => format!("{}:{}:{}", l.source, line, column),
+                            None => l.source,
+                        })
+                        .unwrap_or_default();
+                    out.push((format_path(&at), shown, source));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ConfigFromFile;
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    /// `dir/config.toml` 和 `dir/conf.d/` 下的文件
+    fn write(dir: &Path, main: &str, extra: &[(&str, &str)]) -> String {
+        let path = dir.join("config.toml");
+        std::fs::write(&path, main).unwrap();
+        std::fs::create_dir_all(dir.join(CONF_DIR)).unwrap();
+        for (name, content) in extra {
+            std::fs::write(dir.join(CONF_DIR).join(name), content).unwrap();
+        }
+        path.display().to_string()
+    }
+
+    fn origin<'a>(
+        origins: &'a [(String, String, String)],
+        key: &str,
+    ) -> &'a (String, String, String) {
+        origins.iter().find(|(k, _, _)| k == key).unwrap()
+    }
+
+    const MAIN: &str = r#"paths = []
+
+[misc]
+port = 8080
+host = "0.0.0.0"
+trusted_proxies = ["10.0.0.1", "10.0.0.2"]
+
+[[users]]
+username = "alice"
+password = "x"
+permissions = []
+"#;
+
+    #[test]
+    fn later_layers_override_earlier_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write(
+            dir.path(),
+            MAIN,
+            &[
+                // 按文件名排序，`20-` 覆盖 `10-`
+                ("20-port.toml", "[misc]\nport = 8082\n"),
+                (
+                    "10-users.toml",
+                    "[misc]\nport = 8081\ntrusted_proxies = [\"10.0.0.3\"]\n\n\
+                     [[users]]\nusername = \"bob\"\npassword = \"y\"\npermissions = []\n",
+                ),
+                ("ignored.txt", "[misc]\nport = 1\n"),
+            ],
+        );
+        let overrides = MiscFromFile {
+            host: Some("127.0.0.1".to_string()),
+            ..Default::default()
+        };
+        let vars = env(&[("SFM_MISC__PORT", "9000"), ("SFM_MISC__HOST", "10.1.1.1")]);
+        let layers = Layers::load_with_env(&path, &overrides, vars).unwrap();
+        let config: ConfigFromFile = layers.deserialize().unwrap();
+
+        let misc = config.misc.as_ref().unwrap();
+        assert_eq!(misc.port, Some(9000));
+        assert_eq!(misc.host.as_deref(), Some("127.0.0.1"));
+        // 表数组追加，其余数组整体替换
+        let users: Vec<_> = config.users.iter().map(|u| u.username.as_str()).collect();
+        assert_eq!(users, ["alice", "bob"]);
+        assert_eq!(
+            misc.trusted_proxies.as_deref(),
+            Some(&["10.0.0.3".to_string()][..])
+        );
+
+        let origins = layers.origins();
+        assert_eq!(origin(&origins, "misc.port").2, "环境变量 SFM_MISC__PORT");
+        assert_eq!(origin(&origins, "misc.host").2, "命令行参数");
+        let conf_d = dir.path().join(CONF_DIR).join("10-users.toml");
+        assert_eq!(
+            origin(&origins, "users[1].username").2,
+            format!("{}:6:12", conf_d.display())
+        );
+        assert_eq!(
+            origin(&origins, "users[0].username").2,
+            format!("{}:9:12", path)
+        );
+    }
+
+    #[test]
+    fn env_values_are_parsed_as_toml() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write(dir.path(), MAIN, &[]);
+        let vars = env(&[
+            ("SFM_MISC__TRUSTED_PROXIES", "[\"192.168.0.0/16\"]"),
+            ("SFM_MISC__LOG_LEVEL", "debug"),
+            ("SFM_MISC__HTTP2", "false"),
+            // 没有 `__` 的变量不是配置
+            ("SFM_PORT", "1"),
+            ("OTHER__PORT", "1"),
+        ]);
+        let layers = Layers::load_with_env(&path, &MiscFromFile::default(), vars).unwrap();
+        let config: ConfigFromFile = layers.deserialize().unwrap();
+        let misc = config.misc.as_ref().unwrap();
+        assert_eq!(
+            misc.trusted_proxies.as_deref(),
+            Some(&["192.168.0.0/16".to_string()][..])
+        );
+        assert_eq!(misc.log_level.as_deref(), Some("debug"));
+        assert_eq!(misc.http2, Some(false));
+        assert_eq!(misc.port, Some(8080));
+
+        let vars = env(&[("SFM_MISC____PORT", "1")]);
+        let error = Layers::load_with_env(&path, &MiscFromFile::default(), vars)
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "无法解析的环境变量 SFM_MISC____PORT");
+    }
+
+    #[test]
+    fn env_type_errors_name_the_variable() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write(dir.path(), MAIN, &[]);
+        let vars = env(&[("SFM_MISC__PORT", "http")]);
+        let layers = Layers::load_with_env(&path, &MiscFromFile::default(), vars).unwrap();
+        let error = layers.deserialize::<ConfigFromFile>().err().unwrap();
+        assert!(
+            error.errors[0].starts_with("环境变量 SFM_MISC__PORT: 错误: misc.port"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn secrets_are_read_from_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret = dir.path().join("secret");
+        std::fs::write(&secret, "s3cret\n").unwrap();
+        let main = MAIN.replace(
+            "password = \"x\"",
+            &format!("password_file = {:?}", secret.display().to_string()),
+        );
+        let path = write(dir.path(), &main, &[]);
+        let layers = Layers::load_with_env(&path, &MiscFromFile::default(), []).unwrap();
+        let config: ConfigFromFile = layers.deserialize().unwrap();
+        assert_eq!(config.users[0].password.as_deref(), Some("s3cret"));
+        let origins = layers.origins();
+        let (_, shown, source) = origin(&origins, "users[0].password");
+        assert_eq!(shown, "\"***\"");
+        assert_eq!(source, &format!("{}:10:17", path));
+
+        let both = main.replace("permissions = []", "password = \"x\"\npermissions = []");
+        let path = write(dir.path(), &both, &[]);
+        let error = Layers::load_with_env(&path, &MiscFromFile::default(), [])
+            .err()
+            .unwrap();
+        assert!(
+            error.to_string().contains("不能和 password 同时设置"),
+            "{}",
+            error
+        );
+
+        std::fs::remove_file(&secret).unwrap();
+        let path = write(dir.path(), &main, &[]);
+        let error = Layers::load_with_env(&path, &MiscFromFile::default(), [])
+            .err()
+            .unwrap();
+        assert!(
+            error
+                .to_string()
+                .contains("无法读取 users[0].password_file"),
+            "{}",
+            error
+        );
+    }
+}
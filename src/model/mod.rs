@@ -2,6 +2,10 @@ pub mod app_state;
 pub mod auth;
 pub mod config;
 pub mod file;
+pub mod file_request;
+pub mod layers;
+pub mod share;
+pub mod validate;
 
 pub use app_state::*;
 // pub use auth::*;
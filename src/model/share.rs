@@ -0,0 +1,240 @@
+//! 公开分享链接：持久化在 JSON 文件中，重启后仍然有效
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tokio::sync::Mutex;
+
+use crate::{model::auth, utils::AtomicFile};
+
+pub const DEFAULT_SHARE_STORE: &str = "data/shares.json";
+/// 输入密码后在这段时间内访问分享不需要再次输入（秒）
+pub const SHARE_UNLOCK_SECONDS: i64 = 3600;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Share {
+    pub token: String,
+    pub owner: String,
+    /// `[[paths]]` 的名称
+    pub root: String,
+    /// 相对于 root 的路径，不以 `/` 开头，为空表示整个 root
+    pub path: String,
+    pub is_dir: bool,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// [`auth::hash_password`] 生成的哈希
+    pub password_hash: Option<String>,
+    pub max_downloads: Option<u32>,
+    #[serde(default)]
+    pub downloads: u32,
+    /// 允许访问的 IP 或 CIDR，为空表示不限制
+    #[serde(default)]
+    pub allowed_ips: Vec<String>,
+}
+
+/// 分享链接不可用的原因
+pub enum ShareDenied {
+    NotFound,
+    Expired,
+    LimitReached,
+    IpNotAllowed,
+    PasswordRequired,
+}
+
+impl Share {
+    /// `unlocked` 表示访问者已经提供了正确的密码，分享没有设置密码时忽略
+    pub fn check_access(&self, ip: IpAddr, unlocked: bool) -> Result<(), ShareDenied> {
+        if self.expires_at.is_some_and(|t| t <= Utc::now()) {
+            return Err(ShareDenied::Expired);
+        }
+        if self.max_downloads.is_some_and(|max| self.downloads >= max) {
+            return Err(ShareDenied::LimitReached);
+        }
+        if !self.allowed_ips.is_empty() && !self.allowed_ips.iter().any(|r| ip_in_range(ip, r)) {
+            return Err(ShareDenied::IpNotAllowed);
+        }
+        if self.password_hash.is_some() && !unlocked {
+            return Err(ShareDenied::PasswordRequired);
+        }
+        Ok(())
+    }
+
+    /// 没有设置密码时总是通过
+    pub async fn check_password(&self, password: &str) -> bool {
+        match &self.password_hash {
+            Some(hash) => auth::verify_password(hash, password).await,
+            None => true,
+        }
+    }
+}
+
+/// 校验 `192.168.1.0/24`、`10.0.0.1`、`fd00::/8` 这样的地址范围
+pub fn is_valid_ip_range(range: &str) -> bool {
+    parse_ip_range(range).is_some()
+}
+
+fn parse_ip_range(range: &str) -> Option<(IpAddr, u8)> {
+    let (addr, prefix) = match range.split_once('/') {
+        Some((addr, prefix)) => (addr.trim().parse::<IpAddr>().ok()?, Some(prefix.trim())),
+        None => (range.trim().parse::<IpAddr>().ok()?, None),
+    };
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(p) => p.parse::<u8>().ok().filter(|p| *p <= max)?,
+        None => max,
+    };
+    Some((addr, prefix))
+}
+
+pub fn ip_in_range(ip: IpAddr, range: &str) -> bool {
+    let Some((network, prefix)) = parse_ip_range(range) else {
+        return false;
+    };
+    // IPv4 映射的 IPv6 地址（::ffff:a.b.c.d）按 IPv4 比较
+    let ip = match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        v4 => v4,
+    };
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(net)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(net) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(net)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
+
+/// 读取 JSON 数组，文件不存在时返回空列表
+pub(super) async fn load_json_list<T: DeserializeOwned>(file: &Path) -> std::io::Result<Vec<T>> {
+    match tokio::fs::read(file).await {
+        Ok(data) => serde_json::from_slice(&data)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// 以 JSON 数组的形式原子地写入
+pub(super) async fn save_json_list<'a, T: Serialize + 'a>(
+    file: &Path,
+    items: impl Iterator<Item = &'a T>,
+) -> std::io::Result<()> {
+    let data =
+        serde_json::to_vec_pretty(&items.collect::<Vec<_>>()).map_err(std::io::Error::other)?;
+    let mut file = AtomicFile::create(file).await?;
+    file.write_all(&data).await?;
+    file.commit().await
+}
+
+/// 访问凭据 -> (分享 token, 过期时间)
+type UnlockMap = HashMap<String, (String, DateTime<Utc>)>;
+
+#[derive(Clone)]
+pub struct ShareStore {
+    file: PathBuf,
+    shares: Arc<Mutex<BTreeMap<String, Share>>>,
+    /// 输入过密码的访问凭据，只保存在内存中
+    unlocked: Arc<Mutex<UnlockMap>>,
+}
+
+impl ShareStore {
+    pub async fn load(file: &Path) -> std::io::Result<Self> {
+        let shares = load_json_list::<Share>(file)
+            .await?
+            .into_iter()
+            .map(|s| (s.token.clone(), s))
+            .collect();
+        Ok(ShareStore {
+            file: file.to_path_buf(),
+            shares: Arc::new(Mutex::new(shares)),
+            unlocked: Arc::default(),
+        })
+    }
+
+    async fn save(&self, shares: &BTreeMap<String, Share>) -> std::io::Result<()> {
+        save_json_list(&self.file, shares.values()).await
+    }
+
+    pub async fn insert(&self, share: Share) -> std::io::Result<()> {
+        let mut shares = self.shares.lock().await;
+        shares.insert(share.token.clone(), share);
+        self.save(&shares).await
+    }
+
+    pub async fn get(&self, token: &str) -> Option<Share> {
+        self.shares.lock().await.get(token).cloned()
+    }
+
+    pub async fn list_by_owner(&self, owner: &str) -> Vec<Share> {
+        self.shares
+            .lock()
+            .await
+            .values()
+            .filter(|s| s.owner == owner)
+            .cloned()
+            .collect()
+    }
+
+    /// 只有分享的创建者可以撤销，返回是否删除了分享
+    pub async fn revoke(&self, token: &str, owner: &str) -> std::io::Result<bool> {
+        let mut shares = self.shares.lock().await;
+        if shares.get(token).is_none_or(|s| s.owner != owner) {
+            return Ok(false);
+        }
+        shares.remove(token);
+        self.save(&shares).await?;
+        Ok(true)
+    }
+
+    /// 为输入了正确密码的访问者生成访问凭据，放在 Cookie 中代替密码
+    pub async fn unlock(&self, token: &str) -> String {
+        let now = Utc::now();
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        let mut unlocked = self.unlocked.lock().await;
+        unlocked.retain(|_, (_, expires_at)| *expires_at > now);
+        unlocked.insert(
+            id.clone(),
+            (
+                token.to_string(),
+                now + TimeDelta::seconds(SHARE_UNLOCK_SECONDS),
+            ),
+        );
+        id
+    }
+
+    /// 访问凭据属于这个分享且没有过期
+    pub async fn is_unlocked(&self, token: &str, id: &str) -> bool {
+        self.unlocked
+            .lock()
+            .await
+            .get(id)
+            .is_some_and(|(t, expires_at)| t == token && *expires_at > Utc::now())
+    }
+
+    /// 在锁内重新检查次数限制后计数，避免并发下载超过上限
+    pub async fn record_download(&self, token: &str) -> std::io::Result<bool> {
+        let mut shares = self.shares.lock().await;
+        let Some(share) = shares.get_mut(token) else {
+            return Ok(false);
+        };
+        if share
+            .max_downloads
+            .is_some_and(|max| share.downloads >= max)
+        {
+            return Ok(false);
+        }
+        share.downloads += 1;
+        self.save(&shares).await?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,952 @@
+//! 配置校验：一次列出所有问题，并尽量给出所在的文件（或环境变量）以及行号和列号
+
+use std::{
+    collections::HashMap,
+    fmt,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+use axum::http::{HeaderName, HeaderValue, Method, Uri};
+use tracing_subscriber::EnvFilter;
+
+use crate::{
+    cors,
+    model::{
+        AcmeChallenge, ClientAuthMode, ConfigFromFile, ListenAddress,
+        auth::is_argon2_hash,
+        layers::{self, Layers, Location},
+        share::is_valid_ip_range,
+    },
+};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// 无法启动或重新加载
+    Error,
+    /// 可以运行，但很可能不是想要的结果
+    Warning,
+}
+
+pub struct ConfigIssue {
+    pub severity: Severity,
+    /// 配置项所在的层和位置，无法定位时为 `None`
+    pub location: Option<Location>,
+    pub message: String,
+}
+
+impl ConfigIssue {
+    /// 形如 `config.toml:12:5: 错误: ...`，方便编辑器跳转，无法定位时报告在 `file`
+    pub fn report(&self, file: &str) -> String {
+        let level = match self.severity {
+            Severity::Error => "错误",
+            Severity::Warning => "警告",
+        };
+        match &self.location {
+            Some(Location {
+                source,
+                position: Some((line, column)),
+                ..
+            }) => format!(
+                "{}:{}:{}: {}: {}",
+                source, line, column, level, self.message
+            ),
+            Some(Location { source, .. }) => format!("{}: {}: {}", source, level, self.message),
+            None => format!("{}: {}: {}", file, level, self.message),
+        }
+    }
+}
+
+/// 配置文件存在错误，包含全部错误
+#[derive(Debug)]
+pub struct ConfigError {
+    pub file: String,
+    pub errors: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "配置文件 {} 有 {} 个错误", self.file, self.errors.len())?;
+        for error in &self.errors {
+            write!(f, "\n  {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// 配置项在文件中的路径，例如 `users[0].permissions[1].path_name`
+#[derive(Clone, Copy)]
+enum Seg<'a> {
+    Key(&'a str),
+    Index(usize),
+}
+
+use Seg::{Index, Key};
+
+struct Checker<'l> {
+    layers: &'l Layers,
+    issues: Vec<ConfigIssue>,
+}
+
+impl Checker<'_> {
+    fn push(&mut self, severity: Severity, path: &[Seg], message: String) {
+        let path: Vec<layers::Seg> = path
+            .iter()
+            .map(|seg| match seg {
+                Key(key) => layers::Seg::Key(key.to_string()),
+                Index(i) => layers::Seg::Index(*i),
+            })
+            .collect();
+        let location = self.layers.locate(&path);
+        self.issues.push(ConfigIssue {
+            severity,
+            location,
+            message,
+        });
+    }
+
+    fn error(&mut self, path: &[Seg], message: String) {
+        self.push(Severity::Error, path, message);
+    }
+
+    fn warning(&mut self, path: &[Seg], message: String) {
+        self.push(Severity::Warning, path, message);
+    }
+
+    fn permission(&mut self, path: &[Seg], permission: u8) {
+        if permission > 0b111 {
+            self.error(
+                path,
+                format!(
+                    "无效的权限 {:#b}，只能使用 rwv 三位（0b000 到 0b111）",
+                    permission
+                ),
+            );
+        }
+    }
+
+    fn host(&mut self, path: &[Seg], host: Option<&String>) {
+        if let Some(host) = host
+            && host.parse::<IpAddr>().is_err()
+        {
+            self.error(path, format!("无法解析的监听地址 `{}`，需要 IP 地址", host));
+        }
+    }
+
+    fn file_exists(&mut self, path: &[Seg], file: &str, what: &str) {
+        if !Path::new(file).is_file() {
+            self.error(path, format!("找不到{} {}", what, file));
+        }
+    }
+}
+
+/// 检查配置中的所有问题，`layers` 用于定位配置项
+pub fn validate(layers: &Layers, config: &ConfigFromFile) -> Vec<ConfigIssue> {
+    let mut c = Checker {
+        layers,
+        issues: Vec::new(),
+    };
+
+    let mut path_names: HashMap<&str, usize> = HashMap::new();
+    for (i, p) in config.paths.iter().enumerate() {
+        if let Some(&first) = path_names.get(p.name.as_str()) {
+            c.error(
+                &[Key("paths"), Index(i), Key("name")],
+                format!(
+                    "[[paths]] 名称 `{}` 重复，与第 {} 个 [[paths]] 相同",
+                    p.name,
+                    first + 1
+                ),
+            );
+        } else {
+            path_names.insert(&p.name, i);
+        }
+        c.permission(&[Key("paths"), Index(i), Key("permission")], p.permission);
+        // `/?usr|bin?` 这样的集合写法不是真实路径
+        if !p.path.contains('?') && !Path::new(&p.path).is_dir() {
+            c.warning(
+                &[Key("paths"), Index(i), Key("path")],
+                format!("[[paths]] `{}` 的目录 {} 不存在", p.name, p.path),
+            );
+        }
+    }
+
+    let mut usernames: HashMap<&str, usize> = HashMap::new();
+    let mut access_keys: HashMap<&str, &str> = HashMap::new();
+    for (i, u) in config.users.iter().enumerate() {
+        if u.username.is_empty() {
+            c.error(
+                &[Key("users"), Index(i), Key("username")],
+                "用户名不能为空".to_string(),
+            );
+        }
+        if let Some(&first) = usernames.get(u.username.as_str()) {
+            c.error(
+                &[Key("users"), Index(i), Key("username")],
+                format!(
+                    "用户名 `{}` 重复，与第 {} 个 [[users]] 相同",
+                    u.username,
+                    first + 1
+                ),
+            );
+        } else {
+            usernames.insert(&u.username, i);
+        }
+        match (&u.password, &u.password_hash) {
+            (None, None) => c.error(
+                &[Key("users"), Index(i)],
+                format!("用户 `{}` 需要设置 password 或 password_hash", u.username),
+            ),
+            (Some(_), Some(_)) => c.error(
+                &[Key("users"), Index(i), Key("password_hash")],
+                format!(
+                    "用户 `{}` 只能设置 password 和 password_hash 其中一个",
+                    u.username
+                ),
+            ),
+            (None, Some(hash)) if !is_argon2_hash(hash) => c.error(
+                &[Key("users"), Index(i), Key("password_hash")],
+                format!(
+                    "用户 `{}` 的 password_hash 格式不正确，请用 hash-password 子命令生成",
+                    u.username
+                ),
+            ),
+            _ => {}
+        }
+        for (j, p) in u.permissions.iter().enumerate() {
+            let at = |field| {
+                [
+                    Key("users"),
+                    Index(i),
+                    Key("permissions"),
+                    Index(j),
+                    Key(field),
+                ]
+            };
+            if !path_names.contains_key(p.path_name.as_str()) {
+                let mut known: Vec<&str> = path_names.keys().copied().collect();
+                known.sort();
+                c.error(
+                    &at("path_name"),
+                    format!(
+                        "用户 `{}` 的 path_name `{}` 没有对应的 [[paths]]，可用的名称: {}",
+                        u.username,
+                        p.path_name,
+                        known.join(", ")
+                    ),
+                );
+            }
+            c.permission(&at("permission"), p.permission);
+        }
+        for (k, key) in u.access_keys.iter().enumerate() {
+            if let Some(owner) = access_keys.get(key.access_key_id.as_str()) {
+                c.error(
+                    &[
+                        Key("users"),
+                        Index(i),
+                        Key("access_keys"),
+                        Index(k),
+                        Key("access_key_id"),
+                    ],
+                    format!(
+                        "S3 access_key_id `{}` 重复，用户 `{}` 已经使用",
+                        key.access_key_id, owner
+                    ),
+                );
+            } else {
+                access_keys.insert(&key.access_key_id, &u.username);
+            }
+            // 无法解析的模式不会匹配任何请求，写在 deny 中时等于没有这条限制
+            for (n, statement) in key.policy.iter().enumerate() {
+                for (field, patterns) in [
+                    ("actions", &statement.actions),
+                    ("resources", &statement.resources),
+                ] {
+                    for (m, pattern) in patterns.iter().enumerate() {
+                        if let Err(e) = glob::Pattern::new(pattern) {
+                            c.error(
+                                &[
+                                    Key("users"),
+                                    Index(i),
+                                    Key("access_keys"),
+                                    Index(k),
+                                    Key("policy"),
+                                    Index(n),
+                                    Key(field),
+                                    Index(m),
+                                ],
+                                format!("无法解析的通配模式 `{}`: {}", pattern, e),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(debug) = config.debug.as_ref().filter(|d| d.enable)
+        && let Some(session) = &debug.debug_session
+        && !usernames.contains_key(session.username.as_str())
+    {
+        c.warning(
+            &[Key("debug"), Key("debug_session"), Key("username")],
+            format!("调试会话的用户 `{}` 不存在", session.username),
+        );
+    }
+
+    check_log_level(&mut c, config);
+    check_static_dir(&mut c, config);
+    check_listeners(&mut c, config);
+    check_proxy(&mut c, config);
+    check_cors(&mut c, config);
+    check_security_headers(&mut c, config);
+    check_tls(&mut c, config);
+    c.issues
+        .sort_by_key(|i| i.location.as_ref().map(|l| (l.layer, l.position)));
+    c.issues
+}
+
+/// 日志级别可以被 `EnvFilter` 解析
+fn check_log_level(c: &mut Checker, config: &ConfigFromFile) {
+    if let Some(level) = config.misc.as_ref().and_then(|m| m.log_level.as_ref())
+        && let Err(e) = EnvFilter::try_new(level)
+    {
+        c.error(
+            &[Key("misc"), Key("log_level")],
+            format!("无法解析的 log_level `{}`: {}", level, e),
+        );
+    }
+}
+
+/// 路径前缀只包含普通的路径段，可信代理都是合法的地址或网段
+fn check_proxy(c: &mut Checker, config: &ConfigFromFile) {
+    let Some(misc) = config.misc.as_ref() else {
+        return;
+    };
+    if let Some(base_path) = &misc.base_path {
+        let trimmed = base_path.trim_end_matches('/');
+        if !base_path.starts_with('/') {
+            c.error(
+                &[Key("misc"), Key("base_path")],
+                format!("base_path `{}` 需要以 / 开头", base_path),
+            );
+        } else if !trimmed.is_empty()
+            && trimmed[1..].split('/').any(|seg| {
+                seg.is_empty()
+                    || seg == "."
+                    || seg == ".."
+                    || !seg
+                        .chars()
+                        .all(|ch| ch.is_ascii_alphanumeric() || "-._~".contains(ch))
+            })
+        {
+            c.error(
+                &[Key("misc"), Key("base_path")],
+                format!(
+                    "base_path `{}` 的每一段只能包含字母、数字和 -._~，且不能为空、. 或 ..",
+                    base_path
+                ),
+            );
+        }
+    }
+    for (i, range) in misc.trusted_proxies.iter().flatten().enumerate() {
+        let at = [Key("misc"), Key("trusted_proxies"), Index(i)];
+        if !is_valid_ip_range(range) {
+            c.error(
+                &at,
+                format!("无法解析的可信代理 `{}`，需要 IP 地址或 CIDR 网段", range),
+            );
+        } else if range.trim().ends_with("/0") {
+            c.warning(
+                &at,
+                format!(
+                    "可信代理 `{}` 包含所有地址，任何客户端都可以伪造自己的 IP",
+                    range
+                ),
+            );
+        }
+    }
+}
+
+/// 来源是 `协议://主机[:端口]`，方法和请求头都能解析，允许凭据时不能使用 `*`
+fn check_cors(c: &mut Checker, config: &ConfigFromFile) {
+    let Some(cors) = config.cors.as_ref() else {
+        return;
+    };
+    for (i, origin) in cors.allowed_origins.iter().enumerate() {
+        if origin.trim() == "*" {
+            continue;
+        }
+        let valid = origin.parse::<Uri>().is_ok_and(|uri| {
+            matches!(uri.scheme_str(), Some("http" | "https"))
+                && uri.host().is_some()
+                && matches!(
+                    uri.path_and_query().map(|p| p.as_str()),
+                    None | Some("" | "/")
+                )
+        });
+        if !valid {
+            c.error(
+                &[Key("cors"), Key("allowed_origins"), Index(i)],
+                format!(
+                    "无法解析的来源 `{}`，需要 `https://example.com` 这样的形式，不能带路径",
+                    origin
+                ),
+            );
+        }
+    }
+    for (i, method) in cors.allowed_methods.iter().flatten().enumerate() {
+        if method.trim() != "*" && Method::from_bytes(method.as_bytes()).is_err() {
+            c.error(
+                &[Key("cors"), Key("allowed_methods"), Index(i)],
+                format!("无法解析的方法 `{}`", method),
+            );
+        }
+    }
+    for (field, headers) in [
+        ("allowed_headers", &cors.allowed_headers),
+        ("exposed_headers", &cors.exposed_headers),
+    ] {
+        for (i, name) in headers.iter().flatten().enumerate() {
+            if (field == "exposed_headers" || name.trim() != "*")
+                && HeaderName::from_bytes(name.trim().as_bytes()).is_err()
+            {
+                c.error(
+                    &[Key("cors"), Key(field), Index(i)],
+                    format!("无法解析的头部名称 `{}`", name),
+                );
+            }
+        }
+    }
+    if cors.allow_credentials.unwrap_or(false) {
+        for (field, values) in [
+            ("allowed_origins", Some(&cors.allowed_origins)),
+            ("allowed_methods", cors.allowed_methods.as_ref()),
+            ("allowed_headers", cors.allowed_headers.as_ref()),
+        ] {
+            if values.is_some_and(|v| cors::is_any(v)) {
+                c.error(
+                    &[Key("cors"), Key(field)],
+                    format!(
+                        "allow_credentials = true 时 {} 不能使用 \"*\"，需要逐个列出",
+                        field
+                    ),
+                );
+            }
+        }
+    }
+}
+
+/// 各项都能作为响应头的值，`Referrer-Policy` 是浏览器认识的取值
+fn check_security_headers(c: &mut Checker, config: &ConfigFromFile) {
+    const REFERRER_POLICIES: [&str; 8] = [
+        "no-referrer",
+        "no-referrer-when-downgrade",
+        "origin",
+        "origin-when-cross-origin",
+        "same-origin",
+        "strict-origin",
+        "strict-origin-when-cross-origin",
+        "unsafe-url",
+    ];
+    let Some(headers) = config.security_headers.as_ref() else {
+        return;
+    };
+    for (field, value) in [
+        ("content_security_policy", &headers.content_security_policy),
+        ("frame_ancestors", &headers.frame_ancestors),
+        ("referrer_policy", &headers.referrer_policy),
+    ] {
+        if let Some(value) = value
+            && HeaderValue::from_str(value.trim()).is_err()
+        {
+            c.error(
+                &[Key("security_headers"), Key(field)],
+                format!("{} 包含不能作为响应头的字符", field),
+            );
+        }
+    }
+    if let Some(policy) = &headers.referrer_policy
+        && !policy.trim().is_empty()
+        && !policy
+            .split(',')
+            .all(|p| REFERRER_POLICIES.contains(&p.trim()))
+    {
+        c.warning(
+            &[Key("security_headers"), Key("referrer_policy")],
+            format!(
+                "浏览器不认识的 referrer_policy `{}`，可用的值: {}",
+                policy,
+                REFERRER_POLICIES.join(", ")
+            ),
+        );
+    }
+}
+
+/// 设置了 `static_dir` 时目录中要有 `index.html`
+fn check_static_dir(c: &mut Checker, config: &ConfigFromFile) {
+    if let Some(dir) = config.static_dir()
+        && !Path::new(dir).join("index.html").is_file()
+    {
+        c.warning(
+            &[Key("misc"), Key("static_dir")],
+            format!("static_dir {} 中没有 index.html", dir),
+        );
+    }
+}
+
+/// 监听地址可以解析，且各服务的端口不冲突
+fn check_listeners(c: &mut Checker, config: &ConfigFromFile) {
+    let misc = config.misc.as_ref();
+    c.host(
+        &[Key("misc"), Key("host")],
+        misc.and_then(|m| m.host.as_ref()),
+    );
+
+    let host = config.host();
+    let mut listeners: Vec<(&str, String, u16, Vec<Seg>)> = Vec::new();
+    if let Some(m) = misc
+        && let Some(configured) = m.listeners.as_ref()
+    {
+        for (key, set) in [
+            ("port", m.port.is_some()),
+            ("https_port", m.https_port.is_some()),
+            ("http_listener", m.http_listener.is_some()),
+            ("enable_https", m.enable_https.is_some()),
+        ] {
+            if set {
+                c.warning(
+                    &[Key("misc"), Key(key)],
+                    format!("设置了 listeners 时忽略 {}", key),
+                );
+            }
+        }
+        let mut unix_paths: HashMap<PathBuf, usize> = HashMap::new();
+        for (i, listener) in configured.iter().enumerate() {
+            let at = [Key("misc"), Key("listeners"), Index(i)];
+            match listener.listen_address() {
+                Ok(ListenAddress::Tcp(addr)) => {
+                    for (field, set) in [
+                        ("mode", listener.mode.is_some()),
+                        ("owner", listener.owner.is_some()),
+                        ("group", listener.group.is_some()),
+                    ] {
+                        if set {
+                            c.warning(
+                                &[at[0], at[1], at[2], Key(field)],
+                                format!("{} 只对 Unix 套接字有效", field),
+                            );
+                        }
+                    }
+                    listeners.push((
+                        if listener.tls() { "HTTPS" } else { "HTTP" },
+                        addr.ip().to_string(),
+                        addr.port(),
+                        vec![at[0], at[1], at[2], Key("address")],
+                    ));
+                }
+                Ok(ListenAddress::Unix(_)) if !cfg!(unix) => {
+                    c.error(&at, "当前平台不支持 Unix 套接字".to_string());
+                }
+                Ok(ListenAddress::Unix(_)) if listener.tls() => {
+                    c.error(
+                        &[at[0], at[1], at[2], Key("tls")],
+                        "Unix 套接字不支持 tls".to_string(),
+                    );
+                }
+                Ok(ListenAddress::Unix(path)) => {
+                    if let Some(&first) = unix_paths.get(&path) {
+                        c.error(
+                            &[at[0], at[1], at[2], Key("unix")],
+                            format!(
+                                "Unix 套接字 {} 与第 {} 个 listener 重复",
+                                path.display(),
+                                first + 1
+                            ),
+                        );
+                    } else {
+                        unix_paths.insert(path, i);
+                    }
+                    if listener.mode.is_some_and(|m| m > 0o7777) {
+                        c.error(
+                            &[at[0], at[1], at[2], Key("mode")],
+                            "mode 需要是 0o0000 到 0o7777 之间的权限".to_string(),
+                        );
+                    }
+                }
+                Err(message) => c.error(&at, message),
+            }
+        }
+    } else {
+        listeners.push((
+            "HTTP",
+            host.to_string(),
+            config.port(),
+            vec![Key("misc"), Key("port")],
+        ));
+        if misc.and_then(|m| m.enable_https) != Some(false) {
+            listeners.push((
+                "HTTPS",
+                host.to_string(),
+                misc.and_then(|m| m.https_port)
+                    .unwrap_or(crate::tls::DEFAULT_HTTPS_PORT),
+                vec![Key("misc"), Key("https_port")],
+            ));
+        }
+    }
+    if let Some(s3) = config.s3.as_ref().filter(|s| s.enable) {
+        c.host(&[Key("s3"), Key("host")], s3.host.as_ref());
+        listeners.push((
+            "S3",
+            s3.host.clone().unwrap_or(host.to_string()),
+            s3.port.unwrap_or(crate::s3::DEFAULT_PORT),
+            vec![Key("s3"), Key("port")],
+        ));
+    }
+    if let Some(sftp) = config.sftp.as_ref().filter(|s| s.enable) {
+        c.host(&[Key("sftp"), Key("host")], sftp.host.as_ref());
+        listeners.push((
+            "SFTP",
+            sftp.host.clone().unwrap_or(host.to_string()),
+            sftp.port.unwrap_or(crate::sftp::DEFAULT_PORT),
+            vec![Key("sftp"), Key("port")],
+        ));
+    }
+    if let Some(metrics) = config.metrics.as_ref().filter(|m| m.enable) {
+        c.host(&[Key("metrics"), Key("host")], metrics.host.as_ref());
+        if let Some(port) = metrics.port {
+            listeners.push((
+                "指标",
+                metrics.host.clone().unwrap_or(host.to_string()),
+                port,
+                vec![Key("metrics"), Key("port")],
+            ));
+        } else if metrics.token.is_none() {
+            c.warning(
+                &[Key("metrics"), Key("enable")],
+                "/metrics 与文件服务共用端口且没有设置 token，任何人都可以读取指标".to_string(),
+            );
+        }
+    }
+    if let Some(ftp) = config.ftp.as_ref().filter(|f| f.enable) {
+        c.host(&[Key("ftp"), Key("host")], ftp.host.as_ref());
+        listeners.push((
+            "FTP",
+            ftp.host.clone().unwrap_or(host.to_string()),
+            ftp.port.unwrap_or(crate::ftp::DEFAULT_PORT),
+            vec![Key("ftp"), Key("port")],
+        ));
+        if let Some([min, max]) = ftp.passive_ports
+            && min > max
+        {
+            c.error(
+                &[Key("ftp"), Key("passive_ports")],
+                format!("passive_ports 的起始端口 {} 大于结束端口 {}", min, max),
+            );
+        }
+    }
+
+    for (i, (name, host, port, at)) in listeners.iter().enumerate() {
+        if let Some((other, ..)) = listeners[..i]
+            .iter()
+            .find(|(_, h, p, _)| h == host && p == port)
+        {
+            c.error(
+                at,
+                format!("{} 与 {} 使用了同一个端口 {}", name, other, port),
+            );
+        }
+    }
+}
+
+/// HTTPS、客户端证书和 ACME 需要的文件和取值
+fn check_tls(c: &mut Checker, config: &ConfigFromFile) {
+    let misc = config.misc.as_ref();
+    let acme = config.acme.as_ref().filter(|a| a.enable);
+
+    if let Some(version) = misc.and_then(|m| m.tls_min_version.as_ref())
+        && version != "1.2"
+        && version != "1.3"
+    {
+        c.error(
+            &[Key("misc"), Key("tls_min_version")],
+            format!(
+                "不支持的 tls_min_version `{}`，只能是 \"1.2\" 或 \"1.3\"",
+                version
+            ),
+        );
+    }
+
+    // 设置了 listeners 时由其中的 tls 决定是否启用 HTTPS，忽略 enable_https
+    let listeners = misc.and_then(|m| m.listeners.as_ref());
+    let enable_https = match listeners {
+        Some(listeners) => Some(listeners.iter().any(|l| l.tls())),
+        None => misc.and_then(|m| m.enable_https),
+    };
+    let enable_https_key = if listeners.is_some() {
+        "listeners"
+    } else {
+        "enable_https"
+    };
+
+    if acme.is_none() && enable_https != Some(false) {
+        // 显式指定的证书文件必须存在；使用默认位置时没有证书就不启用 HTTPS
+        let explicit = enable_https == Some(true);
+        let (cert_file, key_file) = config.tls_files();
+        for (field, configured, file, what) in [
+            (
+                "cert_file",
+                misc.is_some_and(|m| m.cert_file.is_some()),
+                cert_file,
+                "证书文件",
+            ),
+            (
+                "key_file",
+                misc.is_some_and(|m| m.key_file.is_some()),
+                key_file,
+                "私钥文件",
+            ),
+        ] {
+            if (explicit || configured) && !file.is_file() {
+                let at = if configured { field } else { enable_https_key };
+                c.error(
+                    &[Key("misc"), Key(at)],
+                    format!("找不到{} {}", what, file.display()),
+                );
+            }
+        }
+    }
+
+    if misc
+        .and_then(|m| m.client_auth)
+        .unwrap_or(ClientAuthMode::Ignore)
+        != ClientAuthMode::Ignore
+    {
+        match misc.and_then(|m| m.client_ca_file.as_ref()) {
+            Some(file) => c.file_exists(
+                &[Key("misc"), Key("client_ca_file")],
+                file,
+                "客户端 CA 证书",
+            ),
+            None => c.error(
+                &[Key("misc"), Key("client_auth")],
+                "client_auth 需要配置 client_ca_file".to_string(),
+            ),
+        }
+    }
+
+    if let Some(acme) = acme {
+        if acme.domains.is_empty() {
+            c.error(
+                &[Key("acme"), Key("domains")],
+                "[acme] domains 不能为空".to_string(),
+            );
+        }
+        if let Some(file) = &acme.directory_ca_file {
+            c.file_exists(
+                &[Key("acme"), Key("directory_ca_file")],
+                file,
+                "ACME 根证书",
+            );
+        }
+        if enable_https == Some(false) {
+            c.error(
+                &[Key("misc"), Key(enable_https_key)],
+                if listeners.is_some() {
+                    "启用 ACME 时 listeners 中需要有 tls = true 的地址".to_string()
+                } else {
+                    "启用 ACME 时不能设置 enable_https = false".to_string()
+                },
+            );
+        }
+        let (http_listener, http_listener_key) = match listeners {
+            Some(listeners) => (
+                listeners.iter().any(|l| l.address.is_some() && !l.tls()),
+                "listeners",
+            ),
+            None => (
+                misc.and_then(|m| m.http_listener).unwrap_or(true),
+                "http_listener",
+            ),
+        };
+        if acme.challenge.unwrap_or(AcmeChallenge::Http01) == AcmeChallenge::Http01
+            && !http_listener
+        {
+            c.warning(
+                &[Key("misc"), Key(http_listener_key)],
+                "ACME 使用 http-01 验证，但没有提供 HTTP 的 TCP 地址，验证将无法通过".to_string(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::MiscFromFile;
+
+    /// 写入 `config.toml` 并检查，返回文件路径和所有问题的报告
+    async fn check(dir: &Path, content: &str) -> (String, Vec<(Severity, String)>) {
+        let path = dir.join("config.toml").display().to_string();
+        std::fs::write(&path, content).unwrap();
+        let (_, issues) = ConfigFromFile::check(&path, &MiscFromFile::default())
+            .await
+            .unwrap();
+        let reports = issues
+            .iter()
+            .map(|i| (i.severity, i.report(&path)))
+            .collect();
+        (path, reports)
+    }
+
+    #[tokio::test]
+    async fn issues_point_at_line_and_column() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = format!(
+            r#"[[paths]]
+name = "data"
+path = {:?}
+permission = 0b111
+
+[[users]]
+username = "alice"
+password = "x"
+permissions = [{{ path_name = "data", permission = 0b111 }}]
+
+[[users]]
+username = "alice"
+password = "x"
+permissions = [{{ path_name = "dtaa", permission = 0b1000 }}]
+"#,
+            dir.path().display().to_string()
+        );
+        let (path, reports) = check(dir.path(), &content).await;
+        let reports: Vec<_> = reports
+            .into_iter()
+            .map(|(severity, report)| {
+                assert!(severity == Severity::Error, "{}", report);
+                report
+            })
+            .collect();
+        assert_eq!(
+            reports,
+            [
+                format!("{path}:12:12: 错误: 用户名 `alice` 重复，与第 1 个 [[users]] 相同"),
+                format!(
+                    "{path}:14:30: 错误: 用户 `alice` 的 path_name `dtaa` 没有对应的 [[paths]]，可用的名称: data"
+                ),
+                format!(
+                    "{path}:14:51: 错误: 无效的权限 0b1000，只能使用 rwv 三位（0b000 到 0b111）"
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn warnings_do_not_block_loading() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing").display().to_string();
+        let content = format!(
+            "users = []\n[[paths]]\nname = \"data\"\npath = {:?}\npermission = 0b111\n",
+            missing
+        );
+        let (path, reports) = check(dir.path(), &content).await;
+        assert_eq!(reports.len(), 1);
+        assert!(reports[0].0 == Severity::Warning);
+        assert!(reports[0].1.starts_with(&format!("{path}:4:8: 警告")));
+        assert!(
+            ConfigFromFile::from_toml(&path, &MiscFromFile::default())
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn errors_are_collected_into_one_config_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = "paths = []\n\n[[users]]\nusername = \"\"\npermissions = []\n";
+        let path = dir.path().join("config.toml").display().to_string();
+        std::fs::write(&path, content).unwrap();
+        let error = ConfigFromFile::from_toml(&path, &MiscFromFile::default())
+            .await
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(
+            error.starts_with(&format!("配置文件 {path} 有 2 个错误")),
+            "{}",
+            error
+        );
+        assert!(
+            error.contains(&format!("{path}:4:12: 错误: 用户名不能为空")),
+            "{}",
+            error
+        );
+        assert!(
+            error.contains("需要设置 password 或 password_hash"),
+            "{}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn type_errors_have_locations() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = "users = []\npaths = []\n\n[misc]\nport = \"http\"\n";
+        let path = dir.path().join("config.toml").display().to_string();
+        std::fs::write(&path, content).unwrap();
+        let error = ConfigFromFile::check(&path, &MiscFromFile::default())
+            .await
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(
+            error.contains(&format!("{path}:5:8: 错误: misc.port")),
+            "{}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn listeners_are_checked() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["cert.pem", "key.pem"] {
+            std::fs::write(dir.path().join(file), "").unwrap();
+        }
+        let content = format!(
+            r#"users = []
+paths = []
+
+[misc]
+port = 8000
+cert_path = {:?}
+listeners = [
+    {{ address = "localhost:8080" }},
+    {{ address = "127.0.0.1:8081", mode = 0o660 }},
+    {{ unix = "/run/sfm.sock" }},
+    {{ unix = "/run/sfm.sock" }},
+    {{ unix = "/run/tls.sock", tls = true }},
+    {{ unix = "/run/sfm.sock", address = "127.0.0.1:8082" }},
+]
+"#,
+            dir.path().display().to_string()
+        );
+        let (path, reports) = check(dir.path(), &content).await;
+        let reports: Vec<_> = reports.into_iter().map(|(_, r)| r).collect();
+        assert_eq!(
+            reports,
+            [
+                format!("{path}:5:8: 警告: 设置了 listeners 时忽略 port"),
+                format!(
+                    "{path}:8:5: 错误: 无法解析的监听地址 `localhost:8080`，需要 `IP:端口`，IPv6 写作 `[::]:8080`"
+                ),
+                format!("{path}:9:42: 警告: mode 只对 Unix 套接字有效"),
+                format!("{path}:11:14: 错误: Unix 套接字 /run/sfm.sock 与第 3 个 listener 重复"),
+                format!("{path}:12:37: 错误: Unix 套接字不支持 tls"),
+                format!("{path}:13:5: 错误: address 和 unix 只能设置其中一个"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,355 @@
+//! 反向代理：`misc.base_path` 把所有页面和接口挂在一个路径前缀下；来自 `misc.trusted_proxies`
+//! 的请求使用 `Forwarded`、`X-Forwarded-For` 和 `X-Forwarded-Proto` 中的客户端地址和协议，
+//! 审计、访问日志和分享的 IP 限制看到的都是这里得到的 [`Client`]。
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts, Request, State},
+    http::{self, HeaderMap, StatusCode, Uri, header},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+};
+
+use crate::{
+    model::{AppState, MiscFromFile, share::ip_in_range},
+    tls::ClientCertNames,
+};
+
+/// 路径前缀和可信代理，启动时由 `[misc]` 得到，修改后需要重启
+#[derive(Clone, Default)]
+pub struct Proxy {
+    base_path: Arc<str>,
+    trusted: Arc<Vec<String>>,
+}
+
+/// 发起请求的客户端，经过可信代理时取代理转发的地址和协议
+#[derive(Clone, Copy)]
+pub struct Client {
+    pub ip: IpAddr,
+    /// 客户端是否通过 HTTPS 访问
+    pub https: bool,
+}
+
+impl Proxy {
+    pub fn new(misc: Option<&MiscFromFile>) -> Self {
+        Proxy {
+            base_path: normalize_base_path(misc.and_then(|m| m.base_path.as_deref())).into(),
+            trusted: Arc::new(
+                misc.and_then(|m| m.trusted_proxies.clone())
+                    .unwrap_or_default(),
+            ),
+        }
+    }
+
+    /// 去掉末尾 `/` 的路径前缀，例如 `/files`，没有设置时为空
+    pub fn base_path(&self) -> &str {
+        &self.base_path
+    }
+
+    /// 加上路径前缀，`path` 以 `/` 开头
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_path, path)
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted.iter().any(|range| ip_in_range(ip, range))
+    }
+
+    /// 直接连接的地址 `peer` 是可信代理时，从右向左跳过转发链中的可信代理，
+    /// 第一个不可信的地址就是客户端；链中有无法解析的地址时停在它右边的一跳
+    pub fn client(&self, peer: IpAddr, headers: &HeaderMap, https: bool) -> Client {
+        if !self.is_trusted(peer) {
+            return Client { ip: peer, https };
+        }
+        let forwarded = forwarded_elements(headers);
+        let (chain, proto): (Vec<Option<IpAddr>>, _) = if forwarded.is_empty() {
+            (
+                header_list(headers, "x-forwarded-for")
+                    .iter()
+                    .map(|hop| parse_node(hop))
+                    .collect(),
+                header_list(headers, "x-forwarded-proto").into_iter().next(),
+            )
+        } else {
+            let proto = forwarded.first().and_then(|(_, proto)| proto.clone());
+            (forwarded.into_iter().map(|(node, _)| node).collect(), proto)
+        };
+
+        let mut ip = peer;
+        for hop in chain.iter().rev() {
+            let Some(hop) = *hop else {
+                break;
+            };
+            ip = hop;
+            if !self.is_trusted(hop) {
+                break;
+            }
+        }
+        let https = match proto.map(|p| p.to_ascii_lowercase()).as_deref() {
+            Some("https") => true,
+            Some("http") => false,
+            _ => https,
+        };
+        Client { ip, https }
+    }
+}
+
+/// 统一为 `/前缀` 的形式，`/` 和空字符串表示不使用前缀
+fn normalize_base_path(base_path: Option<&str>) -> String {
+    let trimmed = base_path.unwrap_or("").trim().trim_matches('/');
+    if trimmed.is_empty() {
+        String::new()
+    } else {
+        format!("/{}", trimmed)
+    }
+}
+
+/// 逗号分隔的请求头，可以出现多次
+fn header_list(headers: &HeaderMap, name: &str) -> Vec<String> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+/// `Forwarded` 中每一跳的 `for` 和 `proto`（RFC 7239）
+fn forwarded_elements(headers: &HeaderMap) -> Vec<(Option<IpAddr>, Option<String>)> {
+    header_list(headers, header::FORWARDED.as_str())
+        .iter()
+        .map(|element| {
+            let mut node = None;
+            let mut proto = None;
+            for pair in element.split(';') {
+                let Some((key, value)) = pair.split_once('=') else {
+                    continue;
+                };
+                let value = value.trim().trim_matches('"');
+                match key.trim().to_ascii_lowercase().as_str() {
+                    "for" => node = parse_node(value),
+                    "proto" => proto = Some(value.to_string()),
+                    _ => {}
+                }
+            }
+            (node, proto)
+        })
+        .collect()
+}
+
+/// `192.0.2.1`、`192.0.2.1:4711`、`2001:db8::1` 或 `[2001:db8::1]:4711`，
+/// `unknown` 和隐藏的标识符返回 `None`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    node.strip_prefix('[')
+        .and_then(|n| n.strip_suffix(']'))
+        .and_then(|n| n.parse().ok())
+}
+
+/// 请求直接来自的地址，HTTPS 连接上总有 [`ClientCertNames`] 扩展
+pub fn peer(extensions: &http::Extensions) -> Option<(IpAddr, bool)> {
+    let ConnectInfo(addr) = extensions.get::<ConnectInfo<SocketAddr>>()?;
+    Some((addr.ip(), extensions.get::<ClientCertNames>().is_some()))
+}
+
+/// 计算 [`Client`] 放进请求扩展，挂在最外层
+pub async fn middleware(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if let Some((ip, https)) = peer(request.extensions()) {
+        let client = state.proxy.client(ip, request.headers(), https);
+        request.extensions_mut().insert(client);
+    }
+    next.run(request).await
+}
+
+/// 去掉 `misc.base_path` 前缀后交给内层路由，前缀之外的路径返回 404。
+/// 不带末尾 `/` 的前缀重定向到 `前缀/`，否则页面中的相对路径会指向上一级
+pub async fn strip_base_path(
+    State(proxy): State<Proxy>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let uri = request.uri();
+    let Some(rest) = uri.path().strip_prefix(proxy.base_path()) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if rest.is_empty() {
+        let location = match uri.query() {
+            Some(query) => format!("{}/?{}", proxy.base_path(), query),
+            None => format!("{}/", proxy.base_path()),
+        };
+        return Redirect::permanent(&location).into_response();
+    }
+    if !rest.starts_with('/') {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{}?{}", rest, query),
+        None => rest.to_string(),
+    };
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = path_and_query.parse().ok();
+    match Uri::from_parts(parts) {
+        Ok(uri) => *request.uri_mut() = uri,
+        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    }
+    next.run(request).await
+}
+
+impl<S> FromRequestParts<S> for Client
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(
+        parts: &mut http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        if let Some(client) = parts.extensions.get::<Client>() {
+            return Ok(*client);
+        }
+        // 没有经过中间件时使用直接连接的地址
+        peer(&parts.extensions)
+            .map(|(ip, https)| Client { ip, https })
+            .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "无法取得客户端地址"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderName, HeaderValue};
+
+    use super::*;
+
+    fn proxy(trusted: &[&str]) -> Proxy {
+        Proxy::new(Some(&MiscFromFile {
+            trusted_proxies: Some(trusted.iter().map(|r| r.to_string()).collect()),
+            ..Default::default()
+        }))
+    }
+
+    /// 同名请求头可以出现多次
+    fn header_map(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(
+                HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        headers
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn untrusted_peer_ignores_forwarded_headers() {
+        let proxy = proxy(&["10.0.0.0/8"]);
+        let headers = header_map(&[
+            ("x-forwarded-for", "203.0.113.7"),
+            ("x-forwarded-proto", "https"),
+            ("forwarded", "for=198.51.100.1;proto=https"),
+        ]);
+        let client = proxy.client(ip("192.0.2.10"), &headers, false);
+        assert_eq!(client.ip, ip("192.0.2.10"));
+        assert!(!client.https);
+    }
+
+    #[test]
+    fn skips_trusted_hops_from_the_right() {
+        let proxy = proxy(&["10.0.0.0/8"]);
+        let headers = header_map(&[
+            ("x-forwarded-for", "198.51.100.1, 203.0.113.7"),
+            ("x-forwarded-for", "10.0.0.2"),
+            ("x-forwarded-proto", "https"),
+        ]);
+        let client = proxy.client(ip("10.0.0.1"), &headers, false);
+        // 最左边的地址可以由客户端伪造，取最右边的不可信地址
+        assert_eq!(client.ip, ip("203.0.113.7"));
+        assert!(client.https);
+    }
+
+    #[test]
+    fn all_trusted_chain_uses_leftmost_hop() {
+        let proxy = proxy(&["10.0.0.0/8", "fd00::/8"]);
+        let headers = header_map(&[("x-forwarded-for", "10.1.2.3, fd00::1, 10.0.0.2")]);
+        let client = proxy.client(ip("10.0.0.1"), &headers, true);
+        assert_eq!(client.ip, ip("10.1.2.3"));
+        assert!(client.https);
+    }
+
+    #[test]
+    fn unknown_hop_stops_the_walk() {
+        let proxy = proxy(&["10.0.0.0/8"]);
+        let headers = header_map(&[("x-forwarded-for", "203.0.113.7, unknown, 10.0.0.2")]);
+        assert_eq!(
+            proxy.client(ip("10.0.0.1"), &headers, false).ip,
+            ip("10.0.0.2")
+        );
+
+        let headers = header_map(&[("forwarded", "for=203.0.113.7, for=_hidden")]);
+        assert_eq!(
+            proxy.client(ip("10.0.0.1"), &headers, false).ip,
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn parses_ipv6_and_ports() {
+        let proxy = proxy(&["10.0.0.0/8"]);
+        let headers = header_map(&[(
+            "forwarded",
+            r#"for="[2001:db8::1]:4711";proto=https, for=10.0.0.2:8080"#,
+        )]);
+        let client = proxy.client(ip("10.0.0.1"), &headers, false);
+        assert_eq!(client.ip, ip("2001:db8::1"));
+        assert!(client.https);
+
+        let headers = header_map(&[("x-forwarded-for", "[2001:db8::2]:443, 192.0.2.1:51000")]);
+        assert_eq!(
+            proxy.client(ip("10.0.0.1"), &headers, false).ip,
+            ip("192.0.2.1")
+        );
+
+        assert_eq!(parse_node("[2001:db8::3]"), Some(ip("2001:db8::3")));
+        assert_eq!(parse_node("2001:db8::4"), Some(ip("2001:db8::4")));
+        assert_eq!(parse_node("unknown"), None);
+    }
+
+    #[test]
+    fn forwarded_takes_precedence_over_x_forwarded() {
+        let proxy = proxy(&["10.0.0.0/8"]);
+        let headers = header_map(&[
+            ("x-forwarded-for", "203.0.113.7"),
+            ("x-forwarded-proto", "https"),
+            ("forwarded", "for=198.51.100.1;proto=http"),
+        ]);
+        let client = proxy.client(ip("10.0.0.1"), &headers, true);
+        assert_eq!(client.ip, ip("198.51.100.1"));
+        assert!(!client.https);
+
+        // 没有 proto 时保持连接本身的协议
+        let headers = header_map(&[
+            ("x-forwarded-for", "203.0.113.7"),
+            ("forwarded", "for=198.51.100.1"),
+        ]);
+        assert!(proxy.client(ip("10.0.0.1"), &headers, true).https);
+    }
+}
@@ -1,24 +1,117 @@
 use crate::model::AppState;
-use crate::{handler, model::ConfigFromFile};
+use crate::{
+    access_log, audit, compression, cors, handler,
+    model::ConfigFromFile,
+    proxy,
+    security_headers::{self, SecurityHeaders},
+    static_files::{self, StaticFiles},
+    telemetry,
+};
 use axum::{
     Router,
     extract::DefaultBodyLimit,
-    routing::{get, post},
+    middleware,
+    routing::{delete, get, post},
 };
 
 pub fn create_router(state: AppState, config: &ConfigFromFile) -> Router {
     let mut router = Router::new()
+        .route("/healthz", get(handler::healthz))
+        .route("/readyz", get(handler::readyz))
         .route("/api/login", post(handler::login))
+        .route("/api/logout", post(handler::logout))
         .route("/api/files", get(handler::list_files))
         .route("/api/upload", post(handler::upload))
         .route("/api/download", get(handler::download))
-        .with_state(state);
+        .route(
+            "/api/admin/log-level",
+            get(handler::get_log_level).put(handler::set_log_level),
+        )
+        .route("/api/admin/audit", get(handler::query_audit))
+        .route(
+            "/api/shares",
+            get(handler::list_shares).post(handler::create_share),
+        )
+        .route("/api/shares/{token}", delete(handler::revoke_share))
+        .route(
+            "/s/{token}",
+            get(handler::public_share).post(handler::unlock_share),
+        )
+        .route(
+            "/api/file-requests",
+            get(handler::list_file_requests).post(handler::create_file_request),
+        )
+        .route(
+            "/api/file-requests/{token}",
+            delete(handler::revoke_file_request),
+        )
+        .route(
+            "/r/{token}",
+            get(handler::file_request_page).post(handler::file_request_upload),
+        )
+        // 只作用于上面的路由，静态文件不记录
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            audit::middleware,
+        ))
+        .with_state(state.clone());
+
+    // 没有单独设置端口时，指标与文件服务共用端口
+    if let Some(metrics) = config
+        .metrics
+        .as_ref()
+        .filter(|m| m.enable && m.port.is_none())
+    {
+        router = router.merge(telemetry::router(state.clone(), metrics));
+    }
 
-    if let Some(max_size) = config.misc.as_ref().and_then(|e| e.max_upload_size.clone()) {
+    if let Some(max_size) = config.misc.as_ref().and_then(|e| e.max_upload_size) {
         tracing::info!("设置最大上传大小为 {}", max_size);
         router = router.layer(DefaultBodyLimit::max(max_size));
     }
 
-    // 添加静态文件服务作为fallback
-    router.fallback_service(tower_http::services::ServeDir::new("static"))
+    // 添加前端页面作为fallback，按内容哈希设置缓存
+    let static_files = StaticFiles::new(config.static_dir(), state.proxy.base_path());
+    let mut router = router
+        .fallback_service(get(static_files::serve).with_state(static_files))
+        .layer(middleware::from_fn(telemetry::middleware));
+    if let Some(compression) = compression::layer(config.misc.as_ref()) {
+        // 在访问日志内侧，记录的是实际发送的字节数
+        router = router.layer(compression);
+    }
+    if let Some(cors) = cors::layer(config.cors.as_ref()) {
+        // 预检请求在这里直接应答，仍然记录访问日志
+        router = router.layer(cors);
+    }
+    let router = router
+        // 分配请求 ID，其余中间件和处理函数都在它的 span 中运行
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            access_log::middleware,
+        ))
+        // 最外层取得真实的客户端地址，访问日志和审计都使用它
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            proxy::middleware,
+        ));
+
+    let router = if state.proxy.base_path().is_empty() {
+        router
+    } else {
+        tracing::info!("所有页面和接口挂在 {} 下", state.proxy.base_path());
+        // 在内层路由匹配之前去掉前缀，路由和审计看到的路径与不设置前缀时相同
+        Router::new()
+            .fallback_service(router)
+            .layer(middleware::from_fn_with_state(
+                state.proxy.clone(),
+                proxy::strip_base_path,
+            ))
+    };
+    match SecurityHeaders::new(config.security_headers.as_ref()) {
+        Some(headers) => router.layer(middleware::from_fn_with_state(
+            headers,
+            security_headers::middleware,
+        )),
+        None => router,
+    }
 }
@@ -0,0 +1,175 @@
+//! 请求体读取：处理 aws-chunked 编码并校验负载哈希
+
+use std::pin::Pin;
+
+use axum::body::Body;
+use bytes::Bytes;
+use futures_util::{StreamExt, TryStreamExt};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
+use tokio_util::io::StreamReader;
+
+use crate::s3::auth::{ChunkSigner, PayloadHash};
+use crate::s3::error::S3Error;
+use crate::utils::AtomicFile;
+
+/// 小型 XML 请求体（DeleteObjects、CompleteMultipartUpload）的大小上限
+const MAX_XML_BODY: u64 = 2 * 1024 * 1024;
+/// aws-chunked 块头的最大长度，防止恶意的超长行
+const MAX_CHUNK_HEADER: u64 = 4096;
+/// aws-chunked 单块数据的大小上限，SDK 通常使用 64 KiB 到 8 MiB 的块
+const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+type Reader = Pin<Box<dyn AsyncRead + Send>>;
+
+pub struct RequestBody {
+    reader: Reader,
+    expected_sha256: Option<String>,
+}
+
+impl RequestBody {
+    pub fn new(body: Body, payload: PayloadHash) -> Self {
+        let raw = StreamReader::new(body.into_data_stream().map_err(std::io::Error::other));
+        match payload {
+            PayloadHash::Unsigned => RequestBody {
+                reader: Box::pin(raw),
+                expected_sha256: None,
+            },
+            PayloadHash::Sha256(hash) => RequestBody {
+                reader: Box::pin(raw),
+                expected_sha256: Some(hash),
+            },
+            PayloadHash::Chunked { signer } => {
+                let decoder = ChunkDecoder {
+                    reader: BufReader::new(Box::pin(raw)),
+                    signer,
+                    done: false,
+                };
+                let stream = futures_util::stream::unfold(decoder, |mut d| async move {
+                    match d.next_chunk().await {
+                        Ok(Some(chunk)) => Some((Ok(chunk), d)),
+                        Ok(None) => None,
+                        Err(e) => {
+                            d.done = true;
+                            Some((Err(e), d))
+                        }
+                    }
+                });
+                RequestBody {
+                    reader: Box::pin(StreamReader::new(stream.boxed())),
+                    expected_sha256: None,
+                }
+            }
+        }
+    }
+
+    /// 流式写入目标文件，返回带引号的 MD5，用作分片的 ETag；哈希不符时不会落盘
+    pub async fn write_to(mut self, target: &std::path::Path) -> Result<String, S3Error> {
+        let mut file = AtomicFile::create(target).await?;
+        let mut md5 = Md5::new();
+        let mut sha256 = Sha256::new();
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = self.reader.read(&mut buf).await.map_err(read_error)?;
+            if n == 0 {
+                break;
+            }
+            md5.update(&buf[..n]);
+            sha256.update(&buf[..n]);
+            file.write_all(&buf[..n]).await?;
+        }
+        self.check_sha256(sha256)?;
+        file.commit().await?;
+        Ok(format!("\"{}\"", hex::encode(md5.finalize())))
+    }
+
+    pub async fn read_xml(mut self) -> Result<String, S3Error> {
+        let mut data = Vec::new();
+        (&mut self.reader)
+            .take(MAX_XML_BODY)
+            .read_to_end(&mut data)
+            .await
+            .map_err(read_error)?;
+        self.check_sha256(Sha256::new_with_prefix(&data))?;
+        String::from_utf8(data).map_err(|_| S3Error::MalformedXml)
+    }
+
+    fn check_sha256(&self, hasher: Sha256) -> Result<(), S3Error> {
+        match &self.expected_sha256 {
+            Some(expected) if hex::encode(hasher.finalize()) != *expected => {
+                Err(S3Error::ContentSha256Mismatch)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn read_error(e: std::io::Error) -> S3Error {
+    if e.kind() == std::io::ErrorKind::PermissionDenied {
+        S3Error::SignatureDoesNotMatch
+    } else if e.kind() == std::io::ErrorKind::InvalidData {
+        S3Error::InvalidArgument(e.to_string())
+    } else {
+        S3Error::Internal(e)
+    }
+}
+
+/// aws-chunked 解码：`<hex-size>[;chunk-signature=<sig>]\r\n<data>\r\n ... 0\r\n<trailers>\r\n`
+struct ChunkDecoder {
+    reader: BufReader<Reader>,
+    signer: Option<ChunkSigner>,
+    done: bool,
+}
+
+impl ChunkDecoder {
+    async fn next_chunk(&mut self) -> std::io::Result<Option<Bytes>> {
+        if self.done {
+            return Ok(None);
+        }
+        let mut line = String::new();
+        (&mut self.reader)
+            .take(MAX_CHUNK_HEADER)
+            .read_line(&mut line)
+            .await?;
+        let line = line.trim_end_matches(['\r', '\n']);
+        let (size, extension) = line.split_once(';').unwrap_or((line, ""));
+        let size = usize::from_str_radix(size.trim(), 16).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "无效的 aws-chunked 块头")
+        })?;
+        // 块大小由客户端决定，按它分配内存前先检查上限
+        if size > MAX_CHUNK_SIZE {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("aws-chunked 块大小 {} 超过上限 {}", size, MAX_CHUNK_SIZE),
+            ));
+        }
+
+        let mut data = vec![0u8; size];
+        self.reader.read_exact(&mut data).await?;
+
+        if let Some(signer) = &mut self.signer {
+            let signature = extension
+                .trim()
+                .strip_prefix("chunk-signature=")
+                .unwrap_or_default();
+            if !signer.verify(&data, signature) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    "块签名校验失败",
+                ));
+            }
+        }
+
+        if size == 0 {
+            // 最后一块之后是可选的 trailer（例如 x-amz-checksum-crc32），直接丢弃
+            self.done = true;
+            tokio::io::copy(&mut self.reader, &mut tokio::io::sink()).await?;
+            return Ok(None);
+        }
+
+        let mut crlf = [0u8; 2];
+        self.reader.read_exact(&mut crlf).await?;
+        Ok(Some(Bytes::from(data)))
+    }
+}
@@ -0,0 +1,120 @@
+use axum::{
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use thiserror::Error;
+use tracing::error;
+
+use crate::s3::xml;
+
+/// S3 协议错误，按 S3 的错误码与 XML 格式返回给客户端
+#[derive(Debug, Error)]
+pub enum S3Error {
+    #[error("Access Denied")]
+    AccessDenied,
+    #[error("The AWS access key Id you provided does not exist in our records.")]
+    InvalidAccessKeyId,
+    #[error("The request signature we calculated does not match the signature you provided.")]
+    SignatureDoesNotMatch,
+    #[error("The authorization header is malformed: {0}")]
+    AuthorizationHeaderMalformed(String),
+    #[error("The difference between the request time and the server's time is too large.")]
+    RequestTimeTooSkewed,
+    #[error("Request has expired")]
+    ExpiredPresignedRequest,
+    #[error("The provided 'x-amz-content-sha256' header does not match what was computed.")]
+    ContentSha256Mismatch,
+    #[error("The specified bucket does not exist")]
+    NoSuchBucket,
+    #[error("The specified key does not exist.")]
+    NoSuchKey,
+    #[error("The specified multipart upload does not exist.")]
+    NoSuchUpload,
+    #[error("One or more of the specified parts could not be found.")]
+    InvalidPart,
+    #[error("The list of parts was not in ascending order.")]
+    InvalidPartOrder,
+    #[error("The requested range is not satisfiable")]
+    InvalidRange,
+    #[error("{0}")]
+    InvalidArgument(String),
+    #[error(
+        "The XML you provided was not well-formed or did not validate against our published schema."
+    )]
+    MalformedXml,
+    #[error("A header you provided implies functionality that is not implemented")]
+    NotImplemented,
+    #[error("We encountered an internal error. Please try again.")]
+    Internal(#[from] std::io::Error),
+}
+
+impl S3Error {
+    pub fn code(&self) -> &'static str {
+        match self {
+            S3Error::AccessDenied | S3Error::ExpiredPresignedRequest => "AccessDenied",
+            S3Error::InvalidAccessKeyId => "InvalidAccessKeyId",
+            S3Error::SignatureDoesNotMatch => "SignatureDoesNotMatch",
+            S3Error::AuthorizationHeaderMalformed(_) => "AuthorizationHeaderMalformed",
+            S3Error::RequestTimeTooSkewed => "RequestTimeTooSkewed",
+            S3Error::ContentSha256Mismatch => "XAmzContentSHA256Mismatch",
+            S3Error::NoSuchBucket => "NoSuchBucket",
+            S3Error::NoSuchKey => "NoSuchKey",
+            S3Error::NoSuchUpload => "NoSuchUpload",
+            S3Error::InvalidPart => "InvalidPart",
+            S3Error::InvalidPartOrder => "InvalidPartOrder",
+            S3Error::InvalidRange => "InvalidRange",
+            S3Error::InvalidArgument(_) => "InvalidArgument",
+            S3Error::MalformedXml => "MalformedXML",
+            S3Error::NotImplemented => "NotImplemented",
+            S3Error::Internal(_) => "InternalError",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            S3Error::AccessDenied
+            | S3Error::ExpiredPresignedRequest
+            | S3Error::InvalidAccessKeyId
+            | S3Error::SignatureDoesNotMatch
+            | S3Error::RequestTimeTooSkewed => StatusCode::FORBIDDEN,
+            S3Error::NoSuchBucket | S3Error::NoSuchKey | S3Error::NoSuchUpload => {
+                StatusCode::NOT_FOUND
+            }
+            S3Error::InvalidRange => StatusCode::RANGE_NOT_SATISFIABLE,
+            S3Error::NotImplemented => StatusCode::NOT_IMPLEMENTED,
+            S3Error::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    /// 文件系统错误中 NotFound 对应 NoSuchKey，其余视为内部错误
+    pub fn from_fs(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::NotFound => S3Error::NoSuchKey,
+            std::io::ErrorKind::PermissionDenied => S3Error::AccessDenied,
+            _ => S3Error::Internal(e),
+        }
+    }
+}
+
+impl IntoResponse for S3Error {
+    fn into_response(self) -> Response {
+        if let S3Error::Internal(e) = &self {
+            error!("S3 内部错误: {}", e);
+        }
+        let request_id = uuid::Uuid::new_v4().simple().to_string();
+        let body = xml::error(self.code(), &self.to_string(), &request_id);
+        (
+            self.status(),
+            [
+                (header::CONTENT_TYPE, "application/xml".to_string()),
+                (
+                    header::HeaderName::from_static("x-amz-request-id"),
+                    request_id,
+                ),
+            ],
+            body,
+        )
+            .into_response()
+    }
+}
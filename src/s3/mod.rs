@@ -0,0 +1,177 @@
+//! S3 兼容网关：每个 `[[paths]]` 作为一个 bucket，使用路径风格寻址
+//! （`/{bucket}/{key}`），SigV4 签名对应用户配置的 `access_keys`。
+
+mod auth;
+mod body;
+mod error;
+mod multipart;
+mod ops;
+mod policy;
+mod xml;
+
+use std::{net::IpAddr, path::PathBuf};
+
+use axum::{
+    Router,
+    body::Body,
+    extract::State,
+    http::{Extensions, HeaderMap, Method, Uri},
+    response::{IntoResponse, Response},
+};
+use percent_encoding::percent_decode_str;
+use tracing::debug;
+
+use crate::model::{AccessKeyFromFile, AppState, S3FromFile, UserConfig};
+use crate::s3::{body::RequestBody, error::S3Error, multipart::MultipartStore};
+use crate::telemetry;
+
+pub const DEFAULT_PORT: u16 = 9000;
+const DEFAULT_MULTIPART_DIR: &str = "data/s3-multipart";
+
+#[derive(Clone)]
+pub struct S3State {
+    pub app: AppState,
+    multipart: MultipartStore,
+}
+
+impl AsRef<AppState> for S3State {
+    fn as_ref(&self) -> &AppState {
+        &self.app
+    }
+}
+
+pub async fn create_router(app: AppState, config: &S3FromFile) -> std::io::Result<Router> {
+    let multipart_dir = config
+        .multipart_dir
+        .clone()
+        .unwrap_or(DEFAULT_MULTIPART_DIR.to_string());
+    let state = S3State {
+        app,
+        multipart: MultipartStore::new(PathBuf::from(multipart_dir)).await?,
+    };
+    Ok(Router::new().fallback(dispatch).with_state(state))
+}
+
+/// 单个 S3 请求的上下文
+pub(crate) struct S3Request<'a> {
+    state: &'a S3State,
+    user: UserConfig,
+    access_key: AccessKeyFromFile,
+    headers: &'a HeaderMap,
+    query: Vec<(String, String)>,
+    client_ip: Option<IpAddr>,
+}
+
+impl S3Request<'_> {
+    fn has_param(&self, name: &str) -> bool {
+        self.query.iter().any(|(k, _)| k == name)
+    }
+
+    fn param(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+async fn dispatch(
+    State(state): State<S3State>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    extensions: Extensions,
+    body: Body,
+) -> Response {
+    let client_ip = crate::proxy::peer(&extensions).map(|(ip, _)| ip);
+    match route(&state, &method, &uri, &headers, client_ip, body).await {
+        Ok(response) => response,
+        Err(e) => {
+            debug!("S3 请求失败 {} {}: {}", method, uri.path(), e.code());
+            e.into_response()
+        }
+    }
+}
+
+async fn route(
+    state: &S3State,
+    method: &Method,
+    uri: &Uri,
+    headers: &HeaderMap,
+    client_ip: Option<IpAddr>,
+    body: Body,
+) -> Result<Response, S3Error> {
+    let creds = auth::authenticate(&state.app, method, uri, headers).inspect_err(|e| {
+        if matches!(
+            e,
+            S3Error::InvalidAccessKeyId | S3Error::SignatureDoesNotMatch
+        ) {
+            telemetry::login_failed("s3");
+        }
+    })?;
+    let body = RequestBody::new(body, creds.payload);
+    let (bucket, key) = split_path(uri.path());
+    let req = S3Request {
+        state,
+        user: creds.user,
+        access_key: creds.key,
+        headers,
+        query: auth::parse_query(uri.query().unwrap_or("")),
+        client_ip,
+    };
+
+    let Some(bucket) = bucket else {
+        return match *method {
+            Method::GET => ops::list_buckets(&req),
+            _ => Err(S3Error::NotImplemented),
+        };
+    };
+
+    let Some(key) = key else {
+        return match *method {
+            Method::GET if req.has_param("location") => ops::get_bucket_location(&req, &bucket),
+            Method::GET if req.param("list-type") == Some("2") => {
+                ops::list_objects_v2(&req, &bucket).await
+            }
+            Method::HEAD => ops::head_bucket(&req, &bucket),
+            Method::POST if req.has_param("delete") => {
+                ops::delete_objects(&req, &bucket, body).await
+            }
+            _ => Err(S3Error::NotImplemented),
+        };
+    };
+
+    let copy_source = headers.contains_key("x-amz-copy-source");
+    match *method {
+        Method::GET if req.has_param("uploadId") => Err(S3Error::NotImplemented),
+        Method::GET => ops::get_object(&req, &bucket, &key, false).await,
+        Method::HEAD => ops::get_object(&req, &bucket, &key, true).await,
+        Method::PUT if req.has_param("uploadId") && copy_source => Err(S3Error::NotImplemented),
+        Method::PUT if req.has_param("uploadId") => {
+            ops::upload_part(&req, &bucket, &key, body).await
+        }
+        Method::PUT if copy_source => ops::copy_object(&req, &bucket, &key).await,
+        Method::PUT => ops::put_object(&req, &bucket, &key, body).await,
+        Method::DELETE if req.has_param("uploadId") => {
+            ops::abort_multipart_upload(&req, &bucket, &key).await
+        }
+        Method::DELETE => ops::delete_object(&req, &bucket, &key).await,
+        Method::POST if req.has_param("uploads") => {
+            ops::create_multipart_upload(&req, &bucket, &key).await
+        }
+        Method::POST if req.has_param("uploadId") => {
+            ops::complete_multipart_upload(&req, &bucket, &key, body).await
+        }
+        _ => Err(S3Error::NotImplemented),
+    }
+}
+
+/// `/bucket/a/b.txt` -> (`bucket`, `a/b.txt`)，两部分均已解码
+fn split_path(path: &str) -> (Option<String>, Option<String>) {
+    let decode = |s: &str| percent_decode_str(s).decode_utf8_lossy().into_owned();
+    let path = path.trim_start_matches('/');
+    let (bucket, key) = path.split_once('/').unwrap_or((path, ""));
+    let bucket = Some(decode(bucket)).filter(|b| !b.is_empty());
+    let key = Some(decode(key)).filter(|k| !k.is_empty());
+    (bucket, key)
+}
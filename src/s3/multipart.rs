@@ -0,0 +1,178 @@
+//! 分片上传的暂存区：分片写入暂存目录，完成时按顺序拼接后原子地落到目标位置
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::s3::error::S3Error;
+use crate::s3::xml::CompletedPart;
+use crate::utils::AtomicFile;
+
+/// S3 规定的分片编号上限
+pub const MAX_PART_NUMBER: u32 = 10000;
+
+pub struct MultipartUpload {
+    pub bucket: String,
+    pub key: String,
+    pub username: String,
+    parts: BTreeMap<u32, PartInfo>,
+}
+
+struct PartInfo {
+    etag: String,
+}
+
+#[derive(Clone)]
+pub struct MultipartStore {
+    dir: PathBuf,
+    uploads: Arc<Mutex<HashMap<String, MultipartUpload>>>,
+}
+
+impl MultipartStore {
+    /// 重启后内存中的上传记录已丢失，暂存目录中残留的分片一并清理
+    pub async fn new(dir: PathBuf) -> std::io::Result<Self> {
+        tokio::fs::create_dir_all(&dir).await?;
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            // 只删除形如 upload id 的子目录，避免误删配置错误时指向的其他目录
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.len() == 32
+                && name.chars().all(|c| c.is_ascii_hexdigit())
+                && let Err(e) = tokio::fs::remove_dir_all(entry.path()).await
+            {
+                warn!("清理分片暂存目录 {:?} 失败: {}", entry.path(), e);
+            }
+        }
+        Ok(MultipartStore {
+            dir,
+            uploads: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    pub async fn create(&self, bucket: &str, key: &str, username: &str) -> std::io::Result<String> {
+        let upload_id = uuid::Uuid::new_v4().simple().to_string();
+        tokio::fs::create_dir_all(self.dir.join(&upload_id)).await?;
+        self.uploads.lock().await.insert(
+            upload_id.clone(),
+            MultipartUpload {
+                bucket: bucket.to_string(),
+                key: key.to_string(),
+                username: username.to_string(),
+                parts: BTreeMap::new(),
+            },
+        );
+        Ok(upload_id)
+    }
+
+    /// 校验上传属于该用户及对象，返回分片应写入的路径
+    pub async fn part_path(
+        &self,
+        upload_id: &str,
+        bucket: &str,
+        key: &str,
+        username: &str,
+        part_number: u32,
+    ) -> Result<PathBuf, S3Error> {
+        self.check_owner(upload_id, bucket, key, username).await?;
+        if part_number == 0 || part_number > MAX_PART_NUMBER {
+            return Err(S3Error::InvalidArgument(format!(
+                "Part number must be an integer between 1 and {}",
+                MAX_PART_NUMBER
+            )));
+        }
+        Ok(self.dir.join(upload_id).join(part_number.to_string()))
+    }
+
+    pub async fn record_part(&self, upload_id: &str, part_number: u32, etag: String) {
+        if let Some(upload) = self.uploads.lock().await.get_mut(upload_id) {
+            upload.parts.insert(part_number, PartInfo { etag });
+        }
+    }
+
+    /// 按客户端给出的分片列表拼接到 `target`
+    pub async fn complete(
+        &self,
+        upload_id: &str,
+        bucket: &str,
+        key: &str,
+        username: &str,
+        parts: &[CompletedPart],
+        target: &Path,
+    ) -> Result<(), S3Error> {
+        self.check_owner(upload_id, bucket, key, username).await?;
+        if parts.is_empty() {
+            return Err(S3Error::MalformedXml);
+        }
+        if parts
+            .windows(2)
+            .any(|w| w[0].part_number >= w[1].part_number)
+        {
+            return Err(S3Error::InvalidPartOrder);
+        }
+
+        {
+            let uploads = self.uploads.lock().await;
+            let upload = uploads.get(upload_id).ok_or(S3Error::NoSuchUpload)?;
+            for part in parts {
+                let info = upload
+                    .parts
+                    .get(&part.part_number)
+                    .ok_or(S3Error::InvalidPart)?;
+                if let Some(etag) = &part.etag
+                    && etag.trim_matches('"') != info.etag.trim_matches('"')
+                {
+                    return Err(S3Error::InvalidPart);
+                }
+            }
+        }
+
+        let mut file = AtomicFile::create(target).await?;
+        for part in parts {
+            let path = self.dir.join(upload_id).join(part.part_number.to_string());
+            let mut part_file = tokio::fs::File::open(&path).await?;
+            tokio::io::copy(&mut part_file, file.as_file_mut()).await?;
+        }
+        file.commit().await?;
+
+        self.remove(upload_id).await;
+        Ok(())
+    }
+
+    pub async fn abort(
+        &self,
+        upload_id: &str,
+        bucket: &str,
+        key: &str,
+        username: &str,
+    ) -> Result<(), S3Error> {
+        self.check_owner(upload_id, bucket, key, username).await?;
+        self.remove(upload_id).await;
+        Ok(())
+    }
+
+    async fn remove(&self, upload_id: &str) {
+        self.uploads.lock().await.remove(upload_id);
+        if let Err(e) = tokio::fs::remove_dir_all(self.dir.join(upload_id)).await {
+            warn!("删除分片暂存目录失败 {}: {}", upload_id, e);
+        }
+    }
+
+    async fn check_owner(
+        &self,
+        upload_id: &str,
+        bucket: &str,
+        key: &str,
+        username: &str,
+    ) -> Result<(), S3Error> {
+        let uploads = self.uploads.lock().await;
+        match uploads.get(upload_id) {
+            Some(u) if u.bucket == bucket && u.key == key && u.username == username => Ok(()),
+            _ => Err(S3Error::NoSuchUpload),
+        }
+    }
+}
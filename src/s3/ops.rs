@@ -0,0 +1,765 @@
+//! S3 各个操作的实现，权限同时受访问密钥策略和用户 rwv 权限树约束
+
+use std::{path::PathBuf, time::SystemTime};
+
+use axum::{
+    body::Body,
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use percent_encoding::percent_decode_str;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use tracing::info;
+
+use crate::audit::{AuditAction, AuditResult, Operation};
+use crate::model::{READ_MASK, VIEW_MASK, WRITE_MASK};
+use crate::s3::{S3Request, body::RequestBody, error::S3Error, policy, xml};
+use crate::telemetry::Transfer;
+use crate::utils::{AtomicFile, ObservedBody, check_permission, is_temp_file};
+
+const MAX_KEYS: usize = 1000;
+/// 空内容的 MD5，目录标记对象使用
+const EMPTY_ETAG: &str = "\"d41d8cd98f00b204e9800998ecf8427e\"";
+
+fn xml_response(status: StatusCode, body: String) -> Response {
+    (status, [(header::CONTENT_TYPE, "application/xml")], body).into_response()
+}
+
+fn audit_result<T>(result: &Result<T, S3Error>) -> AuditResult {
+    match result {
+        Ok(_) => AuditResult::Ok,
+        Err(S3Error::AccessDenied) => AuditResult::Denied,
+        Err(_) => AuditResult::Failed,
+    }
+}
+
+impl S3Request<'_> {
+    /// 同时检查访问密钥策略与权限树，`fs_path` 为文件系统上的完整路径
+    fn authorize(
+        &self,
+        action: &str,
+        resource: &str,
+        fs_path: &str,
+        required: u8,
+    ) -> Result<(), S3Error> {
+        if policy::is_allowed(&self.access_key, action, resource)
+            && check_permission(&self.user.permissions_tree, fs_path, required)
+        {
+            Ok(())
+        } else {
+            Err(S3Error::AccessDenied)
+        }
+    }
+
+    fn root_path(&self, bucket: &str) -> Result<String, S3Error> {
+        self.state
+            .app
+            .get_path(bucket)
+            .map(|p| p.path.clone())
+            .ok_or(S3Error::NoSuchBucket)
+    }
+
+    /// 把对象 key 映射到文件系统路径，拒绝 `..` 等越界的 key
+    fn object_path(&self, bucket: &str, key: &str) -> Result<PathBuf, S3Error> {
+        let root = self.root_path(bucket)?;
+        if key.starts_with('/')
+            || key.contains('\\')
+            || key.split('/').any(|s| s == ".." || s == ".")
+        {
+            return Err(S3Error::InvalidArgument(format!("Invalid key: {}", key)));
+        }
+        Ok(PathBuf::from(format!(
+            "{}/{}",
+            root.trim_end_matches('/'),
+            key
+        )))
+    }
+
+    fn username(&self) -> &str {
+        &self.user.username
+    }
+
+    /// 审计记录以 bucket 作为根目录、key 作为路径
+    fn operation(&self, action: AuditAction, bucket: &str, key: &str) -> Operation {
+        Operation::new(
+            &self.state.app.audit,
+            "s3",
+            action,
+            self.username(),
+            self.client_ip,
+            bucket,
+            key,
+        )
+    }
+}
+
+/// 对象的 ETag 由修改时间和大小得到，上传、HEAD、GET 和列表返回的都是同一个值。
+/// 不使用内容的 MD5：通过 HTTP、SFTP 等其他途径修改的文件没有记录 MD5
+fn object_etag(meta: &std::fs::Metadata) -> String {
+    let modified = meta
+        .modified()
+        .ok()
+        .and_then(|m| m.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    format!("\"{:x}-{:x}\"", modified, meta.len())
+}
+
+fn last_modified(meta: &std::fs::Metadata) -> DateTime<Utc> {
+    meta.modified()
+        .map(DateTime::<Utc>::from)
+        .unwrap_or_default()
+}
+
+fn http_date(time: &DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+pub fn list_buckets(req: &S3Request) -> Result<Response, S3Error> {
+    if !policy::is_allowed(&req.access_key, "s3:ListAllMyBuckets", "*") {
+        return Err(S3Error::AccessDenied);
+    }
+    let buckets: Vec<String> = req
+        .state
+        .app
+        .config()
+        .paths
+        .values()
+        .filter(|p| check_permission(&req.user.permissions_tree, &p.path, VIEW_MASK))
+        .map(|p| p.name.clone())
+        .collect();
+    Ok(xml_response(
+        StatusCode::OK,
+        xml::list_buckets(req.username(), &buckets),
+    ))
+}
+
+pub fn head_bucket(req: &S3Request, bucket: &str) -> Result<Response, S3Error> {
+    let root = req.root_path(bucket)?;
+    req.authorize("s3:ListBucket", bucket, &root, VIEW_MASK)?;
+    Ok(StatusCode::OK.into_response())
+}
+
+pub fn get_bucket_location(req: &S3Request, bucket: &str) -> Result<Response, S3Error> {
+    let root = req.root_path(bucket)?;
+    req.authorize("s3:GetBucketLocation", bucket, &root, VIEW_MASK)?;
+    Ok(xml_response(StatusCode::OK, xml::location_constraint()))
+}
+
+struct Listed {
+    key: String,
+    size: u64,
+    modified: SystemTime,
+    etag: String,
+}
+
+/// 遍历目录收集对象；非递归模式下子目录以 `name/` 的形式出现，由分隔符归并为 CommonPrefixes
+fn walk(
+    dir: &std::path::Path,
+    key_prefix: &str,
+    recursive: bool,
+    tree: &crate::model::Path,
+    out: &mut Vec<Listed>,
+) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if is_temp_file(&name) {
+            continue;
+        }
+        let path = entry.path();
+        let Ok(meta) = std::fs::metadata(&path) else {
+            continue;
+        };
+        let key = format!("{}{}", key_prefix, name);
+        if meta.is_dir() {
+            if !check_permission(tree, &path.to_string_lossy(), VIEW_MASK) {
+                continue;
+            }
+            let is_symlink = entry.file_type().map(|t| t.is_symlink()).unwrap_or(false);
+            if !recursive {
+                out.push(Listed {
+                    key: format!("{}/", key),
+                    size: 0,
+                    modified: SystemTime::UNIX_EPOCH,
+                    etag: EMPTY_ETAG.to_string(),
+                });
+            } else if !is_symlink {
+                // 递归时不跟随目录符号链接，避免环
+                walk(&path, &format!("{}/", key), true, tree, out);
+            }
+        } else {
+            out.push(Listed {
+                key,
+                size: meta.len(),
+                modified: meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                etag: object_etag(&meta),
+            });
+        }
+    }
+}
+
+pub async fn list_objects_v2(req: &S3Request<'_>, bucket: &str) -> Result<Response, S3Error> {
+    let prefix = req.param("prefix").unwrap_or("").to_string();
+    let delimiter = req
+        .param("delimiter")
+        .filter(|d| !d.is_empty())
+        .map(|d| d.to_string());
+    let max_keys = req
+        .param("max-keys")
+        .map(|m| m.parse::<usize>())
+        .transpose()
+        .map_err(|_| S3Error::InvalidArgument("Invalid max-keys".to_string()))?
+        .unwrap_or(MAX_KEYS)
+        .min(MAX_KEYS);
+    let start_after = req.param("start-after").map(|s| s.to_string());
+    let continuation_token = req.param("continuation-token").map(|s| s.to_string());
+    let start = match &continuation_token {
+        Some(token) => Some(
+            hex::decode(token)
+                .ok()
+                .and_then(|b| String::from_utf8(b).ok())
+                .ok_or_else(|| {
+                    S3Error::InvalidArgument(
+                        "The continuation token provided is incorrect".to_string(),
+                    )
+                })?,
+        ),
+        None => start_after.clone(),
+    };
+
+    // 只需要遍历前缀中最后一个 `/` 之前的目录
+    let dir_prefix = prefix[..prefix.rfind('/').map(|i| i + 1).unwrap_or(0)].to_string();
+    let base = if dir_prefix.is_empty() {
+        PathBuf::from(req.root_path(bucket)?)
+    } else {
+        req.object_path(bucket, &dir_prefix)?
+    };
+    req.authorize("s3:ListBucket", bucket, &base.to_string_lossy(), VIEW_MASK)?;
+
+    let recursive = delimiter.as_deref() != Some("/");
+    let tree = req.user.permissions_tree.clone();
+    let key_prefix = dir_prefix.clone();
+    let mut entries = tokio::task::spawn_blocking(move || {
+        let mut out = Vec::new();
+        walk(&base, &key_prefix, recursive, &tree, &mut out);
+        out
+    })
+    .await
+    .map_err(|e| S3Error::Internal(std::io::Error::other(e)))?;
+    entries.sort_by(|a, b| a.key.cmp(&b.key));
+
+    let mut contents = Vec::new();
+    let mut common_prefixes: Vec<String> = Vec::new();
+    let mut last_key: Option<String> = None;
+    let mut truncated = false;
+    for entry in entries.into_iter().filter(|e| {
+        e.key.starts_with(&prefix) && start.as_deref().is_none_or(|s| e.key.as_str() > s)
+    }) {
+        let rest = &entry.key[prefix.len()..];
+        let common = delimiter.as_deref().and_then(|d| {
+            rest.find(d)
+                .map(|i| format!("{}{}", prefix, &rest[..i + d.len()]))
+        });
+        if let Some(common) = common {
+            if common_prefixes.last() == Some(&common)
+                || start.as_deref().is_some_and(|s| common.as_str() <= s)
+            {
+                continue;
+            }
+            if contents.len() + common_prefixes.len() >= max_keys {
+                truncated = max_keys > 0;
+                break;
+            }
+            last_key = Some(common.clone());
+            common_prefixes.push(common);
+        } else {
+            if contents.len() + common_prefixes.len() >= max_keys {
+                truncated = max_keys > 0;
+                break;
+            }
+            last_key = Some(entry.key.clone());
+            contents.push(xml::ObjectEntry {
+                key: entry.key,
+                size: entry.size,
+                last_modified: DateTime::<Utc>::from(entry.modified),
+                etag: entry.etag,
+            });
+        }
+    }
+
+    let result = xml::ListObjectsV2 {
+        bucket: bucket.to_string(),
+        prefix,
+        delimiter,
+        max_keys,
+        start_after,
+        continuation_token,
+        next_continuation_token: last_key.filter(|_| truncated).map(hex::encode),
+        contents,
+        common_prefixes,
+    };
+    Ok(xml_response(StatusCode::OK, xml::list_objects_v2(&result)))
+}
+
+/// 解析 `Range: bytes=a-b`，返回闭区间；多段范围按 S3 的做法忽略
+fn parse_range(value: &str, len: u64) -> Result<Option<(u64, u64)>, S3Error> {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    if spec.contains(',') {
+        return Ok(None);
+    }
+    let (start, end) = spec.split_once('-').ok_or(S3Error::InvalidRange)?;
+    let parse = |s: &str| s.trim().parse::<u64>().map_err(|_| S3Error::InvalidRange);
+    let range = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix = parse(suffix)?;
+            if suffix == 0 || len == 0 {
+                return Err(S3Error::InvalidRange);
+            }
+            (len.saturating_sub(suffix), len - 1)
+        }
+        (start, "") => (parse(start)?, len.saturating_sub(1)),
+        (start, end) => {
+            let (start, end) = (parse(start)?, parse(end)?);
+            if start > end {
+                return Ok(None);
+            }
+            (start, end.min(len.saturating_sub(1)))
+        }
+    };
+    if range.0 >= len {
+        return Err(S3Error::InvalidRange);
+    }
+    Ok(Some(range))
+}
+
+/// GET 的响应体发送完后写入审计记录，HEAD 不记录
+pub async fn get_object(
+    req: &S3Request<'_>,
+    bucket: &str,
+    key: &str,
+    head_only: bool,
+) -> Result<Response, S3Error> {
+    if head_only {
+        return object_response(req, bucket, key, true).await;
+    }
+    let mut operation = req.operation(AuditAction::Download, bucket, key);
+    let result = object_response(req, bucket, key, false).await;
+    let outcome = audit_result(&result);
+    match result {
+        Ok(response) => {
+            // 发送完 Content-Length 个字节后 hyper 不再轮询响应体，看不到流的结束
+            let length = response
+                .headers()
+                .get(header::CONTENT_LENGTH)
+                .and_then(|h| h.to_str().ok()?.parse::<u64>().ok());
+            Ok(response.map(|body| {
+                ObservedBody::wrap(body, move |sent, complete| {
+                    operation.add_bytes(sent);
+                    if complete || Some(sent) == length {
+                        operation.finish(AuditResult::Ok);
+                    }
+                })
+            }))
+        }
+        Err(e) => {
+            operation.finish(outcome);
+            Err(e)
+        }
+    }
+}
+
+async fn object_response(
+    req: &S3Request<'_>,
+    bucket: &str,
+    key: &str,
+    head_only: bool,
+) -> Result<Response, S3Error> {
+    let path = req.object_path(bucket, key)?;
+    req.authorize(
+        "s3:GetObject",
+        &format!("{}/{}", bucket, key),
+        &path.to_string_lossy(),
+        READ_MASK,
+    )?;
+
+    let meta = tokio::fs::metadata(&path).await.map_err(S3Error::from_fs)?;
+    if meta.is_dir() {
+        return Err(S3Error::NoSuchKey);
+    }
+    let len = meta.len();
+    let range = match req.headers.get(header::RANGE).and_then(|h| h.to_str().ok()) {
+        Some(value) => parse_range(value, len)?,
+        None => None,
+    };
+    let (start, end) = range.unwrap_or((0, len.saturating_sub(1)));
+    let content_length = if len == 0 { 0 } else { end - start + 1 };
+
+    let mut builder = Response::builder()
+        .status(if range.is_some() {
+            StatusCode::PARTIAL_CONTENT
+        } else {
+            StatusCode::OK
+        })
+        .header(header::CONTENT_LENGTH, content_length)
+        .header(
+            header::CONTENT_TYPE,
+            mime_guess::from_path(&path)
+                .first_or_octet_stream()
+                .to_string(),
+        )
+        .header(header::ETAG, object_etag(&meta))
+        .header(header::LAST_MODIFIED, http_date(&last_modified(&meta)))
+        .header(header::ACCEPT_RANGES, "bytes");
+    if range.is_some() {
+        builder = builder.header(
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", start, end, len),
+        );
+    }
+
+    let body = if head_only {
+        Body::empty()
+    } else {
+        info!("用户 '{}' 通过 S3 下载: {}/{}", req.username(), bucket, key);
+        let mut file = tokio::fs::File::open(&path)
+            .await
+            .map_err(S3Error::from_fs)?;
+        file.seek(std::io::SeekFrom::Start(start)).await?;
+        Body::from_stream(ReaderStream::new(file.take(content_length)))
+    };
+    builder
+        .body(body)
+        .map_err(|e| S3Error::Internal(std::io::Error::other(e)))
+}
+
+pub async fn put_object(
+    req: &S3Request<'_>,
+    bucket: &str,
+    key: &str,
+    body: RequestBody,
+) -> Result<Response, S3Error> {
+    // 以 `/` 结尾的空对象视为目录标记
+    let action = if key.ends_with('/') {
+        AuditAction::Mkdir
+    } else {
+        AuditAction::Upload
+    };
+    let mut operation = req.operation(action, bucket, key);
+    let result = write_object(req, bucket, key, body).await;
+    if let Ok((_, len)) = &result
+        && action == AuditAction::Upload
+    {
+        operation.add_bytes(*len);
+    }
+    operation.finish(audit_result(&result));
+    let (etag, _) = result?;
+    info!("用户 '{}' 通过 S3 上传: {}/{}", req.username(), bucket, key);
+    Ok((StatusCode::OK, [(header::ETAG, etag)]).into_response())
+}
+
+/// 返回 ETag 和写入的对象大小
+async fn write_object(
+    req: &S3Request<'_>,
+    bucket: &str,
+    key: &str,
+    body: RequestBody,
+) -> Result<(String, u64), S3Error> {
+    let path = req.object_path(bucket, key)?;
+    req.authorize(
+        "s3:PutObject",
+        &format!("{}/{}", bucket, key),
+        &path.to_string_lossy(),
+        WRITE_MASK,
+    )?;
+
+    if key.ends_with('/') {
+        tokio::fs::create_dir_all(&path).await?;
+        return Ok((EMPTY_ETAG.to_string(), 0));
+    }
+    if tokio::fs::metadata(&path).await.is_ok_and(|m| m.is_dir()) {
+        return Err(S3Error::InvalidArgument(format!("{} is a directory", key)));
+    }
+    body.write_to(&path).await?;
+    let meta = tokio::fs::metadata(&path).await?;
+    Ok((object_etag(&meta), meta.len()))
+}
+
+pub async fn copy_object(
+    req: &S3Request<'_>,
+    bucket: &str,
+    key: &str,
+) -> Result<Response, S3Error> {
+    let source = req
+        .headers
+        .get("x-amz-copy-source")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default();
+    let source = source.split('?').next().unwrap_or_default();
+    let source = percent_decode_str(source.trim_start_matches('/')).decode_utf8_lossy();
+    let (src_bucket, src_key) = source
+        .split_once('/')
+        .filter(|(_, k)| !k.is_empty())
+        .ok_or_else(|| S3Error::InvalidArgument("Invalid copy source".to_string()))?;
+
+    let src_path = req.object_path(src_bucket, src_key)?;
+    req.authorize(
+        "s3:GetObject",
+        &format!("{}/{}", src_bucket, src_key),
+        &src_path.to_string_lossy(),
+        READ_MASK,
+    )?;
+    let dest_path = req.object_path(bucket, key)?;
+    req.authorize(
+        "s3:PutObject",
+        &format!("{}/{}", bucket, key),
+        &dest_path.to_string_lossy(),
+        WRITE_MASK,
+    )?;
+
+    let mut src = tokio::fs::File::open(&src_path)
+        .await
+        .map_err(S3Error::from_fs)?;
+    if src.metadata().await?.is_dir() {
+        return Err(S3Error::NoSuchKey);
+    }
+    let mut dest = AtomicFile::create(&dest_path).await?;
+    tokio::io::copy(&mut src, dest.as_file_mut()).await?;
+    dest.commit().await?;
+
+    let meta = tokio::fs::metadata(&dest_path).await?;
+    info!(
+        "用户 '{}' 通过 S3 复制: {}/{} -> {}/{}",
+        req.username(),
+        src_bucket,
+        src_key,
+        bucket,
+        key
+    );
+    Ok(xml_response(
+        StatusCode::OK,
+        xml::copy_object(&object_etag(&meta), &last_modified(&meta)),
+    ))
+}
+
+/// 单个删除与批量删除中的每个 key 各写一条审计记录
+async fn remove_object(req: &S3Request<'_>, bucket: &str, key: &str) -> Result<(), S3Error> {
+    let operation = req.operation(AuditAction::Delete, bucket, key);
+    let result = delete_path(req, bucket, key).await;
+    operation.finish(audit_result(&result));
+    result
+}
+
+async fn delete_path(req: &S3Request<'_>, bucket: &str, key: &str) -> Result<(), S3Error> {
+    let path = req.object_path(bucket, key)?;
+    req.authorize(
+        "s3:DeleteObject",
+        &format!("{}/{}", bucket, key),
+        &path.to_string_lossy(),
+        WRITE_MASK,
+    )?;
+
+    let result = if key.ends_with('/') {
+        tokio::fs::remove_dir(&path).await
+    } else if tokio::fs::metadata(&path).await.is_ok_and(|m| m.is_dir()) {
+        // 目录只能通过 `key/` 删除，与 S3 对不存在的 key 的行为一致，静默成功
+        Ok(())
+    } else {
+        tokio::fs::remove_file(&path).await
+    };
+    match result {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(S3Error::from_fs(e)),
+        _ => {
+            info!("用户 '{}' 通过 S3 删除: {}/{}", req.username(), bucket, key);
+            Ok(())
+        }
+    }
+}
+
+pub async fn delete_object(
+    req: &S3Request<'_>,
+    bucket: &str,
+    key: &str,
+) -> Result<Response, S3Error> {
+    remove_object(req, bucket, key).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+pub async fn delete_objects(
+    req: &S3Request<'_>,
+    bucket: &str,
+    body: RequestBody,
+) -> Result<Response, S3Error> {
+    req.root_path(bucket)?;
+    let body = body.read_xml().await?;
+    let request: xml::DeleteRequest =
+        quick_xml::de::from_str(&body).map_err(|_| S3Error::MalformedXml)?;
+    if request.objects.len() > MAX_KEYS {
+        return Err(S3Error::MalformedXml);
+    }
+
+    let mut outcomes = Vec::with_capacity(request.objects.len());
+    for object in request.objects {
+        match remove_object(req, bucket, &object.key).await {
+            Ok(()) => outcomes.push(xml::DeleteOutcome::Deleted(object.key)),
+            Err(e) => outcomes.push(xml::DeleteOutcome::Failed {
+                key: object.key,
+                code: e.code().to_string(),
+                message: e.to_string(),
+            }),
+        }
+    }
+    Ok(xml_response(
+        StatusCode::OK,
+        xml::delete_result(&outcomes, request.quiet),
+    ))
+}
+
+pub async fn create_multipart_upload(
+    req: &S3Request<'_>,
+    bucket: &str,
+    key: &str,
+) -> Result<Response, S3Error> {
+    let path = req.object_path(bucket, key)?;
+    req.authorize(
+        "s3:PutObject",
+        &format!("{}/{}", bucket, key),
+        &path.to_string_lossy(),
+        WRITE_MASK,
+    )?;
+    let upload_id = req
+        .state
+        .multipart
+        .create(bucket, key, req.username())
+        .await?;
+    Ok(xml_response(
+        StatusCode::OK,
+        xml::initiate_multipart_upload(bucket, key, &upload_id),
+    ))
+}
+
+fn upload_id<'a>(req: &'a S3Request) -> Result<&'a str, S3Error> {
+    req.param("uploadId").ok_or(S3Error::NoSuchUpload)
+}
+
+pub async fn upload_part(
+    req: &S3Request<'_>,
+    bucket: &str,
+    key: &str,
+    body: RequestBody,
+) -> Result<Response, S3Error> {
+    let upload_id = upload_id(req)?;
+    // 分片在完成上传前就会写入磁盘，和 PutObject 一样先检查权限
+    let path = req.object_path(bucket, key)?;
+    req.authorize(
+        "s3:PutObject",
+        &format!("{}/{}", bucket, key),
+        &path.to_string_lossy(),
+        WRITE_MASK,
+    )?;
+    let part_number = req
+        .param("partNumber")
+        .and_then(|p| p.parse::<u32>().ok())
+        .ok_or_else(|| S3Error::InvalidArgument("Invalid partNumber".to_string()))?;
+    let part_path = req
+        .state
+        .multipart
+        .part_path(upload_id, bucket, key, req.username(), part_number)
+        .await?;
+
+    let transfer = Transfer::start("upload");
+    let etag = body.write_to(&part_path).await?;
+    transfer.finish(Some(bucket), tokio::fs::metadata(&part_path).await?.len());
+    req.state
+        .multipart
+        .record_part(upload_id, part_number, etag.clone())
+        .await;
+    Ok((StatusCode::OK, [(header::ETAG, etag)]).into_response())
+}
+
+/// 合并完成时写入一条上传的审计记录
+pub async fn complete_multipart_upload(
+    req: &S3Request<'_>,
+    bucket: &str,
+    key: &str,
+    body: RequestBody,
+) -> Result<Response, S3Error> {
+    let mut operation = req
+        .operation(AuditAction::Upload, bucket, key)
+        .without_transfer();
+    let result = complete_upload(req, bucket, key, body).await;
+    if let Ok((_, len)) = &result {
+        operation.add_bytes(*len);
+    }
+    operation.finish(audit_result(&result));
+    let (etag, _) = result?;
+    Ok(xml_response(
+        StatusCode::OK,
+        xml::complete_multipart_upload(bucket, key, &etag),
+    ))
+}
+
+/// 返回合并后对象的 ETag 和大小
+async fn complete_upload(
+    req: &S3Request<'_>,
+    bucket: &str,
+    key: &str,
+    body: RequestBody,
+) -> Result<(String, u64), S3Error> {
+    let upload_id = upload_id(req)?;
+    let path = req.object_path(bucket, key)?;
+    req.authorize(
+        "s3:PutObject",
+        &format!("{}/{}", bucket, key),
+        &path.to_string_lossy(),
+        WRITE_MASK,
+    )?;
+
+    let body = body.read_xml().await?;
+    let request: xml::CompleteMultipartUpload =
+        quick_xml::de::from_str(&body).map_err(|_| S3Error::MalformedXml)?;
+    req.state
+        .multipart
+        .complete(
+            upload_id,
+            bucket,
+            key,
+            req.username(),
+            &request.parts,
+            &path,
+        )
+        .await?;
+    let meta = tokio::fs::metadata(&path).await?;
+    info!(
+        "用户 '{}' 通过 S3 分片上传: {}/{}",
+        req.username(),
+        bucket,
+        key
+    );
+    Ok((object_etag(&meta), meta.len()))
+}
+
+pub async fn abort_multipart_upload(
+    req: &S3Request<'_>,
+    bucket: &str,
+    key: &str,
+) -> Result<Response, S3Error> {
+    let upload_id = upload_id(req)?;
+    req.authorize(
+        "s3:AbortMultipartUpload",
+        &format!("{}/{}", bucket, key),
+        &req.object_path(bucket, key)?.to_string_lossy(),
+        WRITE_MASK,
+    )?;
+    req.state
+        .multipart
+        .abort(upload_id, bucket, key, req.username())
+        .await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
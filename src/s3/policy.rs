@@ -0,0 +1,125 @@
+//! 访问密钥上的类 IAM 策略：显式 Deny 优先；存在策略语句时需要命中 Allow
+
+use glob::{MatchOptions, Pattern};
+
+use crate::model::{AccessKeyFromFile, PolicyEffect};
+
+const ACTION_MATCH: MatchOptions = MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: false,
+    require_literal_leading_dot: false,
+};
+
+/// `resource` 为 `bucket` 或 `bucket/key`
+pub fn is_allowed(key: &AccessKeyFromFile, action: &str, resource: &str) -> bool {
+    if key.policy.is_empty() {
+        return true;
+    }
+
+    let mut allowed = false;
+    for statement in &key.policy {
+        let action_matches = statement
+            .actions
+            .iter()
+            .any(|a| matches(a, action, ACTION_MATCH));
+        let resource_matches = statement.resources.is_empty()
+            || statement
+                .resources
+                .iter()
+                .any(|r| matches(r, resource, MatchOptions::new()));
+        if !(action_matches && resource_matches) {
+            continue;
+        }
+        match statement.effect {
+            PolicyEffect::Deny => return false,
+            PolicyEffect::Allow => allowed = true,
+        }
+    }
+    allowed
+}
+
+fn matches(pattern: &str, value: &str, options: MatchOptions) -> bool {
+    Pattern::new(pattern)
+        .map(|p| p.matches_with(value, options))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(policy: &str) -> AccessKeyFromFile {
+        toml::from_str(&format!(
+            "access_key_id = \"AKID\"\nsecret_access_key = \"secret\"\n{}",
+            policy
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn empty_policy_allows_everything() {
+        let key = key("");
+        assert!(is_allowed(&key, "s3:DeleteObject", "photos/a.jpg"));
+        assert!(is_allowed(&key, "s3:ListAllMyBuckets", "*"));
+    }
+
+    #[test]
+    fn requires_matching_allow() {
+        let key = key(r#"
+            [[policy]]
+            effect = "allow"
+            actions = ["s3:Get*", "s3:ListBucket"]
+            resources = ["photos", "photos/*"]
+            "#);
+        assert!(is_allowed(&key, "s3:GetObject", "photos/2024/a.jpg"));
+        assert!(is_allowed(&key, "s3:ListBucket", "photos"));
+        assert!(!is_allowed(&key, "s3:PutObject", "photos/a.jpg"));
+        assert!(!is_allowed(&key, "s3:GetObject", "docs/a.txt"));
+        assert!(!is_allowed(&key, "s3:GetObject", "photos-old/a.jpg"));
+    }
+
+    #[test]
+    fn deny_overrides_allow() {
+        let key = key(r#"
+            [[policy]]
+            effect = "allow"
+            actions = ["s3:*"]
+
+            [[policy]]
+            effect = "deny"
+            actions = ["s3:DeleteObject", "s3:PutObject"]
+            resources = ["photos/private/*"]
+            "#);
+        assert!(is_allowed(&key, "s3:DeleteObject", "photos/a.jpg"));
+        assert!(is_allowed(&key, "s3:GetObject", "photos/private/a.jpg"));
+        assert!(!is_allowed(&key, "s3:DeleteObject", "photos/private/a.jpg"));
+        assert!(!is_allowed(
+            &key,
+            "s3:PutObject",
+            "photos/private/2024/a.jpg"
+        ));
+    }
+
+    #[test]
+    fn deny_only_policy_denies_everything() {
+        let key = key(r#"
+            [[policy]]
+            effect = "deny"
+            actions = ["s3:DeleteObject"]
+            "#);
+        assert!(!is_allowed(&key, "s3:GetObject", "photos/a.jpg"));
+        assert!(!is_allowed(&key, "s3:DeleteObject", "photos/a.jpg"));
+    }
+
+    #[test]
+    fn actions_case_insensitive_resources_case_sensitive() {
+        let key = key(r#"
+            [[policy]]
+            effect = "allow"
+            actions = ["S3:getobject"]
+            resources = ["Photos/*"]
+            "#);
+        assert!(is_allowed(&key, "s3:GetObject", "Photos/a.jpg"));
+        assert!(!is_allowed(&key, "s3:GetObject", "photos/a.jpg"));
+    }
+}
@@ -0,0 +1,203 @@
+//! S3 请求/响应的 XML 编解码
+
+use std::fmt::Write;
+
+use chrono::{DateTime, Utc};
+use quick_xml::escape::escape;
+use serde::Deserialize;
+
+const HEADER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>"#;
+const NAMESPACE: &str = "http://s3.amazonaws.com/doc/2006-03-01/";
+
+pub struct ObjectEntry {
+    pub key: String,
+    pub size: u64,
+    pub last_modified: DateTime<Utc>,
+    pub etag: String,
+}
+
+pub struct ListObjectsV2 {
+    pub bucket: String,
+    pub prefix: String,
+    pub delimiter: Option<String>,
+    pub max_keys: usize,
+    pub start_after: Option<String>,
+    pub continuation_token: Option<String>,
+    pub next_continuation_token: Option<String>,
+    pub contents: Vec<ObjectEntry>,
+    pub common_prefixes: Vec<String>,
+}
+
+pub enum DeleteOutcome {
+    Deleted(String),
+    Failed {
+        key: String,
+        code: String,
+        message: String,
+    },
+}
+
+#[derive(Deserialize)]
+pub struct DeleteRequest {
+    #[serde(rename = "Object", default)]
+    pub objects: Vec<DeleteObject>,
+    #[serde(rename = "Quiet", default)]
+    pub quiet: bool,
+}
+
+#[derive(Deserialize)]
+pub struct DeleteObject {
+    #[serde(rename = "Key")]
+    pub key: String,
+}
+
+#[derive(Deserialize)]
+pub struct CompleteMultipartUpload {
+    #[serde(rename = "Part", default)]
+    pub parts: Vec<CompletedPart>,
+}
+
+#[derive(Deserialize)]
+pub struct CompletedPart {
+    #[serde(rename = "PartNumber")]
+    pub part_number: u32,
+    #[serde(rename = "ETag")]
+    pub etag: Option<String>,
+}
+
+pub fn timestamp(time: &DateTime<Utc>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+}
+
+pub fn error(code: &str, message: &str, request_id: &str) -> String {
+    format!(
+        "{HEADER}<Error><Code>{}</Code><Message>{}</Message><RequestId>{}</RequestId></Error>",
+        code,
+        escape(message),
+        request_id
+    )
+}
+
+pub fn list_buckets(owner: &str, buckets: &[String]) -> String {
+    let mut out = format!("{HEADER}<ListAllMyBucketsResult xmlns=\"{NAMESPACE}\">");
+    let _ = write!(
+        out,
+        "<Owner><ID>{0}</ID><DisplayName>{0}</DisplayName></Owner><Buckets>",
+        escape(owner)
+    );
+    for bucket in buckets {
+        let _ = write!(
+            out,
+            "<Bucket><Name>{}</Name><CreationDate>1970-01-01T00:00:00.000Z</CreationDate></Bucket>",
+            escape(bucket)
+        );
+    }
+    out.push_str("</Buckets></ListAllMyBucketsResult>");
+    out
+}
+
+pub fn list_objects_v2(result: &ListObjectsV2) -> String {
+    let mut out = format!("{HEADER}<ListBucketResult xmlns=\"{NAMESPACE}\">");
+    let _ = write!(
+        out,
+        "<Name>{}</Name><Prefix>{}</Prefix><KeyCount>{}</KeyCount><MaxKeys>{}</MaxKeys><IsTruncated>{}</IsTruncated>",
+        escape(&result.bucket),
+        escape(&result.prefix),
+        result.contents.len() + result.common_prefixes.len(),
+        result.max_keys,
+        result.next_continuation_token.is_some()
+    );
+    if let Some(delimiter) = &result.delimiter {
+        let _ = write!(out, "<Delimiter>{}</Delimiter>", escape(delimiter));
+    }
+    if let Some(start_after) = &result.start_after {
+        let _ = write!(out, "<StartAfter>{}</StartAfter>", escape(start_after));
+    }
+    if let Some(token) = &result.continuation_token {
+        let _ = write!(
+            out,
+            "<ContinuationToken>{}</ContinuationToken>",
+            escape(token)
+        );
+    }
+    if let Some(token) = &result.next_continuation_token {
+        let _ = write!(
+            out,
+            "<NextContinuationToken>{}</NextContinuationToken>",
+            escape(token)
+        );
+    }
+    for entry in &result.contents {
+        let _ = write!(
+            out,
+            "<Contents><Key>{}</Key><LastModified>{}</LastModified><ETag>{}</ETag><Size>{}</Size><StorageClass>STANDARD</StorageClass></Contents>",
+            escape(&entry.key),
+            timestamp(&entry.last_modified),
+            escape(&entry.etag),
+            entry.size
+        );
+    }
+    for prefix in &result.common_prefixes {
+        let _ = write!(
+            out,
+            "<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>",
+            escape(prefix)
+        );
+    }
+    out.push_str("</ListBucketResult>");
+    out
+}
+
+pub fn copy_object(etag: &str, last_modified: &DateTime<Utc>) -> String {
+    format!(
+        "{HEADER}<CopyObjectResult xmlns=\"{NAMESPACE}\"><LastModified>{}</LastModified><ETag>{}</ETag></CopyObjectResult>",
+        timestamp(last_modified),
+        escape(etag)
+    )
+}
+
+pub fn delete_result(outcomes: &[DeleteOutcome], quiet: bool) -> String {
+    let mut out = format!("{HEADER}<DeleteResult xmlns=\"{NAMESPACE}\">");
+    for outcome in outcomes {
+        match outcome {
+            DeleteOutcome::Deleted(key) => {
+                if !quiet {
+                    let _ = write!(out, "<Deleted><Key>{}</Key></Deleted>", escape(key));
+                }
+            }
+            DeleteOutcome::Failed { key, code, message } => {
+                let _ = write!(
+                    out,
+                    "<Error><Key>{}</Key><Code>{}</Code><Message>{}</Message></Error>",
+                    escape(key),
+                    code,
+                    escape(message)
+                );
+            }
+        }
+    }
+    out.push_str("</DeleteResult>");
+    out
+}
+
+pub fn initiate_multipart_upload(bucket: &str, key: &str, upload_id: &str) -> String {
+    format!(
+        "{HEADER}<InitiateMultipartUploadResult xmlns=\"{NAMESPACE}\"><Bucket>{}</Bucket><Key>{}</Key><UploadId>{}</UploadId></InitiateMultipartUploadResult>",
+        escape(bucket),
+        escape(key),
+        upload_id
+    )
+}
+
+pub fn complete_multipart_upload(bucket: &str, key: &str, etag: &str) -> String {
+    format!(
+        "{HEADER}<CompleteMultipartUploadResult xmlns=\"{NAMESPACE}\"><Location>/{0}/{1}</Location><Bucket>{0}</Bucket><Key>{1}</Key><ETag>{2}</ETag></CompleteMultipartUploadResult>",
+        escape(bucket),
+        escape(key),
+        escape(etag)
+    )
+}
+
+pub fn location_constraint() -> String {
+    format!("{HEADER}<LocationConstraint xmlns=\"{NAMESPACE}\"/>")
+}
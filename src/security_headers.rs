@@ -0,0 +1,179 @@
+//! 安全响应头：所有响应默认加上 `Content-Security-Policy`、`X-Content-Type-Options`、
+//! `Referrer-Policy` 和 `X-Frame-Options`，由 `[security_headers]` 调整。
+//! 默认的 CSP 允许 `static/` 中页面使用的内联脚本、样式和 jsDelivr 上的 Bootstrap 样式与图标字体，
+//! 也让用户上传的 HTML 在直接打开时不能加载其他来源的脚本。
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderName, HeaderValue, header},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::model::SecurityHeadersFromFile;
+
+/// 前端页面需要的内联脚本、`onclick` 和 CDN 样式，`frame-ancestors` 另外追加
+const DEFAULT_CSP: &str = "default-src 'self'; \
+    script-src 'self' 'unsafe-inline'; \
+    style-src 'self' 'unsafe-inline' https://cdn.jsdelivr.net; \
+    font-src 'self' https://cdn.jsdelivr.net; \
+    img-src 'self' data: blob:; \
+    connect-src 'self'; \
+    object-src 'none'; \
+    base-uri 'self'; \
+    form-action 'self'";
+/// 下载文件时页面会把下载地址放进同源的 iframe，所以默认允许同源嵌入
+const DEFAULT_FRAME_ANCESTORS: &str = "'self'";
+const DEFAULT_REFERRER_POLICY: &str = "same-origin";
+
+/// 要加到每个响应上的响应头，启动时由 `[security_headers]` 得到
+#[derive(Clone)]
+pub struct SecurityHeaders(Arc<Vec<(HeaderName, HeaderValue)>>);
+
+impl SecurityHeaders {
+    /// `enable = false` 时返回 `None`。各项已在加载配置时校验过，无法作为响应头的值直接忽略
+    pub fn new(config: Option<&SecurityHeadersFromFile>) -> Option<Self> {
+        if !config.and_then(|c| c.enable).unwrap_or(true) {
+            return None;
+        }
+        let frame_ancestors = config
+            .and_then(|c| c.frame_ancestors.as_deref())
+            .unwrap_or(DEFAULT_FRAME_ANCESTORS)
+            .trim();
+        let csp = match config.and_then(|c| c.content_security_policy.as_deref()) {
+            Some(csp) => content_security_policy(csp, frame_ancestors),
+            None => content_security_policy(DEFAULT_CSP, frame_ancestors),
+        };
+        let referrer_policy = config
+            .and_then(|c| c.referrer_policy.as_deref())
+            .unwrap_or(DEFAULT_REFERRER_POLICY)
+            .trim();
+
+        let mut headers = vec![(
+            header::X_CONTENT_TYPE_OPTIONS,
+            HeaderValue::from_static("nosniff"),
+        )];
+        let values = [
+            (header::CONTENT_SECURITY_POLICY, csp.as_str()),
+            (header::REFERRER_POLICY, referrer_policy),
+            (header::X_FRAME_OPTIONS, x_frame_options(frame_ancestors)),
+        ];
+        for (name, value) in values {
+            if !value.is_empty()
+                && let Ok(value) = HeaderValue::from_str(value)
+            {
+                headers.push((name, value));
+            }
+        }
+        Some(SecurityHeaders(Arc::new(headers)))
+    }
+}
+
+/// 在 `csp` 末尾加上 `frame-ancestors`，`csp` 中已经有时不再添加
+fn content_security_policy(csp: &str, frame_ancestors: &str) -> String {
+    let csp = csp.trim().trim_end_matches(';').trim_end();
+    if csp.is_empty() || frame_ancestors.is_empty() || csp.contains("frame-ancestors") {
+        return csp.to_string();
+    }
+    format!("{}; frame-ancestors {}", csp, frame_ancestors)
+}
+
+/// 不支持 CSP 的旧浏览器使用的 `X-Frame-Options`，只能表示不允许和只允许同源
+fn x_frame_options(frame_ancestors: &str) -> &'static str {
+    match frame_ancestors {
+        "'none'" => "DENY",
+        "'self'" => "SAMEORIGIN",
+        _ => "",
+    }
+}
+
+/// 给响应加上没有设置过的安全响应头，挂在最外层，路径前缀之外的 404 也会带上
+pub async fn middleware(
+    State(headers): State<SecurityHeaders>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    for (name, value) in headers.0.iter() {
+        if !response.headers().contains_key(name) {
+            response.headers_mut().insert(name.clone(), value.clone());
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Router,
+        body::Body,
+        http::{HeaderMap, Request},
+        middleware,
+        routing::get,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+
+    async fn headers(content: Option<&str>) -> HeaderMap {
+        let config: Option<SecurityHeadersFromFile> = content.map(|c| toml::from_str(c).unwrap());
+        let headers = SecurityHeaders::new(config.as_ref()).unwrap();
+        let router = Router::new()
+            .route(
+                "/",
+                get(|| async { ([(header::REFERRER_POLICY, "no-referrer")], "ok") }),
+            )
+            .layer(middleware::from_fn_with_state(headers, super::middleware));
+        let request = Request::get("/").body(Body::empty()).unwrap();
+        router.oneshot(request).await.unwrap().headers().clone()
+    }
+
+    #[tokio::test]
+    async fn defaults_are_added_without_overriding_handlers() {
+        let headers = headers(None).await;
+        assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert_eq!(headers[header::X_FRAME_OPTIONS], "SAMEORIGIN");
+        assert_eq!(headers[header::REFERRER_POLICY], "no-referrer");
+        let csp = headers[header::CONTENT_SECURITY_POLICY].to_str().unwrap();
+        assert!(csp.starts_with("default-src 'self';"), "{}", csp);
+        assert!(csp.ends_with("; frame-ancestors 'self'"), "{}", csp);
+    }
+
+    #[tokio::test]
+    async fn custom_values_and_empty_strings() {
+        let headers = headers(Some(
+            r#"
+            content_security_policy = "default-src 'none';"
+            frame_ancestors = "https://portal.example.com"
+            referrer_policy = ""
+            "#,
+        ))
+        .await;
+        assert_eq!(
+            headers[header::CONTENT_SECURITY_POLICY],
+            "default-src 'none'; frame-ancestors https://portal.example.com"
+        );
+        assert!(!headers.contains_key(header::X_FRAME_OPTIONS));
+
+        let headers = self::headers(Some(r#"content_security_policy = """#)).await;
+        assert!(!headers.contains_key(header::CONTENT_SECURITY_POLICY));
+        assert_eq!(headers[header::X_FRAME_OPTIONS], "SAMEORIGIN");
+    }
+
+    #[test]
+    fn disabled() {
+        let config: SecurityHeadersFromFile = toml::from_str("enable = false").unwrap();
+        assert!(SecurityHeaders::new(Some(&config)).is_none());
+    }
+
+    #[test]
+    fn frame_ancestors_in_custom_csp_is_kept() {
+        assert_eq!(
+            content_security_policy("default-src 'self'; frame-ancestors 'none'", "'self'"),
+            "default-src 'self'; frame-ancestors 'none'"
+        );
+        assert_eq!(x_frame_options("'none'"), "DENY");
+    }
+}
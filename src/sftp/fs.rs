@@ -0,0 +1,654 @@
+//! SFTP 文件操作：在 [`crate::vfs`] 的虚拟文件系统上按 rwv 权限执行每个请求
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+use russh_sftp::{
+    protocol::{
+        Attrs, Data, File, FileAttributes, Handle, Name, OpenFlags, Status, StatusCode, Version,
+    },
+    server::Handler,
+};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use crate::audit::{AuditAction, AuditResult, Operation};
+use crate::model::{AppState, READ_MASK, VIEW_MASK, WRITE_MASK};
+use crate::utils::{AtomicFile, check_permission, is_temp_file};
+use crate::vfs::{self, Target};
+
+/// 单次 READ 返回的最大字节数
+const MAX_READ_LEN: u32 = 256 * 1024;
+
+enum OpenHandle {
+    File(tokio::fs::File),
+    /// 以截断或新建方式打开的文件，关闭时才替换目标文件
+    Upload(AtomicFile),
+    Dir(Option<Vec<File>>),
+}
+
+pub struct SftpSession {
+    app: AppState,
+    /// 只保存用户名，每次操作都按当前配置检查权限，热加载后立即生效
+    username: String,
+    client_ip: Option<IpAddr>,
+    handles: HashMap<String, OpenHandle>,
+    /// 打开的文件对应的审计记录，关闭时写入，会话结束时还没关闭的记为中断
+    transfers: HashMap<String, Operation>,
+}
+
+fn status_ok(id: u32) -> Status {
+    Status {
+        id,
+        status_code: StatusCode::Ok,
+        error_message: "Ok".to_string(),
+        language_tag: "en-US".to_string(),
+    }
+}
+
+fn fs_error(e: std::io::Error) -> StatusCode {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => StatusCode::NoSuchFile,
+        std::io::ErrorKind::PermissionDenied => StatusCode::PermissionDenied,
+        _ => StatusCode::Failure,
+    }
+}
+
+fn audit_result<T>(result: &Result<T, StatusCode>) -> AuditResult {
+    match result {
+        Ok(_) => AuditResult::Ok,
+        Err(StatusCode::PermissionDenied) => AuditResult::Denied,
+        Err(_) => AuditResult::Failed,
+    }
+}
+
+fn root_dir_attrs() -> FileAttributes {
+    let mut attrs = FileAttributes::dummy();
+    attrs.permissions = Some(0o755);
+    attrs.set_dir(true);
+    attrs
+}
+
+impl SftpSession {
+    pub fn new(app: AppState, username: String, client_ip: Option<IpAddr>) -> Self {
+        SftpSession {
+            app,
+            username,
+            client_ip,
+            handles: HashMap::new(),
+            transfers: HashMap::new(),
+        }
+    }
+
+    fn operation(&self, action: AuditAction, path: &str) -> Operation {
+        let (root, path) = vfs::audit_target(path);
+        Operation::new(
+            &self.app.audit,
+            "sftp",
+            action,
+            &self.username,
+            self.client_ip,
+            &root,
+            &path,
+        )
+    }
+
+    /// 立即完成的操作，写入一条审计记录后原样返回结果
+    fn audited<T>(
+        &self,
+        action: AuditAction,
+        path: &str,
+        result: Result<T, StatusCode>,
+    ) -> Result<T, StatusCode> {
+        self.operation(action, path).finish(audit_result(&result));
+        result
+    }
+
+    fn resolve(&self, path: &str) -> Result<Target, StatusCode> {
+        vfs::resolve(&self.app, path).ok_or(StatusCode::NoSuchFile)
+    }
+
+    /// 用户已从配置中删除时拒绝一切操作
+    fn allowed(&self, path: &Path, required: u8) -> bool {
+        self.app
+            .config()
+            .users
+            .get(&self.username)
+            .is_some_and(|user| {
+                check_permission(&user.permissions_tree, &path.to_string_lossy(), required)
+            })
+    }
+
+    /// 解析为文件系统路径并检查权限，根目录本身与虚拟根目录只能读取
+    async fn resolve_fs(&self, path: &str, required: u8) -> Result<PathBuf, StatusCode> {
+        match self.resolve(path)? {
+            Target::Root => Err(StatusCode::PermissionDenied),
+            Target::Fs { root, path } => {
+                if required & WRITE_MASK != 0 && path == root {
+                    return Err(StatusCode::PermissionDenied);
+                }
+                if !self.allowed(&path, required) {
+                    return Err(StatusCode::PermissionDenied);
+                }
+                vfs::ensure_contained(&root, &path)
+                    .await
+                    .map_err(fs_error)?;
+                Ok(path)
+            }
+        }
+    }
+
+    fn insert_handle(&mut self, id: u32, handle: OpenHandle) -> Handle {
+        let key = uuid::Uuid::new_v4().simple().to_string();
+        self.handles.insert(key.clone(), handle);
+        Handle { id, handle: key }
+    }
+
+    fn list_roots(&self) -> Vec<File> {
+        let mut entries = vec![File::new(".", root_dir_attrs())];
+        let Some(user) = self.app.get_user_config(&self.username) else {
+            return entries;
+        };
+        for path in vfs::visible_roots(&self.app, &user) {
+            let attrs = std::fs::metadata(&path.path)
+                .map(|m| FileAttributes::from(&m))
+                .unwrap_or_else(|_| root_dir_attrs());
+            entries.push(File::new(path.name.clone(), attrs));
+        }
+        entries
+    }
+
+    async fn list_dir(&self, dir: &Path) -> std::io::Result<Vec<File>> {
+        let mut entries = Vec::new();
+        let mut read_dir = tokio::fs::read_dir(dir).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if is_temp_file(&name) || !self.allowed(&entry.path(), VIEW_MASK) {
+                continue;
+            }
+            let Ok(metadata) = entry.metadata().await else {
+                continue;
+            };
+            entries.push(File::new(name, FileAttributes::from(&metadata)));
+        }
+        Ok(entries)
+    }
+
+    async fn stat_path(&self, id: u32, path: &str, follow: bool) -> Result<Attrs, StatusCode> {
+        let path = match self.resolve(path)? {
+            Target::Root => {
+                return Ok(Attrs {
+                    id,
+                    attrs: root_dir_attrs(),
+                });
+            }
+            Target::Fs { root, path } => {
+                if !self.allowed(&path, VIEW_MASK) && !self.allowed(&path, READ_MASK) {
+                    return Err(StatusCode::PermissionDenied);
+                }
+                vfs::ensure_contained(&root, &path)
+                    .await
+                    .map_err(fs_error)?;
+                path
+            }
+        };
+        let metadata = if follow {
+            tokio::fs::metadata(&path).await
+        } else {
+            tokio::fs::symlink_metadata(&path).await
+        }
+        .map_err(fs_error)?;
+        Ok(Attrs {
+            id,
+            attrs: FileAttributes::from(&metadata),
+        })
+    }
+
+    async fn open_file(&self, path: &str, flags: OpenFlags) -> Result<OpenHandle, StatusCode> {
+        let writing = flags.intersects(OpenFlags::WRITE | OpenFlags::APPEND);
+        if !writing {
+            let path = self.resolve_fs(path, READ_MASK).await?;
+            if tokio::fs::metadata(&path).await.map_err(fs_error)?.is_dir() {
+                return Err(StatusCode::Failure);
+            }
+            let file = tokio::fs::File::open(&path).await.map_err(fs_error)?;
+            return Ok(OpenHandle::File(file));
+        }
+
+        let mut required = WRITE_MASK;
+        if flags.contains(OpenFlags::READ) {
+            required |= READ_MASK;
+        }
+        let path = self.resolve_fs(path, required).await?;
+        let exists = tokio::fs::try_exists(&path).await.map_err(fs_error)?;
+        if exists && flags.contains(OpenFlags::EXCLUDE) {
+            return Err(StatusCode::Failure);
+        }
+        if !exists && !flags.contains(OpenFlags::CREATE) {
+            return Err(StatusCode::NoSuchFile);
+        }
+
+        // 覆盖或新建时写入临时文件，断开连接或失败时不会留下写了一半的文件；
+        // 在已有文件上续写（断点续传、追加）时直接打开原文件
+        if !flags.contains(OpenFlags::READ) && (flags.contains(OpenFlags::TRUNCATE) || !exists) {
+            let file = AtomicFile::create(&path).await.map_err(fs_error)?;
+            return Ok(OpenHandle::Upload(file));
+        }
+        let file = tokio::fs::OpenOptions::from(std::fs::OpenOptions::from(flags))
+            .open(&path)
+            .await
+            .map_err(fs_error)?;
+        Ok(OpenHandle::File(file))
+    }
+}
+
+impl Handler for SftpSession {
+    type Error = StatusCode;
+
+    fn unimplemented(&self) -> Self::Error {
+        StatusCode::OpUnsupported
+    }
+
+    async fn init(
+        &mut self,
+        _version: u32,
+        _extensions: HashMap<String, String>,
+    ) -> Result<Version, Self::Error> {
+        Ok(Version::new())
+    }
+
+    async fn open(
+        &mut self,
+        id: u32,
+        filename: String,
+        pflags: OpenFlags,
+        _attrs: FileAttributes,
+    ) -> Result<Handle, Self::Error> {
+        let action = if pflags.intersects(OpenFlags::WRITE | OpenFlags::APPEND) {
+            AuditAction::Upload
+        } else {
+            AuditAction::Download
+        };
+        let handle = match self.open_file(&filename, pflags).await {
+            Ok(handle) => handle,
+            Err(e) => return self.audited(action, &filename, Err(e)),
+        };
+        let transfer = self.operation(action, &filename);
+        let handle = self.insert_handle(id, handle);
+        self.transfers.insert(handle.handle.clone(), transfer);
+        Ok(handle)
+    }
+
+    async fn close(&mut self, id: u32, handle: String) -> Result<Status, Self::Error> {
+        let transfer = self.transfers.remove(&handle);
+        let result = match self.handles.remove(&handle) {
+            Some(OpenHandle::Upload(file)) => file.commit().await.map_err(fs_error),
+            Some(_) => Ok(()),
+            None => Err(StatusCode::Failure),
+        };
+        if let Some(transfer) = transfer {
+            transfer.finish(audit_result(&result));
+        }
+        result.map(|()| status_ok(id))
+    }
+
+    async fn read(
+        &mut self,
+        id: u32,
+        handle: String,
+        offset: u64,
+        len: u32,
+    ) -> Result<Data, Self::Error> {
+        let Some(OpenHandle::File(file)) = self.handles.get_mut(&handle) else {
+            return Err(StatusCode::Failure);
+        };
+        file.seek(std::io::SeekFrom::Start(offset))
+            .await
+            .map_err(fs_error)?;
+        let mut data = vec![0u8; len.min(MAX_READ_LEN) as usize];
+        let n = file.read(&mut data).await.map_err(fs_error)?;
+        if n == 0 {
+            return Err(StatusCode::Eof);
+        }
+        data.truncate(n);
+        if let Some(transfer) = self.transfers.get_mut(&handle) {
+            transfer.add_bytes(n as u64);
+        }
+        Ok(Data { id, data })
+    }
+
+    async fn write(
+        &mut self,
+        id: u32,
+        handle: String,
+        offset: u64,
+        data: Vec<u8>,
+    ) -> Result<Status, Self::Error> {
+        let file = match self.handles.get_mut(&handle) {
+            Some(OpenHandle::File(file)) => file,
+            Some(OpenHandle::Upload(file)) => file.as_file_mut(),
+            _ => return Err(StatusCode::Failure),
+        };
+        file.seek(std::io::SeekFrom::Start(offset))
+            .await
+            .map_err(fs_error)?;
+        file.write_all(&data).await.map_err(fs_error)?;
+        if let Some(transfer) = self.transfers.get_mut(&handle) {
+            transfer.add_bytes(data.len() as u64);
+        }
+        Ok(status_ok(id))
+    }
+
+    async fn lstat(&mut self, id: u32, path: String) -> Result<Attrs, Self::Error> {
+        self.stat_path(id, &path, false).await
+    }
+
+    async fn stat(&mut self, id: u32, path: String) -> Result<Attrs, Self::Error> {
+        self.stat_path(id, &path, true).await
+    }
+
+    async fn fstat(&mut self, id: u32, handle: String) -> Result<Attrs, Self::Error> {
+        let file = match self.handles.get_mut(&handle) {
+            Some(OpenHandle::File(file)) => file,
+            Some(OpenHandle::Upload(file)) => file.as_file_mut(),
+            _ => return Err(StatusCode::Failure),
+        };
+        let metadata = file.metadata().await.map_err(fs_error)?;
+        Ok(Attrs {
+            id,
+            attrs: FileAttributes::from(&metadata),
+        })
+    }
+
+    /// 只支持修改大小和权限位，时间戳等其他属性直接忽略
+    async fn setstat(
+        &mut self,
+        id: u32,
+        path: String,
+        attrs: FileAttributes,
+    ) -> Result<Status, Self::Error> {
+        let path = self.resolve_fs(&path, WRITE_MASK).await?;
+        if let Some(size) = attrs.size {
+            let file = tokio::fs::OpenOptions::new()
+                .write(true)
+                .open(&path)
+                .await
+                .map_err(fs_error)?;
+            file.set_len(size).await.map_err(fs_error)?;
+        }
+        #[cfg(unix)]
+        if let Some(mode) = attrs.permissions {
+            use std::os::unix::fs::PermissionsExt;
+            tokio::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode & 0o777))
+                .await
+                .map_err(fs_error)?;
+        }
+        Ok(status_ok(id))
+    }
+
+    async fn fsetstat(
+        &mut self,
+        id: u32,
+        handle: String,
+        attrs: FileAttributes,
+    ) -> Result<Status, Self::Error> {
+        let file = match self.handles.get_mut(&handle) {
+            Some(OpenHandle::File(file)) => file,
+            Some(OpenHandle::Upload(file)) => file.as_file_mut(),
+            _ => return Err(StatusCode::Failure),
+        };
+        if let Some(size) = attrs.size {
+            file.set_len(size).await.map_err(fs_error)?;
+        }
+        Ok(status_ok(id))
+    }
+
+    async fn opendir(&mut self, id: u32, path: String) -> Result<Handle, Self::Error> {
+        let entries = match self.resolve(&path)? {
+            Target::Root => self.list_roots(),
+            Target::Fs { .. } => {
+                let result = async {
+                    let dir = self.resolve_fs(&path, VIEW_MASK).await?;
+                    self.list_dir(&dir).await.map_err(fs_error)
+                }
+                .await;
+                self.audited(AuditAction::List, &path, result)?
+            }
+        };
+        Ok(self.insert_handle(id, OpenHandle::Dir(Some(entries))))
+    }
+
+    async fn readdir(&mut self, id: u32, handle: String) -> Result<Name, Self::Error> {
+        match self.handles.get_mut(&handle) {
+            Some(OpenHandle::Dir(entries)) => match entries.take() {
+                Some(files) if !files.is_empty() => Ok(Name { id, files }),
+                _ => Err(StatusCode::Eof),
+            },
+            _ => Err(StatusCode::Failure),
+        }
+    }
+
+    async fn remove(&mut self, id: u32, filename: String) -> Result<Status, Self::Error> {
+        let result = async {
+            let path = self.resolve_fs(&filename, WRITE_MASK).await?;
+            tokio::fs::remove_file(&path).await.map_err(fs_error)
+        }
+        .await;
+        self.audited(AuditAction::Delete, &filename, result)
+            .map(|()| status_ok(id))
+    }
+
+    async fn mkdir(
+        &mut self,
+        id: u32,
+        path: String,
+        _attrs: FileAttributes,
+    ) -> Result<Status, Self::Error> {
+        let result = async {
+            let path = self.resolve_fs(&path, WRITE_MASK).await?;
+            tokio::fs::create_dir(&path).await.map_err(fs_error)
+        }
+        .await;
+        self.audited(AuditAction::Mkdir, &path, result)
+            .map(|()| status_ok(id))
+    }
+
+    async fn rmdir(&mut self, id: u32, path: String) -> Result<Status, Self::Error> {
+        let result = async {
+            let path = self.resolve_fs(&path, WRITE_MASK).await?;
+            tokio::fs::remove_dir(&path).await.map_err(fs_error)
+        }
+        .await;
+        self.audited(AuditAction::Delete, &path, result)
+            .map(|()| status_ok(id))
+    }
+
+    async fn realpath(&mut self, id: u32, path: String) -> Result<Name, Self::Error> {
+        let path = format!("/{}", vfs::normalize(&path).join("/"));
+        Ok(Name {
+            id,
+            files: vec![File::dummy(path)],
+        })
+    }
+
+    /// SFTP v3 的重命名不覆盖已存在的目标
+    async fn rename(
+        &mut self,
+        id: u32,
+        oldpath: String,
+        newpath: String,
+    ) -> Result<Status, Self::Error> {
+        let result = async {
+            let from = self.resolve_fs(&oldpath, WRITE_MASK).await?;
+            let to = self.resolve_fs(&newpath, WRITE_MASK).await?;
+            if tokio::fs::try_exists(&to).await.map_err(fs_error)? {
+                return Err(StatusCode::Failure);
+            }
+            tokio::fs::rename(&from, &to).await.map_err(fs_error)
+        }
+        .await;
+        let mut operation = self.operation(AuditAction::Rename, &oldpath);
+        operation.set_new_path(&vfs::audit_target(&newpath).1);
+        operation.finish(audit_result(&result));
+        result.map(|()| status_ok(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{Config, ConfigFromFile};
+
+    fn config(dir: &Path, data_grant: u8) -> String {
+        let dir = dir.display();
+        format!(
+            r#"
+            [[paths]]
+            name = "data"
+            path = "{dir}/data"
+            permission = 0b111
+
+            [[paths]]
+            name = "ro"
+            path = "{dir}/ro"
+            permission = 0b111
+
+            [[users]]
+            username = "alice"
+            password = "x"
+            permissions = [
+                {{ path_name = "data", permission = {data_grant} }},
+                {{ path_name = "ro", permission = 0b101 }},
+            ]
+            "#
+        )
+    }
+
+    async fn session(dir: &Path) -> SftpSession {
+        for name in ["data", "ro"] {
+            std::fs::create_dir_all(dir.join(name)).unwrap();
+        }
+        let app = AppState::for_test(&config(dir, 0b111), dir).await;
+        SftpSession::new(app, "alice".to_string(), None)
+    }
+
+    async fn reload(session: &SftpSession, config: &str) {
+        let config: ConfigFromFile = toml::from_str(config).unwrap();
+        let config = Config::from_config_file(&config).await.unwrap();
+        session.app.replace_config(config).await;
+    }
+
+    const CREATE: OpenFlags = OpenFlags::WRITE
+        .union(OpenFlags::CREATE)
+        .union(OpenFlags::TRUNCATE);
+
+    async fn put(session: &mut SftpSession, path: &str, data: &[u8]) -> Result<(), StatusCode> {
+        let handle = session
+            .open(1, path.to_string(), CREATE, FileAttributes::dummy())
+            .await?
+            .handle;
+        session.write(2, handle.clone(), 0, data.to_vec()).await?;
+        session.close(3, handle).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_then_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut session = session(dir.path()).await;
+        put(&mut session, "/data/a.txt", b"hello").await.unwrap();
+
+        let handle = session
+            .open(
+                4,
+                "/data/a.txt".to_string(),
+                OpenFlags::READ,
+                FileAttributes::dummy(),
+            )
+            .await
+            .unwrap()
+            .handle;
+        let data = session.read(5, handle.clone(), 0, 1024).await.unwrap();
+        assert_eq!(data.data, b"hello");
+        assert!(matches!(
+            session.read(6, handle, 5, 1024).await,
+            Err(StatusCode::Eof)
+        ));
+    }
+
+    #[tokio::test]
+    async fn read_only_grant_cannot_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut session = session(dir.path()).await;
+        assert!(matches!(
+            put(&mut session, "/ro/a.txt", b"x").await,
+            Err(StatusCode::PermissionDenied)
+        ));
+        assert!(!dir.path().join("ro/a.txt").exists());
+        assert!(matches!(
+            session
+                .mkdir(1, "/ro/sub".to_string(), FileAttributes::dummy())
+                .await,
+            Err(StatusCode::PermissionDenied)
+        ));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn cannot_escape_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut session = session(dir.path()).await;
+        std::os::unix::fs::symlink(dir.path().join("ro"), dir.path().join("data/link")).unwrap();
+        assert!(put(&mut session, "/data/link/a.txt", b"x").await.is_err());
+        assert!(put(&mut session, "/data/../ro/a.txt", b"x").await.is_err());
+        assert!(!dir.path().join("ro/a.txt").exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn setstat_only_changes_permission_bits() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut session = session(dir.path()).await;
+        put(&mut session, "/data/a.sh", b"#!/bin/sh").await.unwrap();
+
+        let mut attrs = FileAttributes::empty();
+        attrs.permissions = Some(0o6755);
+        session
+            .setstat(1, "/data/a.sh".to_string(), attrs)
+            .await
+            .unwrap();
+        let mode = std::fs::metadata(dir.path().join("data/a.sh"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o7777, 0o755);
+    }
+
+    #[tokio::test]
+    async fn reloaded_permissions_apply_to_open_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut session = session(dir.path()).await;
+        put(&mut session, "/data/a.txt", b"1").await.unwrap();
+
+        reload(&session, &config(dir.path(), 0b101)).await;
+        assert!(matches!(
+            put(&mut session, "/data/a.txt", b"2").await,
+            Err(StatusCode::PermissionDenied)
+        ));
+        assert_eq!(std::fs::read(dir.path().join("data/a.txt")).unwrap(), b"1");
+
+        // 用户被删除后什么都看不到
+        reload(&session, &config(dir.path(), 0b111).replace("alice", "bob")).await;
+        assert!(matches!(
+            session.stat(1, "/data/a.txt".to_string()).await,
+            Err(StatusCode::PermissionDenied)
+        ));
+        let handle = session.opendir(2, "/".to_string()).await.unwrap().handle;
+        let names = session.readdir(3, handle).await.unwrap();
+        assert_eq!(names.files.len(), 1);
+    }
+}
@@ -0,0 +1,112 @@
+//! 内嵌 SFTP 服务：只提供 `sftp` 子系统，用户通过 `[[users]]` 的密码或公钥登录，
+//! 看到的是由其有权限的 `[[paths]]` 组成的虚拟文件系统。
+
+mod fs;
+mod session;
+
+use std::{path::Path, sync::Arc, time::Duration};
+
+use russh::{
+    MethodKind, MethodSet,
+    keys::{Algorithm, HashAlg, PrivateKey, ssh_key::LineEnding},
+    server::{self, Config, Server},
+};
+use tokio::{net::TcpListener, task::JoinSet};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info};
+
+use crate::model::{AppState, SftpFromFile};
+use crate::sftp::session::SshSession;
+
+pub const DEFAULT_PORT: u16 = 2222;
+const DEFAULT_HOST_KEY_PATH: &str = "data/ssh_host_ed25519_key";
+
+pub struct SftpServer {
+    app: AppState,
+    ssh_config: Arc<Config>,
+}
+
+impl SftpServer {
+    pub async fn new(app: AppState, config: &SftpFromFile) -> std::io::Result<Self> {
+        let host_key_path = config
+            .host_key_path
+            .clone()
+            .unwrap_or(DEFAULT_HOST_KEY_PATH.to_string());
+        let host_key = load_or_generate_host_key(Path::new(&host_key_path)).await?;
+        info!(
+            "SFTP 主机密钥指纹: {}",
+            host_key.public_key().fingerprint(HashAlg::Sha256)
+        );
+
+        let ssh_config = Config {
+            keys: vec![host_key],
+            methods: MethodSet::from(&[MethodKind::Password, MethodKind::PublicKey][..]),
+            auth_rejection_time: Duration::from_secs(1),
+            auth_rejection_time_initial: Some(Duration::from_secs(0)),
+            ..Default::default()
+        };
+        Ok(SftpServer {
+            app,
+            ssh_config: Arc::new(ssh_config),
+        })
+    }
+
+    /// 接受连接直到 `shutdown` 被取消，之后不再接受新连接，等待已有的会话结束
+    pub async fn serve(
+        mut self,
+        listener: TcpListener,
+        shutdown: CancellationToken,
+    ) -> std::io::Result<()> {
+        let mut sessions = JoinSet::new();
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let (socket, peer_addr) = accepted?;
+                    let handler = self.new_client(Some(peer_addr));
+                    let config = self.ssh_config.clone();
+                    sessions.spawn(async move {
+                        let result = match server::run_stream(config, socket, handler).await {
+                            Ok(session) => session.await,
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = result {
+                            debug!("SFTP 连接 {} 异常断开: {}", peer_addr, e);
+                        }
+                    });
+                }
+                Some(_) = sessions.join_next(), if !sessions.is_empty() => {}
+                _ = shutdown.cancelled() => break,
+            }
+        }
+        if !sessions.is_empty() {
+            info!("等待 {} 个 SFTP 会话结束", sessions.len());
+        }
+        while sessions.join_next().await.is_some() {}
+        Ok(())
+    }
+}
+
+impl Server for SftpServer {
+    type Handler = SshSession;
+
+    fn new_client(&mut self, peer_addr: Option<std::net::SocketAddr>) -> SshSession {
+        SshSession::new(self.app.clone(), peer_addr)
+    }
+}
+
+/// 读取 OpenSSH 格式的主机私钥，不存在时生成 Ed25519 密钥并以 0600 权限保存
+async fn load_or_generate_host_key(path: &Path) -> std::io::Result<PrivateKey> {
+    if tokio::fs::try_exists(path).await? {
+        return PrivateKey::read_openssh_file(path).map_err(std::io::Error::other);
+    }
+
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let key =
+        PrivateKey::random(&mut rand::rng(), Algorithm::Ed25519).map_err(std::io::Error::other)?;
+    key.write_openssh_file(path, LineEnding::LF)
+        .map_err(std::io::Error::other)?;
+    info!("已生成 SFTP 主机密钥: {}", path.display());
+    Ok(key)
+}
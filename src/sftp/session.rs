@@ -0,0 +1,149 @@
+//! SSH 连接处理：认证用户并只为 `sftp` 子系统打开通道
+
+use std::{collections::HashMap, net::SocketAddr};
+
+use russh::{
+    Channel, ChannelId,
+    keys::PublicKey,
+    server::{Auth, ChannelOpenHandle, Handler, Msg, Session},
+};
+use tracing::{info, warn};
+
+use crate::model::{AppState, UserConfig};
+use crate::sftp::fs::SftpSession;
+use crate::telemetry;
+
+pub struct SshSession {
+    app: AppState,
+    peer_addr: Option<SocketAddr>,
+    /// 认证通过的用户名，权限在每次文件操作时按当前配置检查
+    username: Option<String>,
+    channels: HashMap<ChannelId, Channel<Msg>>,
+}
+
+impl SshSession {
+    pub fn new(app: AppState, peer_addr: Option<SocketAddr>) -> Self {
+        SshSession {
+            app,
+            peer_addr,
+            username: None,
+            channels: HashMap::new(),
+        }
+    }
+
+    fn accept(&mut self, user: UserConfig, method: &str) -> Auth {
+        info!(
+            "SFTP 用户 {} 通过{}登录，来自 {:?}",
+            user.username, method, self.peer_addr
+        );
+        self.username = Some(user.username);
+        Auth::Accept
+    }
+
+    fn reject(&self, username: &str, method: &str) -> Auth {
+        telemetry::login_failed("sftp");
+        warn!(
+            "SFTP 用户 {} {}认证失败，来自 {:?}",
+            username, method, self.peer_addr
+        );
+        Auth::reject()
+    }
+}
+
+/// 比较公钥本身，忽略注释
+fn is_authorized_key(user: &UserConfig, public_key: &PublicKey) -> bool {
+    user.authorized_keys.iter().any(|line| {
+        PublicKey::from_openssh(line.trim())
+            .map(|k| k.key_data() == public_key.key_data())
+            .unwrap_or(false)
+    })
+}
+
+impl Handler for SshSession {
+    type Error = russh::Error;
+
+    async fn auth_password(&mut self, user: &str, password: &str) -> Result<Auth, Self::Error> {
+        match self.app.get_user_config(user) {
+            Some(u) if u.verify_password(password).await => Ok(self.accept(u, "密码")),
+            _ => Ok(self.reject(user, "密码")),
+        }
+    }
+
+    async fn auth_publickey_offered(
+        &mut self,
+        user: &str,
+        public_key: &PublicKey,
+    ) -> Result<Auth, Self::Error> {
+        match self.app.get_user_config(user) {
+            Some(u) if is_authorized_key(&u, public_key) => Ok(Auth::Accept),
+            _ => Ok(Auth::reject()),
+        }
+    }
+
+    async fn auth_publickey(
+        &mut self,
+        user: &str,
+        public_key: &PublicKey,
+    ) -> Result<Auth, Self::Error> {
+        match self.app.get_user_config(user) {
+            Some(u) if is_authorized_key(&u, public_key) => Ok(self.accept(u, "公钥")),
+            _ => Ok(self.reject(user, "公钥")),
+        }
+    }
+
+    async fn channel_open_session(
+        &mut self,
+        channel: Channel<Msg>,
+        reply: ChannelOpenHandle,
+        _session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        self.channels.insert(channel.id(), channel);
+        reply.accept().await;
+        Ok(())
+    }
+
+    async fn channel_eof(
+        &mut self,
+        channel: ChannelId,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        session.close(channel)
+    }
+
+    async fn subsystem_request(
+        &mut self,
+        channel_id: ChannelId,
+        name: &str,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        let username = self.username.clone();
+        match (name, username, self.channels.remove(&channel_id)) {
+            ("sftp", Some(username), Some(channel)) => {
+                session.channel_success(channel_id)?;
+                let client_ip = self.peer_addr.map(|a| a.ip());
+                let handler = SftpSession::new(self.app.clone(), username, client_ip);
+                russh_sftp::server::run(channel.into_stream(), handler).await;
+            }
+            _ => session.channel_failure(channel_id)?,
+        }
+        Ok(())
+    }
+
+    /// 只提供文件传输，拒绝 shell 与命令执行
+    async fn shell_request(
+        &mut self,
+        channel: ChannelId,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        session.channel_failure(channel)
+    }
+
+    async fn exec_request(
+        &mut self,
+        channel: ChannelId,
+        _data: &[u8],
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        session.channel_failure(channel)
+    }
+}
@@ -0,0 +1,112 @@
+//! 优雅退出：收到 SIGTERM 或 SIGINT 后各个服务停止接受新连接，进行中的请求和传输
+//! 最多再运行 `misc.shutdown_timeout` 秒，超时后放弃剩余的连接并删除上传到一半的临时文件。
+
+use std::time::Duration;
+
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::utils;
+
+/// 收到 SIGTERM 或 SIGINT 时取消返回的 token，各个服务据此开始退出
+pub fn listen() -> CancellationToken {
+    let token = CancellationToken::new();
+    let cancel = token.clone();
+    tokio::spawn(async move {
+        let name = wait_for_signal().await;
+        info!("收到 {}，停止接受新连接并等待进行中的请求完成", name);
+        cancel.cancel();
+    });
+    token
+}
+
+async fn wait_for_signal() -> &'static str {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("无法监听 SIGINT: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                warn!("无法监听 SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => "SIGINT",
+        _ = terminate => "SIGTERM",
+    }
+}
+
+/// 运行 `servers` 直到它结束。收到退出信号后最多再等待 `timeout`，
+/// 超时后不再等待并删除仍在写入的临时文件
+pub async fn drain(
+    servers: impl Future<Output = ()>,
+    token: &CancellationToken,
+    timeout: Duration,
+) {
+    tokio::pin!(servers);
+    tokio::select! {
+        _ = &mut servers => return,
+        _ = token.cancelled() => {}
+    }
+    if tokio::time::timeout(timeout, servers).await.is_ok() {
+        info!("进行中的请求已全部完成");
+        return;
+    }
+    let removed = utils::remove_temp_files();
+    warn!(
+        "等待 {} 秒后仍有请求未完成，强制退出，删除了 {} 个未完成上传的临时文件",
+        timeout.as_secs(),
+        removed
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn drain_returns_when_servers_finish() {
+        let token = CancellationToken::new();
+        let started = std::time::Instant::now();
+        drain(async {}, &token, Duration::from_secs(30)).await;
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn drain_waits_for_servers_after_cancel() {
+        let token = CancellationToken::new();
+        let servers = {
+            let token = token.clone();
+            async move {
+                token.cancelled().await;
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        };
+        token.cancel();
+        let started = std::time::Instant::now();
+        drain(servers, &token, Duration::from_secs(30)).await;
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn drain_gives_up_after_timeout() {
+        let token = CancellationToken::new();
+        token.cancel();
+        let started = std::time::Instant::now();
+        drain(std::future::pending(), &token, Duration::from_millis(100)).await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,454 @@
+//! 前端静态文件：默认使用编译时嵌入程序的 `static/`，设置 `misc.static_dir` 时从磁盘读取，
+//! 方便开发时修改后直接刷新。每个文件按内容计算哈希作为 ETag，HTML 中引用本地文件的
+//! `src` 和 `href` 加上 `?v=哈希`。带有正确哈希的请求可以长期缓存，其余请求（包括 HTML 本身）
+//! 每次用 ETag 重新验证，文件更新后浏览器马上能拿到新版本。
+//! 找不到文件的页面请求返回 `index.html`，由前端处理路径。
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{HeaderMap, HeaderValue, StatusCode, Uri, header},
+    response::{IntoResponse, Response},
+};
+use percent_encoding::percent_decode_str;
+use rust_embed::RustEmbed;
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncReadExt;
+
+/// 带有内容哈希的请求缓存一年
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+/// 没有哈希的请求每次都要重新验证
+const REVALIDATE: &str = "no-cache";
+/// 哈希取 SHA-256 的前 16 个十六进制字符
+const HASH_LEN: usize = 16;
+/// 这些属性引用的本地文件会加上哈希
+const LINK_ATTRS: [&str; 2] = [" src=\"", " href=\""];
+const INDEX_HTML: &str = "index.html";
+
+#[derive(RustEmbed)]
+#[folder = "static/"]
+struct Embedded;
+
+/// 计算哈希时文件的修改时间和大小，两者都没变时沿用哈希
+struct CachedHash {
+    modified: SystemTime,
+    len: u64,
+    hash: String,
+}
+
+enum Source {
+    Embedded,
+    Disk {
+        dir: PathBuf,
+        hashes: Mutex<HashMap<PathBuf, CachedHash>>,
+    },
+}
+
+/// 前端文件的来源，以及挂在 `misc.base_path` 下时页面的基础路径
+#[derive(Clone)]
+pub struct StaticFiles {
+    source: Arc<Source>,
+    base_path: Arc<str>,
+}
+
+impl StaticFiles {
+    /// `static_dir` 为 `None` 时使用嵌入的文件
+    pub fn new(static_dir: Option<&str>, base_path: &str) -> Self {
+        let source = match static_dir {
+            Some(dir) => {
+                tracing::info!("从 {} 读取前端文件", dir);
+                Source::Disk {
+                    dir: PathBuf::from(dir),
+                    hashes: Mutex::default(),
+                }
+            }
+            None => Source::Embedded,
+        };
+        StaticFiles {
+            source: Arc::new(source),
+            base_path: base_path.into(),
+        }
+    }
+
+    /// 文件内容的哈希，文件不存在或无法读取时返回 `None`
+    async fn hash(&self, path: &str) -> Option<String> {
+        let (dir, hashes) = match &*self.source {
+            Source::Embedded => {
+                return Embedded::get(path).map(|f| short_hash(&f.metadata.sha256_hash()));
+            }
+            Source::Disk { dir, hashes } => (dir, hashes),
+        };
+        let file = dir.join(path);
+        let metadata = tokio::fs::metadata(&file)
+            .await
+            .ok()
+            .filter(|m| m.is_file())?;
+        let modified = metadata.modified().ok()?;
+        if let Some(cached) = hashes.lock().unwrap().get(&file)
+            && cached.modified == modified
+            && cached.len == metadata.len()
+        {
+            return Some(cached.hash.clone());
+        }
+
+        let mut reader = tokio::fs::File::open(&file).await.ok()?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = reader.read(&mut buf).await.ok()?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+        let hash = short_hash(&hasher.finalize());
+        hashes.lock().unwrap().insert(
+            file,
+            CachedHash {
+                modified,
+                len: metadata.len(),
+                hash: hash.clone(),
+            },
+        );
+        Some(hash)
+    }
+
+    /// 文件内容和哈希
+    async fn get(&self, path: &str) -> Option<(Cow<'static, [u8]>, String)> {
+        match &*self.source {
+            Source::Embedded => Embedded::get(path).map(|f| {
+                let hash = short_hash(&f.metadata.sha256_hash());
+                (f.data, hash)
+            }),
+            Source::Disk { dir, .. } => {
+                let hash = self.hash(path).await?;
+                let data = tokio::fs::read(dir.join(path)).await.ok()?;
+                Some((Cow::Owned(data), hash))
+            }
+        }
+    }
+
+    /// HTML 中 `link` 引用的本地文件的哈希，外部链接、绝对路径和已有查询参数的链接不处理
+    async fn link_hash(&self, html_dir: &str, link: &str) -> Option<String> {
+        if link.is_empty() || link.starts_with('/') || link.contains([':', '?', '#', '$']) {
+            return None;
+        }
+        let path = asset_path(&format!("{}/{}", html_dir, link))?;
+        self.hash(&path).await
+    }
+
+    /// 给 HTML 中引用本地文件的链接加上 `?v=哈希`
+    async fn versioned_html(&self, path: &str, html: &str) -> String {
+        let html_dir = path.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("");
+        let mut out = String::with_capacity(html.len());
+        let mut rest = html;
+        while let Some(start) = LINK_ATTRS
+            .iter()
+            .filter_map(|attr| rest.find(attr).map(|i| i + attr.len()))
+            .min()
+            && let Some(len) = rest[start..].find('"')
+        {
+            let end = start + len;
+            out.push_str(&rest[..end]);
+            if let Some(hash) = self.link_hash(html_dir, &rest[start..end]).await {
+                out.push_str("?v=");
+                out.push_str(&hash);
+            }
+            rest = &rest[end..];
+        }
+        out.push_str(rest);
+        out
+    }
+
+    /// 发送 `path` 对应的文件，文件不存在时返回 `None`。`fallback` 为 true 时是代替其他路径
+    /// 返回的 `index.html`，加上 `<base>` 让其中的相对路径仍然指向根目录
+    async fn respond(
+        &self,
+        path: &str,
+        query: Option<&str>,
+        headers: &HeaderMap,
+        fallback: bool,
+    ) -> Option<Response> {
+        let (data, hash) = self.get(path).await?;
+        let mime = mime_guess::from_path(path).first_or_octet_stream();
+        let (body, etag, cache_control) = if mime == mime::TEXT_HTML {
+            let mut html = self
+                .versioned_html(path, &String::from_utf8_lossy(&data))
+                .await;
+            if fallback && let Some(head) = html.find("<head>") {
+                let base = format!("<base href=\"{}/\">", self.base_path);
+                html.insert_str(head + "<head>".len(), &base);
+            }
+            let etag = etag(&short_hash(&Sha256::digest(&html)));
+            (Body::from(html), etag, REVALIDATE)
+        } else {
+            let versioned =
+                query.is_some_and(|q| q.split('&').any(|p| p.strip_prefix("v=") == Some(&hash)));
+            let cache_control = if versioned { IMMUTABLE } else { REVALIDATE };
+            (Body::from(data), etag(&hash), cache_control)
+        };
+
+        if matches_etag(headers, &etag) {
+            let mut response = StatusCode::NOT_MODIFIED.into_response();
+            cache_headers(response.headers_mut(), &etag, cache_control);
+            return Some(response);
+        }
+        let mut response = ([(header::CONTENT_TYPE, content_type(&mime))], body).into_response();
+        cache_headers(response.headers_mut(), &etag, cache_control);
+        Some(response)
+    }
+}
+
+/// 请求路径对应的相对路径，以 `/` 结尾时指向其中的 `index.html`；包含 `..` 时返回 `None`
+fn asset_path(path: &str) -> Option<String> {
+    let path = percent_decode_str(path).decode_utf8().ok()?;
+    let mut segments = Vec::new();
+    for segment in path.split('/').filter(|s| !s.is_empty() && *s != ".") {
+        if segment == ".." || segment.contains('\\') {
+            return None;
+        }
+        segments.push(segment);
+    }
+    if segments.is_empty() || path.ends_with('/') {
+        segments.push(INDEX_HTML);
+    }
+    Some(segments.join("/"))
+}
+
+/// 文本类型加上 UTF-8 字符集
+fn content_type(mime: &mime::Mime) -> String {
+    if mime.type_() == mime::TEXT || mime.subtype() == mime::JAVASCRIPT {
+        format!("{}; charset=utf-8", mime)
+    } else {
+        mime.to_string()
+    }
+}
+
+fn short_hash(digest: &[u8]) -> String {
+    hex::encode(&digest[..HASH_LEN / 2])
+}
+
+/// 弱 ETag：压缩后的响应与原文件使用相同的 ETag
+fn etag(hash: &str) -> String {
+    format!("W/\"{}\"", hash)
+}
+
+/// `If-None-Match` 中有相同的 ETag（弱比较）或 `*`
+fn matches_etag(headers: &HeaderMap, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+fn cache_headers(headers: &mut HeaderMap, etag: &str, cache_control: &'static str) {
+    if let Ok(etag) = HeaderValue::from_str(etag) {
+        headers.insert(header::ETAG, etag);
+    }
+    headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static(cache_control),
+    );
+}
+
+/// 浏览器打开页面的请求：最后一段没有扩展名、接受 HTML，且不是接口
+fn is_page_request(path: &str, headers: &HeaderMap) -> bool {
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    !file_name.contains('.')
+        && !path.starts_with("api/")
+        && headers
+            .get(header::ACCEPT)
+            .and_then(|h| h.to_str().ok())
+            .is_some_and(|accept| accept.contains("text/html"))
+}
+
+/// `GET /{*path}`：没有匹配到其他路由的请求
+pub async fn serve(State(files): State<StaticFiles>, uri: Uri, headers: HeaderMap) -> Response {
+    let Some(path) = asset_path(uri.path()) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if let Some(response) = files.respond(&path, uri.query(), &headers, false).await {
+        return response;
+    }
+    if is_page_request(&path, &headers)
+        && let Some(response) = files.respond(INDEX_HTML, None, &headers, true).await
+    {
+        return response;
+    }
+    StatusCode::NOT_FOUND.into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{Router, body::to_bytes, http::Request, routing::get};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    const SCRIPT: &str = "console.log(1);";
+    const INDEX: &str = "<html><head></head><body>\
+        <script src=\"js/app.js\"></script>\
+        <link href=\"https://cdn.example.com/x.css\">\
+        <a href=\"#\"></a></body></html>";
+
+    fn disk_files(dir: &std::path::Path, base_path: &str) -> Router {
+        std::fs::create_dir_all(dir.join("js")).unwrap();
+        std::fs::write(dir.join("js").join("app.js"), SCRIPT).unwrap();
+        std::fs::write(dir.join(INDEX_HTML), INDEX).unwrap();
+        router(StaticFiles::new(
+            Some(&dir.display().to_string()),
+            base_path,
+        ))
+    }
+
+    fn router(files: StaticFiles) -> Router {
+        Router::new().fallback(get(serve)).with_state(files)
+    }
+
+    async fn get_with(router: &Router, uri: &str, headers: &[(&str, &str)]) -> Response {
+        let mut request = Request::get(uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        router
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn text(response: Response) -> String {
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    fn script_hash() -> String {
+        short_hash(&Sha256::digest(SCRIPT))
+    }
+
+    #[tokio::test]
+    async fn html_links_get_content_hashes() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = disk_files(dir.path(), "");
+        let response = get_with(&router, "/", &[]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CACHE_CONTROL], REVALIDATE);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/html; charset=utf-8"
+        );
+        let html = text(response).await;
+        assert!(
+            html.contains(&format!("src=\"js/app.js?v={}\"", script_hash())),
+            "{}",
+            html
+        );
+        assert!(html.contains("href=\"https://cdn.example.com/x.css\""));
+        assert!(html.contains("href=\"#\""));
+    }
+
+    #[tokio::test]
+    async fn only_versioned_assets_are_immutable() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = disk_files(dir.path(), "");
+        let uri = format!("/js/app.js?v={}", script_hash());
+        let response = get_with(&router, &uri, &[]).await;
+        assert_eq!(response.headers()[header::CACHE_CONTROL], IMMUTABLE);
+        assert_eq!(text(response).await, SCRIPT);
+
+        for uri in ["/js/app.js", "/js/app.js?v=0123456789abcdef"] {
+            let response = get_with(&router, uri, &[]).await;
+            assert_eq!(response.headers()[header::CACHE_CONTROL], REVALIDATE);
+        }
+    }
+
+    #[tokio::test]
+    async fn etag_revalidation_returns_304_until_the_file_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = disk_files(dir.path(), "");
+        let response = get_with(&router, "/js/app.js", &[]).await;
+        let etag = response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(etag, format!("W/\"{}\"", script_hash()));
+
+        // 弱比较：带不带 `W/` 都算相同，`*` 匹配任何版本
+        for tag in [etag.as_str(), etag.trim_start_matches("W/"), "*"] {
+            let response = get_with(&router, "/js/app.js", &[("if-none-match", tag)]).await;
+            assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+            assert_eq!(response.headers()[header::ETAG], etag.as_str());
+            assert!(text(response).await.is_empty());
+        }
+
+        let html_etag = get_with(&router, "/", &[]).await.headers()[header::ETAG].clone();
+        std::fs::write(dir.path().join("js").join("app.js"), "console.log(2);").unwrap();
+        let response = get_with(&router, "/js/app.js", &[("if-none-match", &etag)]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(response.headers()[header::ETAG], etag.as_str());
+
+        // HTML 中的哈希变了，HTML 的 ETag 也随之变化
+        let response = get_with(&router, "/", &[]).await;
+        assert_ne!(response.headers()[header::ETAG], html_etag);
+    }
+
+    #[tokio::test]
+    async fn embedded_files_are_served() {
+        let router = router(StaticFiles::new(None, ""));
+        let response = get_with(&router, "/", &[]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let embedded = Embedded::get("js/jquery-3.6.0.min.js").unwrap();
+        let hash = short_hash(&embedded.metadata.sha256_hash());
+
+        let html = text(response).await;
+        assert!(
+            html.contains(&format!("js/jquery-3.6.0.min.js?v={}", hash)),
+            "{}",
+            html
+        );
+        let response = get_with(&router, "/js/jquery-3.6.0.min.js", &[]).await;
+        assert_eq!(response.headers()[header::ETAG], etag(&hash).as_str());
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], &embedded.data[..]);
+    }
+
+    #[tokio::test]
+    async fn page_requests_fall_back_to_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = disk_files(dir.path(), "/sfm");
+        let accept_html = [("accept", "text/html,application/xhtml+xml")];
+
+        let response = get_with(&router, "/files/docs", &accept_html).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let html = text(response).await;
+        // 相对路径仍然指向前端的根目录
+        assert!(html.contains("<head><base href=\"/sfm/\">"), "{}", html);
+        assert!(html.contains("js/app.js?v="));
+
+        // 直接访问 index.html 时不加 <base>
+        let html = text(get_with(&router, "/index.html", &accept_html).await).await;
+        assert!(!html.contains("<base"));
+
+        for (uri, headers) in [
+            ("/files/docs", &[][..]),
+            ("/api/unknown", &accept_html[..]),
+            ("/js/missing.js", &accept_html[..]),
+            ("/%2e%2e/secret", &accept_html[..]),
+        ] {
+            let response = get_with(&router, uri, headers).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", uri);
+        }
+    }
+}
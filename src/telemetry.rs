@@ -0,0 +1,241 @@
+//! Prometheus 指标：按路由和状态码统计的请求数与耗时、每个根目录上传下载的字节数、
+//! 在线会话数、进行中的传输、登录失败次数以及进程状态，由 `[metrics]` 启用。
+//!
+//! 设置 `port` 时指标在独立端口上提供，否则挂在 HTTP 服务的 `/metrics`；
+//! 设置 `token` 时抓取需要 `Authorization: Bearer <token>`。
+
+use std::{sync::OnceLock, time::Instant};
+
+use axum::{
+    Router,
+    extract::{MatchedPath, Request, State},
+    http::{HeaderMap, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use metrics::{counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram};
+use metrics_exporter_prometheus::{BuildError, Matcher, PrometheusBuilder, PrometheusHandle};
+
+use crate::{
+    model::{AppState, ConfigFromFile, MetricsFromFile},
+    utils::constant_time_eq,
+};
+
+pub const METRICS_PATH: &str = "/metrics";
+
+/// 请求耗时直方图的分桶（秒），覆盖从列目录到大文件传输
+const DURATION_BUCKETS: [f64; 14] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0,
+];
+
+static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// 启用 `[metrics]` 时安装全局的指标记录器，未启用时各处的记录都不做任何事
+pub fn init(config: &ConfigFromFile) -> Result<(), BuildError> {
+    if !config.metrics.as_ref().is_some_and(|m| m.enable) {
+        return Ok(());
+    }
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Full("http_request_duration_seconds".to_string()),
+            &DURATION_BUCKETS,
+        )?
+        .install_recorder()?;
+    describe();
+    let _ = HANDLE.set(handle);
+    Ok(())
+}
+
+fn describe() {
+    describe_counter!("http_requests_total", "HTTP 请求数");
+    describe_histogram!(
+        "http_request_duration_seconds",
+        metrics::Unit::Seconds,
+        "HTTP 请求处理到返回响应头的耗时"
+    );
+    describe_counter!(
+        "transfer_bytes_total",
+        metrics::Unit::Bytes,
+        "按根目录统计的上传和下载字节数"
+    );
+    describe_gauge!("transfers_in_flight", "进行中的上传和下载");
+    describe_gauge!("sessions_active", "网页登录的会话数");
+    describe_counter!("login_failures_total", "登录失败次数");
+    describe_gauge!(
+        "process_resident_memory_bytes",
+        metrics::Unit::Bytes,
+        "常驻内存"
+    );
+    describe_gauge!(
+        "process_virtual_memory_bytes",
+        metrics::Unit::Bytes,
+        "虚拟内存"
+    );
+    describe_counter!(
+        "process_cpu_seconds_total",
+        metrics::Unit::Seconds,
+        "用户态和内核态 CPU 时间，精确到秒"
+    );
+    describe_gauge!("process_open_fds", "打开的文件描述符");
+    describe_gauge!("process_threads", "线程数");
+    describe_gauge!(
+        "process_start_time_seconds",
+        metrics::Unit::Seconds,
+        "进程启动的 Unix 时间"
+    );
+}
+
+pub fn enabled() -> bool {
+    HANDLE.get().is_some()
+}
+
+/// 统计每个请求的数量和耗时，`route` 使用路由模板，静态文件等未匹配路由的请求记为 `fallback`
+pub async fn middleware(request: Request, next: Next) -> Response {
+    if !enabled() {
+        return next.run(request).await;
+    }
+    let started = Instant::now();
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or("fallback".to_string());
+    let response = next.run(request).await;
+    let labels = [
+        ("method", method),
+        ("route", route),
+        ("status", response.status().as_u16().to_string()),
+    ];
+    counter!("http_requests_total", &labels).increment(1);
+    histogram!("http_request_duration_seconds", &labels).record(started.elapsed().as_secs_f64());
+    response
+}
+
+pub fn login_failed(protocol: &'static str) {
+    counter!("login_failures_total", "protocol" => protocol).increment(1);
+}
+
+/// 进行中的一次上传或下载，丢弃时从 `transfers_in_flight` 中减去
+pub struct Transfer {
+    direction: &'static str,
+}
+
+impl Transfer {
+    /// `direction` 为 `upload` 或 `download`
+    pub fn start(direction: &'static str) -> Self {
+        gauge!("transfers_in_flight", "direction" => direction).increment(1);
+        Transfer { direction }
+    }
+
+    pub fn finish(self, root: Option<&str>, bytes: u64) {
+        counter!(
+            "transfer_bytes_total",
+            "direction" => self.direction,
+            "root" => root.unwrap_or_default().to_string()
+        )
+        .increment(bytes);
+    }
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        gauge!("transfers_in_flight", "direction" => self.direction).decrement(1);
+    }
+}
+
+/// 只有 `/metrics` 的路由，挂在 HTTP 服务上或在独立端口上运行
+pub fn router(state: AppState, config: &MetricsFromFile) -> Router {
+    let token = config.token.clone();
+    Router::new()
+        .route(
+            METRICS_PATH,
+            get(move |state: State<AppState>, headers: HeaderMap| {
+                scrape(state, headers, token.clone())
+            }),
+        )
+        .with_state(state)
+}
+
+async fn scrape(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    token: Option<String>,
+) -> Response {
+    let Some(handle) = HANDLE.get() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if let Some(token) = token {
+        let provided = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|s| s.strip_prefix("Bearer "))
+            .unwrap_or_default();
+        if !constant_time_eq(provided.as_bytes(), token.as_bytes()) {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    }
+
+    gauge!("sessions_active").set(state.user_sessions.lock().await.len() as f64);
+    process_stats();
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        handle.render(),
+    )
+        .into_response()
+}
+
+/// 从 `/proc` 读取进程状态
+#[cfg(target_os = "linux")]
+fn process_stats() {
+    // /proc 中的 CPU 时间以 USER_HZ 为单位，在 Linux 上固定为 100
+    const TICKS_PER_SECOND: f64 = 100.0;
+
+    if let Ok(status) = std::fs::read_to_string("/proc/self/status") {
+        for line in status.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let number = value
+                .split_whitespace()
+                .next()
+                .and_then(|n| n.parse::<f64>().ok());
+            match (key, number) {
+                ("VmRSS", Some(kb)) => gauge!("process_resident_memory_bytes").set(kb * 1024.0),
+                ("VmSize", Some(kb)) => gauge!("process_virtual_memory_bytes").set(kb * 1024.0),
+                ("Threads", Some(n)) => gauge!("process_threads").set(n),
+                _ => {}
+            }
+        }
+    }
+
+    // 进程名可能包含空格，从最后一个 `)` 之后开始按字段解析
+    if let Ok(stat) = std::fs::read_to_string("/proc/self/stat")
+        && let Some((_, rest)) = stat.rsplit_once(')')
+    {
+        let fields: Vec<&str> = rest.split_whitespace().collect();
+        let field = |i: usize| fields.get(i).and_then(|f| f.parse::<f64>().ok());
+        // 以进程状态为第 0 个字段：utime 为 11，stime 为 12，starttime 为 19
+        if let (Some(utime), Some(stime)) = (field(11), field(12)) {
+            // 计数器只能取整数
+            counter!("process_cpu_seconds_total")
+                .absolute(((utime + stime) / TICKS_PER_SECOND) as u64);
+        }
+        let boot_time = std::fs::read_to_string("/proc/stat").ok().and_then(|s| {
+            s.lines()
+                .find_map(|l| l.strip_prefix("btime "))
+                .and_then(|t| t.trim().parse::<f64>().ok())
+        });
+        if let (Some(start), Some(boot_time)) = (field(19), boot_time) {
+            gauge!("process_start_time_seconds").set(boot_time + start / TICKS_PER_SECOND);
+        }
+    }
+
+    if let Ok(fds) = std::fs::read_dir("/proc/self/fd") {
+        gauge!("process_open_fds").set(fds.count() as f64);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn process_stats() {}
@@ -0,0 +1,358 @@
+//! ACME 自动申请证书：为 `[acme] domains` 申请证书并缓存在 `cache_dir`，到期前自动续期，
+//! 新证书直接替换到 HTTPS 监听器上。
+//! HTTP-01 的验证文件写入 `acme_challenge_dir`，由 HTTP 端口提供；
+//! TLS-ALPN-01 在 HTTPS 端口上对 `acme-tls/1` 握手返回验证证书（RFC 8737）。
+
+use std::{
+    collections::HashMap,
+    io,
+    path::PathBuf,
+    sync::{Arc, LazyLock, RwLock},
+    time::Duration,
+};
+
+use axum_server::tls_rustls::RustlsConfig;
+use instant_acme::{
+    Account, AccountBuilder, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier,
+    LetsEncrypt, NewAccount, NewOrder, OrderStatus, RetryPolicy,
+};
+use rustls::{
+    pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+use x509_parser::prelude::GeneralName;
+
+use crate::{
+    model::{AcmeChallenge, AcmeFromFile, ConfigFromFile},
+    utils::AtomicFile,
+};
+
+pub(super) const ACME_TLS_ALPN: &[u8] = b"acme-tls/1";
+const DEFAULT_RENEW_BEFORE_DAYS: i64 = 30;
+/// 检查证书是否需要续期的间隔
+const CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+/// 申请失败后重试的间隔
+const RETRY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+type AcmeResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// 正在进行的 TLS-ALPN-01 验证，按域名保存验证证书。
+/// 证书重新加载时 `ServerConfig` 会被替换，所以放在全局而不是解析器里
+static CHALLENGE_CERTS: LazyLock<RwLock<HashMap<String, Arc<CertifiedKey>>>> =
+    LazyLock::new(Default::default);
+
+/// 启用 ACME 时使用的证书选择：`acme-tls/1` 握手返回对应域名的验证证书，其余握手返回正常证书
+#[derive(Debug)]
+pub(super) struct AcmeCertResolver {
+    cert: Arc<CertifiedKey>,
+}
+
+impl AcmeCertResolver {
+    pub(super) fn new(cert: CertifiedKey) -> Self {
+        AcmeCertResolver {
+            cert: Arc::new(cert),
+        }
+    }
+}
+
+impl ResolvesServerCert for AcmeCertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let is_challenge = client_hello
+            .alpn()
+            .is_some_and(|mut protocols| protocols.any(|p| p == ACME_TLS_ALPN));
+        if !is_challenge {
+            return Some(self.cert.clone());
+        }
+        let domain = client_hello.server_name()?;
+        CHALLENGE_CERTS.read().ok()?.get(domain).cloned()
+    }
+}
+
+/// 还没有证书时先生成自签名的临时证书，让 HTTPS 端口可以启动，
+/// 申请成功后会被替换
+pub fn prepare(config: &ConfigFromFile) -> io::Result<()> {
+    let Some(acme) = config.acme.as_ref().filter(|a| a.enable) else {
+        return Ok(());
+    };
+    // instant-acme 的 HTTP 客户端使用进程级的默认加密实现，依赖中同时存在 ring 与 aws-lc-rs，
+    // 无法自动选择
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let (cert_file, key_file) = config.tls_files();
+    if cert_file.exists() && key_file.exists() {
+        return Ok(());
+    }
+    let placeholder =
+        rcgen::generate_simple_self_signed(acme.domains.clone()).map_err(io::Error::other)?;
+    std::fs::create_dir_all(acme.cache_dir())?;
+    std::fs::write(&key_file, placeholder.signing_key.serialize_pem())?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&key_file, std::fs::Permissions::from_mode(0o600))?;
+    }
+    std::fs::write(&cert_file, placeholder.cert.pem())?;
+    info!(
+        "已生成临时自签名证书 {}，等待 ACME 申请",
+        cert_file.display()
+    );
+    Ok(())
+}
+
+/// 需要申请新证书时返回原因
+fn renewal_reason(config: &ConfigFromFile, acme: &AcmeFromFile) -> Option<String> {
+    let (cert_file, _) = config.tls_files();
+    let Ok(certs) = super::load_certs(&cert_file) else {
+        return Some("没有可用的证书".to_string());
+    };
+    let Ok((_, cert)) = x509_parser::parse_x509_certificate(certs[0].as_ref()) else {
+        return Some("无法解析现有证书".to_string());
+    };
+    if cert.subject() == cert.issuer() {
+        return Some("当前使用的是自签名证书".to_string());
+    }
+
+    let mut names = Vec::new();
+    if let Ok(Some(san)) = cert.subject_alternative_name() {
+        for name in &san.value.general_names {
+            if let GeneralName::DNSName(n) = name {
+                names.push(n.to_string());
+            }
+        }
+    }
+    if let Some(domain) = acme.domains.iter().find(|d| !names.contains(d)) {
+        return Some(format!("现有证书不包含域名 {}", domain));
+    }
+
+    let not_after = chrono::DateTime::from_timestamp(cert.validity().not_after.timestamp(), 0)?;
+    let renew_before = acme.renew_before_days.unwrap_or(DEFAULT_RENEW_BEFORE_DAYS);
+    if not_after - chrono::Utc::now() < chrono::Duration::days(renew_before) {
+        return Some(format!("证书将于 {} 到期", not_after));
+    }
+    None
+}
+
+/// 账户信息与目录地址一起缓存，目录地址变化时重新注册
+#[derive(Serialize, Deserialize)]
+struct CachedAccount {
+    directory_url: String,
+    credentials: AccountCredentials,
+}
+
+fn account_builder(acme: &AcmeFromFile) -> AcmeResult<AccountBuilder> {
+    Ok(match &acme.directory_ca_file {
+        Some(ca_file) => Account::builder_with_root(ca_file)?,
+        None => Account::builder()?,
+    })
+}
+
+async fn load_or_create_account(acme: &AcmeFromFile) -> AcmeResult<Account> {
+    let directory_url = acme
+        .directory_url
+        .clone()
+        .unwrap_or(LetsEncrypt::Production.url().to_string());
+    let file = acme.cache_dir().join("account.json");
+    if let Ok(data) = tokio::fs::read(&file).await
+        && let Ok(cached) = serde_json::from_slice::<CachedAccount>(&data)
+        && cached.directory_url == directory_url
+    {
+        return Ok(account_builder(acme)?
+            .from_credentials(cached.credentials)
+            .await?);
+    }
+
+    let contact: Vec<String> = acme
+        .contact
+        .iter()
+        .map(|c| {
+            if c.contains(':') {
+                c.clone()
+            } else {
+                format!("mailto:{}", c)
+            }
+        })
+        .collect();
+    let contact: Vec<&str> = contact.iter().map(String::as_str).collect();
+    let (account, credentials) = account_builder(acme)?
+        .create(
+            &NewAccount {
+                contact: &contact,
+                terms_of_service_agreed: true,
+                only_return_existing: false,
+            },
+            directory_url.clone(),
+            None,
+        )
+        .await?;
+    info!("已在 {} 注册 ACME 账户", directory_url);
+
+    let cached = CachedAccount {
+        directory_url,
+        credentials,
+    };
+    let mut out = AtomicFile::create(&file).await?;
+    out.write_all(&serde_json::to_vec_pretty(&cached)?).await?;
+    out.commit().await?;
+    Ok(account)
+}
+
+/// TLS-ALPN-01 的验证证书，带有 acmeIdentifier 扩展
+fn challenge_cert(domain: &str, digest: &[u8]) -> AcmeResult<CertifiedKey> {
+    let key_pair = rcgen::KeyPair::generate()?;
+    let mut params = rcgen::CertificateParams::new(vec![domain.to_string()])?;
+    params.custom_extensions = vec![rcgen::CustomExtension::new_acme_identifier(digest)];
+    let cert = params.self_signed(&key_pair)?;
+    let key = PrivateKeyDer::from(PrivatePkcs8KeyDer::from(key_pair.serialize_der()));
+    let provider = rustls::crypto::ring::default_provider();
+    Ok(CertifiedKey::from_der(
+        vec![cert.der().clone()],
+        key,
+        &provider,
+    )?)
+}
+
+/// 申请结束时（无论成功与否）清理验证文件和验证证书
+#[derive(Default)]
+struct PendingChallenges {
+    files: Vec<PathBuf>,
+    domains: Vec<String>,
+}
+
+impl Drop for PendingChallenges {
+    fn drop(&mut self) {
+        for file in &self.files {
+            let _ = std::fs::remove_file(file);
+        }
+        if let Ok(mut certs) = CHALLENGE_CERTS.write() {
+            for domain in &self.domains {
+                certs.remove(domain);
+            }
+        }
+    }
+}
+
+/// 完成一次订单，把证书和私钥写入 `tls_files`
+async fn issue(config: &ConfigFromFile, acme: &AcmeFromFile) -> AcmeResult<()> {
+    let account = load_or_create_account(acme).await?;
+    let identifiers: Vec<Identifier> = acme
+        .domains
+        .iter()
+        .map(|d| Identifier::Dns(d.clone()))
+        .collect();
+    let mut order = account.new_order(&NewOrder::new(&identifiers)).await?;
+
+    let challenge = acme.challenge.unwrap_or(AcmeChallenge::Http01);
+    let challenge_type = match challenge {
+        AcmeChallenge::Http01 => ChallengeType::Http01,
+        AcmeChallenge::TlsAlpn01 => ChallengeType::TlsAlpn01,
+    };
+    let challenge_dir = PathBuf::from(
+        config
+            .misc
+            .as_ref()
+            .and_then(|m| m.acme_challenge_dir.clone())
+            .unwrap_or(super::DEFAULT_ACME_CHALLENGE_DIR.to_string()),
+    );
+
+    let mut pending = PendingChallenges::default();
+    let mut authorizations = order.authorizations();
+    while let Some(authz) = authorizations.next().await {
+        let mut authz = authz?;
+        match authz.status {
+            AuthorizationStatus::Pending => {}
+            AuthorizationStatus::Valid => continue,
+            status => {
+                return Err(format!("{} 的授权状态为 {:?}", authz.identifier(), status).into());
+            }
+        }
+        let mut handle = authz
+            .challenge(challenge_type.clone())
+            .ok_or_else(|| format!("ACME 服务器没有提供 {:?} 验证", challenge))?;
+        let domain = handle.identifier().to_string();
+        let key_authorization = handle.key_authorization();
+        match challenge {
+            AcmeChallenge::Http01 => {
+                tokio::fs::create_dir_all(&challenge_dir).await?;
+                let file = challenge_dir.join(&handle.token);
+                tokio::fs::write(&file, key_authorization.as_str()).await?;
+                pending.files.push(file);
+            }
+            AcmeChallenge::TlsAlpn01 => {
+                let cert = challenge_cert(&domain, key_authorization.digest().as_ref())?;
+                if let Ok(mut certs) = CHALLENGE_CERTS.write() {
+                    certs.insert(domain.clone(), Arc::new(cert));
+                }
+                pending.domains.push(domain.clone());
+            }
+        }
+        info!("已准备 {} 的 {:?} 验证", domain, challenge);
+        handle.set_ready().await?;
+    }
+
+    let retry = RetryPolicy::new().timeout(Duration::from_secs(120));
+    let status = order.poll_ready(&retry).await?;
+    drop(pending);
+    if status != OrderStatus::Ready {
+        return Err(format!("ACME 订单验证失败，状态为 {:?}", status).into());
+    }
+    let key_pem = order.finalize().await?;
+    let cert_pem = order.poll_certificate(&retry).await?;
+
+    // 先写私钥再写证书，文件监视在两者不匹配时会保留旧证书并重试
+    let (cert_file, key_file) = config.tls_files();
+    let mut key_out = AtomicFile::create(&key_file).await?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        key_out
+            .as_file_mut()
+            .set_permissions(std::fs::Permissions::from_mode(0o600))
+            .await?;
+    }
+    key_out.write_all(key_pem.as_bytes()).await?;
+    key_out.commit().await?;
+    let mut cert_out = AtomicFile::create(&cert_file).await?;
+    cert_out.write_all(cert_pem.as_bytes()).await?;
+    cert_out.commit().await?;
+    Ok(())
+}
+
+/// 在后台申请和续期证书，成功后立即替换 HTTPS 监听器使用的证书
+pub fn spawn_acme(config: Arc<ConfigFromFile>, rustls_config: RustlsConfig) {
+    let Some(acme) = config.acme.clone().filter(|a| a.enable) else {
+        return;
+    };
+    tokio::spawn(async move {
+        loop {
+            let delay = match renewal_reason(&config, &acme) {
+                None => CHECK_INTERVAL,
+                Some(reason) => {
+                    info!(
+                        "{}，开始通过 ACME 申请证书: {}",
+                        reason,
+                        acme.domains.join(", ")
+                    );
+                    match issue(&config, &acme).await {
+                        Ok(()) => {
+                            info!("ACME 证书申请成功");
+                            super::reload::reload(&config, &rustls_config).await;
+                            CHECK_INTERVAL
+                        }
+                        Err(e) => {
+                            error!(
+                                "ACME 证书申请失败，{} 分钟后重试: {}",
+                                RETRY_INTERVAL.as_secs() / 60,
+                                e
+                            );
+                            RETRY_INTERVAL
+                        }
+                    }
+                }
+            };
+            tokio::time::sleep(delay).await;
+        }
+    });
+}
@@ -0,0 +1,248 @@
+//! 客户端证书（mTLS）：握手完成后把证书中的名称放进每个请求的扩展里，
+//! 由 [`crate::extractors::AuthUser`] 映射为用户。
+
+use std::{future::Future, io, pin::Pin, sync::Arc};
+
+use axum_server::{accept::Accept, tls_rustls::RustlsAcceptor};
+use rustls::pki_types::CertificateDer;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::server::TlsStream;
+use tower_http::add_extension::AddExtension;
+use x509_parser::prelude::GeneralName;
+
+/// 连接上经过 CA 验证的客户端证书中的名称（CN 与 SAN），没有证书时为空
+#[derive(Clone, Default)]
+pub struct ClientCertNames(pub Arc<Vec<String>>);
+
+/// 证书的 CN 以及 SAN 中的 DNS 名称和邮箱
+fn cert_names(cert: &CertificateDer<'_>) -> Vec<String> {
+    let Ok((_, cert)) = x509_parser::parse_x509_certificate(cert.as_ref()) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = cert
+        .subject()
+        .iter_common_name()
+        .filter_map(|cn| cn.as_str().ok())
+        .map(|cn| cn.to_string())
+        .collect();
+    if let Ok(Some(san)) = cert.subject_alternative_name() {
+        for name in &san.value.general_names {
+            match name {
+                GeneralName::DNSName(n) | GeneralName::RFC822Name(n) => names.push(n.to_string()),
+                _ => {}
+            }
+        }
+    }
+    names
+}
+
+/// 在 rustls 握手之后读取客户端证书
+#[derive(Clone)]
+pub struct ClientCertAcceptor {
+    inner: RustlsAcceptor,
+}
+
+impl ClientCertAcceptor {
+    pub fn new(inner: RustlsAcceptor) -> Self {
+        ClientCertAcceptor { inner }
+    }
+}
+
+impl<I, S> Accept<I, S> for ClientCertAcceptor
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = TlsStream<I>;
+    type Service = AddExtension<S, ClientCertNames>;
+    type Future =
+        Pin<Box<dyn Future<Output = io::Result<(Self::Stream, Self::Service)>> + Send + 'static>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let handshake = self.inner.accept(stream, service);
+        Box::pin(async move {
+            let (stream, service) = handshake.await?;
+            // rustls 已经按 client_ca_file 验证过证书链，这里只取叶子证书
+            let names = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .map(cert_names)
+                .unwrap_or_default();
+            Ok((
+                stream,
+                AddExtension::new(service, ClientCertNames(Arc::new(names))),
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use axum::http::{Request, Response};
+    use axum_server::tls_rustls::RustlsConfig;
+    use rustls::{
+        ClientConfig, RootCertStore,
+        pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer, ServerName},
+    };
+    use tokio_rustls::TlsConnector;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::tls::tests::{config, write_cert};
+
+    struct Ca {
+        cert: rcgen::Certificate,
+        issuer: rcgen::Issuer<'static, rcgen::KeyPair>,
+    }
+
+    fn ca() -> Ca {
+        let mut params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "Test CA");
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params.key_usages = vec![
+            rcgen::KeyUsagePurpose::KeyCertSign,
+            rcgen::KeyUsagePurpose::DigitalSignature,
+        ];
+        let key_pair = rcgen::KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key_pair).unwrap();
+        Ca {
+            cert,
+            issuer: rcgen::Issuer::new(params, key_pair),
+        }
+    }
+
+    /// CN 为 `common_name`，SAN 中带一个 DNS 名称和一个邮箱
+    fn client_cert(
+        ca: &Ca,
+        common_name: &str,
+    ) -> (CertificateDer<'static>, PrivateKeyDer<'static>) {
+        let mut params = rcgen::CertificateParams::new(vec!["client.example".to_string()]).unwrap();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, common_name);
+        params.subject_alt_names.push(rcgen::SanType::Rfc822Name(
+            "bob@example.com".try_into().unwrap(),
+        ));
+        let key_pair = rcgen::KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key_pair, &ca.issuer).unwrap();
+        let key = PrivateKeyDer::from(PrivatePkcs8KeyDer::from(key_pair.serialize_der()));
+        (cert.der().clone(), key)
+    }
+
+    /// 以 `client_auth = mode` 启动 HTTPS 配置，返回配置和服务端证书
+    fn server(
+        dir: &std::path::Path,
+        ca: &Ca,
+        mode: &str,
+    ) -> (RustlsConfig, CertificateDer<'static>) {
+        let server_cert = write_cert(dir, &["localhost"]);
+        let ca_file = dir.join("ca.pem");
+        std::fs::write(&ca_file, ca.cert.pem()).unwrap();
+        let misc = format!(
+            "client_auth = {:?}\nclient_ca_file = {:?}",
+            mode,
+            ca_file.display().to_string()
+        );
+        let rustls_config = crate::tls::rustls_config(&config(dir, &misc)).unwrap();
+        (rustls_config, server_cert)
+    }
+
+    /// 握手并返回请求扩展中的证书名称
+    async fn connect(
+        rustls_config: RustlsConfig,
+        server_cert: CertificateDer<'static>,
+        client_cert: Option<(CertificateDer<'static>, PrivateKeyDer<'static>)>,
+    ) -> io::Result<Vec<String>> {
+        let mut roots = RootCertStore::empty();
+        roots.add(server_cert).unwrap();
+        let builder =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots);
+        let client = match client_cert {
+            Some((cert, key)) => builder.with_client_auth_cert(vec![cert], key).unwrap(),
+            None => builder.with_no_client_auth(),
+        };
+
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let acceptor = ClientCertAcceptor::new(RustlsAcceptor::new(rustls_config));
+        let service = tower::service_fn(|request: Request<()>| async move {
+            let names = request.extensions().get::<ClientCertNames>().cloned();
+            Ok::<_, Infallible>(Response::new(names))
+        });
+        let connector = TlsConnector::from(Arc::new(client));
+        let (client, server) = tokio::join!(
+            async {
+                // TLS 1.3 下服务端在客户端发出第一条数据后才会拒绝证书
+                let mut stream = connector
+                    .connect(ServerName::try_from("localhost").unwrap(), client_io)
+                    .await?;
+                tokio::io::AsyncWriteExt::write_all(&mut stream, b"ping").await?;
+                Ok::<_, io::Error>(stream)
+            },
+            acceptor.accept(server_io, service)
+        );
+        let _client = client?;
+        let (_, service) = server?;
+        let names = service
+            .oneshot(Request::new(()))
+            .await
+            .unwrap()
+            .into_body()
+            .unwrap();
+        Ok(names.0.to_vec())
+    }
+
+    #[tokio::test]
+    async fn verified_certificate_names_are_attached_to_requests() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca = ca();
+        let (rustls_config, server_cert) = server(dir.path(), &ca, "require");
+        let names = connect(rustls_config, server_cert, Some(client_cert(&ca, "alice")))
+            .await
+            .unwrap();
+        assert_eq!(names, ["alice", "client.example", "bob@example.com"]);
+    }
+
+    #[tokio::test]
+    async fn require_rejects_missing_or_untrusted_certificates() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca = ca();
+        let (rustls_config, server_cert) = server(dir.path(), &ca, "require");
+        assert!(
+            connect(rustls_config.clone(), server_cert.clone(), None)
+                .await
+                .is_err()
+        );
+        let other = client_cert(&self::ca(), "alice");
+        assert!(
+            connect(rustls_config, server_cert, Some(other))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn allow_accepts_clients_without_certificates() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca = ca();
+        let (rustls_config, server_cert) = server(dir.path(), &ca, "allow");
+        let names = connect(rustls_config, server_cert, None).await.unwrap();
+        assert!(names.is_empty());
+    }
+
+    #[test]
+    fn client_auth_needs_a_ca_file() {
+        let dir = tempfile::tempdir().unwrap();
+        write_cert(dir.path(), &["localhost"]);
+        let config = config(dir.path(), "client_auth = \"require\"");
+        assert!(crate::tls::server_config(&config).is_err());
+    }
+}
@@ -0,0 +1,531 @@
+//! HTTPS 服务使用的 rustls 配置：证书与私钥来自 [`ConfigFromFile::tls_files`]，
+//! 可以限制最低 TLS 版本，并通过 ALPN 协商 HTTP/2。FTPS 通过 [`ftps_config`] 使用同一份证书。
+//! 同时提供启用 HTTPS 后 HTTP 端口的重定向路由和 HSTS 响应头。
+
+mod acme;
+mod client_cert;
+mod reload;
+
+pub use acme::{prepare as prepare_acme, spawn_acme};
+pub use client_cert::{ClientCertAcceptor, ClientCertNames};
+pub use reload::spawn_reloader;
+
+use std::{fmt, io, path::Path, sync::Arc};
+
+use axum::{
+    Router,
+    extract::{Request, State},
+    http::{HeaderValue, StatusCode, header, uri::Authority},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+};
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::{
+    RootCertStore, ServerConfig, SupportedProtocolVersion,
+    pki_types::{CertificateDer, PrivateKeyDer},
+    server::{
+        ClientHello, ResolvesServerCert, ServerSessionMemoryCache, WebPkiClientVerifier,
+        danger::ClientCertVerifier,
+    },
+    sign::CertifiedKey,
+};
+use tower_http::services::ServeDir;
+use tracing::{info, warn};
+
+use crate::{
+    model::{ClientAuthMode, ConfigFromFile, HttpMode, MiscFromFile},
+    proxy::{self, Proxy},
+};
+
+pub const DEFAULT_HTTPS_PORT: u16 = 8443;
+pub const DEFAULT_ACME_CHALLENGE_DIR: &str = "data/acme-challenge";
+const ACME_CHALLENGE_PATH: &str = "/.well-known/acme-challenge";
+const DEFAULT_CERT_EXPIRY_WARN_DAYS: i64 = 14;
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn protocol_versions(
+    min_version: Option<&str>,
+) -> io::Result<&'static [&'static SupportedProtocolVersion]> {
+    static TLS12_AND_13: &[&SupportedProtocolVersion] =
+        &[&rustls::version::TLS13, &rustls::version::TLS12];
+    static TLS13_ONLY: &[&SupportedProtocolVersion] = &[&rustls::version::TLS13];
+    match min_version {
+        None | Some("1.2") => Ok(TLS12_AND_13),
+        Some("1.3") => Ok(TLS13_ONLY),
+        Some(v) => Err(invalid(format!("不支持的 tls_min_version: {}", v))),
+    }
+}
+
+fn load_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let data = std::fs::read(path)?;
+    let certs = rustls_pemfile::certs(&mut data.as_slice()).collect::<io::Result<Vec<_>>>()?;
+    if certs.is_empty() {
+        return Err(invalid(format!("{} 中没有证书", path.display())));
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
+    let data = std::fs::read(path)?;
+    rustls_pemfile::private_key(&mut data.as_slice())?
+        .ok_or_else(|| invalid(format!("{} 中没有私钥", path.display())))
+}
+
+/// 输出证书的主题和到期时间，临近到期或已过期时输出警告
+fn log_certificate(cert: &CertificateDer<'_>, warn_days: i64) {
+    let Ok((_, cert)) = x509_parser::parse_x509_certificate(cert.as_ref()) else {
+        warn!("无法解析 TLS 证书，跳过有效期检查");
+        return;
+    };
+    let subject = cert.subject().to_string();
+    let Some(not_after) =
+        chrono::DateTime::from_timestamp(cert.validity().not_after.timestamp(), 0)
+    else {
+        return;
+    };
+    let remaining = not_after - chrono::Utc::now();
+    info!("TLS 证书: {}，有效期至 {}", subject, not_after);
+    if remaining <= chrono::Duration::zero() {
+        warn!("TLS 证书 {} 已于 {} 过期", subject, not_after);
+    } else if remaining < chrono::Duration::days(warn_days) {
+        warn!(
+            "TLS 证书 {} 将在 {} 天后过期（{}）",
+            subject,
+            remaining.num_days(),
+            not_after
+        );
+    }
+}
+
+/// 按 `client_auth` 构建客户端证书验证器，`ignore` 时返回 `None`
+fn client_verifier(
+    misc: Option<&MiscFromFile>,
+    provider: &Arc<rustls::crypto::CryptoProvider>,
+) -> io::Result<Option<Arc<dyn ClientCertVerifier>>> {
+    let mode = misc
+        .and_then(|m| m.client_auth)
+        .unwrap_or(ClientAuthMode::Ignore);
+    if mode == ClientAuthMode::Ignore {
+        return Ok(None);
+    }
+    let ca_file = misc
+        .and_then(|m| m.client_ca_file.as_ref())
+        .ok_or_else(|| invalid("client_auth 需要配置 client_ca_file".to_string()))?;
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(Path::new(ca_file))? {
+        roots
+            .add(cert)
+            .map_err(|e| invalid(format!("无效的 CA 证书 {}: {}", ca_file, e)))?;
+    }
+    let builder = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone());
+    let builder = if mode == ClientAuthMode::Allow {
+        builder.allow_unauthenticated()
+    } else {
+        builder
+    };
+    builder
+        .build()
+        .map(Some)
+        .map_err(|e| invalid(e.to_string()))
+}
+
+/// 按配置构建 rustls 的 `ServerConfig`
+pub fn server_config(config: &ConfigFromFile) -> io::Result<ServerConfig> {
+    let misc = config.misc.as_ref();
+    let (cert_file, key_file) = config.tls_files();
+    let certs = load_certs(&cert_file)?;
+    let key = load_key(&key_file)?;
+    let leaf = certs[0].clone();
+    let versions = protocol_versions(misc.and_then(|m| m.tls_min_version.as_deref()))?;
+
+    // 依赖中同时存在 ring 与 aws-lc-rs，需要显式指定加密实现
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(versions)
+        .map_err(|e| invalid(e.to_string()))?;
+    let builder = match client_verifier(misc, &provider)? {
+        Some(verifier) => builder.with_client_cert_verifier(verifier),
+        None => builder.with_no_client_auth(),
+    };
+    let acme_enabled = config.acme.as_ref().is_some_and(|a| a.enable);
+    let mut server_config = if acme_enabled {
+        // ACME 需要在 TLS-ALPN-01 验证时换用验证证书
+        let cert =
+            CertifiedKey::from_der(certs, key, &provider).map_err(|e| invalid(e.to_string()))?;
+        builder.with_cert_resolver(Arc::new(acme::AcmeCertResolver::new(cert)))
+    } else {
+        builder
+            .with_single_cert(certs, key)
+            .map_err(|e| invalid(e.to_string()))?
+    };
+    log_certificate(
+        &leaf,
+        misc.and_then(|m| m.cert_expiry_warn_days)
+            .unwrap_or(DEFAULT_CERT_EXPIRY_WARN_DAYS),
+    );
+
+    server_config.alpn_protocols = if misc.and_then(|m| m.http2).unwrap_or(true) {
+        vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+    } else {
+        vec![b"http/1.1".to_vec()]
+    };
+    if acme_enabled {
+        server_config
+            .alpn_protocols
+            .push(acme::ACME_TLS_ALPN.to_vec());
+    }
+    Ok(server_config)
+}
+
+pub fn rustls_config(config: &ConfigFromFile) -> io::Result<RustlsConfig> {
+    Ok(RustlsConfig::from_config(Arc::new(server_config(config)?)))
+}
+
+/// 每次握手时取 HTTPS 当前使用的证书，热重载和 ACME 续期后 FTPS 的新连接也使用新证书
+struct SharedCertResolver(RustlsConfig);
+
+impl fmt::Debug for SharedCertResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedCertResolver").finish_non_exhaustive()
+    }
+}
+
+impl ResolvesServerCert for SharedCertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.0.get_inner().cert_resolver.resolve(client_hello)
+    }
+}
+
+/// FTPS 的 rustls 配置，证书来自 `rustls_config`。与 libunftp 的默认设置一致：只用 TLS 1.2
+/// （lftp 在 TLS 1.3 下恢复会话上传会出错），数据连接可以通过会话 ID 或票据恢复控制连接的会话
+pub fn ftps_config(rustls_config: &RustlsConfig) -> io::Result<Arc<ServerConfig>> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut server_config = ServerConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS12])
+        .map_err(|e| invalid(e.to_string()))?
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(SharedCertResolver(rustls_config.clone())));
+    server_config.session_storage = ServerSessionMemoryCache::new(1024);
+    server_config.ticketer =
+        rustls::crypto::ring::Ticketer::new().map_err(|e| invalid(e.to_string()))?;
+    Ok(Arc::new(server_config))
+}
+
+/// 按 `hsts_*` 配置生成 `Strict-Transport-Security` 的值，未配置时返回 `None`
+pub fn hsts_header(misc: &MiscFromFile) -> Option<HeaderValue> {
+    let max_age = misc.hsts_max_age.filter(|age| *age > 0)?;
+    let mut value = format!("max-age={}", max_age);
+    if misc.hsts_include_subdomains.unwrap_or(false) {
+        value.push_str("; includeSubDomains");
+    }
+    if misc.hsts_preload.unwrap_or(false) {
+        value.push_str("; preload");
+    }
+    HeaderValue::from_str(&value).ok()
+}
+
+/// 启用 HTTPS 后 HTTP 端口使用的路由，`serve` 模式下沿用完整的路由 `app`，
+/// `redirect` 模式下可信代理转发的 HTTPS 请求也由 `app` 处理。
+/// 所有模式都会提供 ACME HTTP-01 验证路径，方便申请和续期证书
+pub fn http_router(misc: Option<&MiscFromFile>, https_port: u16, app: Router) -> Router {
+    let mode = misc.and_then(|m| m.http_mode).unwrap_or(HttpMode::Serve);
+    let challenge_dir = misc
+        .and_then(|m| m.acme_challenge_dir.clone())
+        .unwrap_or(DEFAULT_ACME_CHALLENGE_DIR.to_string());
+    let challenges = ServeDir::new(challenge_dir);
+    let router = match mode {
+        HttpMode::Serve => app,
+        HttpMode::Redirect => app.layer(middleware::from_fn_with_state(
+            (https_port, Proxy::new(misc)),
+            redirect_to_https,
+        )),
+        HttpMode::AcmeOnly => Router::new().fallback(|| async { StatusCode::NOT_FOUND }),
+    };
+    router.nest_service(ACME_CHALLENGE_PATH, challenges)
+}
+
+/// 308 重定向到同一主机的 HTTPS 端口，保留路径和查询参数。
+/// 可信代理表明客户端已经使用 HTTPS 时照常处理，否则会不断重定向
+async fn redirect_to_https(
+    State((https_port, proxy)): State<(u16, Proxy)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some((ip, https)) = proxy::peer(request.extensions())
+        && proxy.client(ip, request.headers(), https).https
+    {
+        return next.run(request).await;
+    }
+    let Some(host) = request
+        .headers()
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.parse::<Authority>().ok())
+    else {
+        return (StatusCode::BAD_REQUEST, "缺少 Host 请求头").into_response();
+    };
+    let authority = if https_port == 443 {
+        host.host().to_string()
+    } else {
+        format!("{}:{}", host.host(), https_port)
+    };
+    let path = request
+        .uri()
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or("/");
+    (
+        StatusCode::PERMANENT_REDIRECT,
+        [(header::LOCATION, format!("https://{}{}", authority, path))],
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use axum::{body::Body, extract::ConnectInfo, routing::get};
+    use rustls::{ClientConfig, ProtocolVersion, pki_types::ServerName};
+    use tokio_rustls::{TlsAcceptor, TlsConnector, client::TlsStream};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    /// 自签名证书，`names` 同时作为 SAN，第一个名称作为 CN
+    pub(super) fn self_signed(names: &[&str]) -> (rcgen::Certificate, rcgen::KeyPair) {
+        let key_pair = rcgen::KeyPair::generate().unwrap();
+        let mut params =
+            rcgen::CertificateParams::new(names.iter().map(|n| n.to_string()).collect::<Vec<_>>())
+                .unwrap();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, names[0]);
+        let cert = params.self_signed(&key_pair).unwrap();
+        (cert, key_pair)
+    }
+
+    /// 在 `dir` 下写入 `cert.pem` 和 `key.pem`，返回证书
+    pub(super) fn write_cert(dir: &Path, names: &[&str]) -> CertificateDer<'static> {
+        let (cert, key_pair) = self_signed(names);
+        std::fs::write(dir.join("cert.pem"), cert.pem()).unwrap();
+        std::fs::write(dir.join("key.pem"), key_pair.serialize_pem()).unwrap();
+        cert.der().clone()
+    }
+
+    /// `misc` 为 `[misc]` 中的其他设置
+    pub(super) fn config(dir: &Path, misc: &str) -> ConfigFromFile {
+        toml::from_str(&format!(
+            "users = []\npaths = []\n[misc]\ncert_path = {:?}\n{}",
+            dir.display().to_string(),
+            misc
+        ))
+        .unwrap()
+    }
+
+    /// 信任 `roots` 的客户端配置
+    pub(super) fn client_config(
+        roots: &[CertificateDer<'static>],
+        versions: &[&'static SupportedProtocolVersion],
+        alpn: &[&[u8]],
+    ) -> ClientConfig {
+        let mut store = RootCertStore::empty();
+        for cert in roots {
+            store.add(cert.clone()).unwrap();
+        }
+        let mut config =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_protocol_versions(versions)
+                .unwrap()
+                .with_root_certificates(store)
+                .with_no_client_auth();
+        config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+        config
+    }
+
+    /// 在内存中完成一次握手，返回客户端的连接
+    pub(super) async fn handshake(
+        server: Arc<ServerConfig>,
+        client: ClientConfig,
+    ) -> io::Result<TlsStream<tokio::io::DuplexStream>> {
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let connector = TlsConnector::from(Arc::new(client));
+        let acceptor = TlsAcceptor::from(server);
+        let (client, _) = tokio::join!(
+            connector.connect(ServerName::try_from("localhost").unwrap(), client_io),
+            acceptor.accept(server_io)
+        );
+        client
+    }
+
+    const ALL_VERSIONS: &[&SupportedProtocolVersion] =
+        &[&rustls::version::TLS13, &rustls::version::TLS12];
+
+    #[tokio::test]
+    async fn serves_the_configured_certificate_with_alpn() {
+        let dir = tempfile::tempdir().unwrap();
+        let cert = write_cert(dir.path(), &["localhost"]);
+        let server = Arc::new(server_config(&config(dir.path(), "")).unwrap());
+
+        let client = client_config(
+            std::slice::from_ref(&cert),
+            ALL_VERSIONS,
+            &[b"h2", b"http/1.1"],
+        );
+        let stream = handshake(server.clone(), client).await.unwrap();
+        let (_, connection) = stream.get_ref();
+        assert_eq!(connection.alpn_protocol(), Some(&b"h2"[..]));
+        assert_eq!(connection.peer_certificates().unwrap()[0], cert);
+
+        let client = client_config(std::slice::from_ref(&cert), ALL_VERSIONS, &[b"http/1.1"]);
+        let stream = handshake(server, client).await.unwrap();
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"http/1.1"[..]));
+    }
+
+    #[tokio::test]
+    async fn http2_can_be_disabled() {
+        let dir = tempfile::tempdir().unwrap();
+        let cert = write_cert(dir.path(), &["localhost"]);
+        let server = Arc::new(server_config(&config(dir.path(), "http2 = false")).unwrap());
+        assert_eq!(server.alpn_protocols, vec![b"http/1.1".to_vec()]);
+
+        let client = client_config(&[cert], ALL_VERSIONS, &[b"h2", b"http/1.1"]);
+        let stream = handshake(server, client).await.unwrap();
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"http/1.1"[..]));
+    }
+
+    #[tokio::test]
+    async fn minimum_tls_version_is_enforced() {
+        let dir = tempfile::tempdir().unwrap();
+        let cert = write_cert(dir.path(), &["localhost"]);
+        let tls12 = &[&rustls::version::TLS12];
+
+        let server = Arc::new(server_config(&config(dir.path(), "")).unwrap());
+        let client = client_config(std::slice::from_ref(&cert), tls12, &[]);
+        let stream = handshake(server, client).await.unwrap();
+        assert_eq!(
+            stream.get_ref().1.protocol_version(),
+            Some(ProtocolVersion::TLSv1_2)
+        );
+
+        let misc = "tls_min_version = \"1.3\"";
+        let server = Arc::new(server_config(&config(dir.path(), misc)).unwrap());
+        let client = client_config(&[cert], tls12, &[]);
+        assert!(handshake(server, client).await.is_err());
+
+        let misc = "tls_min_version = \"1.1\"";
+        assert!(server_config(&config(dir.path(), misc)).is_err());
+    }
+
+    #[test]
+    fn missing_or_invalid_files_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let error = server_config(&config(dir.path(), "")).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+
+        write_cert(dir.path(), &["localhost"]);
+        std::fs::write(dir.path().join("key.pem"), "not a key").unwrap();
+        let error = server_config(&config(dir.path(), "")).unwrap_err();
+        assert!(error.to_string().contains("没有私钥"), "{}", error);
+    }
+
+    #[test]
+    fn hsts_header_follows_config() {
+        assert!(hsts_header(&MiscFromFile::default()).is_none());
+        let misc = MiscFromFile {
+            hsts_max_age: Some(0),
+            ..Default::default()
+        };
+        assert!(hsts_header(&misc).is_none());
+        let misc = MiscFromFile {
+            hsts_max_age: Some(31536000),
+            hsts_include_subdomains: Some(true),
+            hsts_preload: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(
+            hsts_header(&misc).unwrap(),
+            "max-age=31536000; includeSubDomains; preload"
+        );
+    }
+
+    async fn get_from(router: &Router, uri: &str, headers: &[(&str, &str)]) -> Response {
+        let mut request = Request::get(uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let mut request = request.body(Body::empty()).unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+        router.clone().oneshot(request).await.unwrap()
+    }
+
+    fn http_app(misc: &MiscFromFile, https_port: u16) -> Router {
+        let app = Router::new().route("/api/ping", get(|| async { "pong" }));
+        http_router(Some(misc), https_port, app)
+    }
+
+    #[tokio::test]
+    async fn redirect_mode_keeps_path_and_query() {
+        let misc = MiscFromFile {
+            http_mode: Some(HttpMode::Redirect),
+            ..Default::default()
+        };
+        let router = http_app(&misc, 8443);
+        let response = get_from(&router, "/api/ping?a=1", &[("host", "example.com:8080")]).await;
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "https://example.com:8443/api/ping?a=1"
+        );
+
+        let router = http_app(&misc, 443);
+        let response = get_from(&router, "/", &[("host", "example.com:8080")]).await;
+        assert_eq!(response.headers()[header::LOCATION], "https://example.com/");
+
+        let response = get_from(&router, "/", &[]).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn redirect_mode_serves_https_requests_from_trusted_proxies() {
+        let misc = MiscFromFile {
+            http_mode: Some(HttpMode::Redirect),
+            trusted_proxies: Some(vec!["127.0.0.1".to_string()]),
+            ..Default::default()
+        };
+        let router = http_app(&misc, 8443);
+        let headers = [("host", "example.com"), ("x-forwarded-proto", "https")];
+        let response = get_from(&router, "/api/ping", &headers).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let headers = [("host", "example.com"), ("x-forwarded-proto", "http")];
+        let response = get_from(&router, "/api/ping", &headers).await;
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+    }
+
+    #[tokio::test]
+    async fn acme_only_mode_serves_only_challenges() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("token"), "key-authorization").unwrap();
+        let misc = MiscFromFile {
+            http_mode: Some(HttpMode::AcmeOnly),
+            acme_challenge_dir: Some(dir.path().display().to_string()),
+            ..Default::default()
+        };
+        let router = http_app(&misc, 8443);
+        let response = get_from(&router, "/api/ping", &[("host", "example.com")]).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = get_from(
+            &router,
+            "/.well-known/acme-challenge/token",
+            &[("host", "example.com")],
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,148 @@
+//! 证书热更新：定期检查证书和私钥文件是否变化，Unix 上收到 SIGHUP 时也会重新加载。
+//! 新配置通过 `RustlsConfig` 原子替换，只影响之后的握手，已有连接不受影响。
+
+use std::{sync::Arc, time::Duration};
+
+use axum_server::tls_rustls::RustlsConfig;
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+use crate::{
+    model::ConfigFromFile,
+    utils::{FileStamp, stamp, watch_hangup},
+};
+
+const DEFAULT_RELOAD_INTERVAL: u64 = 60;
+
+fn stamps(config: &ConfigFromFile) -> (FileStamp, FileStamp) {
+    let (cert_file, key_file) = config.tls_files();
+    (stamp(&cert_file), stamp(&key_file))
+}
+
+pub(super) async fn reload(config: &Arc<ConfigFromFile>, rustls_config: &RustlsConfig) -> bool {
+    let config = config.clone();
+    match tokio::task::spawn_blocking(move || super::server_config(&config)).await {
+        Ok(Ok(server_config)) => {
+            rustls_config.reload_from_config(Arc::new(server_config));
+            info!("TLS 证书已重新加载");
+            true
+        }
+        Ok(Err(e)) => {
+            error!("TLS 证书重新加载失败，继续使用原证书: {}", e);
+            false
+        }
+        Err(e) => {
+            error!("TLS 证书重新加载失败，继续使用原证书: {}", e);
+            false
+        }
+    }
+}
+
+/// 在后台监视证书文件，变化或收到 SIGHUP 时重新加载
+pub fn spawn_reloader(config: Arc<ConfigFromFile>, rustls_config: RustlsConfig) {
+    let interval = config
+        .misc
+        .as_ref()
+        .and_then(|m| m.tls_reload_interval)
+        .unwrap_or(DEFAULT_RELOAD_INTERVAL);
+    let (tx, mut rx) = mpsc::channel(1);
+    watch_hangup(tx);
+
+    // 在启动任务前记录，任务开始运行前发生的变化也能检测到
+    let mut last = stamps(&config);
+    tokio::spawn(async move {
+        // 间隔为 0 时只响应 SIGHUP
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval.max(1)));
+        ticker.tick().await;
+        loop {
+            tokio::select! {
+                _ = ticker.tick(), if interval > 0 => {
+                    let current = stamps(&config);
+                    if current == last {
+                        continue;
+                    }
+                    info!("检测到证书文件变化，重新加载 TLS 证书");
+                    // 证书和私钥可能先后写入，加载失败时保留旧的时间戳，下次检查时重试
+                    if reload(&config, &rustls_config).await {
+                        last = current;
+                    }
+                }
+                Some(()) = rx.recv() => {
+                    info!("收到 SIGHUP，重新加载 TLS 证书");
+                    if reload(&config, &rustls_config).await {
+                        last = stamps(&config);
+                    }
+                }
+                else => break,
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use rustls::pki_types::CertificateDer;
+
+    use super::*;
+    use crate::tls::tests::{client_config, config, handshake, write_cert};
+
+    /// 客户端同时信任新旧证书，返回握手时服务端发送的证书
+    async fn served_cert(
+        rustls_config: &RustlsConfig,
+        trusted: &[CertificateDer<'static>],
+    ) -> CertificateDer<'static> {
+        let client = client_config(trusted, &[&rustls::version::TLS13], &[]);
+        let stream = handshake(rustls_config.get_inner(), client).await.unwrap();
+        stream.get_ref().1.peer_certificates().unwrap()[0].clone()
+    }
+
+    #[tokio::test]
+    async fn reload_swaps_the_certificate() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = write_cert(dir.path(), &["localhost"]);
+        let config = Arc::new(config(dir.path(), ""));
+        let rustls_config = crate::tls::rustls_config(&config).unwrap();
+
+        let new = write_cert(dir.path(), &["localhost"]);
+        let trusted = [old.clone(), new.clone()];
+        assert_eq!(served_cert(&rustls_config, &trusted).await, old);
+        assert!(reload(&config, &rustls_config).await);
+        assert_eq!(served_cert(&rustls_config, &trusted).await, new);
+    }
+
+    #[tokio::test]
+    async fn failed_reload_keeps_the_old_certificate() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = write_cert(dir.path(), &["localhost"]);
+        let config = Arc::new(config(dir.path(), ""));
+        let rustls_config = crate::tls::rustls_config(&config).unwrap();
+
+        // 证书已经换了，私钥还没写入
+        write_cert(dir.path(), &["localhost"]);
+        std::fs::write(dir.path().join("key.pem"), "").unwrap();
+        assert!(!reload(&config, &rustls_config).await);
+        assert_eq!(
+            served_cert(&rustls_config, std::slice::from_ref(&old)).await,
+            old
+        );
+    }
+
+    #[tokio::test]
+    async fn reloader_picks_up_changed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = write_cert(dir.path(), &["localhost"]);
+        let config = Arc::new(config(dir.path(), "tls_reload_interval = 1"));
+        let rustls_config = crate::tls::rustls_config(&config).unwrap();
+        spawn_reloader(config, rustls_config.clone());
+
+        let new = write_cert(dir.path(), &["localhost"]);
+        let trusted = [old, new.clone()];
+        for _ in 0..50 {
+            if served_cert(&rustls_config, &trusted).await == new {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("证书没有重新加载");
+    }
+}
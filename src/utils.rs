@@ -1,4 +1,15 @@
-use crate::model::file::Path;
+use std::{
+    collections::HashSet,
+    pin::Pin,
+    sync::{LazyLock, Mutex},
+    task::{Context, Poll},
+};
+
+use axum::body::{Body, Bytes, HttpBody};
+use http_body::{Frame, SizeHint};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+
+use crate::model::file::{Path, WRITE_MASK};
 
 pub fn check_permission(
     user_permissions_tree: &Path,
@@ -12,33 +23,433 @@ pub fn check_permission(
     check_permission_recursive(user_permissions_tree, &path_parts, required_permission)
 }
 
+/// 沿请求路径向下查找，由最深的一个配置了权限的节点决定；
+/// 树中没有的更深路径继承该节点的权限
 fn check_permission_recursive(
     current_path: &Path,
     remaining_parts: &[&str],
     required_permission: u8,
 ) -> bool {
-    if remaining_parts.is_empty() {
-        // 检查当前路径是否有权限
-        has_permission(current_path, required_permission)
-    } else {
-        // 检查当前路径是否有权限
-        if !has_permission(current_path, required_permission) {
-            return false;
+    let mut effective = current_path;
+    let mut current = current_path;
+    for part in remaining_parts {
+        match current.sub_path.get(*part) {
+            Some(sub_path) => {
+                current = sub_path;
+                if current.permission != 0 {
+                    effective = current;
+                }
+            }
+            None => break,
         }
+    }
+    has_permission(effective, required_permission)
+}
+
+fn has_permission(path: &Path, required_permission: u8) -> bool {
+    (path.permission & required_permission) == required_permission
+}
 
-        let part = remaining_parts[0];
-        let rest_parts = &remaining_parts[1..];
+/// 所有尚未提交或丢弃的 `AtomicFile` 的临时文件，退出时等待超时后由 [`remove_temp_files`] 清理
+static TEMP_FILES: LazyLock<Mutex<HashSet<std::path::PathBuf>>> = LazyLock::new(Default::default);
 
-        // 如果有子路径，检查子路径权限
-        if let Some(sub_path) = current_path.sub_path.get(part) {
-            return check_permission_recursive(sub_path, rest_parts, required_permission);
+/// 删除仍在写入的临时文件，返回删除的数量。用于退出时放弃未完成的上传
+pub fn remove_temp_files() -> usize {
+    let mut temp_files = TEMP_FILES.lock().unwrap_or_else(|e| e.into_inner());
+    let mut removed = 0;
+    for path in temp_files.drain() {
+        if std::fs::remove_file(&path).is_ok() {
+            removed += 1;
         }
+    }
+    removed
+}
+
+/// 原子写入的文件：先写到同目录下的临时文件，`commit` 时再重命名为目标文件。
+/// 未提交就被丢弃时会删除临时文件，避免留下写了一半的文件。
+pub struct AtomicFile {
+    file: tokio::fs::File,
+    temp_path: std::path::PathBuf,
+    target: std::path::PathBuf,
+    committed: bool,
+}
 
-        // 如果没有找到特定子路径，但当前路径有权限，可以继续
-        true
+impl AtomicFile {
+    pub async fn create(target: &std::path::Path) -> std::io::Result<Self> {
+        let parent = target.parent().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "无效的目标路径")
+        })?;
+        let file_name = target
+            .file_name()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "无效的文件名"))?
+            .to_string_lossy();
+        tokio::fs::create_dir_all(parent).await?;
+
+        let temp_path = parent.join(format!(
+            ".{}.{}{}",
+            file_name,
+            uuid::Uuid::new_v4(),
+            TEMP_SUFFIX
+        ));
+        let file = tokio::fs::File::create(&temp_path).await?;
+        TEMP_FILES
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(temp_path.clone());
+        Ok(AtomicFile {
+            file,
+            temp_path,
+            target: target.to_path_buf(),
+            committed: false,
+        })
+    }
+
+    pub fn as_file_mut(&mut self) -> &mut tokio::fs::File {
+        &mut self.file
+    }
+
+    pub async fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        use tokio::io::AsyncWriteExt;
+        self.file.write_all(buf).await
+    }
+
+    /// 刷盘并把临时文件重命名为目标文件
+    pub async fn commit(mut self) -> std::io::Result<()> {
+        use tokio::io::AsyncWriteExt;
+        self.file.flush().await?;
+        self.file.sync_all().await?;
+        tokio::fs::rename(&self.temp_path, &self.target).await?;
+        self.committed = true;
+        Ok(())
+    }
+
+    /// 与 `commit` 相同，但不覆盖已存在的文件：目标存在时依次尝试 `name (1).ext`、
+    /// `name (2).ext`……，返回最终写入的路径
+    pub async fn commit_unique(mut self) -> std::io::Result<std::path::PathBuf> {
+        use tokio::io::AsyncWriteExt;
+        self.file.flush().await?;
+        self.file.sync_all().await?;
+
+        let stem = self
+            .target
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let extension = self
+            .target
+            .extension()
+            .map(|e| format!(".{}", e.to_string_lossy()))
+            .unwrap_or_default();
+        for i in 0..1000 {
+            let candidate = if i == 0 {
+                self.target.clone()
+            } else {
+                self.target
+                    .with_file_name(format!("{} ({}){}", stem, i, extension))
+            };
+            // 硬链接在目标已存在时失败，检查与创建是原子的
+            match tokio::fs::hard_link(&self.temp_path, &candidate).await {
+                Ok(()) => {
+                    let _ = tokio::fs::remove_file(&self.temp_path).await;
+                    self.committed = true;
+                    return Ok(candidate);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            "同名文件过多",
+        ))
     }
 }
 
-fn has_permission(path: &Path, required_permission: u8) -> bool {
-    (path.permission & required_permission) == required_permission
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = std::fs::remove_file(&self.temp_path);
+        }
+        TEMP_FILES
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.temp_path);
+    }
+}
+
+/// 比较签名或令牌，耗时与第一个不同字节的位置无关
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// RFC 5987 `attr-char` 之外的字符都需要编码
+const ATTR_CHAR_ENCODE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
+/// 下载文件的 `Content-Disposition`：`filename` 只保留可打印的 ASCII 字符并转义引号，
+/// 完整的文件名按 RFC 5987 放在 `filename*` 中，现代浏览器优先使用后者
+pub fn content_disposition(file_name: &str) -> String {
+    let mut fallback = String::with_capacity(file_name.len());
+    for c in file_name.chars() {
+        match c {
+            '"' | '\\' => {
+                fallback.push('\\');
+                fallback.push(c);
+            }
+            ' '..='~' => fallback.push(c),
+            _ => fallback.push('_'),
+        }
+    }
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback,
+        utf8_percent_encode(file_name, ATTR_CHAR_ENCODE)
+    )
+}
+
+const TEMP_SUFFIX: &str = ".part";
+
+/// 是否为 `AtomicFile` 产生的临时文件，列目录时应当跳过
+pub fn is_temp_file(name: &str) -> bool {
+    name.starts_with('.') && name.ends_with(TEMP_SUFFIX)
+}
+
+/// 上传文件的统一写入路径：检查目标的写权限后经 `AtomicFile` 写入，返回写入的字节数。
+/// HTTP 上传与 FTP 共用，没有权限时返回 `PermissionDenied`
+pub async fn write_upload<R>(
+    user_permissions_tree: &Path,
+    target: &std::path::Path,
+    reader: &mut R,
+) -> std::io::Result<u64>
+where
+    R: tokio::io::AsyncRead + Unpin + ?Sized,
+{
+    if !check_permission(user_permissions_tree, &target.to_string_lossy(), WRITE_MASK) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "没有写入权限",
+        ));
+    }
+    let mut file = AtomicFile::create(target).await?;
+    let written = tokio::io::copy(reader, file.as_file_mut()).await?;
+    file.commit().await?;
+    Ok(written)
+}
+
+/// 用修改时间和大小判断文件是否变化
+pub type FileStamp = Option<(std::time::SystemTime, u64)>;
+
+pub fn stamp(path: &std::path::Path) -> FileStamp {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// 收到 SIGHUP 时通知重新加载，非 Unix 平台上不做任何事
+pub fn watch_hangup(tx: tokio::sync::mpsc::Sender<()>) {
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        use tokio::signal::unix::{SignalKind, signal};
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(s) => s,
+            Err(e) => {
+                tracing::warn!("无法监听 SIGHUP: {}", e);
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            if tx.send(()).await.is_err() {
+                break;
+            }
+        }
+    });
+    #[cfg(not(unix))]
+    drop(tx);
+}
+
+type OnFinish = Box<dyn FnOnce(u64, bool) + Send>;
+
+/// 统计响应体实际交给连接的字节数，发送完、出错或被丢弃时调用一次 `on_finish(字节数, 是否发送完)`。
+/// 审计日志和访问日志用它在传输结束后再写记录
+pub struct ObservedBody {
+    inner: Body,
+    sent: u64,
+    on_finish: Option<OnFinish>,
+}
+
+impl ObservedBody {
+    pub fn wrap(body: Body, on_finish: impl FnOnce(u64, bool) + Send + 'static) -> Body {
+        Body::new(ObservedBody {
+            inner: body,
+            sent: 0,
+            on_finish: Some(Box::new(on_finish)),
+        })
+    }
+
+    fn finish(&mut self, complete: bool) {
+        if let Some(on_finish) = self.on_finish.take() {
+            on_finish(self.sent, complete);
+        }
+    }
+}
+
+impl HttpBody for ObservedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_frame(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(data) = frame.data_ref() {
+                    this.sent += data.len() as u64;
+                }
+            }
+            Poll::Ready(Some(Err(_))) => this.finish(false),
+            Poll::Ready(None) => this.finish(true),
+            Poll::Pending => {}
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for ObservedBody {
+    fn drop(&mut self) {
+        // 没有响应体，或者最后一帧之后没有再被轮询
+        let complete = self.inner.is_end_stream();
+        self.finish(complete);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::model::file::{READ_MASK, VIEW_MASK};
+
+    /// 按 `(路径, [[paths]] 的权限, 用户授权)` 依次合并出用户的权限树
+    fn tree(grants: &[(&str, u8, u8)]) -> Path {
+        let mut root = Path {
+            path: "/".to_string(),
+            name: "root".to_string(),
+            permission: 0,
+            sub_path: BTreeMap::new(),
+        };
+        for (path, path_permission, grant) in grants {
+            let mut configured = Path {
+                path: path.to_string(),
+                name: path.to_string(),
+                permission: *path_permission,
+                sub_path: BTreeMap::new(),
+            };
+            configured.extract_sub_paths();
+            root.merge_path(&configured, *grant);
+        }
+        root
+    }
+
+    // 授权和 [[paths]] 的权限一致时，新旧规则的结果相同
+    #[test]
+    fn matching_grants_keep_their_access() {
+        let tree = tree(&[("/srv/data", 0b111, 0b111), ("/srv/ro", 0b101, 0b101)]);
+        assert!(check_permission(&tree, "/srv/data/a.txt", WRITE_MASK));
+        assert!(check_permission(&tree, "/srv/data/sub/a.txt", WRITE_MASK));
+        assert!(check_permission(&tree, "/srv/ro/a.txt", READ_MASK));
+        assert!(check_permission(&tree, "/srv/ro/sub/a.txt", VIEW_MASK));
+        assert!(!check_permission(&tree, "/srv/ro/a.txt", WRITE_MASK));
+    }
+
+    // 以下几项是行为变化的地方，旧规则把两者按位或，所以这里都可以写入
+
+    #[test]
+    fn grant_cannot_exceed_path_permission() {
+        let tree = tree(&[("/srv/public", 0b101, 0b111)]);
+        assert!(check_permission(&tree, "/srv/public/a.txt", READ_MASK));
+        assert!(!check_permission(&tree, "/srv/public/a.txt", WRITE_MASK));
+    }
+
+    #[test]
+    fn read_only_grant_on_writable_path() {
+        let tree = tree(&[("/srv/ro", 0b111, 0b101)]);
+        assert!(check_permission(&tree, "/srv/ro/a.txt", READ_MASK));
+        assert!(!check_permission(&tree, "/srv/ro/a.txt", WRITE_MASK));
+    }
+
+    // 旧规则中公共祖先节点的权限由最后合并的授权决定，顺序不同结果也不同
+    #[test]
+    fn grant_order_does_not_matter() {
+        let data = ("/srv/data", 0b111, 0b111);
+        let ro = ("/srv/ro", 0b111, 0b101);
+        for tree in [tree(&[data, ro]), tree(&[ro, data])] {
+            assert!(check_permission(&tree, "/srv/data/a.txt", WRITE_MASK));
+            assert!(!check_permission(&tree, "/srv/ro/a.txt", WRITE_MASK));
+            assert!(check_permission(&tree, "/srv/ro/a.txt", READ_MASK));
+        }
+    }
+
+    // 旧规则中根节点带着最后一次授权的权限，授权目录之外的路径也能访问
+    #[test]
+    fn paths_outside_grants_are_denied() {
+        let tree = tree(&[("/srv/data", 0b111, 0b111)]);
+        assert!(!check_permission(&tree, "/etc/passwd", READ_MASK));
+        assert!(!check_permission(&tree, "/srv", VIEW_MASK));
+        assert!(!check_permission(&tree, "/srv/other/a.txt", READ_MASK));
+        assert!(check_permission(
+            &tree,
+            "/srv/data/deep/er/a.txt",
+            WRITE_MASK
+        ));
+    }
+
+    // 旧规则要求每一级祖先都有权限，但子目录的授权会改写祖先的权限
+    #[test]
+    fn deepest_grant_wins() {
+        let tree = tree(&[("/srv", 0b111, 0b111), ("/srv/archive", 0b111, 0b101)]);
+        assert!(check_permission(&tree, "/srv/a.txt", WRITE_MASK));
+        assert!(!check_permission(&tree, "/srv/archive/a.txt", WRITE_MASK));
+        assert!(check_permission(&tree, "/srv/archive/a.txt", READ_MASK));
+    }
+
+    #[test]
+    fn content_disposition_escapes_file_names() {
+        assert_eq!(
+            content_disposition("report.pdf"),
+            "attachment; filename=\"report.pdf\"; filename*=UTF-8''report.pdf"
+        );
+        assert_eq!(
+            content_disposition("a \"b\"\\c.txt"),
+            "attachment; filename=\"a \\\"b\\\"\\\\c.txt\"; filename*=UTF-8''a%20%22b%22%5Cc.txt"
+        );
+        assert_eq!(
+            content_disposition("报告 1.pdf"),
+            "attachment; filename=\"__ 1.pdf\"; filename*=UTF-8''%E6%8A%A5%E5%91%8A%201.pdf"
+        );
+        // 换行等控制字符不能出现在响应头中
+        let header = content_disposition("a\r\nSet-Cookie: x=1");
+        assert!(axum::http::HeaderValue::from_str(&header).is_ok());
+        assert!(header.starts_with("attachment; filename=\"a__Set-Cookie: x=1\";"));
+    }
 }
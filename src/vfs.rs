@@ -0,0 +1,86 @@
+//! 按用户权限拼出的虚拟文件系统，SFTP 与 FTP 共用：`/` 下列出用户可见的 `[[paths]]`，
+//! `/{name}/...` 映射到该路径下，并拒绝通过 `..` 或符号链接离开所属的根目录。
+
+use std::path::{Path, PathBuf};
+
+use crate::model::{self, AppState, UserConfig, VIEW_MASK};
+use crate::utils::check_permission;
+
+/// 虚拟路径解析结果
+pub enum Target {
+    /// 虚拟根目录 `/`
+    Root,
+    /// 某个 `[[paths]]` 中的文件或目录，`root` 为该路径配置的根目录
+    Fs { root: PathBuf, path: PathBuf },
+}
+
+/// 规范化虚拟路径：相对路径以 `/` 为当前目录，处理 `.` 与 `..`，结果不会越过 `/`
+pub fn normalize(path: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            p => parts.push(p),
+        }
+    }
+    parts
+}
+
+/// 解析虚拟路径，根目录名不存在时返回 `None`
+pub fn resolve(app: &AppState, path: &str) -> Option<Target> {
+    let parts = normalize(path);
+    let Some((name, rest)) = parts.split_first() else {
+        return Some(Target::Root);
+    };
+    if parts.iter().any(|p| p.contains('\\')) {
+        return None;
+    }
+    let root = PathBuf::from(&app.get_path(name)?.path);
+    let mut path = root.clone();
+    path.extend(rest);
+    Some(Target::Fs { root, path })
+}
+
+/// 审计记录中的根目录名和相对路径，例如 `/docs/a/b.txt` -> (`docs`, `a/b.txt`)
+pub fn audit_target(path: &str) -> (String, String) {
+    let parts = normalize(path);
+    match parts.split_first() {
+        Some((root, rest)) => (root.to_string(), rest.join("/")),
+        None => (String::new(), String::new()),
+    }
+}
+
+/// 用户在虚拟根目录下能看到的 `[[paths]]`
+pub fn visible_roots(app: &AppState, user: &UserConfig) -> Vec<model::Path> {
+    app.config()
+        .paths
+        .values()
+        .filter(|p| check_permission(&user.permissions_tree, &p.path, VIEW_MASK))
+        .cloned()
+        .collect()
+}
+
+/// 防止通过符号链接离开根目录：比较真实路径，目标不存在时检查其父目录
+pub async fn ensure_contained(root: &Path, path: &Path) -> std::io::Result<()> {
+    let real_root = tokio::fs::canonicalize(root).await?;
+    let real_path = match tokio::fs::canonicalize(path).await {
+        Ok(p) => p,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let parent = path.parent().ok_or(e)?;
+            tokio::fs::canonicalize(parent).await?
+        }
+        Err(e) => return Err(e),
+    };
+    if real_path.starts_with(&real_root) {
+        Ok(())
+    } else {
+        tracing::debug!("拒绝访问根目录之外的路径: {:?}", path);
+        Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "路径超出根目录",
+        ))
+    }
+}
@@ -0,0 +1,278 @@
+//! 用 [Pebble](https://github.com/letsencrypt/pebble) 测试 ACME 证书的申请和续期，两种验证方式各一个测试。
+//! 需要先启动 Pebble，以及把测试域名解析到 127.0.0.1 的 pebble-challtestsrv，
+//! 启动方式见 `.github/workflows/acme.yml`。测试默认忽略：
+//!
+//! ```bash
+//! ACME_TEST_CA_FILE=pebble/test/certs/pebble.minica.pem \
+//!     cargo test --test acme_pebble -- --ignored --test-threads=1
+//! ```
+//!
+//! Pebble 在 5002 端口做 HTTP-01 验证、在 5001 端口做 TLS-ALPN-01 验证，两个测试分别占用其中一个。
+//! `ACME_TEST_DIRECTORY` 和 `ACME_TEST_DOMAIN` 可以修改目录地址和申请的域名。
+
+use std::{
+    fs::File,
+    net::TcpStream,
+    path::{Path, PathBuf},
+    process::{Child, Command},
+    sync::Arc,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use rustls::{
+    ClientConfig, ClientConnection, DigitallySignedStruct, SignatureScheme,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{CryptoProvider, verify_tls12_signature, verify_tls13_signature},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+const DEFAULT_DIRECTORY: &str = "https://localhost:14000/dir";
+const DEFAULT_DOMAIN: &str = "sfm.test";
+/// 等待申请完成的最长时间
+const ISSUE_TIMEOUT: Duration = Duration::from_secs(90);
+
+#[test]
+#[ignore = "需要 Pebble，见文件开头的说明"]
+fn http01_issue_and_renew() {
+    issue_and_renew("http-01", 5002, 15443);
+}
+
+#[test]
+#[ignore = "需要 Pebble，见文件开头的说明"]
+fn tls_alpn01_issue_and_renew() {
+    issue_and_renew("tls-alpn-01", 15080, 5001);
+}
+
+/// 第一次启动时用自签名证书顶替并申请证书；重启后剩余有效期不足 `renew_before_days` 时
+/// 用缓存的账户续期。每次都检查 HTTPS 端口已经换上新证书，且验证文件已清理
+fn issue_and_renew(challenge: &str, port: u16, https_port: u16) {
+    let pebble = Pebble::from_env();
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("files")).unwrap();
+    let account_file = dir.path().join("data/acme/account.json");
+
+    write_config(dir.path(), &pebble, challenge, port, https_port, 30);
+    let first = {
+        let server = Server::start(dir.path(), "first.log");
+        wait_for_issued(&server, dir.path(), https_port, &pebble.domain, None)
+    };
+    let account = std::fs::read(&account_file).unwrap();
+
+    // Pebble 签发的证书有效期远小于 100 年，重启后会立即续期
+    write_config(dir.path(), &pebble, challenge, port, https_port, 36500);
+    let second = {
+        let server = Server::start(dir.path(), "second.log");
+        wait_for_issued(
+            &server,
+            dir.path(),
+            https_port,
+            &pebble.domain,
+            Some(&first),
+        )
+    };
+    assert_ne!(first, second);
+    assert_eq!(
+        std::fs::read(&account_file).unwrap(),
+        account,
+        "续期时应当复用账户"
+    );
+
+    let challenge_dir = dir.path().join("data/acme-challenge");
+    if let Ok(entries) = std::fs::read_dir(&challenge_dir) {
+        assert_eq!(entries.count(), 0, "HTTP-01 验证文件没有清理");
+    }
+}
+
+struct Pebble {
+    directory: String,
+    ca_file: PathBuf,
+    domain: String,
+}
+
+impl Pebble {
+    fn from_env() -> Self {
+        let ca_file = std::env::var("ACME_TEST_CA_FILE")
+            .expect("需要把 ACME_TEST_CA_FILE 设置为 Pebble 的 test/certs/pebble.minica.pem");
+        Pebble {
+            directory: std::env::var("ACME_TEST_DIRECTORY")
+                .unwrap_or(DEFAULT_DIRECTORY.to_string()),
+            ca_file: std::fs::canonicalize(ca_file).expect("ACME_TEST_CA_FILE 不存在"),
+            domain: std::env::var("ACME_TEST_DOMAIN").unwrap_or(DEFAULT_DOMAIN.to_string()),
+        }
+    }
+}
+
+fn write_config(
+    dir: &Path,
+    pebble: &Pebble,
+    challenge: &str,
+    port: u16,
+    https_port: u16,
+    renew_before_days: i64,
+) {
+    let config = format!(
+        r#"[misc]
+host = "127.0.0.1"
+port = {port}
+https_port = {https_port}
+
+[acme]
+enable = true
+domains = ["{domain}"]
+challenge = "{challenge}"
+directory_url = "{directory}"
+directory_ca_file = "{ca_file}"
+renew_before_days = {renew_before_days}
+
+[[paths]]
+name = "files"
+path = "files"
+permission = 0b111
+
+[[users]]
+username = "admin"
+password = "change-me"
+[[users.permissions]]
+path_name = "files"
+permission = 0b111
+"#,
+        domain = pebble.domain,
+        directory = pebble.directory,
+        ca_file = pebble.ca_file.display(),
+    );
+    std::fs::write(dir.join("config.toml"), config).unwrap();
+}
+
+/// 在 `dir` 中运行服务，输出写入 `log`，结束时杀掉进程
+struct Server {
+    child: Child,
+    log: PathBuf,
+}
+
+impl Server {
+    fn start(dir: &Path, log: &str) -> Self {
+        let log = dir.join(log);
+        let file = File::create(&log).unwrap();
+        let child = Command::new(env!("CARGO_BIN_EXE_simple_file_manager"))
+            .args(["--config", "config.toml"])
+            .current_dir(dir)
+            .stdout(file.try_clone().unwrap())
+            .stderr(file)
+            .spawn()
+            .unwrap();
+        Server { child, log }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// 等到 `dir` 中缓存的是 CA 签发的、与 `previous` 不同的证书，并且 HTTPS 端口已经在使用它，
+/// 返回证书的 DER
+fn wait_for_issued(
+    server: &Server,
+    dir: &Path,
+    https_port: u16,
+    domain: &str,
+    previous: Option<&[u8]>,
+) -> Vec<u8> {
+    let cert_file = dir.join("data/acme/cert.pem");
+    let started = Instant::now();
+    loop {
+        if let Some(cert) = issued_cert(&cert_file)
+            && previous != Some(cert.as_slice())
+            && served_cert(https_port, domain).as_ref() == Some(&cert)
+        {
+            return cert;
+        }
+        assert!(
+            started.elapsed() < ISSUE_TIMEOUT,
+            "{} 秒内没有申请到证书，服务日志:\n{}",
+            ISSUE_TIMEOUT.as_secs(),
+            std::fs::read_to_string(&server.log).unwrap_or_default()
+        );
+        sleep(Duration::from_millis(500));
+    }
+}
+
+/// 证书链中的第一张证书，自签名的临时证书返回 `None`
+fn issued_cert(cert_file: &Path) -> Option<Vec<u8>> {
+    let pem = std::fs::read(cert_file).ok()?;
+    let (_, pem) = x509_parser::pem::parse_x509_pem(&pem).ok()?;
+    let (_, cert) = X509Certificate::from_der(&pem.contents).ok()?;
+    (cert.subject() != cert.issuer()).then_some(pem.contents)
+}
+
+/// 以 `domain` 作为 SNI 握手，返回服务端发送的证书
+fn served_cert(port: u16, domain: &str) -> Option<Vec<u8>> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .ok()?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyCert(provider)))
+        .with_no_client_auth();
+    let name = ServerName::try_from(domain.to_string()).ok()?;
+    let mut conn = ClientConnection::new(Arc::new(config), name).ok()?;
+    let mut socket = TcpStream::connect(("127.0.0.1", port)).ok()?;
+    socket.set_read_timeout(Some(Duration::from_secs(5))).ok()?;
+    while conn.is_handshaking() {
+        conn.complete_io(&mut socket).ok()?;
+    }
+    Some(conn.peer_certificates()?.first()?.to_vec())
+}
+
+/// 只用来取出服务端证书，不校验证书链，但仍然校验握手签名
+#[derive(Debug)]
+struct AcceptAnyCert(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCert {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}